@@ -0,0 +1,180 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::ConfigurafoxError;
+use crate::metadata::Metadata;
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn of(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// If `path` has the extension of an archive `list_entries` and `read_entry` can read
+pub fn is_archive(path: &Path) -> bool {
+    ArchiveKind::of(path).is_some()
+}
+
+fn unsupported(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{}: unsupported archive type, expected .zip, .tar, .tar.gz or .tgz", path.display()),
+    )
+}
+
+fn zip_err(e: zip::result::ZipError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+fn open_tar(data: &[u8], kind: ArchiveKind) -> tar::Archive<Box<dyn Read + '_>> {
+    let reader: Box<dyn Read> = match kind {
+        ArchiveKind::TarGz => Box::new(flate2::read::GzDecoder::new(data)),
+        _ => Box::new(data),
+    };
+    tar::Archive::new(reader)
+}
+
+/// The paths and sizes of all regular files in the archive `data`. `path` is only used for its extension
+pub fn list_entries(path: &Path, data: &[u8]) -> std::io::Result<Vec<(String, u64)>> {
+    let kind = ArchiveKind::of(path).ok_or_else(|| unsupported(path))?;
+
+    let mut entries = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).map_err(zip_err)?;
+            for i in 0..archive.len() {
+                let file = archive.by_index(i).map_err(zip_err)?;
+                if file.is_file() {
+                    entries.push((file.name().to_string(), file.size()));
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            for entry in open_tar(data, kind).entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    entries.push((entry.path()?.to_string_lossy().into_owned(), entry.size()));
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// The paths and contents of all regular files in the archive `data`, read in one pass. `path` is only used for
+/// its extension
+pub fn read_entries(path: &Path, data: &[u8]) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    let kind = ArchiveKind::of(path).ok_or_else(|| unsupported(path))?;
+
+    let mut entries = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).map_err(zip_err)?;
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i).map_err(zip_err)?;
+                if file.is_file() {
+                    let mut contents = Vec::new();
+                    file.read_to_end(&mut contents)?;
+                    entries.push((file.name().to_string(), contents));
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            for entry in open_tar(data, kind).entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let mut contents = Vec::new();
+                    entry.read_to_end(&mut contents)?;
+                    entries.push((entry.path()?.to_string_lossy().into_owned(), contents));
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Reads the entry `name` out of the archive `data`. `path` is only used for its extension. Use `read_entries`
+/// to read several, as this scans the archive from the start
+pub fn read_entry(path: &Path, data: &[u8], name: &str) -> std::io::Result<Vec<u8>> {
+    let kind = ArchiveKind::of(path).ok_or_else(|| unsupported(path))?;
+
+    let mut contents = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).map_err(zip_err)?;
+            archive.by_name(name).map_err(zip_err)?.read_to_end(&mut contents)?;
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = open_tar(data, kind);
+            let mut found = false;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.path()?.to_string_lossy() == name {
+                    entry.read_to_end(&mut contents)?;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{name} not found in {}", path.display())));
+            }
+        }
+    }
+
+    Ok(contents)
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Registers files inside a zip or tar(.gz) archive without extracting it to disk. `parse_resource` gets the
+    /// path of each entry inside the archive. The resources are registered at the path `archive_path/entry`. The
+    /// archive is read once, and the contents of its entries kept for `read`
+    pub fn register_archive<F: Fn(&Path) -> Option<R>>(
+        &mut self,
+        archive_path: PathBuf,
+        parse_resource: F,
+    ) -> Result<(), ConfigurafoxError> {
+        debug!("Adding files in archive {}", archive_path.display());
+
+        let entries = self.archive_entries(&archive_path)?;
+        let mut names = entries.keys().collect::<Vec<_>>();
+        names.sort();
+        for entry in names {
+            let size = entries[entry].len() as u64;
+            let entry_path = archive_path.join(entry);
+
+            let Some(res) = parse_resource(Path::new(&entry)) else {
+                debug!("{}: Not adding", entry_path.display());
+                continue;
+            };
+            info!("{}: Adding {:?}", entry_path.display(), res.identifier());
+
+            let origin = Origin::Archive { archive: archive_path.clone(), entry: entry.clone() };
+            self.insert_with_origin(res.clone(), entry_path, origin, Metadata::new());
+            self.set_size(&res, size);
+        }
+
+        Ok(())
+    }
+}
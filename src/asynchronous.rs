@@ -0,0 +1,115 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+
+use futures_util::stream::{self, StreamExt};
+use tracing::Instrument;
+
+use crate::{ConfigurafoxError, ResourceProcessor};
+use crate::output::OutputBackend;
+use crate::resource_manager::{Resource, ResourceManager};
+
+/// How many resources `run_async` processes at once by default
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// What an `AsyncResourceProcessor` returns. Not `Send`, as resources are processed concurrently on one task
+pub type ProcessFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, ConfigurafoxError>> + 'a>>;
+
+/// A `ResourceProcessor` that can wait on I/O, e.g. fetching oEmbed data or webmentions, without blocking the
+/// other resources
+pub trait AsyncResourceProcessor<R: Resource> {
+    fn name(&self) -> String;
+
+    /// Returns the contents of the output file
+    fn process_resource<'a>(&'a self, source: &'a R, source_path: &'a Path, resources: &'a ResourceManager<R>) -> ProcessFuture<'a>;
+}
+
+impl<R: Resource, P: AsyncResourceProcessor<R> + ?Sized> AsyncResourceProcessor<R> for &P {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn process_resource<'a>(&'a self, source: &'a R, source_path: &'a Path, resources: &'a ResourceManager<R>) -> ProcessFuture<'a> {
+        (**self).process_resource(source, source_path, resources)
+    }
+}
+
+/// Runs a synchronous processor from `run_async`, so async processors can be mixed with the usual ones. It
+/// blocks the task while processing, which is fine for CPU-bound work like `HTMLProcessor`
+pub struct Blocking<P>(pub P);
+
+impl<R: Resource, P: ResourceProcessor<R>> AsyncResourceProcessor<R> for Blocking<P> {
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn process_resource<'a>(&'a self, source: &'a R, source_path: &'a Path, resources: &'a ResourceManager<R>) -> ProcessFuture<'a> {
+        Box::pin(async move { self.0.process_resource(source, source_path, resources) })
+    }
+}
+
+/// Like `run_with_backend`, with processors that are awaited, up to `concurrency` at a time. Outputs are
+/// written as they finish. Runs on whatever executor polls it, see `run_async_blocking` for tokio
+pub async fn run_async<'data, R: Resource, D, F: Fn(&Path, &R, &'data D) -> Box<dyn AsyncResourceProcessor<R> + 'data>>(
+    backend: &mut dyn OutputBackend,
+    resman: &ResourceManager<R>,
+    processor_for: F,
+    data: &'data D,
+    concurrency: usize,
+) -> Result<(), ConfigurafoxError> {
+    let run = tracing::info_span!("run", backend = %backend.describe());
+    let started = std::time::Instant::now();
+
+    info!(parent: &run, "Writing output to {}", backend.describe());
+    resman.diagnostics().clear();
+
+    let processor_for = &processor_for;
+    let mut outputs = stream::iter(resman.iter())
+        .map(|(path, resource)| {
+            let process = tracing::info_span!(parent: &run, "process", resource = %resource.identifier(), path = %path.display());
+            async move {
+                let processor = processor_for(path, resource, data);
+                info!("Processing {} @ {} w/ {}", resource.identifier(), path.display(), processor.name());
+
+                resman.dependency_graph().clear_dependencies_of(resource);
+
+                let processed = processor.process_resource(resource, path, resman).await.map_err(|e| ConfigurafoxError::InResource {
+                    identifier: resource.identifier(),
+                    path: path.to_owned(),
+                    processor: processor.name(),
+                    error: Box::new(e),
+                })?;
+                Ok::<_, ConfigurafoxError>((resource, processed))
+            }
+            .instrument(process)
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut count = 0;
+    while let Some(output) = outputs.next().await {
+        let (resource, processed) = output?;
+        count += 1;
+        let _write = tracing::debug_span!(parent: &run, "write").entered();
+        backend.write_file(&resman.output_path(resource), &processed)?;
+    }
+    drop(outputs);
+
+    let _run = run.entered();
+    crate::finish_run(backend, resman, count, started)
+}
+
+/// `run_async` on a new single-threaded tokio runtime, for calling it from synchronous code. Processors can use
+/// tokio's I/O and timers if the caller enables the corresponding tokio features
+pub fn run_async_blocking<'data, R: Resource, D, F: Fn(&Path, &R, &'data D) -> Box<dyn AsyncResourceProcessor<R> + 'data>>(
+    backend: &mut dyn OutputBackend,
+    resman: &ResourceManager<R>,
+    processor_for: F,
+    data: &'data D,
+    concurrency: usize,
+) -> Result<(), ConfigurafoxError> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(run_async(backend, resman, processor_for, data, concurrency))
+}
@@ -0,0 +1,260 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use html_editor::Node;
+
+use crate::{ConfigurafoxError, slugify};
+use crate::entities::escape_text;
+use crate::metadata::{Metadata, Value};
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::site::SiteData;
+use crate::treewalker::{Context, TreeWalker, element, get_attr};
+
+/// Metadata keys with the authors of a resource: `authors` an array, `author` a single one. Each is an author id,
+/// or a table with a `name` and optionally an `id`, `url` and `avatar`
+pub const AUTHORS_KEY: &str = "authors";
+pub const AUTHOR_KEY: &str = "author";
+
+/// The site variable with a table of authors by id, each a table like in `AUTHORS_KEY`:
+/// `[variables.authors.jane]` with `name = "Jane Doe"`
+pub const AUTHORS_VARIABLE: &str = "authors";
+
+/// Metadata key of the pages `AuthorPages` generates, with the id of the author they're for
+pub const AUTHOR_PAGE_KEY: &str = "author_page";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Author {
+    /// Identifies the author across pages, and names their page
+    pub id: String,
+    pub name: String,
+    /// Their own site, linked instead of their page
+    pub url: Option<String>,
+    /// Image URL
+    pub avatar: Option<String>,
+}
+
+impl Author {
+    /// The author `value` stands for, see `AUTHORS_KEY`. Ids are looked up in the site's `AUTHORS_VARIABLE`, and
+    /// are their own name if they aren't there
+    pub fn from_value<D: SiteData + ?Sized>(value: &Value, site: &D) -> Option<Author> {
+        match value {
+            Value::String(id) => match site.var(&format!("{AUTHORS_VARIABLE}.{id}")) {
+                Some(known) => Author::from_table(known, Some(id)),
+                None => Some(Author { id: id.clone(), name: id.clone(), url: None, avatar: None }),
+            },
+            table => Author::from_table(table, None),
+        }
+    }
+
+    fn from_table(table: &Value, id: Option<&str>) -> Option<Author> {
+        let field = |key| table.get(key).and_then(Value::as_str).map(str::to_string);
+        let name = field("name")?;
+        Some(Author {
+            id: id.map(str::to_string).or_else(|| field("id")).unwrap_or_else(|| slugify(&name)),
+            name,
+            url: field("url"),
+            avatar: field("avatar"),
+        })
+    }
+}
+
+/// The authors in `metadata`, in order. Values that aren't authors are left out
+pub fn authors_of<D: SiteData + ?Sized>(metadata: &Metadata, site: &D) -> Vec<Author> {
+    let values = match (metadata.get(AUTHORS_KEY), metadata.get(AUTHOR_KEY)) {
+        (Some(Value::Array(values)), _) => values.iter().collect(),
+        (Some(value), _) | (None, Some(value)) => vec![value],
+        (None, None) => vec![],
+    };
+    values.into_iter().filter_map(|value| Author::from_value(value, site)).collect()
+}
+
+/// Generates a page for every author of a collection's members, `<id>/index.html`, as resources with their
+/// contents in memory. Each has the author's name as `title`, the author as `AUTHOR_KEY` and `AUTHOR_PAGE_KEY`,
+/// so a `<byline/>` on it shows the author and an `<author-posts/>` lists their members of the collection.
+/// Generate after registering the members
+#[derive(Debug, Clone)]
+pub struct AuthorPages {
+    pub collection: String,
+    /// The HTML of every page. By default a heading with the name, `<byline/>` and `<author-posts/>`
+    pub template: Option<String>,
+}
+
+impl AuthorPages {
+    pub fn new(collection: &str) -> AuthorPages {
+        AuthorPages { collection: collection.to_string(), template: None }
+    }
+
+    pub fn with_template(mut self, html: &str) -> AuthorPages {
+        self.template = Some(html.to_string());
+        self
+    }
+
+    /// Registers the pages under `dir`, with the resource `make_resource` gives for the path of each, like
+    /// `ResourceManager::register_all_files_in_directory` does. Pages it gives None for are skipped.
+    /// Returns the registered resources
+    pub fn generate<R: Resource, D: SiteData + ?Sized, F: Fn(&Path) -> Option<R>>(
+        &self,
+        resources: &mut ResourceManager<R>,
+        site: &D,
+        dir: &Path,
+        make_resource: F,
+    ) -> Result<Vec<R>, ConfigurafoxError> {
+        let members = resources
+            .collection(&self.collection)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "collection", name: self.collection.clone() })?;
+        let authors = members
+            .iter()
+            .flat_map(|(_, member)| authors_of(resources.metadata(member), site))
+            .map(|author| (author.id.clone(), author))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut generated = Vec::new();
+        for (id, author) in authors {
+            let path = dir.join(slugify(&id)).join("index.html");
+            let Some(resource) = make_resource(&path) else {
+                continue;
+            };
+            info!("{}: Generating the page of {:?}", path.display(), author.name);
+
+            let mut table = serde_json::Map::new();
+            table.insert("id".to_string(), Value::from(author.id.as_str()));
+            table.insert("name".to_string(), Value::from(author.name.as_str()));
+            table.extend(author.url.as_deref().map(|url| ("url".to_string(), Value::from(url))));
+            table.extend(author.avatar.as_deref().map(|avatar| ("avatar".to_string(), Value::from(avatar))));
+
+            let mut metadata = Metadata::new();
+            metadata.insert("title", author.name.as_str());
+            metadata.insert(AUTHOR_KEY, Value::Object(table));
+            metadata.insert(AUTHOR_PAGE_KEY, id.as_str());
+            let contents = match &self.template {
+                Some(template) => template.clone(),
+                None => format!("<h1>{}</h1>\n<byline/>\n<author-posts/>\n", escape_text(&author.name)),
+            };
+
+            resources.insert_with_origin(resource.clone(), path, Origin::Memory(contents.into_bytes().into()), metadata);
+            generated.push(resource);
+        }
+        Ok(generated)
+    }
+}
+
+/// The page `AuthorPages` generated for the author `id`, if any
+fn author_page<'a, R: Resource>(resources: &'a ResourceManager<R>, id: &str) -> Option<&'a R> {
+    resources
+        .iter()
+        .map(|(_, resource)| resource)
+        .find(|resource| resources.metadata(resource).get_str(AUTHOR_PAGE_KEY) == Some(id))
+}
+
+/// Replaces `<byline/>` with the authors of the page, each with their avatar and name. Names link to the author's
+/// `url`, or else to their page from `AuthorPages`. Nothing if the page has no authors
+pub struct Byline;
+
+impl<R: Resource, D: SiteData> TreeWalker<R, D> for Byline {
+    fn describe(&self) -> String {
+        "Byline".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "byline"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["byline"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let authors = authors_of(ctx.metadata(), ctx.data);
+        if authors.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut children = Vec::new();
+        for (i, author) in authors.iter().enumerate() {
+            if i > 0 {
+                children.push(Node::Text(if i + 1 == authors.len() { " and " } else { ", " }.to_string()));
+            }
+
+            let mut parts = Vec::new();
+            if let Some(avatar) = &author.avatar {
+                parts.push(element("img", vec![("class", "avatar".to_string()), ("src", avatar.clone()), ("alt", String::new())], vec![]));
+            }
+            let href = match (&author.url, author_page(ctx.resources, &author.id)) {
+                (Some(url), _) => Some(url.clone()),
+                (None, Some(page)) if page != ctx.resource => Some(ctx.link_to(page)?),
+                (None, _) => None,
+            };
+            let name = Node::Text(author.name.clone());
+            parts.push(match href {
+                Some(href) => element("a", vec![("href", href), ("rel", "author".to_string())], vec![name]),
+                None => name,
+            });
+            children.push(element("span", vec![("class", "author".to_string())], parts));
+        }
+
+        let class = get_attr(&attrs, "class").unwrap_or("byline").to_string();
+        Ok(vec![element("div", vec![("class", class)], children)])
+    }
+}
+
+/// Replaces `<author-posts/>` with a list of an author's members of a collection, linking to each with its title.
+/// The author is the `author` attribute or the page's `AUTHOR_PAGE_KEY`, the `collection` attribute overrides
+/// the collection
+pub struct AuthorPosts {
+    /// Listed unless the element says otherwise
+    pub collection: String,
+}
+
+impl AuthorPosts {
+    pub fn new(collection: &str) -> AuthorPosts {
+        AuthorPosts { collection: collection.to_string() }
+    }
+}
+
+impl<R: Resource, D: SiteData> TreeWalker<R, D> for AuthorPosts {
+    fn describe(&self) -> String {
+        format!("AuthorPosts({})", self.collection)
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "author-posts"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["author-posts"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let id = get_attr(&attrs, "author")
+            .or_else(|| ctx.metadata().get_str(AUTHOR_PAGE_KEY))
+            .ok_or_else(|| ConfigurafoxError::MissingAttr {
+                key_name: "author".to_string(),
+                msg: "needed outside author pages".to_string(),
+            })?;
+        let collection = get_attr(&attrs, "collection").unwrap_or(&self.collection);
+        let members = ctx
+            .resources
+            .collection(collection)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "collection", name: collection.to_string() })?;
+
+        let mut items = Vec::new();
+        for (_, member) in members {
+            let metadata = ctx.resources.metadata(member);
+            if !authors_of(metadata, ctx.data).iter().any(|author| author.id == id) {
+                continue;
+            }
+            let title = metadata.get_str("title").map_or_else(|| member.identifier(), str::to_string);
+            let link = element("a", vec![("href", ctx.link_to(member)?)], vec![Node::Text(title)]);
+            items.push(element("li", vec![], vec![link]));
+        }
+        if items.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let class = get_attr(&attrs, "class").unwrap_or("author-posts").to_string();
+        Ok(vec![element("ul", vec![("class", class)], items)])
+    }
+}
@@ -0,0 +1,659 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use configurafox::config::{ProjectConfig, DEFAULT_CONFIG_FILE};
+use configurafox::output::{LocalBackend, MemoryBackend, OutputBackend};
+use configurafox::resource_manager::{PathResource, ResourceManager};
+use configurafox::scaffold::{self, Template};
+use configurafox::timing::TimingLayer;
+use configurafox::changelog::{BuildChanges, Changelog, ChangelogOptions, ManifestBackend, DEFAULT_BUILD_MANIFEST, DEFAULT_CHANGELOG_FILE};
+use configurafox::deploy::{self, DeployManifest, RsyncBackend, DEFAULT_DEPLOY_MANIFEST};
+use configurafox::diff::{BuildDiff, OutputChange};
+use configurafox::git::GitHistory;
+use configurafox::images::{self, ImageProcessor};
+use configurafox::linkcheck::{self, LinkCheckOptions, DEFAULT_LINK_CACHE};
+use configurafox::newsletter::{self, NewsletterOptions};
+use configurafox::site::Site;
+use configurafox::snapshot::{self, DEFAULT_SNAPSHOT_DIR};
+use configurafox::webmention::{self, SentLog};
+use configurafox::{ConfigurafoxError, HTMLProcessor, IdentityProcessor, ResourceProcessor};
+
+#[derive(Parser)]
+#[command(version, about = "Builds configurafox sites")]
+struct Cli {
+    /// The project configuration file
+    #[arg(long, short, default_value = DEFAULT_CONFIG_FILE)]
+    config: PathBuf,
+
+    /// Log more, can be repeated
+    #[arg(long, short, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Builds the site into the output directory
+    Build {
+        /// Writes how long each stage, resource and walker took to this file, as folded stacks for
+        /// `inferno-flamegraph` or `flamegraph.pl`, and prints a summary
+        #[arg(long)]
+        profile: Option<PathBuf>,
+    },
+    /// Builds the site in memory and shows which outputs it would add, remove or change, with diffs of text files
+    Diff {
+        /// Compare against the manifest of the last deploy instead of the output directory, without content diffs
+        #[arg(long)]
+        manifest: bool,
+    },
+    /// Checks the links and anchors in the last build, reporting broken ones
+    CheckLinks {
+        /// Also request links to other sites (with the remote feature). Working ones are cached for a day
+        #[arg(long)]
+        external: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Lists the links to other sites in the last build that haven't been sent webmentions yet
+    Webmentions {
+        /// Send them (with the remote feature), remembering which were sent so they aren't sent again
+        #[arg(long)]
+        send: bool,
+    },
+    /// Builds the site in memory and compares each output against its stored snapshot, failing on differences
+    Snapshot {
+        /// Store the current outputs as the snapshots instead
+        #[arg(long)]
+        update: bool,
+        /// Where snapshots are stored, relative to the project root
+        #[arg(long, default_value = DEFAULT_SNAPSHOT_DIR)]
+        dir: PathBuf,
+    },
+    /// Builds the site in memory and renders posts into one self-contained HTML file to send as a newsletter, with
+    /// absolute links and inlined styles
+    Newsletter {
+        /// The posts, by path relative to the content directory
+        #[arg(required = true)]
+        posts: Vec<String>,
+        /// Where to write the email instead of printing it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Of the email, by default the title of the first post
+        #[arg(long)]
+        title: Option<String>,
+        /// Embed images as `data:` URLs instead of linking to them
+        #[arg(long)]
+        embed_images: bool,
+    },
+    /// Creates a new project
+    New {
+        /// The directory to create it in
+        dir: PathBuf,
+        #[arg(long, default_value = "My site")]
+        title: String,
+        /// Files to copy instead of the starter project, with `{{title}}` etc. filled in: a directory, an archive,
+        /// the URL of an archive (with the remote and archives features), or a git repository URL
+        #[arg(long)]
+        template: Option<String>,
+    },
+    /// Uploads the files of the last build that changed since the last deploy to the same target
+    Deploy {
+        /// `s3://bucket/prefix` (with the s3 feature), or an rsync destination like `user@host:/var/www/site`
+        target: String,
+        /// Only show what would be uploaded and deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// S3 region
+        #[arg(long, default_value = "us-east-1")]
+        region: String,
+        /// S3 endpoint, defaults to AWS in `region`
+        #[arg(long)]
+        endpoint: Option<String>,
+    },
+    /// Creates a dated post with its front matter filled in, from `templates/post.html` if the project has one
+    NewPost {
+        title: String,
+        /// Where to put it, relative to the content directory
+        #[arg(long, default_value = "posts")]
+        dir: PathBuf,
+    },
+    /// Creates a page from `layouts/page.html` if the project has one
+    NewPage {
+        title: String,
+        /// Where to put it, relative to the content directory. The content directory itself by default
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Serves the site from memory, rebuilding it and reloading open pages when sources change
+    #[cfg(feature = "serve")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+    },
+}
+
+struct Processors<'a> {
+    html: HTMLProcessor<'a, PathResource, Site>,
+    images: ImageProcessor,
+    identity: IdentityProcessor,
+}
+
+/// Files under the content directory are identified by their path relative to it. HTML files are pages, images
+/// are copied without their metadata unless configured otherwise, everything else is copied as is
+fn processor_for<'a>(path: &Path, resource: &PathResource, processors: &'a Processors<'_>) -> Box<dyn ResourceProcessor<PathResource> + 'a> {
+    if resource.is_page() {
+        Box::new(&processors.html)
+    } else if images::is_image(path) {
+        Box::new(&processors.images)
+    } else {
+        Box::new(&processors.identity)
+    }
+}
+
+fn build(config: &ProjectConfig, profile: Option<(&Path, &TimingLayer)>) -> ExitCode {
+    let mut resman = ResourceManager::new(config.root.clone());
+    let mut backend = LocalBackend::new(config.output_path());
+
+    let result = match &config.changelog {
+        Some(changelog) => build_with_changelog(config, &mut resman, &mut backend, changelog),
+        None => build_with(config, &mut resman, &mut backend),
+    };
+    let result = result.and_then(|()| {
+        if let Some(error_pages) = &config.error_pages {
+            error_pages.write(&config.output_path(), &resman)?;
+        }
+        #[cfg(feature = "csp")]
+        if let Some(csp) = &config.csp {
+            csp.write(&config.output_path())?;
+        }
+        if let Some(service_worker) = &config.service_worker {
+            let path = service_worker.write(&config.output_path())?;
+            info!("Wrote the service worker to {}", path.display());
+        }
+        Ok(())
+    });
+
+    if let Some((path, timing)) = profile {
+        eprint!("{}", timing.summary());
+        match std::fs::write(path, timing.folded_stacks()) {
+            Ok(()) => eprintln!("Wrote the profile to {}", path.display()),
+            Err(e) => eprintln!("error: could not write the profile to {}: {e}", path.display()),
+        }
+    }
+
+    match result {
+        Ok(()) => {
+            let diagnostics = resman.diagnostics();
+            if !diagnostics.is_empty() {
+                eprintln!("{diagnostics}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report(&e, &resman);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Registers everything in the content directory
+fn register(config: &ProjectConfig, resman: &mut ResourceManager<PathResource>) -> Result<(), ConfigurafoxError> {
+    let content_dir = config.content_dir.clone();
+    resman.register_all_files_in_directory_with_options(
+        content_dir.clone(),
+        |path| {
+            let relative = path.strip_prefix(config.content_path()).ok()?;
+            Some(PathResource::new(relative))
+        },
+        &config.scan_options()?,
+    )?;
+    if config.git_history {
+        resman.apply_git_history(&GitHistory::load(&config.content_path())?);
+    }
+    if let Some(error_pages) = &config.error_pages {
+        resman.apply_error_pages(error_pages);
+    }
+    resman.validate()
+}
+
+fn build_with(
+    config: &ProjectConfig,
+    resman: &mut ResourceManager<PathResource>,
+    backend: &mut dyn OutputBackend,
+) -> Result<(), ConfigurafoxError> {
+    register(config, resman)?;
+
+    let site = config.site();
+    configurafox::run_with_backend(backend, resman, processor_for, &processors(config, &site)?)
+}
+
+fn processors<'a>(config: &ProjectConfig, site: &'a Site) -> Result<Processors<'a>, ConfigurafoxError> {
+    Ok(Processors {
+        html: config.site_html_processor(site)?,
+        images: config.images.clone(),
+        identity: IdentityProcessor,
+    })
+}
+
+/// Like `build_with`, then prints which pages changed since the last build and records them in the changelog,
+/// writing its page if configured. The first build has nothing to compare to, so it only saves its manifest
+fn build_with_changelog(
+    config: &ProjectConfig,
+    resman: &mut ResourceManager<PathResource>,
+    backend: &mut dyn OutputBackend,
+    options: &ChangelogOptions,
+) -> Result<(), ConfigurafoxError> {
+    let manifest_path = config.root.join(DEFAULT_BUILD_MANIFEST);
+    let previous = manifest_path.exists().then(|| DeployManifest::load(&manifest_path)).transpose()?;
+
+    let mut recording = ManifestBackend::new(backend);
+    build_with(config, resman, &mut recording)?;
+
+    let changelog_path = config.root.join(DEFAULT_CHANGELOG_FILE);
+    let mut changelog = Changelog::load(&changelog_path)?;
+    if let Some(previous) = previous {
+        let mut diff = BuildDiff::between(&previous, &recording.manifest);
+        if let Some(page) = &options.page {
+            diff.changes.remove(&page.path);
+        }
+        let changes = BuildChanges::from_diff(&diff, resman, &scaffold::today());
+        eprint!("{changes}");
+        changelog.record(changes, options.keep);
+    }
+    changelog.save(&changelog_path)?;
+
+    if let Some(page) = &options.page {
+        let resource = page.generate(resman, &changelog, &config.content_path().join(&page.path), PathResource::new(&page.path))?;
+        let site = config.site();
+        configurafox::rebuild_with_backend(&mut recording, resman, &HashSet::from([resource]), processor_for, &processors(config, &site)?)?;
+    }
+    recording.manifest.save(&manifest_path)?;
+    Ok(())
+}
+
+fn diff(config: &ProjectConfig, against_manifest: bool) -> ExitCode {
+    let mut resman = ResourceManager::new(config.root.clone());
+    let mut backend = MemoryBackend::new();
+
+    let result = build_with(config, &mut resman, &mut backend).and_then(|()| {
+        if against_manifest {
+            let manifest = DeployManifest::load(&config.root.join(DEFAULT_DEPLOY_MANIFEST))?;
+            Ok(BuildDiff::against_manifest(&manifest, &backend.files))
+        } else {
+            Ok(BuildDiff::against_dir(&config.output_path(), &backend.files)?)
+        }
+    });
+    match result {
+        Ok(diff) => {
+            print!("{diff}");
+            let count = |kind: fn(&OutputChange) -> bool| diff.changes.values().filter(|change| kind(change)).count();
+            eprintln!(
+                "{} added, {} removed, {} changed, {} unchanged",
+                count(|change| matches!(change, OutputChange::Added)),
+                count(|change| matches!(change, OutputChange::Removed)),
+                count(|change| matches!(change, OutputChange::Changed { .. })),
+                diff.unchanged,
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report(&e, &resman);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn check_links(config: &ProjectConfig, external: bool, json: bool) -> ExitCode {
+    let options = LinkCheckOptions {
+        site_url: config.site_url.clone(),
+        external,
+        cache_file: Some(config.root.join(DEFAULT_LINK_CACHE)),
+        ..LinkCheckOptions::default()
+    };
+    match linkcheck::check_output_dir(&config.output_path(), &options) {
+        Ok(report) => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report).expect("reports serialize"));
+            } else {
+                print!("{report}");
+            }
+            if report.is_ok() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Lists or sends the webmentions not sent yet. Whether all of them could be sent
+fn send_webmentions(config: &ProjectConfig, send: bool) -> Result<bool, ConfigurafoxError> {
+    let site_url = config.site_url.as_deref().ok_or_else(|| ConfigurafoxError::Config {
+        path: config.root.join(DEFAULT_CONFIG_FILE),
+        msg: "webmentions need site_url".to_string(),
+    })?;
+    let links = webmention::outgoing_links(&linkcheck::read_build(&config.output_path())?, site_url)?;
+    let log_path = config.root.join(webmention::DEFAULT_SENT_LOG);
+    let mut log = SentLog::load(&log_path)?;
+    let unsent = log.unsent(&links);
+
+    if !send {
+        for link in &unsent {
+            println!("{link}");
+        }
+        eprintln!("{} of {} links to other sites haven't been sent webmentions", unsent.len(), links.len());
+        return Ok(true);
+    }
+
+    #[cfg(feature = "remote")]
+    {
+        let outcomes = webmention::send(&unsent, &mut log, std::time::Duration::from_secs(10));
+        log.save(&log_path)?;
+        for (link, outcome) in unsent.iter().zip(&outcomes) {
+            println!("{link}: {outcome}");
+        }
+        Ok(outcomes.iter().all(|outcome| !matches!(outcome, webmention::SendOutcome::Failed { .. })))
+    }
+    #[cfg(not(feature = "remote"))]
+    {
+        let _ = (&mut log, log_path);
+        Err(ConfigurafoxError::Other("sending webmentions needs configurafox to be built with the remote feature".to_string()))
+    }
+}
+
+fn webmentions(config: &ProjectConfig, send: bool) -> ExitCode {
+    match send_webmentions(config, send) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn export_newsletter(config: &ProjectConfig, posts: &[String], output: Option<&Path>, title: Option<String>, embed_images: bool) -> ExitCode {
+    let mut resman = ResourceManager::new(config.root.clone());
+    let mut backend = MemoryBackend::new();
+
+    let result = build_with(config, &mut resman, &mut backend).and_then(|()| {
+        let site_url = config.site_url.as_deref().ok_or_else(|| ConfigurafoxError::Config {
+            path: config.root.join(DEFAULT_CONFIG_FILE),
+            msg: "newsletters need site_url".to_string(),
+        })?;
+        let posts = posts
+            .iter()
+            .map(|identifier| {
+                let resource = resman
+                    .resource_by_identifier(identifier)
+                    .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: identifier.clone() })?;
+                Ok(resman.output_path(resource))
+            })
+            .collect::<Result<Vec<_>, ConfigurafoxError>>()?;
+        let options = NewsletterOptions { title, embed_images, ..NewsletterOptions::new(site_url) };
+        let email = newsletter::export(&backend.files, &posts, &options)?;
+        match output {
+            Some(path) => {
+                std::fs::write(path, email)?;
+                eprintln!("Wrote the newsletter to {}", path.display());
+            }
+            None => print!("{email}"),
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report(&e, &resman);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn snapshots(config: &ProjectConfig, dir: &Path, update: bool) -> ExitCode {
+    let mut resman = ResourceManager::new(config.root.clone());
+    let mut backend = MemoryBackend::new();
+    let dir = config.root.join(dir);
+
+    if let Err(e) = build_with(config, &mut resman, &mut backend) {
+        report(&e, &resman);
+        return ExitCode::FAILURE;
+    }
+
+    if update {
+        return match snapshot::update_snapshots(&dir, &backend.files) {
+            Ok(()) => {
+                eprintln!("Updated {} snapshots in {}", backend.files.len(), dir.display());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("error: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    match snapshot::compare_snapshots(&dir, &backend.files) {
+        Ok(snapshot_report) => {
+            print!("{snapshot_report}");
+            if snapshot_report.is_ok() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "serve")]
+fn serve(config: &ProjectConfig, addr: &str) -> ExitCode {
+    use std::ops::ControlFlow;
+    use configurafox::serve::DevServer;
+    use configurafox::watch::{watch, Watcher};
+
+    let mut resman = ResourceManager::new(config.root.clone());
+    let site = config.site();
+    let setup = register(config, &mut resman).and_then(|()| {
+        let server = DevServer::start(addr)?;
+        let mut watcher = Watcher::new(&config.root)?;
+        watcher.ignore(&config.output_dir);
+        let processors = Processors { html: config.site_html_processor(&site)?, images: config.images.clone(), identity: IdentityProcessor };
+        Ok((server, watcher, processors, config.scan_options()?))
+    });
+    let (server, watcher, processors, scan_options) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            report(&e, &resman);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    eprintln!("Serving on http://{}", server.addr());
+
+    let result = watch(
+        &watcher,
+        &mut server.backend(),
+        &mut resman,
+        |path| {
+            let relative = path.strip_prefix(&config.content_dir).ok()?;
+            (!scan_options.skips(path)).then(|| PathResource::new(relative))
+        },
+        processor_for,
+        &processors,
+        |resman, rebuild| {
+            match &rebuild.result {
+                Ok(()) => eprintln!("Built {} resources", rebuild.changes.affected.len()),
+                Err(e) => report(e, resman),
+            }
+            ControlFlow::Continue(())
+        },
+    );
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "pretty-diagnostics")]
+fn report(error: &ConfigurafoxError, resman: &ResourceManager<PathResource>) {
+    eprintln!("{}", configurafox::render::render_error(error, resman, true));
+}
+
+#[cfg(not(feature = "pretty-diagnostics"))]
+fn report(error: &ConfigurafoxError, _resman: &ResourceManager<PathResource>) {
+    eprintln!("error: {error}");
+}
+
+fn new_project(dir: &Path, title: &str, template: Option<&str>) -> ExitCode {
+    let created = template
+        .map(Template::from_source)
+        .transpose()
+        .and_then(|template| scaffold::new_project(dir, title, template.as_ref()));
+    match created {
+        Ok(paths) => {
+            for path in paths {
+                eprintln!("Created {}", path.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn new_post(config: &ProjectConfig, dir: &Path, title: &str) -> ExitCode {
+    print_created(scaffold::new_post(config, dir, title))
+}
+
+fn new_page(config: &ProjectConfig, dir: &Path, title: &str) -> ExitCode {
+    print_created(scaffold::new_page(config, dir, title))
+}
+
+fn print_created(created: Result<PathBuf, ConfigurafoxError>) -> ExitCode {
+    match created {
+        Ok(path) => {
+            println!("{}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn deploy_target(target: &str, config: &ProjectConfig, region: &str, endpoint: Option<&str>) -> Result<Box<dyn OutputBackend>, ConfigurafoxError> {
+    if let Some(bucket_and_prefix) = target.strip_prefix("s3://") {
+        #[cfg(feature = "s3")]
+        {
+            let (bucket, prefix) = bucket_and_prefix.split_once('/').unwrap_or((bucket_and_prefix, ""));
+            let endpoint = endpoint.map(str::to_string).unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com"));
+            let mut backend = configurafox::output::S3Backend::from_env(&endpoint, bucket, region)?;
+            backend.prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix.trim_end_matches('/')) };
+            return Ok(Box::new(backend));
+        }
+        #[cfg(not(feature = "s3"))]
+        {
+            let _ = (bucket_and_prefix, region, endpoint);
+            return Err(ConfigurafoxError::Other("deploying to S3 needs configurafox to be built with the s3 feature".to_string()));
+        }
+    }
+    Ok(Box::new(RsyncBackend::new(config.output_path(), target)))
+}
+
+fn deploy(config: &ProjectConfig, target: &str, dry_run: bool, region: &str, endpoint: Option<&str>) -> ExitCode {
+    let result = deploy_target(target, config, region, endpoint).and_then(|mut backend| {
+        deploy::deploy(&config.output_path(), &mut *backend, &config.root.join(DEFAULT_DEPLOY_MANIFEST), dry_run)
+    });
+    match result {
+        Ok(plan) => {
+            if dry_run {
+                for path in &plan.upload {
+                    println!("upload {}", path.display());
+                }
+                for path in &plan.delete {
+                    println!("delete {}", path.display());
+                }
+                eprintln!("{} to upload, {} to delete, {} unchanged", plan.upload.len(), plan.delete.len(), plan.unchanged);
+            } else {
+                eprintln!("{} uploaded, {} deleted, {} unchanged", plan.upload.len(), plan.delete.len(), plan.unchanged);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let level = match cli.verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let timing = TimingLayer::new();
+    let profiling = matches!(cli.command, Command::Build { profile: Some(_) });
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr).with_filter(LevelFilter::from_level(level)))
+        .with(profiling.then(|| timing.clone()))
+        .init();
+
+    // Runs before there is a config to load
+    if let Command::New { dir, title, template } = &cli.command {
+        return new_project(dir, title, template.as_deref());
+    }
+
+    let config = if cli.config.exists() {
+        ProjectConfig::load(&cli.config)
+    } else if cli.config == Path::new(DEFAULT_CONFIG_FILE) {
+        Ok(ProjectConfig::default_for(PathBuf::from(".")))
+    } else {
+        Err(ConfigurafoxError::Config { path: cli.config.clone(), msg: "not found".to_string() })
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match cli.command {
+        Command::New { .. } => unreachable!("handled before loading the config"),
+        Command::Build { profile } => build(&config, profile.as_deref().map(|path| (path, &timing))),
+        Command::CheckLinks { external, json } => check_links(&config, external, json),
+        Command::Webmentions { send } => webmentions(&config, send),
+        Command::Snapshot { update, dir } => snapshots(&config, &dir, update),
+        Command::Diff { manifest } => diff(&config, manifest),
+        Command::Newsletter { posts, output, title, embed_images } => export_newsletter(&config, &posts, output.as_deref(), title, embed_images),
+        Command::NewPost { title, dir } => new_post(&config, &dir, &title),
+        Command::NewPage { title, dir } => new_page(&config, dir.as_deref().unwrap_or(Path::new("")), &title),
+        Command::Deploy { target, dry_run, region, endpoint } => deploy(&config, &target, dry_run, &region, endpoint.as_deref()),
+        #[cfg(feature = "serve")]
+        Command::Serve { addr } => serve(&config, &addr),
+    }
+}
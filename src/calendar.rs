@@ -0,0 +1,321 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use html_editor::Node;
+
+use crate::ConfigurafoxError;
+use crate::entities::escape_text;
+use crate::metadata::{Metadata, Value};
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker, element, get_attr};
+
+/// The metadata key with the date of a resource, as an ISO 8601 date, or date and time
+pub const DATE_KEY: &str = "date";
+
+/// Metadata keys of the pages `ArchiveGenerator` generates: the collection, year and month they list. Year pages
+/// have no month
+pub const ARCHIVE_COLLECTION_KEY: &str = "archive_collection";
+pub const ARCHIVE_YEAR_KEY: &str = "archive_year";
+pub const ARCHIVE_MONTH_KEY: &str = "archive_month";
+
+pub const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December",
+];
+
+/// The year and month of an ISO 8601 date like `2024-03-05` or `2024-03-05T12:00:00Z`
+pub fn year_month(date: &str) -> Option<(i32, u32)> {
+    let (year, rest) = date.split_once('-')?;
+    let month = rest.get(..2)?.parse().ok().filter(|month| (1..=12).contains(month))?;
+    Some((year.parse().ok()?, month))
+}
+
+/// An ISO 8601 date, or date and time, the way RSS wants it, e.g. `Tue, 05 Mar 2024 00:00:00 +0000`. Times
+/// without an offset are taken as UTC
+pub fn rfc822(date: &str) -> Option<String> {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+    let (year, month) = year_month(date)?;
+    let rest = &date[date.find('-')? + 3..];
+    let day: u32 = rest.strip_prefix('-')?.get(..2)?.parse().ok().filter(|day| (1..=31).contains(day))?;
+    let time = rest[3..].strip_prefix(['T', 't', ' ']).unwrap_or("");
+
+    let number = |range: std::ops::Range<usize>| time.get(range).and_then(|n| n.parse::<u32>().ok());
+    let (hour, minute) = if time.is_empty() { (0, 0) } else { (number(0..2)?, number(3..5)?) };
+    let second = if time.get(5..6) == Some(":") { number(6..8)? } else { 0 };
+    let zone = &time[time.find(['Z', 'z', '+', '-']).unwrap_or(time.len())..];
+    let offset = match zone.get(..1) {
+        Some("+" | "-") => format!("{}{}", &zone[..1], zone[1..].replace(':', "")),
+        _ => "+0000".to_string(),
+    };
+
+    let weekday = WEEKDAYS[days_from_civil(year.into(), month, day).rem_euclid(7) as usize];
+    let month = &MONTH_NAMES[month as usize - 1][..3];
+    Some(format!("{weekday}, {day:02} {month} {year:04} {hour:02}:{minute:02}:{second:02} {offset}"))
+}
+
+/// Days since 1970-01-01 of a civil date, from Howard Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The civil date (year, month, day) `days` after 1970-01-01, from Howard Hinnant's `civil_from_days`
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month as u32, day as u32)
+}
+
+/// `2024`, or `March 2024` with a month
+pub fn period_title(year: i32, month: Option<u32>) -> String {
+    match month {
+        Some(month) => format!("{} {year}", MONTH_NAMES[month as usize - 1]),
+        None => year.to_string(),
+    }
+}
+
+/// The day of an ISO 8601 date, or date and time, as in `March 5, 2024`
+pub fn long_date(date: &str) -> Option<String> {
+    let (year, month) = year_month(date)?;
+    let day: u32 = date[date.find('-')? + 3..].strip_prefix('-')?.get(..2)?.parse().ok().filter(|day| (1..=31).contains(day))?;
+    Some(format!("{} {day}, {year}", MONTH_NAMES[month as usize - 1]))
+}
+
+/// The members of a collection by the year and month of their `DATE_KEY`, each month in the collection's order.
+/// Members without a date are left out
+pub struct DateGroups<'a, R> {
+    pub months: BTreeMap<(i32, u32), Vec<&'a R>>,
+}
+
+impl<'a, R: Resource> DateGroups<'a, R> {
+    pub fn of(resources: &'a ResourceManager<R>, members: &[(&'a Path, &'a R)]) -> DateGroups<'a, R> {
+        let mut months = BTreeMap::<_, Vec<_>>::new();
+        for (path, resource) in members {
+            match resources.metadata(resource).get_str(DATE_KEY).and_then(year_month) {
+                Some(period) => months.entry(period).or_default().push(*resource),
+                None => debug!("{}: No {DATE_KEY}, leaving it out of the archive", path.display()),
+            }
+        }
+        DateGroups { months }
+    }
+
+    /// The groups of the collection `name`, see `ResourceManager::define_collection`
+    pub fn collection(resources: &'a ResourceManager<R>, name: &str) -> Result<DateGroups<'a, R>, ConfigurafoxError> {
+        let members = resources
+            .collection(name)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "collection", name: name.to_string() })?;
+        Ok(DateGroups::of(resources, &members))
+    }
+
+    /// The years with any members, in order
+    pub fn years(&self) -> Vec<i32> {
+        let mut years = self.months.keys().map(|(year, _)| *year).collect::<Vec<_>>();
+        years.dedup();
+        years
+    }
+
+    /// The months of `year` with any members, in order
+    pub fn months_of(&self, year: i32) -> impl DoubleEndedIterator<Item = (u32, &[&'a R])> {
+        self.months.range((year, 1)..=(year, 12)).map(|((_, month), members)| (*month, members.as_slice()))
+    }
+
+    pub fn month(&self, year: i32, month: u32) -> &[&'a R] {
+        self.months.get(&(year, month)).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Generates an index page for every year and month with members of a collection, `2024/index.html` and
+/// `2024/03/index.html`, as resources with their contents in memory. Each has a `title` like `March 2024` and the
+/// `ARCHIVE_*` metadata, so an `<archive-list/>` on it lists just that period, see `ArchiveList`. Generate after
+/// registering the members
+#[derive(Debug, Clone)]
+pub struct ArchiveGenerator {
+    pub collection: String,
+    /// Whether to generate month pages as well as year pages
+    pub monthly: bool,
+    /// The HTML of every page. By default a heading with the title and `<archive-list/>`
+    pub template: Option<String>,
+}
+
+impl ArchiveGenerator {
+    pub fn new(collection: &str) -> ArchiveGenerator {
+        ArchiveGenerator { collection: collection.to_string(), monthly: true, template: None }
+    }
+
+    pub fn monthly(mut self, monthly: bool) -> ArchiveGenerator {
+        self.monthly = monthly;
+        self
+    }
+
+    /// E.g. `<h1>$page.title</h1><archive-list/>` with `VariableReplacer` in use
+    pub fn with_template(mut self, html: &str) -> ArchiveGenerator {
+        self.template = Some(html.to_string());
+        self
+    }
+
+    /// Registers the pages under `dir`, with the resource `make_resource` gives for the path of each, like
+    /// `ResourceManager::register_all_files_in_directory` does. Pages it gives None for are skipped.
+    /// Returns the registered resources
+    pub fn generate<R: Resource, F: Fn(&Path) -> Option<R>>(
+        &self,
+        resources: &mut ResourceManager<R>,
+        dir: &Path,
+        make_resource: F,
+    ) -> Result<Vec<R>, ConfigurafoxError> {
+        let groups = DateGroups::collection(resources, &self.collection)?;
+        let mut periods = Vec::new();
+        for year in groups.years() {
+            periods.push((year, None));
+            if self.monthly {
+                periods.extend(groups.months_of(year).map(|(month, _)| (year, Some(month))));
+            }
+        }
+
+        let mut generated = Vec::new();
+        for (year, month) in periods {
+            let path = match month {
+                Some(month) => dir.join(format!("{year}/{month:02}/index.html")),
+                None => dir.join(format!("{year}/index.html")),
+            };
+            let Some(resource) = make_resource(&path) else {
+                continue;
+            };
+            let title = period_title(year, month);
+            info!("{}: Generating the archive of {:?} for {title}", path.display(), self.collection);
+
+            let mut metadata = Metadata::new();
+            metadata.insert("title", title.as_str());
+            metadata.insert(ARCHIVE_COLLECTION_KEY, self.collection.as_str());
+            metadata.insert(ARCHIVE_YEAR_KEY, year);
+            if let Some(month) = month {
+                metadata.insert(ARCHIVE_MONTH_KEY, month);
+            }
+            let contents = match &self.template {
+                Some(template) => template.clone(),
+                None => format!("<h1>{}</h1>\n<archive-list/>\n", escape_text(&title)),
+            };
+
+            resources.insert_with_origin(resource.clone(), path, Origin::Memory(contents.into_bytes().into()), metadata);
+            generated.push(resource);
+        }
+        Ok(generated)
+    }
+}
+
+/// Replaces `<archive-list/>` with nested lists of the members of a collection by year and month, newest first.
+/// Members are linked to as `@identifier` references with their title, so `LinkReplacer` must also be in use, as
+/// are years and months with a page from `ArchiveGenerator`. On such a page, only its period is listed. The
+/// `collection` attribute overrides the collection
+pub struct ArchiveList {
+    /// Listed unless the page or the element says otherwise
+    pub collection: String,
+}
+
+impl ArchiveList {
+    pub fn new(collection: &str) -> ArchiveList {
+        ArchiveList { collection: collection.to_string() }
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for ArchiveList {
+    fn describe(&self) -> String {
+        format!("ArchiveList({})", self.collection)
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "archive-list"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["archive-list"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let page = ctx.metadata();
+        let collection = get_attr(&attrs, "collection")
+            .or_else(|| page.get_str(ARCHIVE_COLLECTION_KEY))
+            .unwrap_or(&self.collection);
+        let groups = DateGroups::collection(ctx.resources, collection)?;
+        let pages = period_pages(ctx.resources, collection);
+        let period_link = |year: i32, month: Option<u32>| {
+            let text = match month {
+                Some(month) => MONTH_NAMES[month as usize - 1].to_string(),
+                None => year.to_string(),
+            };
+            link(pages.get(&(year, month)).copied(), text)
+        };
+        let month_item = |year: i32, month: u32, members: &[&R]| {
+            element("li", vec![], vec![period_link(year, Some(month)), list(members_items(ctx.resources, members), None)])
+        };
+        let year_items = |year: i32| groups.months_of(year).rev().map(|(month, members)| month_item(year, month, members)).collect();
+
+        let year = page.get(ARCHIVE_YEAR_KEY).and_then(Value::as_i64).and_then(|year| i32::try_from(year).ok());
+        let month = page.get(ARCHIVE_MONTH_KEY).and_then(Value::as_u64).and_then(|month| u32::try_from(month).ok());
+        let items: Vec<Node> = match (year, month) {
+            (Some(year), Some(month)) => members_items(ctx.resources, groups.month(year, month)),
+            (Some(year), None) => year_items(year),
+            _ => groups
+                .years()
+                .into_iter()
+                .rev()
+                .map(|year| element("li", vec![], vec![period_link(year, None), list(year_items(year), None)]))
+                .collect(),
+        };
+        if items.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let class = get_attr(&attrs, "class").unwrap_or("archive");
+        Ok(vec![list(items, Some(class))])
+    }
+}
+
+/// The `ArchiveGenerator` pages of `collection`, by year and month
+fn period_pages<'a, R: Resource>(resources: &'a ResourceManager<R>, collection: &str) -> HashMap<(i32, Option<u32>), &'a R> {
+    resources
+        .resources_with(ARCHIVE_COLLECTION_KEY, collection)
+        .into_iter()
+        .filter_map(|resource| {
+            let metadata = resources.metadata(resource);
+            let year = i32::try_from(metadata.get(ARCHIVE_YEAR_KEY)?.as_i64()?).ok()?;
+            let month = metadata.get(ARCHIVE_MONTH_KEY).and_then(Value::as_u64).and_then(|month| u32::try_from(month).ok());
+            Some(((year, month), resource))
+        })
+        .collect()
+}
+
+fn members_items<R: Resource>(resources: &ResourceManager<R>, members: &[&R]) -> Vec<Node> {
+    members
+        .iter()
+        .map(|resource| {
+            let title = resources.metadata(resource).get_str("title").map_or_else(|| resource.identifier(), str::to_string);
+            element("li", vec![], vec![link(Some(*resource), title)])
+        })
+        .collect()
+}
+
+/// `text`, linking to `resource` if there is one
+fn link<R: Resource>(resource: Option<&R>, text: String) -> Node {
+    match resource {
+        Some(resource) => element("a", vec![("href", format!("@{}", resource.identifier()))], vec![Node::Text(text)]),
+        None => Node::Text(text),
+    }
+}
+
+fn list(items: Vec<Node>, class: Option<&str>) -> Node {
+    element("ul", class.map(|class| ("class", class.to_string())).into_iter().collect(), items)
+}
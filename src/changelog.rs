@@ -0,0 +1,241 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ConfigurafoxError, relative_url};
+use crate::calendar::long_date;
+use crate::deploy::{DeployManifest, content_hash};
+use crate::diff::{BuildDiff, OutputChange};
+use crate::entities::{escape_attr, escape_text};
+use crate::error_pages::is_error_page;
+use crate::metadata::Metadata;
+use crate::output::OutputBackend;
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+
+/// Where the manifest of the previous build is kept by default, relative to the project root
+pub const DEFAULT_BUILD_MANIFEST: &str = ".configurafox-cache/build-manifest.json";
+
+/// Where the changes of past builds are kept by default, relative to the project root
+pub const DEFAULT_CHANGELOG_FILE: &str = ".configurafox-cache/changelog.json";
+
+/// How many builds a `Changelog` keeps by default
+pub const DEFAULT_KEEP: usize = 20;
+
+/// Passes everything on to another backend, keeping the content hash of each output written, e.g. to compare
+/// the build against the previous one with `BuildDiff::between`
+pub struct ManifestBackend<'a> {
+    pub inner: &'a mut dyn OutputBackend,
+    pub manifest: DeployManifest,
+}
+
+impl<'a> ManifestBackend<'a> {
+    pub fn new(inner: &'a mut dyn OutputBackend) -> ManifestBackend<'a> {
+        let target = inner.describe();
+        ManifestBackend { inner, manifest: DeployManifest { target, files: Default::default() } }
+    }
+}
+
+impl OutputBackend for ManifestBackend<'_> {
+    fn describe(&self) -> String {
+        format!("ManifestBackend({})", self.inner.describe())
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), ConfigurafoxError> {
+        self.manifest.files.insert(path.to_owned(), content_hash(contents));
+        self.inner.write_file(path, contents)
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<(), ConfigurafoxError> {
+        self.manifest.files.remove(path);
+        self.inner.remove_file(path)
+    }
+
+    fn finish(&mut self) -> Result<(), ConfigurafoxError> {
+        self.inner.finish()
+    }
+}
+
+/// A page in `BuildChanges`, by its output path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedPage {
+    pub path: PathBuf,
+    /// Its `title` metadata when it was built. Removed pages have none
+    pub title: Option<String>,
+}
+
+impl ChangedPage {
+    /// The title, or else the path
+    pub fn name(&self) -> String {
+        self.title.clone().unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+/// The pages a build added, changed and removed compared to the previous one. Only HTML outputs count as pages,
+/// and error pages don't
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildChanges {
+    /// The day of the build, as in `2026-10-16`
+    pub date: String,
+    pub added: Vec<ChangedPage>,
+    pub changed: Vec<ChangedPage>,
+    pub removed: Vec<ChangedPage>,
+}
+
+impl BuildChanges {
+    /// The pages in `diff`, titled from the metadata of the resources in `resources` they are the outputs of.
+    /// Remove the `RecentChanges` page from `diff` first, or it shows up in every build
+    pub fn from_diff<R: Resource>(diff: &BuildDiff, resources: &ResourceManager<R>, date: &str) -> BuildChanges {
+        let outputs = resources.iter().map(|(_, resource)| (resources.output_path(resource), resource)).collect::<HashMap<_, _>>();
+        let error_page = |path: &Path| outputs.get(path).is_some_and(|resource| is_error_page(resources, resource));
+        let page = |path: &Path| ChangedPage {
+            path: path.to_owned(),
+            title: outputs.get(path).and_then(|resource| resources.metadata(resource).get_str("title")).map(str::to_string),
+        };
+
+        let mut changes = BuildChanges { date: date.to_string(), ..BuildChanges::default() };
+        for (path, change) in &diff.changes {
+            if path.extension().is_none_or(|ext| ext != "html") || error_page(path) {
+                continue;
+            }
+            match change {
+                OutputChange::Added => changes.added.push(page(path)),
+                OutputChange::Changed { .. } => changes.changed.push(page(path)),
+                OutputChange::Removed => changes.removed.push(ChangedPage { path: path.clone(), title: None }),
+            }
+        }
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A summary line like `2 pages added, 1 changed, 0 removed`, followed by an `A`, `M` or `D` line for each page
+impl fmt::Display for BuildChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pages = if self.added.len() == 1 { "page" } else { "pages" };
+        writeln!(f, "{} {pages} added, {} changed, {} removed", self.added.len(), self.changed.len(), self.removed.len())?;
+        for (kind, pages) in [("A", &self.added), ("M", &self.changed), ("D", &self.removed)] {
+            for page in pages {
+                match &page.title {
+                    Some(title) => writeln!(f, "{kind} {} ({title})", page.path.display())?,
+                    None => writeln!(f, "{kind} {}", page.path.display())?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The changes of the latest builds that changed any pages, newest first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changelog {
+    pub builds: Vec<BuildChanges>,
+}
+
+impl Changelog {
+    /// The changelog saved at `path`, or an empty one if there is none
+    pub fn load(path: &Path) -> std::io::Result<Changelog> {
+        match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Changelog::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the changelog as JSON to `path`, creating its directory if needed
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    /// Adds `changes` as the newest build, unless they're empty, keeping at most `keep` builds
+    pub fn record(&mut self, changes: BuildChanges, keep: usize) {
+        if !changes.is_empty() {
+            self.builds.insert(0, changes);
+        }
+        self.builds.truncate(keep);
+    }
+}
+
+/// What to keep of the changes between builds, and whether to show them on a page
+#[derive(Debug, Clone)]
+pub struct ChangelogOptions {
+    /// How many builds to keep
+    pub keep: usize,
+    pub page: Option<RecentChanges>,
+}
+
+impl Default for ChangelogOptions {
+    fn default() -> ChangelogOptions {
+        ChangelogOptions { keep: DEFAULT_KEEP, page: None }
+    }
+}
+
+/// Generates a "recent changes" page listing the pages added, changed and removed by the latest builds in a
+/// `Changelog`, as a resource with its contents in memory. Pages that still exist are linked. Generate after
+/// building and recording the build's changes, and build the page on its own, e.g. with `rebuild_with_backend`
+#[derive(Debug, Clone)]
+pub struct RecentChanges {
+    pub path: PathBuf,
+    pub title: String,
+}
+
+impl RecentChanges {
+    pub fn new<P: Into<PathBuf>>(path: P) -> RecentChanges {
+        RecentChanges { path: path.into(), title: "Recent changes".to_string() }
+    }
+
+    pub fn with_title(mut self, title: &str) -> RecentChanges {
+        self.title = title.to_string();
+        self
+    }
+
+    /// The HTML of the page, linking relative to where it's written
+    pub fn html(&self, changelog: &Changelog) -> Result<String, ConfigurafoxError> {
+        let mut out = format!("<h1>{}</h1>\n", escape_text(&self.title));
+        for build in &changelog.builds {
+            let date = long_date(&build.date).unwrap_or_else(|| build.date.clone());
+            out.push_str(&format!("<section class=\"recent-changes\">\n<h2><time datetime=\"{}\">{}</time></h2>\n<ul>\n", escape_attr(&build.date), escape_text(&date)));
+            for (class, label, pages) in [("added", "Added", &build.added), ("changed", "Changed", &build.changed), ("removed", "Removed", &build.removed)] {
+                for page in pages {
+                    let name = escape_text(&page.name()).into_owned();
+                    let name = if class == "removed" {
+                        name
+                    } else {
+                        format!("<a href=\"{}\">{name}</a>", escape_attr(&relative_url(&page.path, &self.path)?))
+                    };
+                    out.push_str(&format!("<li class=\"{class}\">{label} {name}</li>\n"));
+                }
+            }
+            out.push_str("</ul>\n</section>\n");
+        }
+        Ok(out)
+    }
+
+    /// Registers the page as `resource`, with `path` as its source, replacing it if it's registered already.
+    /// `resource` should be written to `self.path`
+    pub fn generate<R: Resource>(
+        &self,
+        resources: &mut ResourceManager<R>,
+        changelog: &Changelog,
+        path: &Path,
+        resource: R,
+    ) -> Result<R, ConfigurafoxError> {
+        info!("{}: Generating the recent changes of {} builds", self.path.display(), changelog.builds.len());
+        let mut metadata = Metadata::new();
+        metadata.insert("title", self.title.as_str());
+        let contents = self.html(changelog)?;
+        resources.insert_with_origin(resource.clone(), path.to_owned(), Origin::Memory(contents.into_bytes().into()), metadata);
+        Ok(resource)
+    }
+}
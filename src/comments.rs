@@ -0,0 +1,221 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use html_editor::Node;
+
+use crate::{ConfigurafoxError, is_web_url};
+use crate::metadata::{Value, toml_to_value};
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker, element, get_attr};
+use crate::vfs::{EntryKind, Vfs};
+
+/// Where comments are stored unless configured otherwise, relative to the project root
+pub const DEFAULT_COMMENTS_DIR: &str = "comments";
+
+/// Metadata key naming the directory of a page's comments, below the comments directory. By default the page's
+/// output path without its extension and any trailing `index`, e.g. `posts/hello` for `posts/hello.html` and
+/// `posts/hello/index.html`
+pub const COMMENTS_ID_KEY: &str = "comments_id";
+
+/// A comment stored as a file of its own, staticman-style: `<comments dir>/<page>/<file>.json` or `.toml`.
+/// (Staticman writes YAML by default, set its `format` to `json`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The `_id` or `id` field, or else the file name without its extension
+    pub id: String,
+    /// Id of the comment this replies to, the `parent` or `replying_to` field
+    pub parent: Option<String>,
+    pub name: Option<String>,
+    /// The commenter's site
+    pub url: Option<String>,
+    /// Image URL
+    pub avatar: Option<String>,
+    /// ISO 8601, comments are shown in this order
+    pub date: Option<String>,
+    /// Plain text, paragraphs separated by blank lines
+    pub message: String,
+}
+
+impl Comment {
+    /// The comment in the table `value`, None if it has no `message`. `file_id` is used if it has no id field
+    pub fn from_value(value: &Value, file_id: &str) -> Option<Comment> {
+        let field = |key| value.get(key).and_then(Value::as_str).filter(|s| !s.is_empty()).map(str::to_string);
+        Some(Comment {
+            id: field("_id").or_else(|| field("id")).unwrap_or_else(|| file_id.to_string()),
+            parent: field("parent").or_else(|| field("replying_to")),
+            name: field("name"),
+            url: field("url"),
+            avatar: field("avatar"),
+            date: field("date"),
+            message: field("message")?,
+        })
+    }
+}
+
+/// Parses a comment file, as JSON or TOML by its extension. None for files of other types
+fn parse_comment_file(path: &Path, source: &str) -> Option<Result<Value, String>> {
+    match path.extension()?.to_str()? {
+        "json" => Some(serde_json::from_str(source).map_err(|e| e.to_string())),
+        "toml" => Some(source.parse::<toml::Table>().map(|table| toml_to_value(toml::Value::Table(table))).map_err(|e| e.to_string())),
+        _ => None,
+    }
+}
+
+/// Reads every comment below `dir`, by the page they're on: the directory they're in, relative to `dir` and
+/// `/`-separated. Each page's comments are sorted by date. A missing `dir` has no comments
+pub fn load_comments(vfs: &dyn Vfs, dir: &Path) -> Result<BTreeMap<String, Vec<Comment>>, ConfigurafoxError> {
+    let mut comments = BTreeMap::new();
+    match vfs.metadata(dir) {
+        Ok(_) => load_dir(vfs, dir, Path::new(""), &mut comments)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => debug!("{} doesn't exist, no comments", dir.display()),
+        Err(e) => return Err(e.into()),
+    }
+    for page in comments.values_mut() {
+        page.sort_by(|a: &Comment, b: &Comment| (&a.date, &a.id).cmp(&(&b.date, &b.id)));
+    }
+    Ok(comments)
+}
+
+fn load_dir(vfs: &dyn Vfs, dir: &Path, page: &Path, comments: &mut BTreeMap<String, Vec<Comment>>) -> Result<(), ConfigurafoxError> {
+    let mut entries = vfs.read_dir(&dir.join(page))?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in entries {
+        let path = page.join(&entry.name);
+        let kind = match entry.kind {
+            EntryKind::Symlink => vfs.metadata(&dir.join(&path))?.kind,
+            kind => kind,
+        };
+        if kind == EntryKind::Dir {
+            load_dir(vfs, dir, &path, comments)?;
+            continue;
+        }
+
+        let source = vfs.read(&dir.join(&path))?;
+        let Some(value) = parse_comment_file(&path, &String::from_utf8_lossy(&source)) else {
+            debug!("{}: Not a comment file, skipping", dir.join(&path).display());
+            continue;
+        };
+        let value = value.map_err(|msg| ConfigurafoxError::Other(format!("{}: {msg}", dir.join(&path).display())))?;
+        let file_id = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let Some(comment) = Comment::from_value(&value, &file_id) else {
+            warn!("{}: Comment has no message, skipping", dir.join(&path).display());
+            continue;
+        };
+        comments.entry(crate::url_path(page)?.trim_start_matches('/').to_string()).or_default().push(comment);
+    }
+    Ok(())
+}
+
+/// The directory of the comments on the page written to `output_path`, see `COMMENTS_ID_KEY`
+pub fn comments_id(output_path: &Path) -> Result<String, ConfigurafoxError> {
+    let path = crate::url_path(&output_path.with_extension(""))?;
+    let path = path.trim_start_matches('/');
+    let path = if path == "index" { "" } else { path.strip_suffix("/index").unwrap_or(path) };
+    Ok(path.to_string())
+}
+
+/// Replaces `<comments/>` with the comments on the page, from files loaded with `load_comments`, threaded by
+/// what they reply to: a `<section class="comments">` with the element's children followed by an `<ol>`, where
+/// every comment's replies are nested in an `<ol>` of their own. Replies to comments that don't exist are shown
+/// at the top level. Nothing if the page has no comments
+pub struct CommentList {
+    /// Relative to the project root
+    pub dir: PathBuf,
+    /// Read when first needed
+    comments: OnceLock<Result<BTreeMap<String, Vec<Comment>>, String>>,
+}
+
+impl CommentList {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> CommentList {
+        CommentList { dir: dir.into(), comments: OnceLock::new() }
+    }
+
+    fn comments<R: Resource>(&self, resources: &ResourceManager<R>) -> Result<&BTreeMap<String, Vec<Comment>>, ConfigurafoxError> {
+        let comments = self.comments.get_or_init(|| load_comments(resources.vfs(), &self.dir).map_err(|e| e.to_string()));
+        comments.as_ref().map_err(|msg| ConfigurafoxError::Other(msg.clone()))
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for CommentList {
+    fn describe(&self) -> String {
+        format!("CommentList({})", self.dir.display())
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "comments"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["comments"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let id = match ctx.metadata().get_str(COMMENTS_ID_KEY) {
+            Some(id) => id.trim_matches('/').to_string(),
+            None => comments_id(&ctx.resources.output_path(ctx.resource))?,
+        };
+        let Some(comments) = self.comments(ctx.resources)?.get(&id).filter(|comments| !comments.is_empty()) else {
+            return Ok(vec![]);
+        };
+
+        let mut replies = BTreeMap::<Option<&str>, Vec<&Comment>>::new();
+        for comment in comments {
+            let parent = comment.parent.as_deref().filter(|parent| comments.iter().any(|other| other.id == *parent && other.id != comment.id));
+            replies.entry(parent).or_default().push(comment);
+        }
+
+        let mut shown = 0;
+        let mut section = children;
+        section.push(thread(&replies, None, &mut shown));
+        if shown < comments.len() {
+            warn!("{id}: {} comments reply to each other in a cycle, and aren't shown", comments.len() - shown);
+        }
+        let class = get_attr(&attrs, "class").unwrap_or("comments").to_string();
+        Ok(vec![element("section", vec![("class", class)], section)])
+    }
+}
+
+/// The replies to `parent` and theirs, as an `<ol>`. Counts the comments in `shown`
+fn thread(replies: &BTreeMap<Option<&str>, Vec<&Comment>>, parent: Option<&str>, shown: &mut usize) -> Node {
+    let mut items = Vec::new();
+    for comment in replies.get(&parent).into_iter().flatten() {
+        *shown += 1;
+        let mut children = render_comment(comment);
+        if replies.contains_key(&Some(comment.id.as_str())) {
+            children.push(thread(replies, Some(&comment.id), shown));
+        }
+        items.push(element("li", vec![("class", "comment".to_string()), ("id", format!("comment-{}", comment.id))], children));
+    }
+    let class = if parent.is_some() { "comment-replies" } else { "comment-list" };
+    element("ol", vec![("class", class.to_string())], items)
+}
+
+/// URLs in it that `is_web_url` doesn't accept aren't linked to, as anyone can send them
+fn render_comment(comment: &Comment) -> Vec<Node> {
+    let mut author = Vec::new();
+    if let Some(avatar) = comment.avatar.as_ref().filter(|avatar| is_web_url(avatar)) {
+        author.push(element("img", vec![("class", "avatar".to_string()), ("src", avatar.clone()), ("alt", String::new())], vec![]));
+    }
+    author.push(Node::Text(comment.name.clone().unwrap_or_else(|| "Anonymous".to_string())));
+    let author = match comment.url.as_ref().filter(|url| is_web_url(url)) {
+        Some(url) => element("a", vec![("class", "author".to_string()), ("href", url.clone()), ("rel", "nofollow".to_string())], author),
+        None => element("span", vec![("class", "author".to_string())], author),
+    };
+
+    let mut header = vec![author];
+    if let Some(date) = &comment.date {
+        let permalink = element("a", vec![("href", format!("#comment-{}", comment.id))], vec![Node::Text(date.get(..10).unwrap_or(date).to_string())]);
+        header.push(Node::Text(" ".to_string()));
+        header.push(element("time", vec![("datetime", date.clone())], vec![permalink]));
+    }
+
+    let mut nodes = vec![element("p", vec![("class", "comment-meta".to_string())], header)];
+    for paragraph in comment.message.split("\n\n").map(str::trim).filter(|paragraph| !paragraph.is_empty()) {
+        nodes.push(element("p", vec![], vec![Node::Text(paragraph.to_string())]));
+    }
+    nodes
+}
@@ -0,0 +1,459 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use crate::{ConfigurafoxError, HTMLProcessor};
+use crate::changelog::{ChangelogOptions, RecentChanges};
+#[cfg(feature = "csp")]
+use crate::csp::{ContentSecurityPolicy, CspOutput};
+use crate::error_pages::{ErrorPages, Host};
+use crate::images::{ImageProcessor, exif_tag};
+use crate::lint::{LintRule, Lints};
+use crate::registry::{Registry, WalkerSpec};
+use crate::resource_manager::{Resource, ScanOptions};
+use crate::metadata::toml_to_value;
+use crate::serialize::Whitespace;
+use crate::service_worker::ServiceWorker;
+use crate::site::{Site, SiteData};
+use crate::treewalker::TreeWalker;
+
+/// Name of the project configuration file, at the project root
+pub const DEFAULT_CONFIG_FILE: &str = "configurafox.toml";
+
+/// The walkers applied when a configuration doesn't list any, see `Registry::builtin`. `katex` and
+/// `syntax-highlight` only with their features
+pub const WALKERS: &[&str] = &[
+    #[cfg(feature = "katex")]
+    "katex",
+    "variables",
+    "links",
+    "backlinks",
+    #[cfg(feature = "syntax-highlight")]
+    "syntax-highlight",
+];
+
+/// The variable `site_url` is made available as, unless set explicitly
+pub const SITE_URL_VARIABLE: &str = "site_url";
+
+/// The variable used as `SiteData::title`, see `ProjectConfig::site`
+pub const TITLE_VARIABLE: &str = "title";
+
+/// The settings of a project, read from `configurafox.toml`. Everything is optional:
+///
+/// ```toml
+/// site_url = "https://example.com"
+/// content_dir = "content"
+/// output_dir = "public"
+/// # Skipped when scanning the content directory, relative to the project root
+/// ignore = ["content/drafts", "**/*.bak"]
+///
+/// # Names in a `Registry`, applied in this order, optionally with options
+/// walkers = ["katex", "variables", "links", "backlinks", { name = "syntax-highlight", theme = "InspiredGitHub" }]
+/// # `ProjectConfig::site_html_processor`, used by the command line tool, also has `canonical-link`,
+/// # `opengraph-meta`, `json-ld`, `byline` and `author-posts`
+/// # `variables` and `links` apply to every attribute, unless given the ones to apply to, e.g.
+/// # { name = "links", attributes = ["href", "src", "srcset"] }
+/// # `<pre-hl lang="rs" run>` links to the Rust Playground, other languages to playgrounds given like
+/// # { name = "syntax-highlight", playgrounds = { py = "https://play.example.org/?code={code}" } }
+/// # `integrity` pins scripts and stylesheets from other sites by hash, e.g. { name = "integrity", algorithm = "sha384" }
+/// # `shift-headings` nests the headings of included content, `<shift-headings>` shifting by 1 unless given, e.g.
+/// # { name = "shift-headings", by = 2 }
+/// # `heading-anchors` gives headings IDs, keeping old ones when their text changes a little, e.g.
+/// # { name = "heading-anchors", min_similarity = 0.5 }
+/// # `tables` adds header scopes, column alignment and wrappers to tables, e.g.
+/// # { name = "tables", class = "table-wrapper", sticky = true }
+/// # Syntax highlighting theme, unless given with the walker
+/// theme = "InspiredGitHub"
+/// trim = false
+/// # Indent the generated HTML
+/// pretty = false
+/// # Elements whose whitespace `trim` and `pretty` keep, besides `pre`, `textarea`, `script` and `style`
+/// preformatted = ["ascii-art"]
+/// # Lint rule codes, or "all"
+/// lints = ["missing-alt", "duplicate-id"]
+/// # WASM walkers, applied after the built-in ones. Needs the `wasm-plugins` feature
+/// plugins = ["plugins/admonitions.wasm"]
+/// # Write a service worker after building, see `service_worker::ServiceWorker`. `true` for the defaults
+/// service_worker = { precache = ["**/*.html", "**/*.css"], offline_page = "offline.html" }
+/// # How images are copied, see `images::ImageProcessor`. Their metadata is stripped by default, except for
+/// # the EXIF tags kept
+/// images = { strip_metadata = true, keep_exif = ["Orientation", "Copyright"] }
+/// # Set when files were created and last updated, and who contributed to them, from their git history, unless
+/// # front matter does
+/// git_history = false
+/// # Keep which pages each build added, changed and removed, see `changelog::Changelog`. `true` for the
+/// # defaults, `page` to also write them to a "recent changes" page
+/// changelog = { keep = 20, page = "changes.html", title = "Recent changes" }
+/// # Write a Content-Security-Policy allowing the inline scripts and styles by hash after building, see
+/// # `csp::ContentSecurityPolicy`. `output` is "headers" for a `_headers` file or "meta" for a meta tag in each
+/// # page. Needs the `csp` feature
+/// csp = { output = "headers", directives = { img-src = "'self' data:" } }
+/// # Where the host serves error pages from, see `error_pages::Host`. Pages are made error pages with
+/// # `error_page = 404` in their metadata. Their links start with the path of `site_url` unless `base_path` is given
+/// error_pages = { host = "github-pages", base_path = "/" }
+///
+/// [variables]
+/// title = "My site"
+/// # Used as `$author.name`
+/// author = { name = "Jane Doe", email = "jane@example.com" }
+/// # Authors by id, for `author = "jane"` in front matter, see `authors::AUTHORS_VARIABLE`
+/// authors.jane = { name = "Jane Doe", url = "https://jane.example.com", avatar = "/jane.png" }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProjectConfig {
+    /// The directory containing the configuration file. All other paths are relative to it
+    pub root: PathBuf,
+    pub site_url: Option<String>,
+    pub content_dir: PathBuf,
+    pub output_dir: PathBuf,
+    /// Glob patterns of paths to skip when scanning
+    pub ignore: Vec<String>,
+    /// Values for `VariableReplacer`, unless given with the walker
+    pub variables: toml::Table,
+    /// In the order they're applied
+    pub walkers: Vec<WalkerSpec>,
+    pub theme: String,
+    pub trim: bool,
+    pub pretty: bool,
+    /// Tag names added to `serialize::PREFORMATTED_ELEMENTS`
+    pub preformatted: Vec<String>,
+    pub lints: Lints,
+    /// Paths of `plugin::WasmWalker`s
+    pub plugins: Vec<PathBuf>,
+    /// Written to the output directory after building, if set
+    pub service_worker: Option<ServiceWorker>,
+    /// Copies the images of the content directory
+    pub images: ImageProcessor,
+    /// Whether to date resources and credit their contributors by their git history, see
+    /// `ResourceManager::apply_git_history`
+    pub git_history: bool,
+    /// Whether to keep the changes between builds, and how
+    pub changelog: Option<ChangelogOptions>,
+    /// Where error pages go, see `ResourceManager::apply_error_pages`. Error pages work without it, but stay
+    /// where they are and link from the root
+    pub error_pages: Option<ErrorPages>,
+    /// Written after building, if set
+    #[cfg(feature = "csp")]
+    pub csp: Option<ContentSecurityPolicy>,
+}
+
+impl ProjectConfig {
+    /// The configuration of a project without a configuration file
+    pub fn default_for(root: PathBuf) -> ProjectConfig {
+        ProjectConfig {
+            root,
+            site_url: None,
+            content_dir: PathBuf::from("content"),
+            output_dir: PathBuf::from("public"),
+            ignore: Vec::new(),
+            variables: toml::Table::new(),
+            walkers: WALKERS.iter().map(|name| WalkerSpec::new(name)).collect(),
+            theme: "InspiredGitHub".to_string(),
+            trim: false,
+            pretty: false,
+            preformatted: Vec::new(),
+            lints: Lints::none(),
+            plugins: Vec::new(),
+            service_worker: None,
+            images: ImageProcessor::default(),
+            git_history: false,
+            changelog: None,
+            error_pages: None,
+            #[cfg(feature = "csp")]
+            csp: None,
+        }
+    }
+
+    /// Reads the configuration file at `path`. Its directory becomes the project root
+    pub fn load(path: &Path) -> Result<ProjectConfig, ConfigurafoxError> {
+        let source = std::fs::read_to_string(path)?;
+        let root = path.parent().map(Path::to_owned).unwrap_or_default();
+        ProjectConfig::parse(root, &source).map_err(|msg| ConfigurafoxError::Config { path: path.to_owned(), msg })
+    }
+
+    pub fn parse(root: PathBuf, source: &str) -> Result<ProjectConfig, String> {
+        let table = source.parse::<toml::Table>().map_err(|e| e.to_string())?;
+        let mut config = ProjectConfig::default_for(root);
+        let error_pages_base_given = table.get("error_pages").and_then(|error_pages| error_pages.get("base_path")).is_some();
+
+        for (key, value) in table {
+            match key.as_str() {
+                "site_url" => config.site_url = Some(expect_str(&key, &value)?),
+                "content_dir" => config.content_dir = PathBuf::from(expect_str(&key, &value)?),
+                "output_dir" => config.output_dir = PathBuf::from(expect_str(&key, &value)?),
+                "ignore" => config.ignore = expect_str_array(&key, &value)?,
+                "theme" => config.theme = expect_str(&key, &value)?,
+                "plugins" => config.plugins = expect_str_array(&key, &value)?.into_iter().map(PathBuf::from).collect(),
+                "trim" => config.trim = value.as_bool().ok_or("trim must be a boolean")?,
+                "pretty" => config.pretty = value.as_bool().ok_or("pretty must be a boolean")?,
+                "git_history" => config.git_history = value.as_bool().ok_or("git_history must be a boolean")?,
+                "preformatted" => config.preformatted = expect_str_array(&key, &value)?,
+                "walkers" => {
+                    config.walkers = value.try_into().map_err(|e: toml::de::Error| format!("walkers: {}", e.message()))?;
+                }
+                "lints" => {
+                    config.lints = if value.as_str() == Some("all") {
+                        Lints::all()
+                    } else {
+                        let mut lints = Lints::none();
+                        for code in expect_str_array(&key, &value)? {
+                            lints = lints.enable(LintRule::from_code(&code).ok_or_else(|| format!("unknown lint {code:?}"))?);
+                        }
+                        lints
+                    };
+                }
+                "service_worker" => config.service_worker = parse_service_worker(&value)?,
+                "images" => config.images = parse_images(&value)?,
+                "changelog" => config.changelog = parse_changelog(&value)?,
+                "error_pages" => config.error_pages = Some(parse_error_pages(&value)?),
+                #[cfg(feature = "csp")]
+                "csp" => config.csp = parse_csp(&value)?,
+                #[cfg(not(feature = "csp"))]
+                "csp" => return Err("csp needs configurafox to be built with the csp feature".to_string()),
+                "variables" => {
+                    let toml::Value::Table(variables) = value else {
+                        return Err("variables must be a table".to_string());
+                    };
+                    config.variables = variables;
+                }
+                _ => warn!("Unknown configuration key {key}"),
+            }
+        }
+
+        if let (Some(site_url), Some(error_pages)) = (&config.site_url, &mut config.error_pages) {
+            if !error_pages_base_given {
+                let path = site_url.split("://").nth(1).and_then(|rest| rest.split_once('/')).map_or("", |(_, path)| path);
+                *error_pages = error_pages.clone().with_base_path(path);
+            }
+        }
+        if let Some(site_url) = &config.site_url {
+            config.variables.entry(SITE_URL_VARIABLE).or_insert_with(|| toml::Value::String(site_url.clone()));
+        }
+
+        Ok(config)
+    }
+
+    /// The absolute location of `content_dir`
+    pub fn content_path(&self) -> PathBuf {
+        self.root.join(&self.content_dir)
+    }
+
+    /// The absolute location of `output_dir`
+    pub fn output_path(&self) -> PathBuf {
+        self.root.join(&self.output_dir)
+    }
+
+    /// Options for scanning `content_dir`: sidecar files are read, and hidden files and `ignore` are skipped
+    pub fn scan_options(&self) -> Result<ScanOptions, ConfigurafoxError> {
+        let mut options = ScanOptions { sidecars: true, skip_hidden: true, ..ScanOptions::default() };
+        for pattern in &self.ignore {
+            options = options.exclude(pattern)?;
+        }
+        Ok(options)
+    }
+
+    /// The configured walkers built by `Registry::builtin`, in order, followed by the plugins
+    pub fn walkers<R: Resource + 'static, D: 'static>(&self) -> Result<Vec<Box<dyn TreeWalker<R, D>>>, ConfigurafoxError> {
+        self.walkers_with(&Registry::builtin())
+    }
+
+    /// Like `walkers`, with walkers registered in `registry`. `variables` gets `variables`, and `syntax-highlight`
+    /// and `git-snippet` get `theme`, unless they're given options of their own
+    pub fn walkers_with<R: Resource + 'static, D: 'static>(&self, registry: &Registry<R, D>) -> Result<Vec<Box<dyn TreeWalker<R, D>>>, ConfigurafoxError> {
+        let specs = self.walkers.iter().map(|spec| self.with_defaults(spec)).collect::<Vec<_>>();
+        let walkers = registry.walkers(&specs)?;
+
+        #[cfg(feature = "wasm-plugins")]
+        let walkers = {
+            let mut walkers = walkers;
+            for plugin in &self.plugins {
+                walkers.push(Box::new(crate::plugin::WasmWalker::load(&self.root.join(plugin))?));
+            }
+            walkers
+        };
+        #[cfg(not(feature = "wasm-plugins"))]
+        if !self.plugins.is_empty() {
+            return Err(ConfigurafoxError::Config {
+                path: self.root.join(DEFAULT_CONFIG_FILE),
+                msg: "plugins need configurafox to be built with the wasm-plugins feature".to_string(),
+            });
+        }
+
+        Ok(walkers)
+    }
+
+    fn with_defaults(&self, spec: &WalkerSpec) -> WalkerSpec {
+        let mut spec = spec.clone();
+        match spec.name.as_str() {
+            "variables" if !spec.options.contains_key("variables") => {
+                spec.options.insert("variables".to_string(), toml::Value::Table(self.variables.clone()));
+            }
+            "syntax-highlight" | "git-snippet" if !spec.options.contains_key("theme") => {
+                spec.options.insert("theme".to_string(), toml::Value::String(self.theme.clone()));
+            }
+            _ => {}
+        }
+        spec
+    }
+
+    /// The site's `site_url`, `title` variable and variables, as user data for walkers needing `SiteData`
+    pub fn site(&self) -> Site {
+        Site {
+            base_url: self.site_url.clone(),
+            title: self.variables.get(TITLE_VARIABLE).and_then(toml::Value::as_str).map(str::to_string),
+            variables: toml_to_value(toml::Value::Table(self.variables.clone())),
+        }
+    }
+
+    /// An `HTMLProcessor` with the configured walkers, trimming, pretty-printing and lints
+    pub fn html_processor<'data, R: Resource + 'static, D: 'static>(&self, data: &'data D) -> Result<HTMLProcessor<'data, R, D>, ConfigurafoxError> {
+        self.html_processor_with(&Registry::builtin(), data)
+    }
+
+    /// Like `html_processor`, with the walkers of `Registry::with_site_walkers` available too
+    pub fn site_html_processor<'data, R: Resource + 'static, D: SiteData + 'static>(&self, site: &'data D) -> Result<HTMLProcessor<'data, R, D>, ConfigurafoxError> {
+        self.html_processor_with(&Registry::builtin().with_site_walkers(), site)
+    }
+
+    /// Like `html_processor`, with walkers registered in `registry`
+    pub fn html_processor_with<'data, R: Resource + 'static, D: 'static>(
+        &self,
+        registry: &Registry<R, D>,
+        data: &'data D,
+    ) -> Result<HTMLProcessor<'data, R, D>, ConfigurafoxError> {
+        Ok(HTMLProcessor::builder()
+            .data(data)
+            .walkers(self.walkers_with(registry)?)
+            .trim(self.trim)
+            .pretty(self.pretty)
+            .whitespace(Whitespace::default().with_preformatted(&self.preformatted))
+            .lints(self.lints.clone())
+            .build())
+    }
+}
+
+fn expect_str(key: &str, value: &toml::Value) -> Result<String, String> {
+    value.as_str().map(str::to_string).ok_or_else(|| format!("{key} must be a string"))
+}
+
+fn expect_str_array(key: &str, value: &toml::Value) -> Result<Vec<String>, String> {
+    value
+        .as_array()
+        .and_then(|values| values.iter().map(|v| v.as_str().map(str::to_string)).collect())
+        .ok_or_else(|| format!("{key} must be an array of strings"))
+}
+
+/// `true`, `false`, or a table of `precache`, `offline_page` and `cache_name`
+fn parse_service_worker(value: &toml::Value) -> Result<Option<ServiceWorker>, String> {
+    let table = match value {
+        toml::Value::Boolean(enabled) => return Ok(enabled.then(ServiceWorker::new)),
+        toml::Value::Table(table) => table,
+        _ => return Err("service_worker must be a boolean or a table".to_string()),
+    };
+
+    let mut service_worker = ServiceWorker::new();
+    for (key, value) in table {
+        match key.as_str() {
+            "precache" => {
+                service_worker.precache = ServiceWorker::with_precache(&expect_str_array(key, value)?).map_err(|e| e.to_string())?.precache;
+            }
+            "offline_page" => service_worker.offline_page = Some(PathBuf::from(expect_str(key, value)?)),
+            "cache_name" => service_worker.cache_name = expect_str(key, value)?,
+            _ => return Err(format!("unknown service_worker option {key}")),
+        }
+    }
+    Ok(Some(service_worker))
+}
+
+fn parse_changelog(value: &toml::Value) -> Result<Option<ChangelogOptions>, String> {
+    let table = match value {
+        toml::Value::Boolean(enabled) => return Ok(enabled.then(ChangelogOptions::default)),
+        toml::Value::Table(table) => table,
+        _ => return Err("changelog must be a boolean or a table".to_string()),
+    };
+
+    let mut changelog = ChangelogOptions::default();
+    let mut title = None;
+    for (key, value) in table {
+        match key.as_str() {
+            "keep" => {
+                changelog.keep = value.as_integer().and_then(|keep| usize::try_from(keep).ok()).ok_or("keep must be a non-negative integer")?;
+            }
+            "page" => changelog.page = Some(RecentChanges::new(expect_str(key, value)?)),
+            "title" => title = Some(expect_str(key, value)?),
+            _ => return Err(format!("unknown changelog option {key}")),
+        }
+    }
+    match (&mut changelog.page, title) {
+        (Some(page), Some(title)) => page.title = title,
+        (None, Some(_)) => return Err("changelog has a title but no page".to_string()),
+        _ => {}
+    }
+    Ok(Some(changelog))
+}
+
+#[cfg(feature = "csp")]
+fn parse_csp(value: &toml::Value) -> Result<Option<ContentSecurityPolicy>, String> {
+    let table = match value {
+        toml::Value::Boolean(enabled) => return Ok(enabled.then(ContentSecurityPolicy::default)),
+        toml::Value::Table(table) => table,
+        _ => return Err("csp must be a boolean or a table".to_string()),
+    };
+
+    let mut csp = ContentSecurityPolicy::default();
+    for (key, value) in table {
+        match key.as_str() {
+            "output" => {
+                csp.output = match expect_str(key, value)?.as_str() {
+                    "headers" => CspOutput::Headers,
+                    "meta" => CspOutput::Meta,
+                    output => return Err(format!("csp output must be \"headers\" or \"meta\", not {output:?}")),
+                };
+            }
+            "directives" => {
+                let directives = value.as_table().ok_or("directives must be a table")?;
+                for (directive, sources) in directives {
+                    csp = csp.with_directive(directive, &expect_str(directive, sources)?);
+                }
+            }
+            _ => return Err(format!("unknown csp option {key}")),
+        }
+    }
+    Ok(Some(csp))
+}
+
+fn parse_error_pages(value: &toml::Value) -> Result<ErrorPages, String> {
+    let table = value.as_table().ok_or("error_pages must be a table")?;
+    let mut error_pages = ErrorPages::default();
+    for (key, value) in table {
+        match key.as_str() {
+            "host" => {
+                let host = expect_str(key, value)?;
+                error_pages.host = Host::from_name(&host).ok_or_else(|| format!("unknown host {host:?}"))?;
+            }
+            "base_path" => error_pages = error_pages.with_base_path(&expect_str(key, value)?),
+            _ => return Err(format!("unknown error_pages option {key}")),
+        }
+    }
+    Ok(error_pages)
+}
+
+/// A table of `strip_metadata` and `keep_exif`, the names of EXIF tags
+fn parse_images(value: &toml::Value) -> Result<ImageProcessor, String> {
+    let table = value.as_table().ok_or("images must be a table")?;
+    let mut images = ImageProcessor::new();
+    for (key, value) in table {
+        match key.as_str() {
+            "strip_metadata" => images.strip_metadata = value.as_bool().ok_or("strip_metadata must be a boolean")?,
+            "keep_exif" => {
+                images.keep_exif = expect_str_array(key, value)?
+                    .iter()
+                    .map(|name| exif_tag(name).ok_or_else(|| format!("unknown EXIF tag {name:?}")))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => return Err(format!("unknown images option {key}")),
+        }
+    }
+    Ok(images)
+}
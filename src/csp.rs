@@ -0,0 +1,224 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use html_editor::Node;
+use sha2::{Digest, Sha256};
+
+use crate::ConfigurafoxError;
+use crate::deploy::DeployManifest;
+use crate::treewalker::get_attr;
+
+/// Where `CspOutput::Headers` writes the policy, relative to the output root. Netlify and Cloudflare Pages read
+/// headers from it
+pub const HEADERS_FILE: &str = "_headers";
+
+/// Starts the part of `HEADERS_FILE` that is replaced on every build. What comes before it is kept
+const HEADERS_MARKER: &str = "# Content-Security-Policy generated by configurafox, replaced on every build";
+
+/// Script types that browsers run, and so CSP applies to. Others, like `application/ld+json`, are data
+const SCRIPT_TYPES: &[&str] = &["", "text/javascript", "application/javascript", "module"];
+
+/// `'sha256-…'`, as a CSP source allowing inline content that is exactly `content`
+pub fn hash_source(content: &str) -> String {
+    format!("'sha256-{}'", base64::engine::general_purpose::STANDARD.encode(Sha256::digest(content.as_bytes())))
+}
+
+/// The hash sources of the inline `<script>`s, `<style>`s and `style` attributes of pages, like those of
+/// highlighted code. Scripts with `src` and data blocks aren't inline scripts. Event handler attributes aren't
+/// included, pages shouldn't need them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InlineHashes {
+    pub scripts: BTreeSet<String>,
+    pub styles: BTreeSet<String>,
+    /// Allowed by `'unsafe-hashes'`, which despite its name only allows attributes with exactly these values
+    pub style_attrs: BTreeSet<String>,
+}
+
+impl InlineHashes {
+    /// The hashes of the page `html`
+    pub fn of_page(html: &str) -> Result<InlineHashes, html_editor::HTMLParseError> {
+        fn visit(nodes: &[Node], hashes: &mut InlineHashes) {
+            for node in nodes {
+                let Node::Element(element) = node else { continue };
+                if let Some(style) = get_attr(&element.attrs, "style") {
+                    hashes.style_attrs.insert(hash_source(&crate::entities::decode_attr(style)));
+                }
+                let text = || element.children.iter().filter_map(|child| if let Node::Text(text) = child { Some(&**text) } else { None }).collect::<String>();
+                match &*element.name {
+                    "script" if get_attr(&element.attrs, "src").is_none() => {
+                        let kind = get_attr(&element.attrs, "type").unwrap_or_default().trim().to_ascii_lowercase();
+                        if SCRIPT_TYPES.contains(&&*kind) {
+                            hashes.scripts.insert(hash_source(&text()));
+                        }
+                    }
+                    "style" => {
+                        hashes.styles.insert(hash_source(&text()));
+                    }
+                    _ => visit(&element.children, hashes),
+                }
+            }
+        }
+
+        let mut hashes = InlineHashes::default();
+        visit(&html_editor::parse(html)?, &mut hashes);
+        Ok(hashes)
+    }
+
+    pub fn extend(&mut self, other: InlineHashes) {
+        self.scripts.extend(other.scripts);
+        self.styles.extend(other.styles);
+        self.style_attrs.extend(other.style_attrs);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty() && self.styles.is_empty() && self.style_attrs.is_empty()
+    }
+}
+
+/// Where `ContentSecurityPolicy::write` puts the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CspOutput {
+    /// A single policy for the whole site in `HEADERS_FILE`, allowing the inline content of every page
+    Headers,
+    /// A `<meta http-equiv="Content-Security-Policy">` at the start of the `<head>` of each page, allowing only its
+    /// own inline content. Pages that have such a meta tag already are left as they are
+    Meta,
+}
+
+/// A Content-Security-Policy for a built site that allows its inline scripts, styles and style attributes by their
+/// hashes, so the policy can be strict without `'unsafe-inline'`. Run `write` after the build, since the hashes must be of the
+/// final outputs
+#[derive(Debug, Clone)]
+pub struct ContentSecurityPolicy {
+    /// Sources by directive, e.g. `img-src` = `'self' data:`. `script-src` and `style-src` get the hashes added
+    pub directives: BTreeMap<String, String>,
+    pub output: CspOutput,
+}
+
+impl Default for ContentSecurityPolicy {
+    fn default() -> Self {
+        ContentSecurityPolicy::new(CspOutput::Headers)
+    }
+}
+
+impl ContentSecurityPolicy {
+    /// Allows `'self'` for everything
+    pub fn new(output: CspOutput) -> ContentSecurityPolicy {
+        let directives = ["default-src", "script-src", "style-src"].into_iter().map(|name| (name.to_string(), "'self'".to_string())).collect();
+        ContentSecurityPolicy { directives, output }
+    }
+
+    /// Sets the sources of `directive`, or removes it if `sources` is empty
+    pub fn with_directive(mut self, directive: &str, sources: &str) -> ContentSecurityPolicy {
+        if sources.trim().is_empty() {
+            self.directives.remove(directive);
+        } else {
+            self.directives.insert(directive.to_string(), sources.trim().to_string());
+        }
+        self
+    }
+
+    /// The value of the header, with `hashes` allowed
+    pub fn policy(&self, hashes: &InlineHashes) -> String {
+        let mut directives = self.directives.clone();
+        let mut style_sources = hashes.styles.iter().cloned().collect::<Vec<_>>();
+        if !hashes.style_attrs.is_empty() {
+            style_sources.push("'unsafe-hashes'".to_string());
+            style_sources.extend(hashes.style_attrs.iter().cloned());
+        }
+        for (name, sources) in [("script-src", hashes.scripts.iter().cloned().collect()), ("style-src", style_sources)] {
+            if sources.is_empty() {
+                continue;
+            }
+            let directive = directives.entry(name.to_string()).or_default();
+            for source in sources {
+                directive.push(' ');
+                directive.push_str(&source);
+            }
+            *directive = directive.trim_start().to_string();
+        }
+        directives.iter().map(|(name, sources)| format!("{name} {sources}")).collect::<Vec<_>>().join("; ")
+    }
+
+    /// Hashes the inline content of the pages in `output_dir` and writes the policy as configured. Returns the
+    /// files written
+    pub fn write(&self, output_dir: &Path) -> Result<Vec<PathBuf>, ConfigurafoxError> {
+        let manifest = DeployManifest::from_dir(output_dir, "")?;
+        let mut pages = Vec::new();
+        for path in manifest.files.keys().filter(|path| path.extension().is_some_and(|ext| ext == "html" || ext == "htm")) {
+            let html = std::fs::read_to_string(output_dir.join(path))?;
+            let hashes = InlineHashes::of_page(&html).map_err(|error| ConfigurafoxError::parse_html(path, &html, 1, error))?;
+            trace!("{}: {} inline scripts, {} styles and {} style attributes", path.display(), hashes.scripts.len(), hashes.styles.len(), hashes.style_attrs.len());
+            pages.push((path, html, hashes));
+        }
+
+        match self.output {
+            CspOutput::Headers => {
+                let mut all = InlineHashes::default();
+                for (_, _, hashes) in pages {
+                    all.extend(hashes);
+                }
+                info!(
+                    "Allowing {} inline scripts, {} styles and {} style attributes in the Content-Security-Policy",
+                    all.scripts.len(),
+                    all.styles.len(),
+                    all.style_attrs.len(),
+                );
+
+                let path = output_dir.join(HEADERS_FILE);
+                let existing = match std::fs::read_to_string(&path) {
+                    Ok(existing) => existing,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut headers = existing.split(HEADERS_MARKER).next().unwrap_or_default().to_string();
+                if !headers.is_empty() && !headers.ends_with('\n') {
+                    headers.push('\n');
+                }
+                headers.push_str(&format!("{HEADERS_MARKER}\n/*\n  Content-Security-Policy: {}\n", self.policy(&all)));
+                std::fs::write(&path, headers)?;
+                Ok(vec![path])
+            }
+            CspOutput::Meta => {
+                let mut written = Vec::new();
+                for (path, html, hashes) in pages {
+                    let lowercase = html.to_ascii_lowercase();
+                    if lowercase.contains("http-equiv=\"content-security-policy\"") {
+                        debug!("{}: Has a Content-Security-Policy already", path.display());
+                        continue;
+                    }
+                    let Some(head) = find_start_tag(&lowercase, "head") else {
+                        warn!("{}: No <head> to put the Content-Security-Policy in", path.display());
+                        continue;
+                    };
+                    let meta = format!("<meta http-equiv=\"Content-Security-Policy\" content=\"{}\">", crate::entities::escape_attr(&self.policy(&hashes)));
+                    let html = format!("{}{meta}{}", &html[..head], &html[head..]);
+                    let path = output_dir.join(path);
+                    std::fs::write(&path, html)?;
+                    written.push(path);
+                }
+                info!("Added a Content-Security-Policy to {} pages", written.len());
+                Ok(written)
+            }
+        }
+    }
+}
+
+/// The index just after the first `<name>` or `<name …>` start tag in `html`, which must be lowercase
+fn find_start_tag(html: &str, name: &str) -> Option<usize> {
+    let open = format!("<{name}");
+    let mut from = 0;
+    while let Some(index) = html[from..].find(&open) {
+        let after = from + index + open.len();
+        match html[after..].chars().next() {
+            Some('>') => return Some(after + 1),
+            Some(c) if c.is_ascii_whitespace() || c == '/' => return html[after..].find('>').map(|end| after + end + 1),
+            _ => from = after,
+        }
+    }
+    None
+}
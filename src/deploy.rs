@@ -0,0 +1,192 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ConfigurafoxError, fingerprint};
+use crate::output::{OutputBackend, confine_output};
+
+/// Where `deploy` remembers what it uploaded, relative to the project root
+pub const DEFAULT_DEPLOY_MANIFEST: &str = ".configurafox-deploy.json";
+
+/// `fingerprint` in hex
+pub(crate) fn content_hash(contents: &[u8]) -> String {
+    format!("{:016x}", fingerprint(contents))
+}
+
+/// The content hash of every file of a build, by path relative to the output directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeployManifest {
+    /// What the files were deployed to, as described by the backend. A manifest for another target is
+    /// treated as empty
+    pub target: String,
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+impl DeployManifest {
+    /// Hashes every file below `output_dir`
+    pub fn from_dir(output_dir: &Path, target: &str) -> std::io::Result<DeployManifest> {
+        fn visit(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, String>) -> std::io::Result<()> {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    visit(root, &path, files)?;
+                } else {
+                    let relative = path.strip_prefix(root).expect("read_dir stays below root").to_owned();
+                    files.insert(relative, content_hash(&std::fs::read(&path)?));
+                }
+            }
+            Ok(())
+        }
+
+        let mut files = BTreeMap::new();
+        visit(output_dir, output_dir, &mut files)?;
+        Ok(DeployManifest { target: target.to_string(), files })
+    }
+
+    /// The manifest saved at `path`, or an empty one if there is none
+    pub fn load(path: &Path) -> std::io::Result<DeployManifest> {
+        match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DeployManifest::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    /// What needs to change at the target to go from `previous` to this manifest
+    pub fn plan(&self, previous: &DeployManifest) -> DeployPlan {
+        let empty = BTreeMap::new();
+        let previous_files = if previous.target == self.target { &previous.files } else { &empty };
+
+        let mut plan = DeployPlan::default();
+        for (path, hash) in &self.files {
+            if previous_files.get(path) == Some(hash) {
+                plan.unchanged += 1;
+            } else {
+                plan.upload.push(path.clone());
+            }
+        }
+        plan.delete = previous_files.keys().filter(|path| !self.files.contains_key(*path)).cloned().collect();
+        plan
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeployPlan {
+    /// New or changed files
+    pub upload: Vec<PathBuf>,
+    /// Files that were deployed before, but aren't part of the build anymore
+    pub delete: Vec<PathBuf>,
+    pub unchanged: usize,
+}
+
+impl DeployPlan {
+    pub fn is_empty(&self) -> bool {
+        self.upload.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// Syncs the build in `output_dir` to `backend`, uploading only the files whose contents changed since the
+/// deploy recorded in the manifest at `manifest_path`, and removing the ones that disappeared. The manifest is
+/// updated once the backend finishes. With `dry_run`, only returns the plan
+pub fn deploy(output_dir: &Path, backend: &mut dyn OutputBackend, manifest_path: &Path, dry_run: bool) -> Result<DeployPlan, ConfigurafoxError> {
+    let target = backend.describe();
+    let current = DeployManifest::from_dir(output_dir, &target)?;
+    let previous = DeployManifest::load(manifest_path)?;
+    let plan = current.plan(&previous);
+
+    info!("Deploying to {target}: {} to upload, {} to delete, {} unchanged", plan.upload.len(), plan.delete.len(), plan.unchanged);
+    if dry_run {
+        return Ok(plan);
+    }
+
+    for path in &plan.upload {
+        backend.write_file(path, &std::fs::read(output_dir.join(path))?)?;
+    }
+    for path in &plan.delete {
+        backend.remove_file(path)?;
+    }
+    backend.finish()?;
+
+    current.save(manifest_path)?;
+    Ok(plan)
+}
+
+/// Copies outputs to a remote directory with `rsync`, e.g. over ssh to `user@host:/var/www/site`. Reads the
+/// files from `source`, the output directory, and sends everything written or removed in one `rsync` call
+/// when finishing
+pub struct RsyncBackend {
+    pub source: PathBuf,
+    pub destination: String,
+    /// Passed to `rsync` before the paths, e.g. `-e "ssh -p 2222"`
+    pub extra_args: Vec<String>,
+    paths: Vec<PathBuf>,
+}
+
+impl RsyncBackend {
+    pub fn new(source: PathBuf, destination: &str) -> RsyncBackend {
+        RsyncBackend { source, destination: destination.to_string(), extra_args: Vec::new(), paths: Vec::new() }
+    }
+}
+
+impl OutputBackend for RsyncBackend {
+    fn describe(&self) -> String {
+        format!("RsyncBackend({})", self.destination)
+    }
+
+    /// The contents are read from `source` by `rsync` itself
+    fn write_file(&mut self, path: &Path, _contents: &[u8]) -> Result<(), ConfigurafoxError> {
+        self.paths.push(confine_output(path)?);
+        Ok(())
+    }
+
+    /// Files missing from `source` are deleted at the destination, see `--delete-missing-args`
+    fn remove_file(&mut self, path: &Path) -> Result<(), ConfigurafoxError> {
+        self.paths.push(confine_output(path)?);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ConfigurafoxError> {
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+
+        let backend_err = |msg: String| ConfigurafoxError::OutputBackend { backend: self.describe(), msg };
+
+        let mut source = self.source.as_os_str().to_owned();
+        source.push("/");
+        let mut child = Command::new("rsync")
+            .args(["--archive", "--compress", "--relative", "--files-from=-", "--from0", "--delete-missing-args"])
+            .args(&self.extra_args)
+            .arg(source)
+            .arg(&self.destination)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| backend_err(format!("could not run rsync: {e}")))?;
+
+        let mut list = Vec::new();
+        for path in &self.paths {
+            list.extend_from_slice(path.to_string_lossy().as_bytes());
+            list.push(0);
+        }
+        child.stdin.take().expect("stdin is piped").write_all(&list)?;
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(backend_err(format!("rsync failed with {status}")));
+        }
+
+        self.paths.clear();
+        Ok(())
+    }
+}
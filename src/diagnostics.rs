@@ -0,0 +1,226 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::ConfigurafoxError;
+use crate::position::SourcePos;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    /// A failure that was recovered from in lenient mode
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// What to do about a problem that can be ignored, see `Strictness`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handling {
+    /// Fail the build
+    Error,
+    /// Record a warning, and keep the offending markup as it is
+    Warn,
+    /// Silently keep the offending markup as it is
+    PassThrough,
+}
+
+/// How the build reacts to problems that are bugs in a release, but normal while drafting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Strictness {
+    /// `$name` without a value
+    pub unknown_variables: Handling,
+    /// `@identifier` not matching any resource
+    pub unresolved_links: Handling,
+    /// `<code-hl lang=...>` with a language syntect doesn't know. Passed-through code is shown as plain text
+    pub unknown_languages: Handling,
+    /// Tags left in the final DOM that aren't standard HTML, e.g. a tag like `<note>` no walker handled. Names with
+    /// a `-` are custom elements and allowed, see `lint::is_custom_element_name`
+    pub unknown_tags: Handling,
+}
+
+impl Default for Strictness {
+    fn default() -> Self {
+        Strictness {
+            unknown_variables: Handling::Error,
+            unresolved_links: Handling::Error,
+            unknown_languages: Handling::Error,
+            unknown_tags: Handling::PassThrough,
+        }
+    }
+}
+
+impl Strictness {
+    /// Everything is an error, e.g. for release builds
+    pub fn strict() -> Strictness {
+        Strictness {
+            unknown_variables: Handling::Error,
+            unresolved_links: Handling::Error,
+            unknown_languages: Handling::Error,
+            unknown_tags: Handling::Error,
+        }
+    }
+
+    /// Everything is a warning, e.g. while drafting
+    pub fn permissive() -> Strictness {
+        Strictness {
+            unknown_variables: Handling::Warn,
+            unresolved_links: Handling::Warn,
+            unknown_languages: Handling::Warn,
+            unknown_tags: Handling::Warn,
+        }
+    }
+}
+
+/// A problem that doesn't stop the build, e.g. an image without alt text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable name for the kind of problem, e.g. `missing-alt`, for tools to filter on
+    pub code: Option<String>,
+    /// The source file, relative to the project root
+    pub path: Option<PathBuf>,
+    pub pos: Option<SourcePos>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+            if let Some(pos) = self.pos {
+                write!(f, "{pos}:")?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)?;
+        if let Some(code) = &self.code {
+            write!(f, " [{code}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostic {
+    pub fn with_code<S: Into<String>>(self, code: S) -> Diagnostic {
+        Diagnostic { code: Some(code.into()), ..self }
+    }
+
+    /// `{"file", "line", "column", "code", "severity", "message"}`, with nulls for unknown parts
+    pub fn to_json(&self) -> Value {
+        json!({
+            "file": self.path.as_ref().map(|p| p.to_string_lossy()),
+            "line": self.pos.map(|p| p.line),
+            "column": self.pos.map(|p| p.column),
+            "code": self.code,
+            "severity": self.severity.to_string(),
+            "message": self.message,
+        })
+    }
+}
+
+/// Diagnostics emitted during a build. Kept by the `ResourceManager`, see `ResourceManager::diagnostics`
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+    /// Makes `run` fail at the end of a build that emitted any warnings
+    pub warnings_as_errors: bool,
+    /// Replace elements whose walker failed with a visible placeholder and record an error here, instead of
+    /// failing the build. See `treewalker::error_placeholder`
+    pub lenient: bool,
+    pub strictness: Strictness,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        debug!("{diagnostic}");
+        self.entries.push(diagnostic);
+    }
+
+    pub fn warn<S: Into<String>>(&mut self, path: Option<&Path>, pos: Option<SourcePos>, message: S) {
+        self.push(Diagnostic { severity: Severity::Warning, code: None, path: path.map(Path::to_owned), pos, message: message.into() });
+    }
+
+    pub fn note<S: Into<String>>(&mut self, path: Option<&Path>, pos: Option<SourcePos>, message: S) {
+        self.push(Diagnostic { severity: Severity::Note, code: None, path: path.map(Path::to_owned), pos, message: message.into() });
+    }
+
+    pub fn error<S: Into<String>>(&mut self, path: Option<&Path>, pos: Option<SourcePos>, message: S) {
+        self.push(Diagnostic { severity: Severity::Error, code: None, path: path.map(Path::to_owned), pos, message: message.into() });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.entries.iter()
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.entries.iter().filter(|d| d.severity == Severity::Warning).count()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.entries.iter().filter(|d| d.severity == Severity::Error).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drops the diagnostics about the file at `path`, e.g. before rebuilding it
+    pub fn clear_path(&mut self, path: &Path) {
+        self.entries.retain(|d| d.path.as_deref() != Some(path));
+    }
+
+    /// The diagnostics sorted by file and position, as a copy
+    pub fn sorted(&self) -> Vec<Diagnostic> {
+        let mut sorted = self.entries.clone();
+        sorted.sort_by(|a, b| (&a.path, a.pos).cmp(&(&b.path, b.pos)));
+        sorted
+    }
+}
+
+/// A machine-readable report of a build, e.g. for editors and CI annotations: `errors` (such as the one
+/// `run` failed with) followed by the collected `diagnostics`, sorted, as
+/// `{"diagnostics": [...], "errors": n, "warnings": n}`. See `Diagnostic::to_json`
+pub fn json_report(errors: &[&ConfigurafoxError], diagnostics: &Diagnostics) -> String {
+    let mut all = errors.iter().map(|e| e.to_diagnostic()).collect::<Vec<_>>();
+    all.extend(diagnostics.sorted());
+
+    let report = json!({
+        "diagnostics": all.iter().map(Diagnostic::to_json).collect::<Vec<_>>(),
+        "errors": all.iter().filter(|d| d.severity == Severity::Error).count(),
+        "warnings": all.iter().filter(|d| d.severity == Severity::Warning).count(),
+    });
+    report.to_string()
+}
+
+/// One diagnostic per line, sorted by file and position, followed by a summary
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in self.sorted() {
+            writeln!(f, "{diagnostic}")?;
+        }
+        let notes = self.entries.len() - self.warning_count() - self.error_count();
+        write!(f, "{} error(s), {} warning(s), {notes} note(s)", self.error_count(), self.warning_count())
+    }
+}
@@ -0,0 +1,198 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::deploy::{content_hash, DeployManifest};
+
+/// Lines of unchanged context around each hunk of a content diff
+pub const DIFF_CONTEXT: usize = 3;
+
+/// Above this many compared line pairs, changed files are reported without a content diff
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChange {
+    Added,
+    Removed,
+    /// A unified diff of the contents, if both versions are text and known
+    Changed { diff: Option<String> },
+}
+
+/// How a build changes the outputs of a previous one, by path relative to the output directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildDiff {
+    pub changes: BTreeMap<PathBuf, OutputChange>,
+    pub unchanged: usize,
+}
+
+impl BuildDiff {
+    /// Compares `current`, e.g. the files of a `MemoryBackend`, against what is in `output_dir`, with content diffs
+    /// of changed text files. A missing output directory counts as empty
+    pub fn against_dir(output_dir: &Path, current: &HashMap<PathBuf, Vec<u8>>) -> std::io::Result<BuildDiff> {
+        let previous = match DeployManifest::from_dir(output_dir, "") {
+            Ok(manifest) => manifest,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DeployManifest::default(),
+            Err(e) => return Err(e),
+        };
+
+        let mut diff = BuildDiff::against_manifest(&previous, current);
+        for (path, change) in &mut diff.changes {
+            if let OutputChange::Changed { diff } = change {
+                let old = std::fs::read(output_dir.join(path))?;
+                *diff = text_diff(&old, &current[path]);
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Compares `current` against the hashes of a deploy manifest. The old contents aren't known, so there are no
+    /// content diffs
+    pub fn against_manifest(previous: &DeployManifest, current: &HashMap<PathBuf, Vec<u8>>) -> BuildDiff {
+        let files = current.iter().map(|(path, contents)| (path.clone(), content_hash(contents))).collect();
+        BuildDiff::between(previous, &DeployManifest { target: previous.target.clone(), files })
+    }
+
+    /// Compares the hashes of two manifests, without content diffs
+    pub fn between(previous: &DeployManifest, current: &DeployManifest) -> BuildDiff {
+        let mut diff = BuildDiff::default();
+        for (path, hash) in &current.files {
+            match previous.files.get(path) {
+                None => {
+                    diff.changes.insert(path.clone(), OutputChange::Added);
+                }
+                Some(previous_hash) if previous_hash == hash => diff.unchanged += 1,
+                Some(_) => {
+                    diff.changes.insert(path.clone(), OutputChange::Changed { diff: None });
+                }
+            }
+        }
+        for path in previous.files.keys().filter(|path| !current.files.contains_key(*path)) {
+            diff.changes.insert(path.clone(), OutputChange::Removed);
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Lists the changes as `A`, `D` and `M` lines like `git status --short`, each modification followed by its diff
+impl fmt::Display for BuildDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, change) in &self.changes {
+            match change {
+                OutputChange::Added => writeln!(f, "A {}", path.display())?,
+                OutputChange::Removed => writeln!(f, "D {}", path.display())?,
+                OutputChange::Changed { diff } => {
+                    writeln!(f, "M {}", path.display())?;
+                    if let Some(diff) = diff {
+                        write!(f, "{diff}")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A unified diff from `old` to `new`, None if either isn't UTF-8 or they are too large to compare
+pub fn text_diff(old: &[u8], new: &[u8]) -> Option<String> {
+    let old = std::str::from_utf8(old).ok()?;
+    let new = std::str::from_utf8(new).ok()?;
+    line_diff(&old.lines().collect::<Vec<_>>(), &new.lines().collect::<Vec<_>>(), DIFF_CONTEXT)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// The edits turning `old` into `new`, from the longest common subsequence of the lines between their common
+/// prefix and suffix
+fn edits(old: &[&str], new: &[&str]) -> Option<Vec<Op>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        return None;
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut ops = vec![Op::Keep; prefix];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            ops.push(Op::Keep);
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Keep, suffix));
+    Some(ops)
+}
+
+fn line_diff(old: &[&str], new: &[&str], context: usize) -> Option<String> {
+    let ops = edits(old, new)?;
+
+    // Each op with the old and new line it's at
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for op in &ops {
+        positions.push((old_line, new_line));
+        match op {
+            Op::Keep => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Op::Delete => old_line += 1,
+            Op::Insert => new_line += 1,
+        }
+    }
+
+    // Ranges of ops to show, merging changes less than two contexts apart
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, _) in ops.iter().enumerate().filter(|(_, op)| **op != Op::Keep) {
+        let (start, end) = (index.saturating_sub(context), (index + context + 1).min(ops.len()));
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = String::new();
+    for (start, end) in hunks {
+        let old_count = ops[start..end].iter().filter(|op| **op != Op::Insert).count();
+        let new_count = ops[start..end].iter().filter(|op| **op != Op::Delete).count();
+        let (old_start, new_start) = positions[start];
+        out.push_str(&format!("@@ -{},{old_count} +{},{new_count} @@\n", old_start + 1, new_start + 1));
+        for (op, (old_line, new_line)) in ops[start..end].iter().zip(&positions[start..end]) {
+            match op {
+                Op::Keep => out.push_str(&format!(" {}\n", old[*old_line])),
+                Op::Delete => out.push_str(&format!("-{}\n", old[*old_line])),
+                Op::Insert => out.push_str(&format!("+{}\n", new[*new_line])),
+            }
+        }
+    }
+    Some(out)
+}
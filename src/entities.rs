@@ -0,0 +1,219 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::borrow::Cow;
+
+use html_editor::{Element, HTMLParseError, Node};
+
+use crate::named_references::NAMED_REFERENCES;
+
+/// Elements whose text isn't decoded or escaped
+pub const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Longest name in `NAMED_REFERENCES`, `;` included, so `reference` doesn't look further for the end of one
+const MAX_NAME_LEN: usize = 32;
+
+/// Longest legacy name in `NAMED_REFERENCES`, one without a `;`
+const MAX_LEGACY_NAME_LEN: usize = 6;
+
+/// What browsers make of `&#128;` to `&#159;`, which old pages use for the Windows-1252 characters at those codes
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}', '\u{90}', '‘', '’', '“',
+    '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// `text` with its character references, like `&amp;`, `&#233;` and `&#xE9;`, replaced by the characters they
+/// stand for, the way browsers decode text. That includes every name in `NAMED_REFERENCES`, legacy ones like
+/// `&copy` without their `;`, and numbers without theirs. Anything else is kept as it is
+pub fn decode(text: &str) -> Cow<'_, str> {
+    decode_with(text, false)
+}
+
+/// Like `decode`, but the way browsers decode attribute values: legacy references without a `;` are kept as they
+/// are when a letter, digit or `=` follows, as in `?a=1&copy=2`
+pub fn decode_attr(value: &str) -> Cow<'_, str> {
+    decode_with(value, true)
+}
+
+fn decode_with(text: &str, in_attr: bool) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match reference(rest, in_attr) {
+            Some((decoded, len)) => {
+                out.push_str(&decoded);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// What the reference at the start of `text` stands for, and the reference's length
+fn reference(text: &str, in_attr: bool) -> Option<(Cow<'static, str>, usize)> {
+    let body = &text[1..];
+    if let Some(number) = body.strip_prefix('#') {
+        let (digits, radix, prefix) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 2),
+            None => (number, 10, 1),
+        };
+        let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+        if len == 0 {
+            return None;
+        }
+        let code = digits[..len].chars().fold(0u32, |code, digit| {
+            code.saturating_mul(radix).saturating_add(digit.to_digit(radix).expect("checked above"))
+        });
+        let c = match code {
+            0x80..=0x9F => WINDOWS_1252[code as usize - 0x80],
+            // As browsers do with NUL, surrogates and anything out of range
+            _ => char::from_u32(code).filter(|&c| c != '\0').unwrap_or(char::REPLACEMENT_CHARACTER),
+        };
+        let semicolon = usize::from(digits[len..].starts_with(';'));
+        return Some((Cow::Owned(c.to_string()), 1 + prefix + len + semicolon));
+    }
+
+    let name_len = body.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(body.len());
+    if name_len == 0 {
+        return None;
+    }
+    if body[name_len..].starts_with(';') && name_len < MAX_NAME_LEN {
+        if let Some(decoded) = named(&body[..=name_len]) {
+            return Some((Cow::Borrowed(decoded), name_len + 2));
+        }
+    }
+    // The longest legacy name the reference starts with, like `not` in `&notit;`
+    let (len, decoded) = (1..=name_len.min(MAX_LEGACY_NAME_LEN)).rev().find_map(|len| Some((len, named(&body[..len])?)))?;
+    let next = body[len..].chars().next();
+    if in_attr && next.is_some_and(|c| c.is_ascii_alphanumeric() || c == '=') {
+        return None;
+    }
+    Some((Cow::Borrowed(decoded), len + 1))
+}
+
+fn named(name: &str) -> Option<&'static str> {
+    let index = NAMED_REFERENCES.binary_search_by_key(&name, |(name, _)| name).ok()?;
+    Some(NAMED_REFERENCES[index].1)
+}
+
+/// `text` escaped for use as text: `&`, `<` and `>`, and no-break spaces so they stay visible in the source
+pub fn escape_text(text: &str) -> Cow<'_, str> {
+    escape(text, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '\u{A0}' => Some("&nbsp;"),
+        _ => None,
+    })
+}
+
+/// `value` escaped for use in a double-quoted attribute value
+pub fn escape_attr(value: &str) -> Cow<'_, str> {
+    escape(value, |c| match c {
+        '&' => Some("&amp;"),
+        '"' => Some("&quot;"),
+        '\u{A0}' => Some("&nbsp;"),
+        _ => None,
+    })
+}
+
+fn escape(text: &str, replacement: impl Fn(char) -> Option<&'static str>) -> Cow<'_, str> {
+    if !text.chars().any(|c| replacement(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len() + text.len() / 8);
+    for c in text.chars() {
+        match replacement(c) {
+            Some(escaped) => out.push_str(escaped),
+            None => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Decodes the text and attribute values of `dom`, except the text of `RAW_TEXT_ELEMENTS`
+pub fn decode_dom(dom: &mut [Node]) {
+    for node in dom {
+        match node {
+            Node::Element(Element { name, attrs, children }) => {
+                for (_, value) in attrs.iter_mut() {
+                    if let Cow::Owned(decoded) = decode_attr(value) {
+                        *value = decoded;
+                    }
+                }
+                if !RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+                    decode_dom(children);
+                }
+            }
+            Node::Text(text) => {
+                if let Cow::Owned(decoded) = decode(text) {
+                    *text = decoded;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parses `html` with its text and attribute values decoded, the way walkers see documents. Text they add is
+/// escaped again by `serialize::to_html`, markup should be added as `Node::RawHTML`
+pub fn parse(html: &str) -> Result<Vec<Node>, HTMLParseError> {
+    let mut dom = html_editor::parse(html)?;
+    decode_dom(&mut dom);
+    Ok(dom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_attr, escape_text, parse};
+    use crate::named_references::NAMED_REFERENCES;
+    use crate::serialize::to_html;
+
+    fn round_trip(html: &str) -> String {
+        to_html(&parse(html).expect("valid HTML"))
+    }
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(NAMED_REFERENCES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn known_names() {
+        assert_eq!(decode("&Dagger; &Prime; &frasl; &fnof; &rightarrow; &NotEqualTilde; &fjlig;"), "‡ ″ ⁄ ƒ → ≂\u{338} fj");
+        assert_eq!(escape_text(&decode("&lt;&Dagger;&gt; &amp;&nbsp;")), "&lt;‡&gt; &amp;&nbsp;");
+        assert_eq!(round_trip("<p title=\"&rightarrow;\">&Dagger; &CounterClockwiseContourIntegral;</p>"), "<p title=\"→\">‡ ∳</p>");
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert_eq!(decode("&nosuch; &; & &#; &#x;"), "&nosuch; &; & &#; &#x;");
+        // Escaped, they still show up as written
+        assert_eq!(round_trip("<p>&nosuch; AT&T</p>"), "<p>&amp;nosuch; AT&amp;T</p>");
+    }
+
+    #[test]
+    fn legacy_names() {
+        assert_eq!(decode("&copy 2024, &notit;, &ampx"), "© 2024, ¬it;, &x");
+        assert_eq!(decode_attr("?a=1&copy=2&not;&amp&notx"), "?a=1&copy=2¬&&notx");
+        assert_eq!(round_trip("<a href=\"?a&copy=2\">&copy 2024</a>"), "<a href=\"?a&amp;copy=2\">© 2024</a>");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(decode("&#8225; &#x2021 &#X2021; &#150;"), "‡ ‡ ‡ –");
+        assert_eq!(decode("&#0; &#xD800; &#x110000; &#99999999999999999999;"), "\u{FFFD} \u{FFFD} \u{FFFD} \u{FFFD}");
+        assert_eq!(round_trip("<p>&#60;&#x3e;&#38;</p>"), "<p>&lt;&gt;&amp;</p>");
+    }
+}
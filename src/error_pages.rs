@@ -0,0 +1,200 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::metadata::{Metadata, OUTPUT_PATH_KEY, Value};
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::AttributeRules;
+
+/// Metadata key flagging a page as the one served for an HTTP error, with its status code, e.g. `error_page = 404`.
+/// Error pages are left out of collections and backlinks, and their links are absolute, since they're served at
+/// whatever path was requested
+pub const ERROR_PAGE_KEY: &str = "error_page";
+
+/// Metadata key with the path the absolute links of an error page start with, `/` unless set. Set it for sites
+/// served below the root, like `/project/`
+pub const BASE_PATH_KEY: &str = "base_path";
+
+/// Starts the part of Apache's `.htaccess` that is replaced on every build. What comes before it is kept
+const HTACCESS_MARKER: &str = "# Error pages generated by configurafox, replaced on every build";
+
+/// The status code a page with `metadata` is the error page for, if it's one
+pub fn error_status(metadata: &Metadata) -> Option<u16> {
+    match metadata.get(ERROR_PAGE_KEY)? {
+        Value::String(status) => status.trim().parse().ok(),
+        status => status.as_u64().and_then(|status| u16::try_from(status).ok()),
+    }
+}
+
+/// Whether `resource` is an error page
+pub fn is_error_page<R: Resource>(resources: &ResourceManager<R>, resource: &R) -> bool {
+    error_status(resources.metadata(resource)).is_some()
+}
+
+/// Where a static host looks for error pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Host {
+    /// `404.html`, `500.html` and so on at the root, for hosts configured separately, like S3 or nginx
+    #[default]
+    Generic,
+    /// Only `404.html` at the root. Also Netlify, GitLab Pages, Cloudflare Pages and Codeberg Pages
+    GitHubPages,
+    /// Like `Generic`, with `ErrorDocument` lines in `.htaccess` pointing at the pages
+    Apache,
+}
+
+impl Host {
+    /// By name, as in `host = "netlify"`
+    pub fn from_name(name: &str) -> Option<Host> {
+        match name {
+            "generic" | "s3" | "nginx" => Some(Host::Generic),
+            "github-pages" | "gitlab-pages" | "netlify" | "cloudflare-pages" | "codeberg-pages" => Some(Host::GitHubPages),
+            "apache" => Some(Host::Apache),
+            _ => None,
+        }
+    }
+
+    /// Where the error page for `status` goes, relative to the output root. None if the host only serves custom
+    /// pages for other statuses
+    pub fn error_page_path(self, status: u16) -> Option<PathBuf> {
+        match self {
+            Host::GitHubPages if status != 404 => None,
+            _ => Some(PathBuf::from(format!("{status}.html"))),
+        }
+    }
+}
+
+/// Puts error pages where a `Host` serves them from, see `ResourceManager::apply_error_pages`
+#[derive(Debug, Clone)]
+pub struct ErrorPages {
+    pub host: Host,
+    /// `BASE_PATH_KEY` of the error pages that don't set it
+    pub base_path: String,
+}
+
+impl Default for ErrorPages {
+    fn default() -> Self {
+        ErrorPages::new(Host::default())
+    }
+}
+
+impl ErrorPages {
+    pub fn new(host: Host) -> ErrorPages {
+        ErrorPages { host, base_path: "/".to_string() }
+    }
+
+    /// Sets the base path, adding the slashes it must start and end with
+    pub fn with_base_path(mut self, base_path: &str) -> ErrorPages {
+        let trimmed = base_path.trim_matches('/');
+        self.base_path = if trimmed.is_empty() { "/".to_string() } else { format!("/{trimmed}/") };
+        self
+    }
+
+    /// The error pages of `resources`, with their status and output path, by status
+    pub fn pages<R: Resource>(resources: &ResourceManager<R>) -> Vec<(u16, &R, PathBuf)> {
+        let mut pages = resources
+            .iter()
+            .filter_map(|(_, resource)| Some((error_status(resources.metadata(resource))?, resource, resources.output_path(resource))))
+            .collect::<Vec<_>>();
+        pages.sort_by_key(|(status, _, _)| *status);
+        pages
+    }
+
+    /// Writes what the host needs besides the pages to `output_dir` after the build: `.htaccess` for Apache.
+    /// Returns the files written
+    pub fn write<R: Resource>(&self, output_dir: &Path, resources: &ResourceManager<R>) -> Result<Vec<PathBuf>, ConfigurafoxError> {
+        if self.host != Host::Apache {
+            return Ok(vec![]);
+        }
+
+        let path = output_dir.join(".htaccess");
+        let existing = match std::fs::read_to_string(&path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut htaccess = existing.split(HTACCESS_MARKER).next().unwrap_or_default().to_string();
+        if !htaccess.is_empty() && !htaccess.ends_with('\n') {
+            htaccess.push('\n');
+        }
+        htaccess.push_str(HTACCESS_MARKER);
+        htaccess.push('\n');
+        for (status, resource, output) in ErrorPages::pages(resources) {
+            let base = resources.metadata(resource).get_str(BASE_PATH_KEY).unwrap_or(&self.base_path);
+            htaccess.push_str(&format!("ErrorDocument {status} {base}{}\n", crate::url_path(&output)?));
+        }
+        std::fs::write(&path, htaccess)?;
+        Ok(vec![path])
+    }
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Writes the error pages where `pages.host` serves them from, and sets their `BASE_PATH_KEY` to
+    /// `pages.base_path`, unless their metadata sets these already. Pages for a status the host has no custom
+    /// pages for are left where they are, with a warning
+    pub fn apply_error_pages(&mut self, pages: &ErrorPages) {
+        let found = ErrorPages::pages(self).into_iter().map(|(status, resource, _)| (status, resource.clone())).collect::<Vec<_>>();
+        for (status, resource) in found {
+            let Some(path) = pages.host.error_page_path(status) else {
+                warn!("{}: {:?} has no custom pages for status {status}", resource.identifier(), pages.host);
+                continue;
+            };
+            let Some(metadata) = self.metadata_mut(&resource) else { continue };
+            if metadata.get(OUTPUT_PATH_KEY).is_none() {
+                debug!("{}: Error page for {status}, writing it to {}", resource.identifier(), path.display());
+                metadata.insert(OUTPUT_PATH_KEY, path.to_string_lossy().as_ref());
+            }
+            if metadata.get(BASE_PATH_KEY).is_none() {
+                metadata.insert(BASE_PATH_KEY, pages.base_path.as_str());
+            }
+        }
+    }
+}
+
+/// Makes the relative URLs in the `treewalker::LINK_ATTRIBUTES` of the page written to `page` absolute paths
+/// starting with `base`, e.g. `../style.css` on `errors/404.html` becomes `/style.css`. URLs with a scheme,
+/// absolute paths and fragments are kept, and so are URLs leaving the site
+pub fn absolute_links(dom: &mut [Node], page: &Path, base: &str) -> Result<(), ConfigurafoxError> {
+    let rules = AttributeRules::links();
+    let dir = crate::url_path(page.parent().unwrap_or(Path::new("")))?;
+    for node in dom {
+        let Node::Element(Element { attrs, children, .. }) = node else { continue };
+        for (key, value) in attrs.iter_mut() {
+            *value = rules.rewrite(key, std::mem::take(value), |url| Ok::<_, ConfigurafoxError>(absolute_url(url, &dir, base)))?;
+        }
+        absolute_links(children, page, base)?;
+    }
+    Ok(())
+}
+
+/// `url` seen from the directory `dir` (a URL path without slashes at the ends), as an absolute path below `base`
+fn absolute_url(url: &str, dir: &str, base: &str) -> String {
+    let trimmed = url.trim();
+    let has_scheme = trimmed
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)));
+    if trimmed.is_empty() || has_scheme || trimmed.starts_with(['/', '#', '?']) {
+        return url.to_string();
+    }
+
+    let split = trimmed.find(['?', '#']).unwrap_or(trimmed.len());
+    let (path, suffix) = trimmed.split_at(split);
+    let mut segments = dir.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return url.to_string();
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let slash = if path.ends_with('/') && !segments.is_empty() { "/" } else { "" };
+    format!("{base}{}{slash}{suffix}", segments.join("/"))
+}
@@ -0,0 +1,188 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::metadata::Metadata;
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker};
+#[cfg(feature = "images")]
+use crate::ResourceProcessor;
+
+/// Metadata key of the resources `Favicons` registers, with the name of the file of `FAVICON_SET` they are
+pub const FAVICON_KEY: &str = "favicon";
+
+/// One file of a favicon set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaviconFile {
+    pub name: &'static str,
+    /// Square sizes, in pixels. More than one only for ICO files
+    pub sizes: &'static [u32],
+    /// The `rel` of its `<link>`, None for files only referenced from the web app manifest
+    pub rel: Option<&'static str>,
+}
+
+/// The files `Favicons` generates: what browsers, iOS and Android look for
+pub const FAVICON_SET: &[FaviconFile] = &[
+    FaviconFile { name: "favicon.ico", sizes: &[16, 32, 48], rel: Some("icon") },
+    FaviconFile { name: "favicon-16x16.png", sizes: &[16], rel: Some("icon") },
+    FaviconFile { name: "favicon-32x32.png", sizes: &[32], rel: Some("icon") },
+    FaviconFile { name: "apple-touch-icon.png", sizes: &[180], rel: Some("apple-touch-icon") },
+    FaviconFile { name: "android-chrome-192x192.png", sizes: &[192], rel: None },
+    FaviconFile { name: "android-chrome-512x512.png", sizes: &[512], rel: None },
+];
+
+impl FaviconFile {
+    pub fn by_name(name: &str) -> Option<&'static FaviconFile> {
+        FAVICON_SET.iter().find(|file| file.name == name)
+    }
+
+    pub fn is_ico(&self) -> bool {
+        self.name.ends_with(".ico")
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        if self.is_ico() { "image/x-icon" } else { "image/png" }
+    }
+
+    /// As in the `sizes` attribute, e.g. `16x16 32x32`
+    pub fn sizes_attr(&self) -> String {
+        self.sizes.iter().map(|size| format!("{size}x{size}")).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// The file of `FAVICON_SET` `resource` is, if `Favicons` registered it
+pub fn favicon_file<R: Resource>(resources: &ResourceManager<R>, resource: &R) -> Option<&'static FaviconFile> {
+    resources.metadata(resource).get_str(FAVICON_KEY).and_then(FaviconFile::by_name)
+}
+
+/// The resources `Favicons` registered, in the order of `FAVICON_SET`
+pub fn favicons<R: Resource>(resources: &ResourceManager<R>) -> Vec<(&R, &'static FaviconFile)> {
+    FAVICON_SET
+        .iter()
+        .flat_map(|file| resources.resources_with(FAVICON_KEY, file.name).into_iter().map(move |resource| (resource, file)))
+        .collect()
+}
+
+/// Registers the files of `FAVICON_SET` made from one square, high resolution source image (512x512 or more),
+/// to be processed by `FaviconProcessor`: every file is a resource read from the source, which the processor
+/// resizes and encodes as the file needs
+#[derive(Debug, Clone)]
+pub struct Favicons {
+    /// A PNG or JPEG, relative to the project root
+    pub source: PathBuf,
+}
+
+impl Favicons {
+    pub fn new<P: Into<PathBuf>>(source: P) -> Favicons {
+        Favicons { source: source.into() }
+    }
+
+    /// Registers the files under `dir`, usually the output root, with the resource `make_resource` gives for the
+    /// path of each, like `ResourceManager::register_all_files_in_directory` does. Files it gives None for are
+    /// skipped. Returns the registered resources
+    pub fn generate<R: Resource, F: Fn(&Path) -> Option<R>>(&self, resources: &mut ResourceManager<R>, dir: &Path, make_resource: F) -> Result<Vec<R>, ConfigurafoxError> {
+        if !resources.vfs().is_file(&self.source) {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not a file", self.source.display())).into());
+        }
+
+        let mut generated = Vec::new();
+        for file in FAVICON_SET {
+            let path = dir.join(file.name);
+            let Some(resource) = make_resource(&path) else {
+                continue;
+            };
+            debug!("{}: Adding favicon {} from {}", path.display(), file.name, self.source.display());
+
+            let mut metadata = Metadata::new();
+            metadata.insert(FAVICON_KEY, file.name);
+            resources.insert_with_origin(resource.clone(), self.source.clone(), Origin::File, metadata);
+            generated.push(resource);
+        }
+        Ok(generated)
+    }
+}
+
+/// Turns the source image of a resource `Favicons` registered into its file of the favicon set. Images that
+/// aren't square are cropped to their center
+#[cfg(feature = "images")]
+pub struct FaviconProcessor;
+
+#[cfg(feature = "images")]
+impl<R: Resource> ResourceProcessor<R> for FaviconProcessor {
+    fn name(&self) -> String {
+        "FaviconProcessor".to_string()
+    }
+
+    fn process_resource(&self, source: &R, source_path: &Path, resources: &ResourceManager<R>) -> Result<Vec<u8>, ConfigurafoxError> {
+        let image_error = |msg: String| ConfigurafoxError::Image { path: source_path.to_owned(), msg };
+        let file = favicon_file(resources, source)
+            .ok_or_else(|| image_error(format!("{} is not a favicon registered by Favicons", source.identifier())))?;
+        debug!("Making {} from {}", file.name, source_path.display());
+
+        let image = image::load_from_memory(&resources.read(source)?).map_err(|e| image_error(e.to_string()))?;
+        if image.width().min(image.height()) < file.sizes.iter().copied().max().unwrap_or(0) {
+            warn!("{}: {}x{} is too small for {}, it will be blurry", source_path.display(), image.width(), image.height(), file.name);
+        }
+        let resized = |size: u32| image.resize_to_fill(size, size, image::imageops::FilterType::Lanczos3).into_rgba8();
+
+        let mut out = Vec::new();
+        if file.is_ico() {
+            let frames = file
+                .sizes
+                .iter()
+                .map(|&size| image::codecs::ico::IcoFrame::as_png(&resized(size), size, size, image::ExtendedColorType::Rgba8))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| image_error(e.to_string()))?;
+            image::codecs::ico::IcoEncoder::new(&mut out).encode_images(&frames).map_err(|e| image_error(e.to_string()))?;
+        } else {
+            resized(file.sizes[0])
+                .write_to(&mut std::io::Cursor::new(&mut out), image::ImageFormat::Png)
+                .map_err(|e| image_error(e.to_string()))?;
+        }
+        Ok(out)
+    }
+}
+
+/// Replaces `<favicon-links/>`, which belongs in `<head>`, with a `<link>` to each file of the favicon set
+/// `Favicons` registered that pages link to
+pub struct FaviconLinks;
+
+impl<R: Resource, D> TreeWalker<R, D> for FaviconLinks {
+    fn describe(&self) -> String {
+        "FaviconLinks".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "favicon-links"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["favicon-links"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let favicons = favicons(ctx.resources);
+        if favicons.is_empty() {
+            return Err(ConfigurafoxError::NotRegistered { kind: "favicon", name: FAVICON_SET[0].name.to_string() });
+        }
+
+        let mut links = Vec::new();
+        for (resource, file) in favicons {
+            let Some(rel) = file.rel else {
+                continue;
+            };
+            let mut attrs = vec![("rel".to_string(), rel.to_string())];
+            if !file.is_ico() {
+                attrs.push(("type".to_string(), file.mime_type().to_string()));
+                attrs.push(("sizes".to_string(), file.sizes_attr()));
+            }
+            attrs.push(("href".to_string(), ctx.link_to(resource)?));
+            links.push(Node::Element(Element { name: "link".to_string(), attrs, children: vec![] }));
+        }
+        Ok(links)
+    }
+}
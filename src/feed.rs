@@ -0,0 +1,326 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::{ConfigurafoxError, slugify};
+use crate::calendar::{DATE_KEY, rfc822};
+use crate::git::CREATED_KEY;
+use crate::metadata::{Metadata, Value};
+use crate::podcast::{AUDIO_KEY, EPISODE_KEY, EXPLICIT_KEY, Podcast, SEASON_KEY, audio_info};
+use crate::query::tags_of;
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::site::{SiteData, absolute_url};
+use crate::treewalker::{Context, TreeWalker, get_attr, variable_text};
+
+/// Metadata key with the text of a feed item, besides its `title` and `calendar::DATE_KEY`
+pub const DESCRIPTION_KEY: &str = "description";
+
+/// Metadata keys of the feeds `FeedGenerator` generates: the collection, and for tag feeds the tag
+pub const FEED_COLLECTION_KEY: &str = "feed_collection";
+pub const FEED_TAG_KEY: &str = "feed_tag";
+
+/// Metadata key of a page listing a tag, whose `<feed-link/>` links the tag's feed
+pub const TAG_PAGE_KEY: &str = "tag";
+
+/// Where a feed is generated, relative to the directory given to `FeedGenerator::generate`: `feed.xml` for the
+/// main feed and `tags/<slug>/feed.xml` for the feed of a tag
+pub fn feed_path(tag: Option<&str>) -> PathBuf {
+    match tag {
+        Some(tag) => PathBuf::from(format!("tags/{}/feed.xml", slugify(tag))),
+        None => PathBuf::from("feed.xml"),
+    }
+}
+
+/// Generates an RSS feed of a collection, and optionally one for each tag its members have, see `feed_path`, as
+/// resources with their contents in memory. Links are absolute, so the site needs a `SiteData::base_url`.
+/// Generate after registering the members
+#[derive(Debug, Clone)]
+pub struct FeedGenerator {
+    pub collection: String,
+    /// The channel title. By default `SiteData::title`, or the collection name
+    pub title: Option<String>,
+    /// Whether to generate a feed per tag as well
+    pub per_tag: bool,
+    /// The most items in a feed, the first ones of the collection
+    pub limit: Option<usize>,
+    /// Makes the feeds podcast feeds, see `podcast_rss`
+    pub podcast: Option<Podcast>,
+}
+
+impl FeedGenerator {
+    pub fn new(collection: &str) -> FeedGenerator {
+        FeedGenerator { collection: collection.to_string(), title: None, per_tag: false, limit: None, podcast: None }
+    }
+
+    pub fn with_title(mut self, title: &str) -> FeedGenerator {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn per_tag(mut self, per_tag: bool) -> FeedGenerator {
+        self.per_tag = per_tag;
+        self
+    }
+
+    pub fn limit(mut self, n: usize) -> FeedGenerator {
+        self.limit = Some(n);
+        self
+    }
+
+    pub fn with_podcast(mut self, podcast: Podcast) -> FeedGenerator {
+        self.podcast = Some(podcast);
+        self
+    }
+
+    /// Registers the feeds under `dir`, with the resource `make_resource` gives for the path of each, like
+    /// `ResourceManager::register_all_files_in_directory` does. Feeds it gives None for are skipped.
+    /// Returns the registered resources
+    pub fn generate<R: Resource, D: SiteData + ?Sized, F: Fn(&Path) -> Option<R>>(
+        &self,
+        resources: &mut ResourceManager<R>,
+        site: &D,
+        dir: &Path,
+        make_resource: F,
+    ) -> Result<Vec<R>, ConfigurafoxError> {
+        let members = resources
+            .collection(&self.collection)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "collection", name: self.collection.clone() })?
+            .into_iter()
+            .map(|(_, resource)| resource.clone())
+            .collect::<Vec<_>>();
+
+        let mut feeds = vec![(None, members.clone())];
+        if self.per_tag {
+            let tags = members.iter().flat_map(|member| resources.tags(member)).collect::<BTreeSet<_>>();
+            for tag in tags {
+                let tagged = members.iter().filter(|member| resources.tags(member).contains(&tag)).cloned().collect();
+                feeds.push((Some(tag.to_string()), tagged));
+            }
+        }
+
+        let title = self.title.as_deref().or(site.title()).unwrap_or(&self.collection);
+        let mut generated = Vec::new();
+        for (tag, mut items) in feeds {
+            let path = dir.join(feed_path(tag.as_deref()));
+            let Some(resource) = make_resource(&path) else {
+                continue;
+            };
+            match &tag {
+                Some(tag) => info!("{}: Generating the feed of {:?} tagged {tag:?}", path.display(), self.collection),
+                None => info!("{}: Generating the feed of {:?}", path.display(), self.collection),
+            }
+
+            if let Some(limit) = self.limit {
+                items.truncate(limit);
+            }
+            let title = match &tag {
+                Some(tag) => format!("{title}: {tag}"),
+                None => title.to_string(),
+            };
+            let contents = match &self.podcast {
+                Some(podcast) => podcast_rss(resources, site, &title, &items, podcast)?,
+                None => rss(resources, site, &title, &items)?,
+            };
+
+            let mut metadata = Metadata::new();
+            metadata.insert("title", title.as_str());
+            metadata.insert(FEED_COLLECTION_KEY, self.collection.as_str());
+            if let Some(tag) = &tag {
+                metadata.insert(FEED_TAG_KEY, tag.as_str());
+            }
+            resources.insert_with_origin(resource.clone(), path, Origin::Memory(contents.into_bytes().into()), metadata);
+            generated.push(resource);
+        }
+        Ok(generated)
+    }
+}
+
+/// An RSS 2.0 document with an item for each of `items`, dated by their `calendar::DATE_KEY`, or else their
+/// `git::CREATED_KEY`
+pub fn rss<R: Resource, D: SiteData + ?Sized>(resources: &ResourceManager<R>, site: &D, title: &str, items: &[R]) -> Result<String, ConfigurafoxError> {
+    channel(resources, site, title, items, None)
+}
+
+/// Like `rss`, with the iTunes tags podcast directories read, and an `<enclosure>` with the audio file of each of
+/// `items`, see `podcast::AUDIO_KEY`. Items without one are left out
+pub fn podcast_rss<R: Resource, D: SiteData + ?Sized>(resources: &ResourceManager<R>, site: &D, title: &str, items: &[R], podcast: &Podcast) -> Result<String, ConfigurafoxError> {
+    channel(resources, site, title, items, Some(podcast))
+}
+
+fn channel<R: Resource, D: SiteData + ?Sized>(resources: &ResourceManager<R>, site: &D, title: &str, items: &[R], podcast: Option<&Podcast>) -> Result<String, ConfigurafoxError> {
+    let base_url = absolute_url(site, Path::new(""))?;
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    match podcast {
+        Some(_) => out.push_str("<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n<channel>\n"),
+        None => out.push_str("<rss version=\"2.0\">\n<channel>\n"),
+    }
+    out.push_str(&format!("<title>{}</title>\n<link>{}</link>\n", escape_xml(title), escape_xml(&base_url)));
+    out.push_str(&format!("<description>{}</description>\n", escape_xml(title)));
+    if let Some(podcast) = podcast {
+        out.push_str(&podcast_channel_tags(resources, site, podcast)?);
+    }
+
+    for item in items {
+        let metadata = resources.metadata(item);
+        let enclosure = match (podcast, metadata.get_str(AUDIO_KEY)) {
+            (Some(_), None) => {
+                debug!("Leaving {} out of the podcast feed, it has no {AUDIO_KEY}", item.identifier());
+                continue;
+            }
+            (Some(_), Some(identifier)) => Some(
+                resources
+                    .resource_by_identifier(identifier)
+                    .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: identifier.to_string() })?,
+            ),
+            (None, _) => None,
+        };
+
+        let url = absolute_url(site, &resources.output_path(item))?;
+        out.push_str("<item>\n");
+        out.push_str(&format!("<title>{}</title>\n", escape_xml(metadata.get_str("title").unwrap_or(&item.identifier()))));
+        out.push_str(&format!("<link>{0}</link>\n<guid>{0}</guid>\n", escape_xml(&url)));
+        if let Some(date) = metadata.get_str(DATE_KEY).or_else(|| metadata.get_str(CREATED_KEY)).and_then(rfc822) {
+            out.push_str(&format!("<pubDate>{date}</pubDate>\n"));
+        }
+        if let Some(description) = metadata.get_str(DESCRIPTION_KEY) {
+            out.push_str(&format!("<description>{}</description>\n", escape_xml(description)));
+        }
+        for tag in tags_of(metadata) {
+            out.push_str(&format!("<category>{}</category>\n", escape_xml(tag)));
+        }
+        if let Some(audio) = enclosure {
+            let info = audio_info(resources, audio)?;
+            let audio_url = absolute_url(site, &resources.output_path(audio))?;
+            out.push_str(&format!("<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n", escape_xml(&audio_url), info.size, info.mime_type));
+            if let Some(duration) = info.duration {
+                out.push_str(&format!("<itunes:duration>{}</itunes:duration>\n", duration.round() as u64));
+            }
+            for (tag, key) in [("itunes:episode", EPISODE_KEY), ("itunes:season", SEASON_KEY)] {
+                if let Some(number) = metadata.get(key).and_then(variable_text) {
+                    out.push_str(&format!("<{tag}>{}</{tag}>\n", escape_xml(&number)));
+                }
+            }
+            if let Some(explicit) = metadata.get(EXPLICIT_KEY).and_then(Value::as_bool) {
+                out.push_str(&format!("<itunes:explicit>{explicit}</itunes:explicit>\n"));
+            }
+        }
+        out.push_str("</item>\n");
+    }
+
+    out.push_str("</channel>\n</rss>\n");
+    Ok(out)
+}
+
+fn podcast_channel_tags<R: Resource, D: SiteData + ?Sized>(resources: &ResourceManager<R>, site: &D, podcast: &Podcast) -> Result<String, ConfigurafoxError> {
+    let mut out = String::new();
+    if let Some(language) = &podcast.language {
+        out.push_str(&format!("<language>{}</language>\n", escape_xml(language)));
+    }
+    if let Some(author) = &podcast.author {
+        out.push_str(&format!("<itunes:author>{}</itunes:author>\n", escape_xml(author)));
+    }
+    if let Some(image) = &podcast.image {
+        let url = if image.contains("://") {
+            image.clone()
+        } else {
+            let resource = resources
+                .resource_by_identifier(image)
+                .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: image.clone() })?;
+            absolute_url(site, &resources.output_path(resource))?
+        };
+        out.push_str(&format!("<itunes:image href=\"{}\"/>\n", escape_xml(&url)));
+    }
+    for category in &podcast.categories {
+        match category.split_once('/') {
+            Some((category, subcategory)) => out.push_str(&format!(
+                "<itunes:category text=\"{}\">\n<itunes:category text=\"{}\"/>\n</itunes:category>\n",
+                escape_xml(category),
+                escape_xml(subcategory),
+            )),
+            None => out.push_str(&format!("<itunes:category text=\"{}\"/>\n", escape_xml(category))),
+        }
+    }
+    out.push_str(&format!("<itunes:explicit>{}</itunes:explicit>\n", podcast.explicit));
+    if let Some((name, email)) = &podcast.owner {
+        out.push_str(&format!(
+            "<itunes:owner>\n<itunes:name>{}</itunes:name>\n<itunes:email>{}</itunes:email>\n</itunes:owner>\n",
+            escape_xml(name),
+            escape_xml(email),
+        ));
+    }
+    Ok(out)
+}
+
+/// `text` escaped for XML text and attribute values. Unlike the `entities` functions, without HTML's named
+/// references
+fn escape_xml(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"']) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;"))
+}
+
+/// Replaces `<feed-link/>` with a `<link rel="alternate">` to the feed `FeedGenerator` generated of a collection.
+/// On a page with `TAG_PAGE_KEY` metadata, or with a `tag` attribute, to the feed of that tag. The `collection`
+/// attribute overrides the collection
+pub struct FeedLink {
+    /// Linked unless the element says otherwise
+    pub collection: String,
+}
+
+impl FeedLink {
+    pub fn new(collection: &str) -> FeedLink {
+        FeedLink { collection: collection.to_string() }
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for FeedLink {
+    fn describe(&self) -> String {
+        format!("FeedLink({})", self.collection)
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "feed-link"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["feed-link"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let collection = get_attr(&attrs, "collection").unwrap_or(&self.collection);
+        let tag = get_attr(&attrs, "tag").or_else(|| ctx.metadata().get_str(TAG_PAGE_KEY));
+
+        let feed = ctx
+            .resources
+            .iter()
+            .map(|(_, resource)| resource)
+            .find(|resource| {
+                let metadata = ctx.resources.metadata(resource);
+                metadata.get_str(FEED_COLLECTION_KEY) == Some(collection) && metadata.get_str(FEED_TAG_KEY) == tag
+            })
+            .ok_or_else(|| ConfigurafoxError::NotRegistered {
+                kind: "feed",
+                name: tag.map_or_else(|| collection.to_string(), |tag| format!("{collection} tagged {tag}")),
+            })?;
+
+        let title = ctx.resources.metadata(feed).get_str("title").unwrap_or(collection).to_string();
+        Ok(vec![
+            Node::Element(Element {
+                name: "link".to_string(),
+                attrs: vec![
+                    ("rel".to_string(), "alternate".to_string()),
+                    ("type".to_string(), "application/rss+xml".to_string()),
+                    ("title".to_string(), title),
+                    ("href".to_string(), ctx.link_to(feed)?),
+                ],
+                children: vec![],
+            }),
+        ])
+    }
+}
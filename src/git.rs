@@ -0,0 +1,239 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::calendar::long_date;
+use crate::jsonld::UPDATED_KEY;
+use crate::metadata::Value;
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker};
+
+/// Metadata key with when a source file was first committed, see `ResourceManager::apply_git_history`.
+/// `jsonld::UPDATED_KEY` gets when it was last committed
+pub const CREATED_KEY: &str = "created";
+
+/// Metadata key with who committed to a source file, an array of tables with their `name`, `email` and number of
+/// `commits`, most commits first
+pub const CONTRIBUTORS_KEY: &str = "contributors";
+
+/// Runs `git` in `repo`, returning what it wrote to stdout
+fn git(repo: &Path, args: &[&str]) -> Result<String, ConfigurafoxError> {
+    let git_error = |msg: String| ConfigurafoxError::Git { repo: repo.to_owned(), msg };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "core.quotePath=false"])
+        .args(args)
+        .output()
+        .map_err(|e| git_error(format!("could not run git: {e}")))?;
+    if !output.status.success() {
+        return Err(git_error(format!("git {} failed with {}: {}", args[0], output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    String::from_utf8(output.stdout).map_err(|_| git_error("git wrote invalid UTF-8".to_string()))
+}
+
+/// Someone who committed changes to a file, identified by their email
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contributor {
+    /// The name of their latest commit
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+}
+
+/// What the history of a file says about it
+#[derive(Debug, Clone, Default)]
+pub struct FileHistory {
+    /// The date of the first commit touching the file
+    pub created: String,
+    /// The date of the last
+    pub updated: String,
+    /// Most commits first, then by name
+    pub contributors: Vec<Contributor>,
+}
+
+/// The history of the files below a directory in the git repository containing it, read once with the `git`
+/// command. Dates are the commits' author dates, in ISO 8601, and contributors the commits' authors. Renames
+/// aren't followed, so a moved file counts as created when it was moved, and uncommitted changes don't count
+#[derive(Debug, Clone, Default)]
+pub struct GitHistory {
+    /// The root of the repository
+    pub toplevel: PathBuf,
+    /// By path relative to `toplevel`
+    pub files: HashMap<PathBuf, FileHistory>,
+}
+
+impl GitHistory {
+    /// The history of the files below `dir`
+    pub fn load(dir: &Path) -> Result<GitHistory, ConfigurafoxError> {
+        let toplevel = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim_end());
+        let log = git(dir, &["log", "--format=%x00%aI%x00%aN%x00%aE", "--name-only", "--no-renames", "--", "."])?;
+
+        // Newest first, so the first commit seen for a file is its last, and the last its first
+        let mut files = HashMap::<PathBuf, FileHistory>::new();
+        let (mut date, mut name, mut email) = ("", "", "");
+        for line in log.lines() {
+            if let Some(commit) = line.strip_prefix('\0') {
+                let mut fields = commit.split('\0');
+                (date, name, email) = (fields.next().unwrap_or_default(), fields.next().unwrap_or_default(), fields.next().unwrap_or_default());
+            } else if !line.is_empty() {
+                let file = files.entry(PathBuf::from(line)).or_insert_with(|| FileHistory { updated: date.to_string(), ..FileHistory::default() });
+                file.created = date.to_string();
+                match file.contributors.iter_mut().find(|contributor| contributor.email == email) {
+                    Some(contributor) => contributor.commits += 1,
+                    None => file.contributors.push(Contributor { name: name.to_string(), email: email.to_string(), commits: 1 }),
+                }
+            }
+        }
+        for file in files.values_mut() {
+            file.contributors.sort_by(|a, b| b.commits.cmp(&a.commits).then_with(|| a.name.cmp(&b.name)));
+        }
+        debug!("Read the git history of {} files below {}", files.len(), dir.display());
+        Ok(GitHistory { toplevel, files })
+    }
+
+    /// The history of the file at `path`, absolute or relative to the current directory
+    pub fn of(&self, path: &Path) -> Option<&FileHistory> {
+        let path = path.canonicalize().ok()?;
+        let toplevel = self.toplevel.canonicalize().ok()?;
+        self.files.get(path.strip_prefix(toplevel).ok()?)
+    }
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Sets the `CREATED_KEY` and `jsonld::UPDATED_KEY` metadata of the resources read from files to when they
+    /// were first and last committed to git, and `CONTRIBUTORS_KEY` to who committed to them, unless they're set
+    /// already, e.g. in front matter. Files that were never committed are left as they are
+    pub fn apply_git_history(&mut self, history: &GitHistory) {
+        let mut found = Vec::new();
+        for (path, resource) in self.iter() {
+            if !matches!(self.origin(resource), Some(Origin::File)) {
+                continue;
+            }
+            match self.vfs().physical_path(path).and_then(|path| history.of(&path)) {
+                Some(file) => found.push((resource.clone(), file)),
+                None => trace!("{}: Not in git", path.display()),
+            }
+        }
+
+        for (resource, file) in found {
+            let Some(metadata) = self.metadata_mut(&resource) else { continue };
+            if metadata.get(CREATED_KEY).is_none() {
+                metadata.insert(CREATED_KEY, file.created.as_str());
+            }
+            if metadata.get(UPDATED_KEY).is_none() {
+                metadata.insert(UPDATED_KEY, file.updated.as_str());
+            }
+            if metadata.get(CONTRIBUTORS_KEY).is_none() {
+                let contributors = file
+                    .contributors
+                    .iter()
+                    .map(|contributor| serde_json::json!({ "name": contributor.name, "email": contributor.email, "commits": contributor.commits }))
+                    .collect::<Vec<_>>();
+                metadata.insert(CONTRIBUTORS_KEY, Value::Array(contributors));
+            }
+        }
+    }
+}
+
+/// Replaces `<last-updated/>` with a `<time>` of the page's `jsonld::UPDATED_KEY`, e.g. `October 16, 2026`,
+/// after the element's children, like `<last-updated>Last updated </last-updated>`. Pages without the key get
+/// nothing. See `ResourceManager::apply_git_history` for setting it from git
+pub struct LastUpdated;
+
+impl<R: Resource, D> TreeWalker<R, D> for LastUpdated {
+    fn describe(&self) -> String {
+        "LastUpdated".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "last-updated"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["last-updated"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, mut children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let Some(updated) = ctx.metadata().get_str(UPDATED_KEY) else {
+            return Ok(vec![]);
+        };
+        let text = long_date(updated).unwrap_or_else(|| updated.to_string());
+        children.push(Node::Element(Element {
+            name: "time".to_string(),
+            attrs: vec![("datetime".to_string(), updated.to_string())],
+            children: vec![Node::Text(text)],
+        }));
+        Ok(children)
+    }
+}
+
+/// Replaces `<contributors/>` with a `<ul class="contributors">` of the people who committed to the page's source,
+/// from its `CONTRIBUTORS_KEY`, most commits first. Names link to the profile given for their email or name, if
+/// any. Pages without contributors get nothing
+#[derive(Debug, Clone, Default)]
+pub struct Contributors {
+    /// Profile URLs by email or name
+    pub profiles: BTreeMap<String, String>,
+}
+
+impl Contributors {
+    pub fn new() -> Contributors {
+        Contributors::default()
+    }
+
+    /// Links the contributor with the email or name `who` to `url`
+    pub fn with_profile(mut self, who: &str, url: &str) -> Contributors {
+        self.profiles.insert(who.to_string(), url.to_string());
+        self
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for Contributors {
+    fn describe(&self) -> String {
+        "Contributors".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "contributors"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["contributors"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let Some(Value::Array(contributors)) = ctx.metadata().get(CONTRIBUTORS_KEY) else {
+            return Ok(vec![]);
+        };
+
+        let mut items = Vec::new();
+        for contributor in contributors {
+            let field = |key| contributor.get(key).and_then(Value::as_str);
+            let Some(name) = field("name") else { continue };
+            let profile = field("email").and_then(|email| self.profiles.get(email)).or_else(|| self.profiles.get(name));
+
+            let name = Node::Text(name.to_string());
+            let child = match profile {
+                Some(url) => Node::Element(Element { name: "a".to_string(), attrs: vec![("href".to_string(), url.clone())], children: vec![name] }),
+                None => name,
+            };
+            let attrs = match contributor.get("commits").and_then(Value::as_u64) {
+                Some(1) => vec![("title".to_string(), "1 commit".to_string())],
+                Some(commits) => vec![("title".to_string(), format!("{commits} commits"))],
+                None => vec![],
+            };
+            items.push(Node::Element(Element { name: "li".to_string(), attrs, children: vec![child] }));
+        }
+        if items.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![Node::Element(Element { name: "ul".to_string(), attrs: vec![("class".to_string(), "contributors".to_string())], children: items })])
+    }
+}
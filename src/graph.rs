@@ -0,0 +1,133 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ConfigurafoxError;
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::attr_references;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "state", derive(serde::Serialize, serde::Deserialize))]
+pub enum DependencyKind {
+    /// An `@`-reference: only the target's output path ends up in the dependent's output
+    Link,
+    /// The target's contents end up in the dependent's output (includes, inlined SVGs, code snippets, ...)
+    Include,
+}
+
+/// Which resources reference which others. Edges are recorded by walkers while processing, and are
+/// used to find out what needs rebuilding when a source changes
+#[derive(Debug, Clone)]
+pub struct DependencyGraph<R: Resource> {
+    /// from -> to
+    edges: HashMap<R, HashSet<(R, DependencyKind)>>,
+}
+
+impl<R: Resource> Default for DependencyGraph<R> {
+    fn default() -> Self {
+        DependencyGraph { edges: HashMap::new() }
+    }
+}
+
+impl<R: Resource> DependencyGraph<R> {
+    pub fn new() -> DependencyGraph<R> {
+        DependencyGraph::default()
+    }
+
+    pub fn add(&mut self, from: &R, to: &R, kind: DependencyKind) {
+        if from == to {
+            return;
+        }
+        trace!("{} depends on {} ({:?})", from.identifier(), to.identifier(), kind);
+        self.edges.entry(from.clone()).or_default().insert((to.clone(), kind));
+    }
+
+    /// Forgets everything `resource` depends on, e.g. before it is processed again
+    pub fn clear_dependencies_of(&mut self, resource: &R) {
+        self.edges.remove(resource);
+    }
+
+    /// Forgets `resource` entirely, both as a dependent and as a dependency
+    pub fn remove(&mut self, resource: &R) {
+        self.edges.remove(resource);
+        for deps in self.edges.values_mut() {
+            deps.retain(|(to, _)| to != resource);
+        }
+    }
+
+    /// What `resource` references directly
+    pub fn dependencies_of(&self, resource: &R) -> impl Iterator<Item = (&R, DependencyKind)> {
+        self.edges
+            .get(resource)
+            .into_iter()
+            .flatten()
+            .map(|(to, kind)| (to, *kind))
+    }
+
+    /// What references `resource` directly, i.e. its backlinks
+    pub fn dependents_of<'a>(&'a self, resource: &'a R) -> impl Iterator<Item = (&'a R, DependencyKind)> + 'a {
+        self.edges
+            .iter()
+            .flat_map(move |(from, deps)| {
+                deps.iter()
+                    .filter(move |(to, _)| to == resource)
+                    .map(move |(_, kind)| (from, *kind))
+            })
+    }
+
+    /// Every resource whose output may change when `changed` changes: `changed` itself, and everything
+    /// that (transitively) includes it. Links don't propagate, as a linking page only depends on the output path
+    pub fn affected_by(&self, changed: &R) -> HashSet<R> {
+        let mut affected = HashSet::new();
+        let mut queue = vec![changed.clone()];
+
+        while let Some(current) = queue.pop() {
+            if !affected.insert(current.clone()) {
+                continue;
+            }
+
+            for (from, deps) in &self.edges {
+                if deps.contains(&(current.clone(), DependencyKind::Include)) {
+                    queue.push(from.clone());
+                }
+            }
+        }
+
+        affected
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (&R, &R, DependencyKind)> {
+        self.edges
+            .iter()
+            .flat_map(|(from, deps)| deps.iter().map(move |(to, kind)| (from, to, *kind)))
+    }
+}
+
+/// Records a `Link` dependency for every `@identifier` attribute in the resources selected by `should_scan`,
+/// which must be HTML files. Run this before `run` if a page needs to know what links to it (e.g. for
+/// `BacklinksWalker`), as dependencies are otherwise only known for pages that have already been processed
+pub fn scan_links<R: Resource, F: Fn(&std::path::Path, &R) -> bool>(
+    resources: &ResourceManager<R>,
+    should_scan: F,
+) -> Result<(), ConfigurafoxError> {
+    for (path, resource) in resources.iter() {
+        if !should_scan(path, resource) {
+            continue;
+        }
+
+        debug!("Scanning {} for links", path.display());
+
+        let source = resources.read_to_string(resource)?;
+        let body = crate::metadata::strip_front_matter(&source);
+        let dom = html_editor::parse(body).map_err(|e| ConfigurafoxError::parse_html(path, body, crate::metadata::body_first_line(&source), e))?;
+
+        for identifier in attr_references(&dom, '@') {
+            if let Some(target) = resources.resource_by_identifier(&identifier) {
+                resources.record_dependency(resource, target, DependencyKind::Link);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,378 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+use serde::{Deserialize, Serialize};
+
+use crate::{ConfigurafoxError, slugify};
+use crate::output::confine_output;
+use crate::resource_manager::Resource;
+use crate::treewalker::{Context, TreeWalker, get_attr, text_content};
+
+/// Where `HeadingAnchors` keeps the anchors of each page by default, relative to the project root
+pub const DEFAULT_ANCHORS_DIR: &str = ".configurafox-cache/anchors";
+
+/// How similar the new text of a heading must be to the old one by default for it to keep its old anchor, see
+/// `similarity`
+pub const DEFAULT_SIMILARITY: f64 = 0.5;
+
+/// Marks a heading `HeadingShift` shifted with the level it was written as, until `HeadingShift::finish`
+const SHIFTED_FROM_ATTR: &str = "data-shifted-from";
+
+/// The level of `<h1>` to `<h6>`
+pub fn heading_level(tag_name: &str) -> Option<u8> {
+    match tag_name.as_bytes() {
+        [b'h', level @ b'1'..=b'6'] => Some(level - b'0'),
+        _ => None,
+    }
+}
+
+/// Whether the page has shifted headings for `HeadingShift::finish` to check
+#[derive(Clone, Copy)]
+struct ShiftedHeadings;
+
+/// Replaces `<shift-headings by="2">` with its children, their headings shifted two levels down, so content
+/// written on its own with an `<h1>`, like a partial, nests as `<h3>` in a page. `to="3"` shifts the highest
+/// heading inside to `<h3>` and the others along with it instead, and negative `by`s shift up. Without either,
+/// headings are shifted by the walker's `by`, 1 by default. Shifts by `by` inside another `<shift-headings>` add
+/// to its shift, while `to` is always the level the highest heading ends up at. Levels are kept between `<h1>` and
+/// `<h6>`, with a warning
+///
+/// Once the page is finished, shifted headings more than one level below the heading before them are warned
+/// about, e.g. an `<h1>` shifted to `<h4>` after an `<h2>`
+pub struct HeadingShift {
+    pub by: i8,
+}
+
+impl Default for HeadingShift {
+    fn default() -> Self {
+        HeadingShift { by: 1 }
+    }
+}
+
+impl HeadingShift {
+    pub fn new() -> HeadingShift {
+        HeadingShift::default()
+    }
+
+    pub fn with_by(mut self, by: i8) -> HeadingShift {
+        self.by = by;
+        self
+    }
+
+    /// How far the `<shift-headings>` with `attrs` around `children` shifts, inside one shifting by `outer`
+    fn offset(&self, attrs: &[(String, String)], children: &[Node], outer: i8) -> Result<i8, ConfigurafoxError> {
+        let parse = |key: &str, value: &str| {
+            value.trim().parse::<i8>().map_err(|_| ConfigurafoxError::MalformedAttrs { key_name: key.to_string(), msg: format!("{value:?} isn't a number") })
+        };
+        match (get_attr(attrs, "by"), get_attr(attrs, "to")) {
+            (Some(_), Some(_)) => Err(ConfigurafoxError::MalformedAttrs { key_name: "to".to_string(), msg: "give either by or to".to_string() }),
+            (Some(by), None) => Ok(outer.saturating_add(parse("by", by)?)),
+            (None, Some(to)) => {
+                let to = parse("to", to)?;
+                if !(1..=6).contains(&to) {
+                    return Err(ConfigurafoxError::MalformedAttrs { key_name: "to".to_string(), msg: format!("there is no <h{to}>") });
+                }
+                Ok(highest_level(children).map_or(0, |highest| to - highest as i8))
+            }
+            (None, None) => Ok(outer.saturating_add(self.by)),
+        }
+    }
+
+    fn shift<R: Resource, D>(&self, nodes: &mut Vec<Node>, offset: i8, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        for node in std::mem::take(nodes) {
+            let Node::Element(mut element) = node else {
+                nodes.push(node);
+                continue;
+            };
+            if element.name == "shift-headings" {
+                let inner = self.offset(&element.attrs, &element.children, offset)?;
+                self.shift(&mut element.children, inner, ctx)?;
+                nodes.extend(element.children);
+                continue;
+            }
+            if let Some(level) = heading_level(&element.name) {
+                let shifted = (level as i8).saturating_add(offset);
+                let clamped = shifted.clamp(1, 6) as u8;
+                if shifted != clamped as i8 {
+                    ctx.warn(format!("<h{level}> shifted by {offset} is kept at <h{clamped}>"));
+                }
+                if clamped != level {
+                    element.name = format!("h{clamped}");
+                    if get_attr(&element.attrs, SHIFTED_FROM_ATTR).is_none() {
+                        element.attrs.push((SHIFTED_FROM_ATTR.to_string(), level.to_string()));
+                    }
+                    ctx.extensions.insert(ShiftedHeadings);
+                }
+            }
+            self.shift(&mut element.children, offset, ctx)?;
+            nodes.push(Node::Element(element));
+        }
+        Ok(())
+    }
+}
+
+/// The level of the highest heading in `dom`, `<h1>` being the highest
+fn highest_level(dom: &[Node]) -> Option<u8> {
+    dom.iter()
+        .filter_map(|node| match node {
+            Node::Element(Element { name, children, .. }) => heading_level(name).into_iter().chain(highest_level(children)).min(),
+            _ => None,
+        })
+        .min()
+}
+
+/// Warns about shifted headings that skip levels, removing their marks. `last` is the level of the heading before
+fn check_shifted<R: Resource, D>(dom: &mut [Node], last: &mut Option<u8>, ctx: Context<'_, '_, R, D>) {
+    for node in dom {
+        let Node::Element(Element { name, attrs, children }) = node else { continue };
+        if let Some(level) = heading_level(name) {
+            let shifted_from = attrs.iter().position(|(key, _)| key == SHIFTED_FROM_ATTR).map(|index| attrs.remove(index).1);
+            if let (Some(from), Some(previous)) = (shifted_from, *last) {
+                if level > previous + 1 {
+                    ctx.warn(format!("<h{from}> shifted to <h{level}> follows <h{previous}>, skipping a level"));
+                }
+            }
+            *last = Some(level);
+        }
+        check_shifted(children, last, ctx);
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for HeadingShift {
+    fn describe(&self) -> String {
+        format!("HeadingShift({})", self.by)
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "shift-headings"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["shift-headings"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, mut children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let offset = self.offset(&attrs, &children, 0)?;
+        self.shift(&mut children, offset, ctx)?;
+        Ok(children)
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        if ctx.extensions.remove::<ShiftedHeadings>().is_some() {
+            check_shifted(dom, &mut None, ctx);
+        }
+        Ok(())
+    }
+}
+
+/// A heading ID `HeadingAnchors` generated, as kept for the next build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedAnchor {
+    pub id: String,
+    /// The text of the heading, to recognize it by once it changes
+    pub text: String,
+    /// The IDs the heading had in earlier builds, which still link to it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous: Vec<String>,
+}
+
+/// How alike the texts `a` and `b` are, from 0 to 1: the Sørensen–Dice coefficient of the letter pairs of their
+/// slugs, so case, punctuation and spacing don't count
+pub fn similarity(a: &str, b: &str) -> f64 {
+    fn pairs(text: &str) -> Vec<(char, char)> {
+        let chars = slugify(text).chars().collect::<Vec<_>>();
+        let mut pairs = chars.windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    let (a, b) = (pairs(a), pairs(b));
+    if a.is_empty() || b.is_empty() {
+        return if a == b { 1.0 } else { 0.0 };
+    }
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+/// Gives headings without an `id` one made from their text, like `id="getting-started"`, so they can be linked to.
+/// The IDs of each page are kept in a file below `dir` for the next build, with the text of their headings. When
+/// the text of a heading changes a little, so that its ID does too, the old ID is kept as an empty
+/// `<span id="…">` at the start of the heading, and links made before the change still lead to it. Headings count
+/// as the same if their texts are at least `min_similarity` alike, see `similarity`
+pub struct HeadingAnchors {
+    /// Relative to the project root
+    pub dir: PathBuf,
+    pub min_similarity: f64,
+}
+
+impl Default for HeadingAnchors {
+    fn default() -> Self {
+        HeadingAnchors::new(DEFAULT_ANCHORS_DIR)
+    }
+}
+
+impl HeadingAnchors {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> HeadingAnchors {
+        HeadingAnchors { dir: dir.into(), min_similarity: DEFAULT_SIMILARITY }
+    }
+
+    pub fn with_min_similarity(mut self, min_similarity: f64) -> HeadingAnchors {
+        self.min_similarity = min_similarity;
+        self
+    }
+
+    /// The anchors recorded at `path`, none if there is no such file
+    pub fn load(path: &Path) -> Result<Vec<RecordedAnchor>, ConfigurafoxError> {
+        match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| ConfigurafoxError::Other(format!("{}: {e}", path.display()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(path: &Path, anchors: &[RecordedAnchor]) -> Result<(), ConfigurafoxError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(anchors).map_err(std::io::Error::other)?;
+        Ok(std::fs::write(path, json)?)
+    }
+
+    /// Carries the earlier IDs of `recorded` over to the `generated` anchors of this build: to the one with the
+    /// same ID, or else to the most similar one that is new
+    fn carry_over(&self, recorded: &[RecordedAnchor], generated: &mut [RecordedAnchor]) {
+        let current = generated.iter().map(|anchor| anchor.id.clone()).collect::<HashSet<_>>();
+        let mut gone = Vec::new();
+        for old in recorded {
+            match generated.iter_mut().find(|anchor| anchor.id == old.id) {
+                Some(anchor) => anchor.previous = old.previous.clone(),
+                None => gone.push(old),
+            }
+        }
+
+        let known = recorded.iter().map(|anchor| anchor.id.as_str()).collect::<HashSet<_>>();
+        let mut candidates = Vec::new();
+        for (i, old) in gone.iter().enumerate() {
+            for (j, anchor) in generated.iter().enumerate().filter(|(_, anchor)| !known.contains(anchor.id.as_str())) {
+                let similarity = similarity(&old.text, &anchor.text);
+                if similarity >= self.min_similarity {
+                    candidates.push((similarity, i, j));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let (mut matched_old, mut matched_new) = (HashSet::new(), HashSet::new());
+        for (_, i, j) in candidates {
+            if matched_old.contains(&i) || matched_new.contains(&j) {
+                continue;
+            }
+            matched_old.insert(i);
+            matched_new.insert(j);
+            let old = gone[i];
+            debug!("Heading {:?} was {:?}, keeping #{}", generated[j].text, old.text, old.id);
+            generated[j].previous = std::iter::once(&old.id)
+                .chain(&old.previous)
+                .filter(|id| !current.contains(*id))
+                .cloned()
+                .collect();
+        }
+    }
+}
+
+/// Collects the IDs in `dom`
+fn collect_ids(dom: &[Node], ids: &mut HashSet<String>) {
+    for node in dom {
+        let Node::Element(Element { attrs, children, .. }) = node else { continue };
+        if let Some(id) = get_attr(attrs, "id") {
+            ids.insert(id.to_string());
+        }
+        collect_ids(children, ids);
+    }
+}
+
+/// Gives the headings in `dom` without an ID one, unique among `ids`, recording them in `generated`
+fn generate_ids(dom: &mut [Node], ids: &mut HashSet<String>, generated: &mut Vec<RecordedAnchor>) {
+    for node in dom {
+        let Node::Element(Element { name, attrs, children }) = node else { continue };
+        if heading_level(name).is_none() || get_attr(attrs, "id").is_some() {
+            generate_ids(children, ids, generated);
+            continue;
+        }
+        let text = text_content(children).split_whitespace().collect::<Vec<_>>().join(" ");
+        let slug = slugify(&text);
+        let id = (1..).map(|n| if n == 1 { slug.clone() } else { format!("{slug}-{n}") }).find(|id| !ids.contains(id)).unwrap_or(slug);
+        ids.insert(id.clone());
+        attrs.push(("id".to_string(), id.clone()));
+        generated.push(RecordedAnchor { id, text, previous: vec![] });
+    }
+}
+
+/// Puts the earlier IDs of the `generated` anchors at the start of their headings, unless something else in the
+/// page has them now
+fn add_previous(dom: &mut [Node], generated: &[RecordedAnchor], taken: &mut HashSet<String>) {
+    for node in dom {
+        let Node::Element(Element { name, attrs, children }) = node else { continue };
+        let anchor = get_attr(attrs, "id").filter(|_| heading_level(name).is_some()).and_then(|id| generated.iter().find(|anchor| anchor.id == id));
+        let Some(anchor) = anchor else {
+            add_previous(children, generated, taken);
+            continue;
+        };
+        let spans = anchor.previous.iter().filter(|id| taken.insert(id.to_string())).map(|id| {
+            Node::Element(Element { name: "span".to_string(), attrs: vec![("id".to_string(), id.clone())], children: vec![] })
+        });
+        children.splice(0..0, spans.collect::<Vec<_>>());
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for HeadingAnchors {
+    fn describe(&self) -> String {
+        format!("HeadingAnchors({})", self.dir.display())
+    }
+
+    fn matches(&self, _tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        false
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec![])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, children: Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok(children)
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        let mut ids = HashSet::new();
+        collect_ids(dom, &mut ids);
+        let mut generated = Vec::new();
+        generate_ids(dom, &mut ids, &mut generated);
+
+        let mut file = ctx.resources.absolute_path(&self.dir).join(confine_output(&ctx.resources.output_path(ctx.resource))?);
+        file.as_mut_os_string().push(".json");
+        let recorded = HeadingAnchors::load(&file)?;
+        self.carry_over(&recorded, &mut generated);
+        add_previous(dom, &generated, &mut ids);
+
+        if generated != recorded {
+            trace!("{}: {} heading anchors", file.display(), generated.len());
+            HeadingAnchors::save(&file, &generated)?;
+        }
+        Ok(())
+    }
+}
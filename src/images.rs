@@ -0,0 +1,563 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "images")]
+use html_editor::{Element, Node};
+
+use crate::{ConfigurafoxError, ResourceProcessor};
+#[cfg(feature = "images")]
+use crate::graph::DependencyKind;
+use crate::resource_manager::{Resource, ResourceManager};
+#[cfg(feature = "images")]
+use crate::treewalker::{Context, TreeWalker, get_attr};
+
+/// Extensions of the images `strip_metadata` understands
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// EXIF tags of the first IFD, the one describing the image itself, that `strip_metadata` can keep, by name
+pub const EXIF_TAGS: &[(&str, u16)] = &[
+    ("ImageDescription", 0x010E),
+    ("Make", 0x010F),
+    ("Model", 0x0110),
+    ("Orientation", 0x0112),
+    ("XResolution", 0x011A),
+    ("YResolution", 0x011B),
+    ("ResolutionUnit", 0x0128),
+    ("Software", 0x0131),
+    ("DateTime", 0x0132),
+    ("Artist", 0x013B),
+    ("Copyright", 0x8298),
+];
+
+/// Which way up the image is, without which many photos show sideways
+pub const ORIENTATION: u16 = 0x0112;
+
+/// The EXIF tags kept by default
+pub const DEFAULT_KEPT_EXIF: &[u16] = &[ORIENTATION];
+
+pub fn exif_tag(name: &str) -> Option<u16> {
+    EXIF_TAGS.iter().find(|(tag_name, _)| *tag_name == name).map(|(_, tag)| *tag)
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// `image` without the metadata cameras and editors embed: EXIF, with the location, time and camera serial
+/// number of a photo and a thumbnail of the uncropped original, XMP, IPTC, comments and text chunks. Only the
+/// EXIF tags in `keep` are kept, and only those of the first IFD, see `EXIF_TAGS`. Color profiles are kept, and
+/// the pixels aren't touched. JPEG, PNG and WebP images are recognized by their contents, others are returned
+/// as they are
+pub fn strip_metadata(path: &Path, image: &[u8], keep: &[u16]) -> Result<Vec<u8>, ConfigurafoxError> {
+    let stripped = if image.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg(image, keep)
+    } else if image.starts_with(PNG_SIGNATURE) {
+        strip_png(image, keep)
+    } else if image.starts_with(b"RIFF") && image.get(8..12) == Some(b"WEBP") {
+        strip_webp(image, keep)
+    } else {
+        debug!("{}: Not a JPEG, PNG or WebP, keeping its metadata", path.display());
+        return Ok(image.to_vec());
+    };
+    let stripped = stripped.map_err(|msg| ConfigurafoxError::Image { path: path.to_owned(), msg })?;
+    debug!("{}: Stripped {} bytes of metadata", path.display(), image.len().saturating_sub(stripped.len()));
+    Ok(stripped)
+}
+
+/// Copies every segment before the image data except APP1 (EXIF and XMP), APP3 to APP13 (IPTC and the like),
+/// APP15 and comments. APP0 (JFIF), APP2 (color profiles) and APP14 (Adobe color transforms) are needed to show
+/// the image right
+fn strip_jpeg(jpeg: &[u8], keep: &[u16]) -> Result<Vec<u8>, String> {
+    let mut out = jpeg[..2].to_vec();
+    let mut i = 2;
+    loop {
+        match jpeg.get(i..i + 2) {
+            Some([0xFF, 0xFF]) => {
+                i += 1;
+                continue;
+            }
+            // Start of scan or end of image: the rest is image data
+            Some([0xFF, 0xDA | 0xD9]) => {
+                out.extend_from_slice(&jpeg[i..]);
+                return Ok(out);
+            }
+            Some([0xFF, _]) => {}
+            Some(_) => return Err(format!("expected a JPEG marker at byte {i}")),
+            None => return Err("truncated JPEG".to_string()),
+        }
+
+        let marker = jpeg[i + 1];
+        let length = jpeg.get(i + 2..i + 4).map(|length| u16::from_be_bytes([length[0], length[1]]) as usize);
+        let length = length.ok_or("truncated JPEG segment")?;
+        // The length includes its own two bytes
+        if length < 2 {
+            return Err(format!("invalid JPEG segment length {length} at byte {i}"));
+        }
+        let end = Some(i + 2 + length).filter(|end| *end <= jpeg.len()).ok_or("truncated JPEG segment")?;
+        let payload = &jpeg[i + 4..end];
+        match marker {
+            0xE1 if payload.starts_with(b"Exif\0\0") => {
+                if let Some(tiff) = minimal_exif(&payload[6..], keep)? {
+                    let exif = [b"Exif\0\0".as_slice(), &tiff].concat();
+                    out.extend_from_slice(&[0xFF, 0xE1]);
+                    out.extend_from_slice(&u16::try_from(exif.len() + 2).map_err(|_| "EXIF too long")?.to_be_bytes());
+                    out.extend_from_slice(&exif);
+                }
+            }
+            0xE1 | 0xE3..=0xED | 0xEF | 0xFE => {}
+            _ => out.extend_from_slice(&jpeg[i..end]),
+        }
+        i = end;
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Copies every chunk except text chunks and the modification time, and `eXIf` with only the tags in `keep`
+fn strip_png(png: &[u8], keep: &[u16]) -> Result<Vec<u8>, String> {
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut i = PNG_SIGNATURE.len();
+    while i < png.len() {
+        let length = png.get(i..i + 4).map(|length| u32::from_be_bytes(length.try_into().expect("4 bytes")) as usize);
+        let end = length.map(|length| i + 12 + length).filter(|end| *end <= png.len()).ok_or("truncated PNG chunk")?;
+        let kind = &png[i + 4..i + 8];
+        match kind {
+            b"eXIf" => {
+                if let Some(tiff) = minimal_exif(&png[i + 8..end - 4], keep)? {
+                    out.extend_from_slice(&(tiff.len() as u32).to_be_bytes());
+                    out.extend_from_slice(b"eXIf");
+                    out.extend_from_slice(&tiff);
+                    out.extend_from_slice(&crc32(&[b"eXIf".as_slice(), &tiff].concat()).to_be_bytes());
+                }
+            }
+            b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" => {}
+            _ => out.extend_from_slice(&png[i..end]),
+        }
+        i = end;
+        if kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Copies every chunk except `XMP `, and `EXIF` with only the tags in `keep`, updating the flags of `VP8X` and
+/// the size of the file
+fn strip_webp(webp: &[u8], keep: &[u16]) -> Result<Vec<u8>, String> {
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+
+    let mut chunks = Vec::new();
+    let mut i = 12;
+    while i + 8 <= webp.len() {
+        let size = u32::from_le_bytes(webp[i + 4..i + 8].try_into().expect("4 bytes")) as usize;
+        let end = i + 8 + size;
+        let data = webp.get(i + 8..end).ok_or("truncated WebP chunk")?;
+        chunks.push((&webp[i..i + 4], data.to_vec()));
+        i = end + size % 2;
+    }
+
+    let mut has_exif = false;
+    let mut kept = Vec::new();
+    for (kind, data) in chunks {
+        match kind {
+            b"EXIF" => {
+                // Some writers prefix the TIFF structure like JPEG does
+                let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(&data);
+                if let Some(tiff) = minimal_exif(tiff, keep)? {
+                    has_exif = true;
+                    kept.push((kind, tiff));
+                }
+            }
+            b"XMP " => {}
+            _ => kept.push((kind, data)),
+        }
+    }
+
+    let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+    for (kind, mut data) in kept {
+        if kind == b"VP8X" && !data.is_empty() {
+            data[0] &= !(EXIF_FLAG | XMP_FLAG);
+            if has_exif {
+                data[0] |= EXIF_FLAG;
+            }
+        }
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+    let size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&size.to_le_bytes());
+    Ok(out)
+}
+
+/// A TIFF structure, as EXIF is stored, with only the entries of the first IFD whose tags are in `keep`, in the
+/// same byte order. None if there are none
+fn minimal_exif(tiff: &[u8], keep: &[u16]) -> Result<Option<Vec<u8>>, String> {
+    let big_endian = match tiff.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return Err("invalid EXIF byte order".to_string()),
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+
+    let mut entries = Vec::new();
+    let ifd = u32_at(4).ok_or("truncated EXIF")? as usize;
+    for n in 0..u16_at(ifd).ok_or("truncated EXIF")? as usize {
+        let entry = ifd + 2 + 12 * n;
+        let tag = u16_at(entry).ok_or("truncated EXIF")?;
+        if !keep.contains(&tag) {
+            continue;
+        }
+        let kind = u16_at(entry + 2).ok_or("truncated EXIF")?;
+        let count = u32_at(entry + 4).ok_or("truncated EXIF")?;
+        let size = exif_type_size(kind).ok_or_else(|| format!("unknown EXIF type {kind}"))?.checked_mul(count as usize).ok_or("EXIF value too long")?;
+        let value = if size <= 4 {
+            tiff.get(entry + 8..entry + 12).ok_or("truncated EXIF")?
+        } else {
+            let offset = u32_at(entry + 8).ok_or("truncated EXIF")? as usize;
+            tiff.get(offset..offset.saturating_add(size)).ok_or("truncated EXIF value")?
+        };
+        entries.push((tag, kind, count, value));
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    entries.sort_by_key(|(tag, ..)| *tag);
+
+    let put_u16 = |out: &mut Vec<u8>, value: u16| out.extend_from_slice(&if big_endian { value.to_be_bytes() } else { value.to_le_bytes() });
+    let put_u32 = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&if big_endian { value.to_be_bytes() } else { value.to_le_bytes() });
+    let mut out = tiff[..2].to_vec();
+    put_u16(&mut out, 42);
+    put_u32(&mut out, 8);
+    put_u16(&mut out, entries.len() as u16);
+    // Values that don't fit in their entry go after the IFD and its (empty) link to the next one
+    let data_start = 8 + 2 + 12 * entries.len() + 4;
+    let mut data = Vec::new();
+    for (tag, kind, count, value) in entries {
+        put_u16(&mut out, tag);
+        put_u16(&mut out, kind);
+        put_u32(&mut out, count);
+        if value.len() <= 4 {
+            out.extend_from_slice(value);
+            out.resize(out.len() + 4 - value.len(), 0);
+        } else {
+            put_u32(&mut out, (data_start + data.len()) as u32);
+            data.extend_from_slice(value);
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+    }
+    put_u32(&mut out, 0);
+    out.extend(data);
+    Ok(Some(out))
+}
+
+/// Bytes per value of an EXIF type
+fn exif_type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// The CRC-32 of PNG chunks
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Copies images without their metadata if `strip_metadata` is set, see `strip_metadata`, protecting the
+/// privacy of whoever took them. Other files are copied as they are, like `IdentityProcessor` does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageProcessor {
+    pub strip_metadata: bool,
+    /// EXIF tags kept, see `EXIF_TAGS`
+    pub keep_exif: Vec<u16>,
+}
+
+impl Default for ImageProcessor {
+    fn default() -> Self {
+        ImageProcessor { strip_metadata: true, keep_exif: DEFAULT_KEPT_EXIF.to_vec() }
+    }
+}
+
+impl ImageProcessor {
+    pub fn new() -> ImageProcessor {
+        ImageProcessor::default()
+    }
+
+    pub fn with_strip_metadata(mut self, strip_metadata: bool) -> ImageProcessor {
+        self.strip_metadata = strip_metadata;
+        self
+    }
+
+    pub fn with_kept_exif(mut self, tags: &[u16]) -> ImageProcessor {
+        self.keep_exif = tags.to_vec();
+        self
+    }
+}
+
+impl<R: Resource> ResourceProcessor<R> for ImageProcessor {
+    fn name(&self) -> String {
+        "ImageProcessor".to_string()
+    }
+
+    fn process_resource(&self, source: &R, source_path: &Path, resources: &ResourceManager<R>) -> Result<Vec<u8>, ConfigurafoxError> {
+        let contents = resources.read(source)?;
+        if !self.strip_metadata {
+            debug!("Copying {} from {}", source.identifier(), source_path.display());
+            return Ok(contents.to_vec());
+        }
+        debug!("Copying {} from {} without its metadata", source.identifier(), source_path.display());
+        strip_metadata(source_path, &contents, &self.keep_exif)
+    }
+}
+
+/// Where the thumbnail `width` pixels wide of the image output at `image` is written: next to it, with the width
+/// before the extension, e.g. `photos/cat.320w.jpg`
+pub fn thumbnail_path(image: &Path, width: u32) -> PathBuf {
+    let stem = image.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match image.extension() {
+        Some(ext) => format!("{stem}.{width}w.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{width}w"),
+    };
+    image.with_file_name(name)
+}
+
+/// A smaller version of an image, requested with `thumbnail`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// Relative to the output root
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// JPEG quality of thumbnails, from 1 to 100
+pub const THUMBNAIL_QUALITY: u8 = 85;
+
+/// A version of `image` at most `width` pixels wide, keeping its aspect ratio, for `page` to link to, recording
+/// that it depends on the image. The thumbnail is made at the end of the run, see
+/// `ResourceManager::request_derivative`, once however many pages request it. Thumbnails are upright and have no
+/// metadata. Images that are already narrow enough are copied, keeping only their orientation, see
+/// `strip_metadata`. JPEG and PNG images are supported
+#[cfg(feature = "images")]
+pub fn thumbnail<R: Resource>(resources: &ResourceManager<R>, page: &R, image: &R, width: u32) -> Result<Thumbnail, ConfigurafoxError> {
+    use image::ImageDecoder;
+    use image::metadata::Orientation;
+
+    let output = resources.output_path(image);
+    let image_error = |msg: String| ConfigurafoxError::Image { path: output.clone(), msg };
+    if width == 0 {
+        return Err(image_error("thumbnails must be at least 1 pixel wide".to_string()));
+    }
+    resources.record_dependency(page, image, DependencyKind::Include);
+
+    let contents = resources.read(image)?;
+    let reader = image::ImageReader::new(std::io::Cursor::new(&contents[..])).with_guessed_format()?;
+    let Some(format) = reader.format().filter(|format| [image::ImageFormat::Jpeg, image::ImageFormat::Png].contains(format)) else {
+        return Err(image_error(format!("can't make thumbnails of {}, only of JPEG and PNG images", image.identifier())));
+    };
+    let mut decoder = reader.into_decoder().map_err(|e| image_error(e.to_string()))?;
+    let (mut original_width, mut original_height) = decoder.dimensions();
+    let orientation = decoder.orientation().map_err(|e| image_error(e.to_string()))?;
+    if matches!(orientation, Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH) {
+        std::mem::swap(&mut original_width, &mut original_height);
+    }
+
+    let width = width.min(original_width);
+    let height = ((original_height as u64 * width as u64 + original_width as u64 / 2) / original_width as u64).max(1) as u32;
+    let path = thumbnail_path(&output, width);
+    let thumbnail = Thumbnail { path: path.clone(), width, height };
+
+    resources.request_derivative(path.clone(), image, move |source| {
+        let image_error = |msg: String| ConfigurafoxError::Image { path: path.clone(), msg };
+        let contents = std::fs::read(source)?;
+        if width == original_width {
+            return strip_metadata(&path, &contents, DEFAULT_KEPT_EXIF);
+        }
+
+        let mut decoder = image::ImageReader::with_format(std::io::Cursor::new(contents), format)
+            .into_decoder()
+            .map_err(|e| image_error(e.to_string()))?;
+        let orientation = decoder.orientation().map_err(|e| image_error(e.to_string()))?;
+        let mut upright = image::DynamicImage::from_decoder(decoder).map_err(|e| image_error(e.to_string()))?;
+        upright.apply_orientation(orientation);
+        let resized = upright.resize_exact(width, height, image::imageops::FilterType::Lanczos3);
+
+        let mut out = Vec::new();
+        let written = match format {
+            image::ImageFormat::Jpeg => {
+                let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, THUMBNAIL_QUALITY);
+                image::DynamicImage::from(resized.into_rgb8()).write_with_encoder(encoder)
+            }
+            _ => resized.write_to(&mut std::io::Cursor::new(&mut out), format),
+        };
+        written.map_err(|e| image_error(e.to_string()))?;
+        Ok(out)
+    });
+    Ok(thumbnail)
+}
+
+/// Replaces `<thumbnail of="photos/cat.jpg" width="320">` with an `<img>` of the thumbnail of the image with that
+/// identifier, see `thumbnail`, with its `width` and `height`, keeping the other attributes like `alt`
+#[cfg(feature = "images")]
+pub struct ThumbnailImage;
+
+#[cfg(feature = "images")]
+impl<R: Resource, D> TreeWalker<R, D> for ThumbnailImage {
+    fn describe(&self) -> String {
+        "ThumbnailImage".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "thumbnail"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["thumbnail"])
+    }
+
+    fn replace(&self, _tag_name: &str, mut attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let missing = |key: &str, msg: &str| ConfigurafoxError::MissingAttr { key_name: key.to_string(), msg: msg.to_string() };
+        let identifier = get_attr(&attrs, "of").ok_or_else(|| missing("of", "the identifier of the image"))?;
+        let image = ctx
+            .resources
+            .resource_by_identifier(identifier)
+            .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: identifier.to_string() })?;
+        let width = get_attr(&attrs, "width").ok_or_else(|| missing("width", "the width of the thumbnail, in pixels"))?;
+        let width = width
+            .parse()
+            .map_err(|_| ConfigurafoxError::MalformedAttrs { key_name: "width".to_string(), msg: format!("{width:?} is not a number of pixels") })?;
+
+        let thumbnail = thumbnail(ctx.resources, ctx.resource, image, width)?;
+        attrs.retain(|(key, _)| !["of", "src", "width", "height"].contains(&key.as_str()));
+        attrs.insert(0, ("src".to_string(), crate::relative_url(&thumbnail.path, &ctx.resources.output_path(ctx.resource))?));
+        attrs.push(("width".to_string(), thumbnail.width.to_string()));
+        attrs.push(("height".to_string(), thumbnail.height.to_string()));
+        Ok(vec![Node::Element(Element { name: "img".to_string(), attrs, children: vec![] })])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ORIENTATION, strip_jpeg, strip_png, strip_webp};
+
+    const MAKE: u16 = 0x010F;
+
+    /// A little-endian TIFF structure with one IFD of `entries`: tag, type, count and value
+    fn tiff(entries: &[(u16, u16, u32, [u8; 4])]) -> Vec<u8> {
+        let mut out = b"II\x2a\0\x08\0\0\0".to_vec();
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&count.to_le_bytes());
+            out.extend_from_slice(value);
+        }
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn photo_exif() -> Vec<u8> {
+        tiff(&[(MAKE, 2, 4, *b"Fox\0"), (ORIENTATION, 3, 1, [6, 0, 0, 0])])
+    }
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        [&[0xFF, marker][..], &(payload.len() as u16 + 2).to_be_bytes(), payload].concat()
+    }
+
+    #[test]
+    fn jpeg() {
+        let exif = [b"Exif\0\0".as_slice(), &photo_exif()].concat();
+        let image_data = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9];
+        let jpeg = [&[0xFF, 0xD8][..], &segment(0xE0, b"JFIF\0"), &segment(0xE1, &exif), &segment(0xFE, b"comment"), &image_data].concat();
+
+        let kept = [b"Exif\0\0".as_slice(), &tiff(&[(ORIENTATION, 3, 1, [6, 0, 0, 0])])].concat();
+        let expected = [&[0xFF, 0xD8][..], &segment(0xE0, b"JFIF\0"), &segment(0xE1, &kept), &image_data].concat();
+        assert_eq!(strip_jpeg(&jpeg, &[ORIENTATION]).unwrap(), expected);
+
+        let expected = [&[0xFF, 0xD8][..], &segment(0xE0, b"JFIF\0"), &image_data].concat();
+        assert_eq!(strip_jpeg(&jpeg, &[]).unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed_jpeg() {
+        for jpeg in [&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x00][..], &[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x01], &[0xFF, 0xD8, 0xFF, 0xE1], &[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10, 0x00], &[0xFF, 0xD8, 0x12]] {
+            assert!(strip_jpeg(jpeg, &[ORIENTATION]).is_err(), "{jpeg:x?}");
+        }
+
+        // An IFD cut off in the middle of a kept entry
+        let mut exif = [b"Exif\0\0".as_slice(), &tiff(&[(ORIENTATION, 3, 1, [6, 0, 0, 0])])].concat();
+        exif.truncate(exif.len() - 8);
+        let jpeg = [&[0xFF, 0xD8][..], &segment(0xE1, &exif), &[0xFF, 0xD9]].concat();
+        assert_eq!(strip_jpeg(&jpeg, &[ORIENTATION]), Err("truncated EXIF".to_string()));
+
+        // A value pointing past the end
+        let exif = [b"Exif\0\0".as_slice(), &tiff(&[(MAKE, 2, 100, [0xF0, 0, 0, 0])])].concat();
+        let jpeg = [&[0xFF, 0xD8][..], &segment(0xE1, &exif), &[0xFF, 0xD9]].concat();
+        assert_eq!(strip_jpeg(&jpeg, &[MAKE]), Err("truncated EXIF value".to_string()));
+    }
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let crc = super::crc32(&[kind, data].concat());
+        [&(data.len() as u32).to_be_bytes()[..], kind, data, &crc.to_be_bytes()].concat()
+    }
+
+    #[test]
+    fn png() {
+        let header = chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]);
+        let png = [super::PNG_SIGNATURE, &header, &chunk(b"tEXt", b"Author\0Fox"), &chunk(b"eXIf", &photo_exif()), &chunk(b"IEND", b"")].concat();
+        let expected = [super::PNG_SIGNATURE, &header, &chunk(b"eXIf", &tiff(&[(ORIENTATION, 3, 1, [6, 0, 0, 0])])), &chunk(b"IEND", b"")].concat();
+        assert_eq!(strip_png(&png, &[ORIENTATION]).unwrap(), expected);
+
+        assert!(strip_png(&png[..png.len() - 3], &[ORIENTATION]).is_err());
+        assert!(strip_png(&png[..super::PNG_SIGNATURE.len() + 2], &[ORIENTATION]).is_err());
+    }
+
+    fn riff(chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (kind, data) in chunks {
+            body.extend_from_slice(kind);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        [b"RIFF".as_slice(), &(body.len() as u32).to_le_bytes(), &body].concat()
+    }
+
+    #[test]
+    fn webp() {
+        let webp = riff(&[(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]), (b"VP8L", &[1, 2, 3]), (b"EXIF", &photo_exif()), (b"XMP ", b"<x/>")]);
+        let exif = tiff(&[(ORIENTATION, 3, 1, [6, 0, 0, 0])]);
+        assert_eq!(strip_webp(&webp, &[ORIENTATION]).unwrap(), riff(&[(b"VP8X", &[0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0]), (b"VP8L", &[1, 2, 3]), (b"EXIF", &exif)]));
+        assert_eq!(strip_webp(&webp, &[]).unwrap(), riff(&[(b"VP8X", &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), (b"VP8L", &[1, 2, 3])]));
+
+        assert!(strip_webp(&webp[..webp.len() - 3], &[ORIENTATION]).is_err());
+    }
+}
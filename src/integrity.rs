@@ -0,0 +1,156 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use base64::Engine;
+use html_editor::{Element, Node};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::ConfigurafoxError;
+use crate::remote::{DEFAULT_CACHE_DIR, fetch_cached};
+use crate::resource_manager::Resource;
+use crate::treewalker::{Context, TreeWalker, get_attr};
+
+/// `<link rel>` values whose fetches browsers check the integrity of
+const INTEGRITY_RELS: &[&str] = &["stylesheet", "preload", "modulepreload"];
+
+/// The hash function of an `integrity` attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    Sha256,
+    #[default]
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// By name, as in `sha384`
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha384" => Some(HashAlgorithm::Sha384),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// The value of an `integrity` attribute for `contents`, like `sha384-…`
+    pub fn integrity(self, contents: &[u8]) -> String {
+        let (name, digest) = match self {
+            HashAlgorithm::Sha256 => ("sha256", Sha256::digest(contents).to_vec()),
+            HashAlgorithm::Sha384 => ("sha384", Sha384::digest(contents).to_vec()),
+            HashAlgorithm::Sha512 => ("sha512", Sha512::digest(contents).to_vec()),
+        };
+        format!("{name}-{}", base64::engine::general_purpose::STANDARD.encode(digest))
+    }
+}
+
+/// The URL fetched for `url` if it points to another site, as in `https://cdn.example.com/lib.js` or
+/// `//cdn.example.com/lib.js`
+fn external_url(url: &str) -> Option<String> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Some(url.to_string())
+    } else {
+        url.strip_prefix("//").map(|rest| format!("https://{rest}"))
+    }
+}
+
+/// Adds Subresource Integrity to the `<script src>`s and `<link href>`s (stylesheets and preloads) of a page that
+/// load files from other sites, like the KaTeX stylesheet `<katex-prelude/>` adds: an `integrity` attribute with
+/// the hash of the file, fetched at build time, and `crossorigin="anonymous"` unless given. Elements with an
+/// `integrity` attribute already are left as they are, and so are those with `integrity="off"`, minus the
+/// attribute
+///
+/// It works on the finished page, so it sees elements other walkers added wherever it is in the list. Fetched
+/// files are cached in `remote::DEFAULT_CACHE_DIR`, and revalidated the first time a page uses them
+pub struct SubresourceIntegrity {
+    pub algorithm: HashAlgorithm,
+    /// Where fetched files are kept, relative to the project root
+    pub cache_dir: PathBuf,
+    /// Integrity by URL, for the files fetched during this build
+    hashes: Mutex<HashMap<String, String>>,
+}
+
+impl Default for SubresourceIntegrity {
+    fn default() -> Self {
+        SubresourceIntegrity::new()
+    }
+}
+
+impl SubresourceIntegrity {
+    pub fn new() -> SubresourceIntegrity {
+        SubresourceIntegrity { algorithm: HashAlgorithm::default(), cache_dir: PathBuf::from(DEFAULT_CACHE_DIR), hashes: Mutex::default() }
+    }
+
+    pub fn with_algorithm(mut self, algorithm: HashAlgorithm) -> SubresourceIntegrity {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn with_cache_dir<P: Into<PathBuf>>(mut self, cache_dir: P) -> SubresourceIntegrity {
+        self.cache_dir = cache_dir.into();
+        self
+    }
+
+    fn integrity<R: Resource, D>(&self, url: &str, ctx: Context<'_, '_, R, D>) -> Result<String, ConfigurafoxError> {
+        if let Some(integrity) = self.hashes.lock().unwrap_or_else(|e| e.into_inner()).get(url) {
+            return Ok(integrity.clone());
+        }
+        let cached = fetch_cached(url, &ctx.resources.absolute_path(&self.cache_dir))?;
+        let integrity = self.algorithm.integrity(&std::fs::read(cached)?);
+        debug!("{url}: {integrity}");
+        self.hashes.lock().unwrap_or_else(|e| e.into_inner()).insert(url.to_string(), integrity.clone());
+        Ok(integrity)
+    }
+
+    fn add_integrity<R: Resource, D>(&self, nodes: &mut [Node], ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        for node in nodes {
+            let Node::Element(Element { name, attrs, children }) = node else { continue };
+            let url = match name.as_str() {
+                "script" => get_attr(attrs, "src"),
+                "link" => get_attr(attrs, "href").filter(|_| {
+                    get_attr(attrs, "rel").is_some_and(|rel| rel.split_ascii_whitespace().any(|rel| INTEGRITY_RELS.contains(&&*rel.to_ascii_lowercase())))
+                }),
+                _ => None,
+            };
+            match (url.and_then(external_url), get_attr(attrs, "integrity")) {
+                (_, Some("off")) => attrs.retain(|(key, _)| key != "integrity"),
+                (Some(url), None) => {
+                    let integrity = self.integrity(&url, ctx)?;
+                    attrs.push(("integrity".to_string(), integrity));
+                    if get_attr(attrs, "crossorigin").is_none() {
+                        attrs.push(("crossorigin".to_string(), "anonymous".to_string()));
+                    }
+                }
+                _ => {}
+            }
+            self.add_integrity(children, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for SubresourceIntegrity {
+    fn describe(&self) -> String {
+        format!("SubresourceIntegrity({:?})", self.algorithm)
+    }
+
+    fn matches(&self, _tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        false
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec![])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, children: Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok(children)
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        self.add_integrity(dom, ctx)
+    }
+}
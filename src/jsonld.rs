@@ -0,0 +1,198 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::authors::authors_of;
+use crate::calendar::DATE_KEY;
+use crate::feed::DESCRIPTION_KEY;
+use crate::graph::DependencyKind;
+use crate::metadata::Value;
+use crate::opengraph::IMAGE_KEY;
+use crate::resource_manager::Resource;
+use crate::site::{SiteData, absolute_url};
+use crate::treewalker::{Context, TreeWalker, get_attr};
+
+/// Metadata key with when a page was last changed, besides `calendar::DATE_KEY` for when it was published
+pub const UPDATED_KEY: &str = "updated";
+
+/// The schema.org types `JsonLd` describes pages as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaType {
+    /// The site itself, on its home page
+    WebSite,
+    /// A dated page, with its authors
+    Article,
+    /// The pages above the page, by directory
+    BreadcrumbList,
+}
+
+impl SchemaType {
+    pub fn from_name(name: &str) -> Option<SchemaType> {
+        match name {
+            "WebSite" => Some(SchemaType::WebSite),
+            "Article" => Some(SchemaType::Article),
+            "BreadcrumbList" => Some(SchemaType::BreadcrumbList),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces `<json-ld/>`, which belongs in `<head>`, with a `<script type="application/ld+json">` describing the
+/// page for search engines, from its metadata and the site's. By default the home page is a `WebSite`, pages with a
+/// `calendar::DATE_KEY` are `Article`s, and pages below the root get a `BreadcrumbList` of the `index.html` pages
+/// above them. The `types` attribute overrides that, e.g. `types="Article BreadcrumbList"`. URLs are absolute,
+/// so the site needs a `SiteData::base_url`
+pub struct JsonLd;
+
+impl JsonLd {
+    fn default_types<R: Resource, D>(ctx: Context<'_, '_, R, D>) -> Vec<SchemaType> {
+        let output = ctx.resources.output_path(ctx.resource);
+        let mut types = Vec::new();
+        if output == Path::new("index.html") {
+            types.push(SchemaType::WebSite);
+        }
+        if ctx.metadata().get(DATE_KEY).is_some() {
+            types.push(SchemaType::Article);
+        }
+        if output.parent().is_some_and(|parent| parent != Path::new("")) {
+            types.push(SchemaType::BreadcrumbList);
+        }
+        types
+    }
+}
+
+impl<R: Resource, D: SiteData> TreeWalker<R, D> for JsonLd {
+    fn describe(&self) -> String {
+        "JsonLd".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "json-ld"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["json-ld"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let types = match get_attr(&attrs, "types") {
+            Some(names) => names
+                .split_whitespace()
+                .map(|name| {
+                    SchemaType::from_name(name).ok_or_else(|| ConfigurafoxError::MalformedAttrs {
+                        key_name: "types".to_string(),
+                        msg: format!("unknown type {name:?}, expected WebSite, Article or BreadcrumbList"),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => JsonLd::default_types(ctx),
+        };
+
+        let mut graph = Vec::new();
+        for schema_type in types {
+            let item = match schema_type {
+                SchemaType::WebSite => website(ctx)?,
+                SchemaType::Article => article(ctx)?,
+                SchemaType::BreadcrumbList => match breadcrumbs(ctx)? {
+                    Some(item) => item,
+                    None => continue,
+                },
+            };
+            graph.push(item);
+        }
+        if graph.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let document = serde_json::json!({ "@context": "https://schema.org", "@graph": graph });
+        // `</script>` in a string would end the element early
+        let json = serde_json::to_string(&document).expect("JSON values serialize").replace("</", "<\\/");
+        Ok(vec![Node::Element(Element {
+            name: "script".to_string(),
+            attrs: vec![("type".to_string(), "application/ld+json".to_string())],
+            children: vec![Node::Text(json)],
+        })])
+    }
+}
+
+fn website<R: Resource, D: SiteData>(ctx: Context<'_, '_, R, D>) -> Result<Value, ConfigurafoxError> {
+    let mut item = serde_json::json!({ "@type": "WebSite", "url": absolute_url(ctx.data, Path::new(""))? });
+    if let Some(title) = ctx.data.title() {
+        item["name"] = Value::from(title);
+    }
+    if let Some(description) = ctx.data.var(DESCRIPTION_KEY).and_then(Value::as_str) {
+        item["description"] = Value::from(description);
+    }
+    Ok(item)
+}
+
+fn article<R: Resource, D: SiteData>(ctx: Context<'_, '_, R, D>) -> Result<Value, ConfigurafoxError> {
+    let metadata = ctx.metadata();
+    let url = absolute_url(ctx.data, &ctx.resources.output_path(ctx.resource))?;
+    let mut item = serde_json::json!({ "@type": "Article", "url": url, "mainEntityOfPage": url });
+    let fields = [("headline", "title"), ("description", DESCRIPTION_KEY), ("datePublished", DATE_KEY), ("dateModified", UPDATED_KEY)];
+    for (property, key) in fields {
+        if let Some(value) = metadata.get_str(key) {
+            item[property] = Value::from(value);
+        }
+    }
+    if let Some(image) = metadata.get_str(IMAGE_KEY) {
+        let image = if image.contains("://") { image.to_string() } else { absolute_url(ctx.data, image.trim_start_matches('/').as_ref())? };
+        item["image"] = Value::from(image);
+    }
+
+    let authors = authors_of(metadata, ctx.data)
+        .into_iter()
+        .map(|author| {
+            let mut person = serde_json::json!({ "@type": "Person", "name": author.name });
+            if let Some(url) = author.url {
+                person["url"] = Value::from(url);
+            }
+            person
+        })
+        .collect::<Vec<_>>();
+    if !authors.is_empty() {
+        item["author"] = Value::Array(authors);
+    }
+    if let Some(title) = ctx.data.title() {
+        item["publisher"] = serde_json::json!({ "@type": "Organization", "name": title });
+    }
+    Ok(item)
+}
+
+/// The `index.html` pages in the directories above the page, from the root, then the page itself. None if none
+/// of them exists
+fn breadcrumbs<R: Resource, D: SiteData>(ctx: Context<'_, '_, R, D>) -> Result<Option<Value>, ConfigurafoxError> {
+    let output = ctx.resources.output_path(ctx.resource);
+    let mut ancestors = output.ancestors().skip(1).map(|dir| dir.join("index.html")).collect::<Vec<PathBuf>>();
+    ancestors.reverse();
+
+    let mut crumbs = Vec::new();
+    for path in ancestors.iter().filter(|path| **path != output) {
+        let Some((_, page)) = ctx.resources.iter().find(|(_, resource)| ctx.resources.output_path(resource) == *path) else {
+            continue;
+        };
+        ctx.resources.record_dependency(ctx.resource, page, DependencyKind::Link);
+        crumbs.push((page, ctx.resources.output_path(page)));
+    }
+    if crumbs.is_empty() {
+        return Ok(None);
+    }
+    crumbs.push((ctx.resource, output));
+
+    let mut items = Vec::new();
+    for (position, (page, path)) in crumbs.into_iter().enumerate() {
+        let name = match ctx.resources.metadata(page).get_str("title") {
+            Some(title) => title.to_string(),
+            None if path == Path::new("index.html") => ctx.data.title().map_or_else(|| page.identifier(), str::to_string),
+            None => page.identifier(),
+        };
+        let url = absolute_url(ctx.data, &path)?;
+        items.push(serde_json::json!({ "@type": "ListItem", "position": position + 1, "name": name, "item": url }));
+    }
+    Ok(Some(serde_json::json!({ "@type": "BreadcrumbList", "itemListElement": items })))
+}
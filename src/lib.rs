@@ -2,15 +2,87 @@
 use tracing::{trace, debug, info, warn, error, instrument, Level};
 
 use std::path::{Path, PathBuf};
-use std::io::{Read, Write};
 
-use html_editor::{operation::{Htmlifiable, Editable}, HTMLParseError};
+use html_editor::HTMLParseError;
 
 pub mod resource_manager;
 pub mod treewalker;
+pub mod output;
+pub mod metadata;
+pub mod query;
+pub mod graph;
+pub mod vfs;
+pub mod stats;
+pub mod position;
+pub mod diagnostics;
+pub mod lint;
+pub mod config;
+pub mod scaffold;
+pub mod registry;
+pub mod deploy;
+pub mod diff;
+pub mod changelog;
+pub mod linkcheck;
+pub mod snapshot;
+pub mod testing;
+pub mod serialize;
+pub mod entities;
+mod named_references;
+pub mod site;
+pub mod calendar;
+pub mod feed;
+pub mod authors;
+pub mod webmention;
+pub mod comments;
+pub mod opengraph;
+pub mod favicon;
+pub mod manifest;
+pub mod service_worker;
+pub mod jsonld;
+pub mod newsletter;
+pub mod print;
+pub mod slides;
+pub mod images;
+pub mod video;
+pub mod podcast;
+pub mod git;
+pub mod error_pages;
+pub mod headings;
+pub mod tables;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(all(feature = "remote", feature = "syntax-highlight"))]
+pub mod snippet;
+#[cfg(feature = "remote")]
+pub mod integrity;
+#[cfg(feature = "csp")]
+pub mod csp;
+#[cfg(feature = "archives")]
+pub mod archive;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[cfg(feature = "state")]
+pub mod state;
+
+#[cfg(feature = "pretty-diagnostics")]
+pub mod render;
+
+#[cfg(feature = "timing")]
+pub mod timing;
+
+#[cfg(feature = "serve")]
+pub mod serve;
+
+#[cfg(feature = "wasm-plugins")]
+pub mod plugin;
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
 
 use resource_manager::{Resource, ResourceManager};
-use treewalker::{Context, TreeWalker, walk};
+use output::{OutputBackend, LocalBackend};
+use treewalker::{Context, Extensions, TreeWalker, walk};
 
 #[allow(unused)]
 #[derive(Debug)]
@@ -18,24 +90,296 @@ pub enum ConfigurafoxError {
     MalformedAttrs { key_name: String, msg: String, },
     MissingAttr { key_name: String, msg: String, },
     MissingBody { msg: String, },
-    ParseHTMLError { path: PathBuf, error: HTMLParseError },
+    MalformedBody { tag: String, msg: String, },
+    UnknownVariable { name: String, },
+    /// A variable used as text is an array or a table
+    NonScalarVariable { name: String, },
+    /// A walker needs a `site::SiteData` setting the user data doesn't have
+    MissingSiteData { key: &'static str, },
+    UnknownIdentifier { identifier: String, },
+    UnknownLanguage { lang: String, },
+    UnknownTheme { theme: String, },
+    InvalidGeneratedHTML { generator: String, html: String, },
+    /// A tag that isn't standard HTML was left in the output, see `Strictness::unknown_tags`
+    UnknownTag { tag: String, },
+    /// A walker was asked to replace a tag it doesn't handle
+    UnexpectedTag { walker: String, tag: String, },
+    Katex { msg: String, },
+    /// The image or font at `path` couldn't be read, or an image couldn't be written
+    Image { path: PathBuf, msg: String, },
+    /// The video at `path` couldn't be probed, or its poster couldn't be made
+    Video { path: PathBuf, msg: String, },
+    /// The history of the git repository at `repo` couldn't be read
+    Git { repo: PathBuf, msg: String, },
+    /// No relative path from `base` to `path` exists, e.g. because only one of them is absolute
+    Unrelativizable { path: PathBuf, base: PathBuf, },
+    /// Each identifier in `duplicates` is used by the resources at several paths, sorted by identifier and path
+    DuplicateIdentifier { duplicates: Vec<(String, Vec<PathBuf>)>, },
+    PathOutsideProject { path: PathBuf, },
+    NonUtf8Path { path: PathBuf, },
+    /// `pos` is where parsing most likely failed, see `position::parse_error_position`
+    ParseHTMLError { path: PathBuf, pos: Option<position::SourcePos>, error: HTMLParseError },
+    /// `error` happened while processing the element at `pos` in `path`
+    Located { path: PathBuf, pos: position::SourcePos, error: Box<ConfigurafoxError>, },
+    /// The project configuration file at `path` is invalid
+    Config { path: PathBuf, msg: String, },
+    /// `error` came from the walker described as `walker`
+    InWalker { walker: String, error: Box<ConfigurafoxError>, },
+    /// `error` happened while `processor` was processing the resource `identifier` at `path`
+    InResource { identifier: String, path: PathBuf, processor: String, error: Box<ConfigurafoxError>, },
+    OutputBackend { backend: String, msg: String, },
+    Remote { url: String, msg: String, },
+    /// Nothing is registered as `name` in a `registry::Registry`. `kind` is "walker" or "processor"
+    NotRegistered { kind: &'static str, name: String, },
+    /// The options given to the walker or processor registered as `name` don't fit it
+    InvalidOptions { name: String, msg: String, },
+    /// The WASM plugin at `plugin` couldn't be loaded, or broke its interface, see `plugin::WasmWalker`
+    Plugin { plugin: String, msg: String, },
+    /// The build emitted warnings (or recovered from errors), and `Diagnostics::warnings_as_errors` is set
+    WarningsAsErrors { count: usize, },
     IO(std::io::Error),
+    #[cfg(feature = "syntax-highlight")]
     SyntectError(syntect::Error),
+    #[cfg(feature = "watch")]
+    Notify(notify::Error),
+    #[cfg(feature = "watch")]
+    WatcherStopped,
+    /// For errors from user-defined walkers and processors that don't fit any other variant
     Other(String),
 }
 
+impl std::fmt::Display for ConfigurafoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MalformedAttrs { key_name, msg } => write!(f, "malformed attribute {key_name}: {msg}"),
+            Self::MissingAttr { key_name, msg } => write!(f, "missing attribute {key_name}: {msg}"),
+            Self::MissingBody { msg } => write!(f, "missing body: {msg}"),
+            Self::MalformedBody { tag, msg } => write!(f, "malformed <{tag}> body: {msg}"),
+            Self::UnknownVariable { name } => write!(f, "unknown variable ${name}"),
+            Self::NonScalarVariable { name } => write!(f, "variable ${name} is an array or a table, not text"),
+            Self::MissingSiteData { key } => write!(f, "the site data has no {key}"),
+            Self::UnknownIdentifier { identifier } => write!(f, "unknown identifier @{identifier}"),
+            Self::UnknownLanguage { lang } => write!(f, "unknown language {lang}"),
+            Self::UnknownTheme { theme } => write!(f, "unknown theme {theme}"),
+            Self::InvalidGeneratedHTML { generator, html } => write!(f, "{generator} generated invalid html: {html:?}"),
+            Self::UnknownTag { tag } => write!(f, "<{tag}> is not an HTML element"),
+            Self::UnexpectedTag { walker, tag } => write!(f, "{walker} can't replace <{tag}>"),
+            Self::Katex { msg } => write!(f, "katex: {msg}"),
+            Self::Image { path, msg } => write!(f, "{}: {msg}", path.display()),
+            Self::Video { path, msg } => write!(f, "{}: {msg}", path.display()),
+            Self::Git { repo, msg } => write!(f, "git repository {}: {msg}", repo.display()),
+            Self::Unrelativizable { path, base } => write!(f, "{} can't be made relative to {}", path.display(), base.display()),
+            Self::DuplicateIdentifier { duplicates } => {
+                let duplicates = duplicates
+                    .iter()
+                    .map(|(identifier, paths)| {
+                        let paths = paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ");
+                        format!("{identifier:?} ({paths})")
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                write!(f, "identifiers used by several resources: {duplicates}")
+            }
+            Self::PathOutsideProject { path } => write!(f, "{} points outside the project root", path.display()),
+            Self::NonUtf8Path { path } => write!(f, "{} is not valid UTF-8", path.display()),
+            Self::ParseHTMLError { path, pos: Some(pos), error } => write!(f, "{}:{pos}: could not parse html: {error:?}", path.display()),
+            Self::ParseHTMLError { path, pos: None, error } => write!(f, "{}: could not parse html: {error:?}", path.display()),
+            Self::Located { path, pos, error } => write!(f, "{}:{pos}: {error}", path.display()),
+            Self::Config { path, msg } => write!(f, "{}: {msg}", path.display()),
+            Self::InWalker { walker, error } => write!(f, "{walker}: {error}"),
+            Self::InResource { identifier, path, processor, error } => write!(f, "{identifier} @ {} ({processor}): {error}", path.display()),
+            Self::OutputBackend { backend, msg } => write!(f, "{backend}: {msg}"),
+            Self::Remote { url, msg } => write!(f, "{url}: {msg}"),
+            Self::NotRegistered { kind, name } => write!(f, "no {kind} is registered as {name:?}"),
+            Self::InvalidOptions { name, msg } => write!(f, "invalid options for {name}: {msg}"),
+            Self::Plugin { plugin, msg } => write!(f, "plugin {plugin}: {msg}"),
+            Self::WarningsAsErrors { count } => write!(f, "{count} warning(s), treated as errors"),
+            Self::IO(e) => write!(f, "{e}"),
+            #[cfg(feature = "syntax-highlight")]
+            Self::SyntectError(e) => write!(f, "syntect: {e}"),
+            #[cfg(feature = "watch")]
+            Self::Notify(e) => write!(f, "file watcher: {e}"),
+            #[cfg(feature = "watch")]
+            Self::WatcherStopped => write!(f, "file watcher stopped"),
+            Self::Other(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigurafoxError {}
+
+impl ConfigurafoxError {
+    /// The underlying error, without the context added by `Located`, `InWalker` and `InResource`
+    pub fn innermost(&self) -> &ConfigurafoxError {
+        match self {
+            Self::Located { error, .. } | Self::InWalker { error, .. } | Self::InResource { error, .. } => error.innermost(),
+            error => error,
+        }
+    }
+
+    /// A stable, kebab-case name for the kind of the innermost error, e.g. `unknown-variable`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Located { error, .. } | Self::InWalker { error, .. } | Self::InResource { error, .. } => error.code(),
+            Self::MalformedAttrs { .. } => "malformed-attrs",
+            Self::MissingAttr { .. } => "missing-attr",
+            Self::MissingBody { .. } => "missing-body",
+            Self::MalformedBody { .. } => "malformed-body",
+            Self::UnknownVariable { .. } => "unknown-variable",
+            Self::NonScalarVariable { .. } => "non-scalar-variable",
+            Self::MissingSiteData { .. } => "missing-site-data",
+            Self::UnknownIdentifier { .. } => "unknown-identifier",
+            Self::UnknownLanguage { .. } => "unknown-language",
+            Self::UnknownTheme { .. } => "unknown-theme",
+            Self::InvalidGeneratedHTML { .. } => "invalid-generated-html",
+            Self::UnknownTag { .. } => "unknown-tag",
+            Self::UnexpectedTag { .. } => "unexpected-tag",
+            Self::Katex { .. } => "katex",
+            Self::Image { .. } => "image",
+            Self::Video { .. } => "video",
+            Self::Git { .. } => "git",
+            Self::Unrelativizable { .. } => "unrelativizable",
+            Self::DuplicateIdentifier { .. } => "duplicate-identifier",
+            Self::PathOutsideProject { .. } => "path-outside-project",
+            Self::NonUtf8Path { .. } => "non-utf8-path",
+            Self::ParseHTMLError { .. } => "parse-html",
+            Self::Config { .. } => "config",
+            Self::OutputBackend { .. } => "output-backend",
+            Self::Remote { .. } => "remote",
+            Self::NotRegistered { .. } => "not-registered",
+            Self::InvalidOptions { .. } => "invalid-options",
+            Self::Plugin { .. } => "plugin",
+            Self::WarningsAsErrors { .. } => "warnings-as-errors",
+            Self::IO(_) => "io",
+            #[cfg(feature = "syntax-highlight")]
+            Self::SyntectError(_) => "syntect",
+            #[cfg(feature = "watch")]
+            Self::Notify(_) => "notify",
+            #[cfg(feature = "watch")]
+            Self::WatcherStopped => "watcher-stopped",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// A `ParseHTMLError` for failing to parse `html`, the contents of `path` from line `first_line` on
+    pub fn parse_html(path: &Path, html: &str, first_line: usize, error: HTMLParseError) -> ConfigurafoxError {
+        ConfigurafoxError::ParseHTMLError { path: path.to_owned(), pos: position::parse_error_position(html, first_line), error }
+    }
+
+    /// This error as an error-severity diagnostic, with the file and position it happened at if known
+    pub fn to_diagnostic(&self) -> diagnostics::Diagnostic {
+        let mut path = None;
+        let mut pos = None;
+        let mut error = self;
+        loop {
+            match error {
+                Self::Located { path: p, pos: located, error: inner } => {
+                    path = Some(p.clone());
+                    pos = Some(*located);
+                    error = inner;
+                }
+                Self::InResource { path: p, error: inner, .. } => {
+                    path = path.or_else(|| Some(p.clone()));
+                    error = inner;
+                }
+                Self::ParseHTMLError { path: p, pos: parsed, .. } => {
+                    path = path.or_else(|| Some(p.clone()));
+                    pos = pos.or(*parsed);
+                    break;
+                }
+                Self::Image { path: p, .. } | Self::Video { path: p, .. } => {
+                    path = path.or_else(|| Some(p.clone()));
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        diagnostics::Diagnostic {
+            severity: diagnostics::Severity::Error,
+            code: Some(self.code().to_string()),
+            path,
+            pos,
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "syntax-highlight")]
 impl From<syntect::Error> for ConfigurafoxError {
     fn from(v: syntect::Error) -> Self {
         Self::SyntectError(v)
     }
 }
 
+#[cfg(feature = "watch")]
+impl From<notify::Error> for ConfigurafoxError {
+    fn from(v: notify::Error) -> Self {
+        Self::Notify(v)
+    }
+}
+
 impl From<std::io::Error> for ConfigurafoxError {
     fn from(v: std::io::Error) -> Self {
         Self::IO(v)
     }
 }
 
+/// Whether `url` is an absolute `http` or `https` URL, the only ones safe to link to from data other sites or
+/// visitors sent, as e.g. `javascript:` URLs run when clicked
+pub fn is_web_url(url: &str) -> bool {
+    ["http://", "https://"].iter().any(|scheme| url.get(..scheme.len()).is_some_and(|start| start.eq_ignore_ascii_case(scheme)))
+}
+
+/// `path` as used in URLs: `/`-separated, regardless of platform. Fails on paths that aren't valid UTF-8
+pub fn url_path(path: &Path) -> Result<String, ConfigurafoxError> {
+    let mut url = String::new();
+    for component in path.components() {
+        if component == std::path::Component::RootDir {
+            url.push('/');
+            continue;
+        }
+        if !url.is_empty() && !url.ends_with('/') {
+            url.push('/');
+        }
+        url.push_str(component.as_os_str().to_str().ok_or_else(|| ConfigurafoxError::NonUtf8Path { path: path.to_owned() })?);
+    }
+    Ok(url)
+}
+
+/// Lowercase ASCII letters and digits, with runs of anything else turned into a single `-`
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for ch in title.chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() { "untitled".to_string() } else { slug.to_string() }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust versions, so it can be saved between builds
+pub(crate) fn fingerprint(contents: &[u8]) -> u64 {
+    contents.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The URL of the file written to `path` relative to the page written to `page`, both relative to the output root
+pub fn relative_url(path: &Path, page: &Path) -> Result<String, ConfigurafoxError> {
+    // Links are followed from where the page is written, which needn't mirror where its source is
+    let diff = if let Some(page_dir) = page.parent() {
+        pathdiff::diff_paths(path, page_dir).ok_or_else(|| ConfigurafoxError::Unrelativizable { path: path.to_owned(), base: page_dir.to_owned() })?
+    } else {
+        path.to_owned()
+    };
+
+    debug!("{} - {} = {}", path.display(), page.display(), diff.display());
+
+    url_path(&diff)
+}
+
 pub trait ResourceProcessor<R: Resource> {
     fn name(&self) -> String;
 
@@ -48,40 +392,143 @@ pub trait ResourceProcessor<R: Resource> {
     ) -> Result<Vec<u8>, ConfigurafoxError>;
 }
 
+/// So that one processor can be shared between resources, e.g. by returning `Box::new(&processor)` from
+/// the `processor_for` closure of `run`
+impl<R: Resource, P: ResourceProcessor<R> + ?Sized> ResourceProcessor<R> for &P {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn process_resource(
+        &self,
+        source: &R,
+        source_path: &Path,
+        resources: &ResourceManager<R>
+    ) -> Result<Vec<u8>, ConfigurafoxError> {
+        (**self).process_resource(source, source_path, resources)
+    }
+}
+
+/// So that processors a `Registry` builds can be wrapped by others, like `slides::SlideDeck`
+impl<R: Resource, P: ResourceProcessor<R> + ?Sized> ResourceProcessor<R> for Box<P> {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn process_resource(
+        &self,
+        source: &R,
+        source_path: &Path,
+        resources: &ResourceManager<R>
+    ) -> Result<Vec<u8>, ConfigurafoxError> {
+        (**self).process_resource(source, source_path, resources)
+    }
+}
+
+/// Processes every registered resource and writes the results to the directory `output_path`
 pub fn run<'data, R: Resource, D, F: Fn(&Path, &R, &'data D) -> Box<dyn ResourceProcessor<R> + 'data>>(
     output_path: &Path,
     resman: &ResourceManager<R>,
     processor_for: F,
     data: &'data D,
 ) -> Result<(), ConfigurafoxError> {
+    run_with_backend(&mut LocalBackend::new(output_path.to_owned()), resman, processor_for, data)
+}
 
-    for (resource, path) in resman.all_registered_files() {
-        let processor = processor_for(&path, &resource, data);
+/// Like `run`, but hands the outputs to an arbitrary `OutputBackend`
+pub fn run_with_backend<'data, R: Resource, D, F: Fn(&Path, &R, &'data D) -> Box<dyn ResourceProcessor<R> + 'data>>(
+    backend: &mut dyn OutputBackend,
+    resman: &ResourceManager<R>,
+    processor_for: F,
+    data: &'data D,
+) -> Result<(), ConfigurafoxError> {
+    resman.diagnostics().clear();
+    run_selected(backend, resman, |_| true, processor_for, data)
+}
 
+/// Like `run_with_backend`, but only processes `resources`, e.g. the `affected` set of a `watch::ChangeSet`.
+/// Diagnostics of other resources are kept
+pub fn rebuild_with_backend<'data, R: Resource, D, F: Fn(&Path, &R, &'data D) -> Box<dyn ResourceProcessor<R> + 'data>>(
+    backend: &mut dyn OutputBackend,
+    resman: &ResourceManager<R>,
+    resources: &std::collections::HashSet<R>,
+    processor_for: F,
+    data: &'data D,
+) -> Result<(), ConfigurafoxError> {
+    {
+        let mut diagnostics = resman.diagnostics();
+        for (path, _) in resman.iter().filter(|(_, resource)| resources.contains(resource)) {
+            diagnostics.clear_path(path);
+        }
+    }
+    run_selected(backend, resman, |resource| resources.contains(resource), processor_for, data)
+}
+
+fn run_selected<'data, R: Resource, D, S: Fn(&R) -> bool, F: Fn(&Path, &R, &'data D) -> Box<dyn ResourceProcessor<R> + 'data>>(
+    backend: &mut dyn OutputBackend,
+    resman: &ResourceManager<R>,
+    selected: S,
+    processor_for: F,
+    data: &'data D,
+) -> Result<(), ConfigurafoxError> {
+    let _run = tracing::info_span!("run", backend = %backend.describe()).entered();
+    let started = std::time::Instant::now();
+
+    info!("Writing output to {}", backend.describe());
+
+    let mut count = 0;
+    for (path, resource) in resman.iter().filter(|(_, resource)| selected(resource)) {
+        count += 1;
+        let processor = processor_for(path, resource, data);
+
+        let _process = tracing::info_span!("process", resource = %resource.identifier(), path = %path.display()).entered();
         info!("Processing {} @ {} w/ {}", resource.identifier(), path.display(), processor.name());
 
-        let processed = processor.process_resource(
-            &resource,
-            &path,
-            resman,
-        )?;
+        resman.dependency_graph().clear_dependencies_of(resource);
+
+        let processed = processor
+            .process_resource(
+                resource,
+                path,
+                resman,
+            )
+            .map_err(|e| ConfigurafoxError::InResource {
+                identifier: resource.identifier(),
+                path: path.to_owned(),
+                processor: processor.name(),
+                error: Box::new(e),
+            })?;
+
+        let _write = tracing::debug_span!("write").entered();
+        backend.write_file(&resman.output_path(resource), &processed)?;
+    }
 
-        let output_path = {
-            let mut output_path = output_path.to_owned();
-            output_path.push(resource.output_path());
-            output_path
-        };
+    finish_run(backend, resman, count, started)
+}
 
-        let output_dir = output_path.parent().expect("No parent dir to output path"); // should never happen as output_path was created with a push
-        if !output_dir.exists() {
-            debug!("Creating output directory {}", output_dir.display());
-            std::fs::create_dir_all(output_dir)?;
-        }
+/// Writes the derivatives requested during a run that built `count` resources, finishes the backend, and reports
+/// the diagnostics
+pub(crate) fn finish_run<R: Resource>(backend: &mut dyn OutputBackend, resman: &ResourceManager<R>, count: usize, started: std::time::Instant) -> Result<(), ConfigurafoxError> {
+    let derivatives = resman.take_derivatives();
+    for (path, derivative) in &derivatives {
+        let _derive = tracing::info_span!("derive", resource = %derivative.source.identifier(), path = %path.display()).entered();
+        info!("Deriving {} from {}", path.display(), derivative.source.identifier());
+
+        let contents = resman.with_file(&derivative.source, |source| (derivative.make)(source))??;
+        backend.write_file(path, &contents)?;
+    }
 
-        debug!("Writing {} bytes to {}", processed.len(), output_path.display());
+    backend.finish()?;
 
-        let mut f = std::fs::File::create(output_path)?;
-        f.write_all(&processed)?;
+    info!("Built {count} resources and {} derivatives in {:.2?}", derivatives.len(), started.elapsed());
+
+    let diagnostics = resman.diagnostics();
+    for diagnostic in diagnostics.sorted() {
+        warn!("{diagnostic}");
+    }
+    let count = diagnostics.warning_count() + diagnostics.error_count();
+    if diagnostics.warnings_as_errors && count > 0 {
+        return Err(ConfigurafoxError::WarningsAsErrors { count });
     }
 
     Ok(())
@@ -102,22 +549,150 @@ impl<R: Resource> ResourceProcessor<R> for IdentityProcessor {
         source_path: &Path,
         resources: &ResourceManager<R>
     ) -> Result<Vec<u8>, ConfigurafoxError> {
-        debug!("Copying {}", source.identifier());
+        debug!("Copying {} from {}", source.identifier(), source_path.display());
 
-        let mut file = std::fs::File::open(resources.absolute_path(source_path))?;
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)?;
-
-        Ok(data)
+        Ok(resources.read(source)?.to_vec())
     }
 }
 
 /// TODO: Add an image-compressor thingy or something
 
+#[non_exhaustive]
 pub struct HTMLProcessor<'data, R: Resource, D> {
     pub walkers: Vec<Box<dyn TreeWalker<R, D>>>,
     pub trim: bool,
+    /// Indents the output, see `serialize::to_pretty_html`
+    pub pretty: bool,
+    /// Where `trim` and `pretty` keep whitespace
+    pub whitespace: serialize::Whitespace,
     pub data: &'data D,
+    /// Checks run over the final DOM, reported as warnings through `ResourceManager::diagnostics`
+    pub lints: lint::Lints,
+    /// Overrides the build's `Diagnostics::strictness` for the pages of this processor
+    pub strictness: Option<diagnostics::Strictness>,
+    /// How print variants are made from their page, see `print::PrintVariants`
+    pub print: print::PrintOptions,
+}
+
+impl<R: Resource + 'static> HTMLProcessor<'static, R, ()> {
+    /// An `HTMLProcessor` without walkers, trimming, pretty-printing, lints or data, to add to with the builder's
+    /// methods
+    pub fn builder() -> HTMLProcessorBuilder<'static, R, ()> {
+        HTMLProcessorBuilder {
+            processor: HTMLProcessor {
+                walkers: Vec::new(),
+                trim: false,
+                pretty: false,
+                whitespace: serialize::Whitespace::default(),
+                data: &(),
+                lints: lint::Lints::none(),
+                strictness: None,
+                print: print::PrintOptions::default(),
+            },
+        }
+    }
+}
+
+/// Builds an `HTMLProcessor`, see `HTMLProcessor::builder`
+pub struct HTMLProcessorBuilder<'data, R: Resource, D> {
+    processor: HTMLProcessor<'data, R, D>,
+}
+
+impl<R: Resource + 'static> HTMLProcessorBuilder<'static, R, ()> {
+    /// Makes `data` available to walkers as `Context::data`. Walkers added before keep seeing `()`
+    pub fn data<'data, D>(self, data: &'data D) -> HTMLProcessorBuilder<'data, R, D> {
+        let HTMLProcessor { walkers, trim, pretty, whitespace, data: _, lints, strictness, print } = self.processor;
+        let walkers = walkers.into_iter().map(|walker| Box::new(WithoutData(walker)) as Box<dyn TreeWalker<R, D>>).collect();
+        HTMLProcessorBuilder { processor: HTMLProcessor { walkers, trim, pretty, whitespace, data, lints, strictness, print } }
+    }
+}
+
+impl<'data, R: Resource, D> HTMLProcessorBuilder<'data, R, D> {
+    /// Adds a walker after the ones added so far. The first walker matching an element replaces it
+    pub fn walker<W: TreeWalker<R, D> + 'static>(mut self, walker: W) -> Self {
+        self.processor.walkers.push(Box::new(walker));
+        self
+    }
+
+    pub fn walkers<I: IntoIterator<Item = Box<dyn TreeWalker<R, D>>>>(mut self, walkers: I) -> Self {
+        self.processor.walkers.extend(walkers);
+        self
+    }
+
+    /// Removes whitespace-only text between elements, except in preformatted ones, see `whitespace`
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.processor.trim = trim;
+        self
+    }
+
+    /// Indents the output for reading and diffing, see `serialize::to_pretty_html`. Independent of `trim`
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.processor.pretty = pretty;
+        self
+    }
+
+    /// Which elements keep their whitespace when trimming or pretty-printing, `serialize::PREFORMATTED_ELEMENTS` by default
+    pub fn whitespace(mut self, whitespace: serialize::Whitespace) -> Self {
+        self.processor.whitespace = whitespace;
+        self
+    }
+
+    pub fn lints(mut self, lints: lint::Lints) -> Self {
+        self.processor.lints = lints;
+        self
+    }
+
+    /// Uses `Strictness::strict` for these pages, or the build's strictness again
+    pub fn strict(self, strict: bool) -> Self {
+        self.strictness(strict.then(diagnostics::Strictness::strict))
+    }
+
+    pub fn strictness(mut self, strictness: Option<diagnostics::Strictness>) -> Self {
+        self.processor.strictness = strictness;
+        self
+    }
+
+    pub fn print(mut self, print: print::PrintOptions) -> Self {
+        self.processor.print = print;
+        self
+    }
+
+    pub fn build(self) -> HTMLProcessor<'data, R, D> {
+        self.processor
+    }
+}
+
+/// Runs a walker added before `HTMLProcessorBuilder::data` with `()` as data
+struct WithoutData<R: Resource>(Box<dyn TreeWalker<R, ()>>);
+
+impl<R: Resource, D> TreeWalker<R, D> for WithoutData<R> {
+    fn describe(&self) -> String {
+        self.0.describe()
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], ctx: Context<'_, '_, R, D>) -> bool {
+        self.0.matches(tag_name, attrs, ctx.without_data())
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<html_editor::Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<html_editor::Node>, ConfigurafoxError> {
+        self.0.replace(tag_name, attrs, children, ctx.without_data())
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        self.0.tags()
+    }
+
+    fn matches_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> bool {
+        self.0.matches_text(text, ancestors, ctx.without_data())
+    }
+
+    fn replace_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> Result<Vec<html_editor::Node>, ConfigurafoxError> {
+        self.0.replace_text(text, ancestors, ctx.without_data())
+    }
+
+    fn finish(&self, dom: &mut Vec<html_editor::Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        self.0.finish(dom, ctx.without_data())
+    }
 }
 
 impl<'data, R: Resource, D> ResourceProcessor<R> for HTMLProcessor<'data, R, D> {
@@ -134,30 +709,74 @@ impl<'data, R: Resource, D> ResourceProcessor<R> for HTMLProcessor<'data, R, D>
     ) -> Result<Vec<u8>, ConfigurafoxError> {
         debug!("Loading {}", source.identifier());
 
-        let mut file = std::fs::File::open(resources.absolute_path(&source_path))?;
-        let mut data = String::new();
-        file.read_to_string(&mut data)?;
+        // Print variants are rendered from their page, as if they were it, see `print::PrintVariants`
+        let print_of = print::print_of(resources, source);
+        let variant = source;
+        let (source, source_path) = match print_of {
+            Some(page) => (page, resources.path_of(page).unwrap_or(source_path)),
+            None => (source, source_path),
+        };
+
+        let data = resources.read_to_string(source)?;
+        let body = metadata::strip_front_matter(&data);
+        let first_line = metadata::body_first_line(&data);
 
-        let mut dom = html_editor::parse(&data).map_err(|e| ConfigurafoxError::ParseHTMLError { path: source_path.to_owned(), error: e })?;
+        let parsed = tracing::debug_span!("parse").in_scope(|| entities::parse(&position::annotate_positions(body, first_line)));
+        let mut dom = parsed.map_err(|e| ConfigurafoxError::parse_html(source_path, body, first_line, e))?;
 
+        let extensions = Extensions::new();
         let ctx = Context {
-            resource: source,
-            source_path,
-            data: self.data,
-            resources,
+            strictness: self.strictness,
+            ..Context::new(source, source_path, resources, self.data, &extensions)
         };
 
-        walk(
+        tracing::debug_span!("walk").in_scope(|| walk(
             &mut dom,
             &self.walkers,
             ctx,
-        )?;
+        ))?;
+
+        if let Some(page) = print_of {
+            self.print.apply(&mut dom, variant, page, resources)?;
+        }
+
+        if error_pages::is_error_page(resources, variant) {
+            let base = resources.metadata(variant).get_str(error_pages::BASE_PATH_KEY).unwrap_or("/");
+            error_pages::absolute_links(&mut dom, &resources.output_path(variant), base)?;
+        }
+
+        let unknown_tags = ctx.strictness().unknown_tags;
+        if unknown_tags != diagnostics::Handling::PassThrough {
+            for tag in lint::unknown_tags(&dom) {
+                ctx.tolerate(unknown_tags, ConfigurafoxError::UnknownTag { tag })?;
+            }
+        }
 
         if self.trim {
-            dom.trim();
+            serialize::trim(&mut dom, &self.whitespace);
+        }
+
+        if !self.lints.is_empty() {
+            let _lint = tracing::debug_span!("lint").entered();
+            let mut diagnostics = resources.diagnostics();
+            for (rule, message) in lint::lint(&dom, &self.lints) {
+                diagnostics.push(diagnostics::Diagnostic {
+                    severity: diagnostics::Severity::Warning,
+                    code: Some(rule.code().to_string()),
+                    path: Some(source_path.to_owned()),
+                    pos: None,
+                    message,
+                });
+            }
         }
 
-        let html_str = dom.html();
+        let html_str = tracing::debug_span!("serialize").in_scope(|| {
+            if self.pretty {
+                serialize::to_pretty_html(&dom, &self.whitespace)
+            } else {
+                serialize::to_html(&dom)
+            }
+        });
 
         Ok(html_str.into_bytes())
     }
@@ -0,0 +1,377 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use html_editor::{Element, Node};
+use serde::Serialize;
+
+use crate::ConfigurafoxError;
+
+/// Where checked external links are remembered, relative to the project root
+pub const DEFAULT_LINK_CACHE: &str = ".configurafox-cache/links.json";
+
+/// Attributes holding a URL to check
+const URL_ATTRS: [&str; 3] = ["href", "src", "poster"];
+
+/// Schemes that don't point at anything checkable
+const IGNORED_SCHEMES: [&str; 4] = ["mailto:", "tel:", "javascript:", "data:"];
+
+#[derive(Debug, Clone)]
+pub struct LinkCheckOptions {
+    /// The URL the site is served at. Absolute links below it are checked as internal links
+    pub site_url: Option<String>,
+    /// Also request `http` and `https` links to other sites. Needs the `remote` feature
+    pub external: bool,
+    /// Where to remember external links that worked, so they aren't requested on every check
+    pub cache_file: Option<PathBuf>,
+    /// How long a working external link is trusted without requesting it again
+    pub cache_ttl: Duration,
+    /// The least time between two requests to the same host
+    pub host_delay: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for LinkCheckOptions {
+    fn default() -> Self {
+        LinkCheckOptions {
+            site_url: None,
+            external: false,
+            cache_file: None,
+            cache_ttl: Duration::from_secs(24 * 60 * 60),
+            host_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum LinkProblem {
+    /// Nothing in the output is at the link's path
+    MissingTarget,
+    /// The target exists, but has no element with the link's fragment as `id`
+    MissingAnchor { anchor: String },
+    /// An external link answered with an error status
+    Status { status: u16 },
+    /// An external link couldn't be requested at all
+    Unreachable { msg: String },
+}
+
+impl fmt::Display for LinkProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkProblem::MissingTarget => write!(f, "target does not exist"),
+            LinkProblem::MissingAnchor { anchor } => write!(f, "target has no anchor #{anchor}"),
+            LinkProblem::Status { status } => write!(f, "status {status}"),
+            LinkProblem::Unreachable { msg } => write!(f, "unreachable: {msg}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenLink {
+    /// The page with the link, relative to the output directory
+    pub page: PathBuf,
+    pub url: String,
+    pub problem: LinkProblem,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LinkReport {
+    pub pages: usize,
+    pub internal_links: usize,
+    pub external_links: usize,
+    /// Sorted by page, then URL
+    pub broken: Vec<BrokenLink>,
+}
+
+impl LinkReport {
+    pub fn is_ok(&self) -> bool {
+        self.broken.is_empty()
+    }
+}
+
+impl fmt::Display for LinkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for BrokenLink { page, url, problem } in &self.broken {
+            writeln!(f, "{}: {url}: {problem}", page.display())?;
+        }
+        writeln!(
+            f,
+            "Checked {} internal and {} external links in {} pages, {} broken",
+            self.internal_links,
+            self.external_links,
+            self.pages,
+            self.broken.len(),
+        )
+    }
+}
+
+/// Every URL in an attribute of `URL_ATTRS`, and every `id` (and `name` of `<a>`), in document order
+fn collect(dom: &[Node], urls: &mut Vec<String>, ids: &mut HashSet<String>) {
+    for node in dom {
+        let Node::Element(Element { name, attrs, children }) = node else {
+            continue;
+        };
+        for (key, value) in attrs {
+            if URL_ATTRS.contains(&key.as_str()) {
+                urls.push(value.clone());
+            } else if key == "id" || (name == "a" && key == "name") {
+                ids.insert(value.clone());
+            }
+        }
+        collect(children, urls, ids);
+    }
+}
+
+/// Decodes `%XX` escapes, leaving invalid ones alone
+pub(crate) fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The output path `url_path` points at, seen from `page`. None if it leaves the output directory
+pub(crate) fn resolve(page: &Path, url_path: &str) -> Option<PathBuf> {
+    let joined = match url_path.strip_prefix('/') {
+        Some(absolute) => PathBuf::from(absolute),
+        None => page.parent().unwrap_or(Path::new("")).join(url_path),
+    };
+
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::ParentDir => {
+                let true = resolved.pop() else {
+                    return None;
+                };
+            }
+            _ => {}
+        }
+    }
+    Some(resolved)
+}
+
+/// The file serving `path`: the file itself, `index.html` in the directory, or the file with `.html` added,
+/// like most static hosts do
+fn find_target<'a>(files: &'a HashMap<PathBuf, Vec<u8>>, path: &Path, is_dir: bool) -> Option<&'a Path> {
+    let mut candidates = vec![path.join("index.html")];
+    if !is_dir {
+        candidates.insert(0, path.to_owned());
+        candidates.push(path.with_extension("html"));
+    }
+    candidates.into_iter().find_map(|candidate| files.get_key_value(&candidate).map(|(key, _)| key.as_path()))
+}
+
+pub(crate) fn is_html(path: &Path) -> bool {
+    matches!(path.extension().and_then(|ext| ext.to_str()), Some("html" | "htm"))
+}
+
+/// Checks every link in the HTML files of a build, given by path relative to the output directory. Internal
+/// links must point at a file of the build, and their fragment at an `id` of the target. External links are
+/// only counted, unless `options.external` is set
+pub fn check_links(files: &HashMap<PathBuf, Vec<u8>>, options: &LinkCheckOptions) -> Result<LinkReport, ConfigurafoxError> {
+    let mut pages = files.keys().filter(|path| is_html(path)).cloned().collect::<Vec<_>>();
+    pages.sort();
+
+    let mut links = Vec::new();
+    let mut ids = HashMap::new();
+    for page in &pages {
+        let source = String::from_utf8_lossy(&files[page]);
+        let dom = crate::entities::parse(&source).map_err(|e| ConfigurafoxError::parse_html(page, &source, 1, e))?;
+        let (mut urls, mut page_ids) = (Vec::new(), HashSet::new());
+        collect(&dom, &mut urls, &mut page_ids);
+        links.extend(urls.into_iter().map(|url| (page, url)));
+        ids.insert(page.as_path(), page_ids);
+    }
+
+    let site_url = options.site_url.as_deref().map(|url| url.trim_end_matches('/'));
+    let mut report = LinkReport { pages: pages.len(), ..LinkReport::default() };
+    // External URLs with the pages linking to them, each checked once
+    let mut external: HashMap<String, BTreeSet<&PathBuf>> = HashMap::new();
+
+    for (page, url) in links {
+        if url.is_empty() || IGNORED_SCHEMES.iter().any(|scheme| url.to_ascii_lowercase().starts_with(scheme)) {
+            continue;
+        }
+
+        let local = match site_url.and_then(|site_url| url.strip_prefix(site_url)) {
+            Some(rest) if rest.is_empty() || rest.starts_with(['/', '?', '#']) => format!("/{}", rest.trim_start_matches('/')),
+            _ if url.starts_with("//") || url.contains("://") => {
+                report.external_links += 1;
+                external.entry(url).or_default().insert(page);
+                continue;
+            }
+            _ => url.clone(),
+        };
+        report.internal_links += 1;
+
+        let (without_fragment, fragment) = local.split_once('#').map_or((local.as_str(), None), |(path, fragment)| (path, Some(fragment)));
+        let path = without_fragment.split('?').next().unwrap_or("");
+
+        let target = if path.is_empty() {
+            Some(page.as_path())
+        } else {
+            resolve(page, &percent_decode(path)).and_then(|resolved| find_target(files, &resolved, path.ends_with('/')))
+        };
+        let problem = match (target, fragment) {
+            (None, _) => Some(LinkProblem::MissingTarget),
+            (Some(target), Some(anchor)) if !anchor.is_empty() && anchor != "top" => {
+                let anchor = percent_decode(anchor);
+                let found = ids.get(target).is_none_or(|ids| ids.contains(&anchor));
+                (!found).then_some(LinkProblem::MissingAnchor { anchor })
+            }
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            report.broken.push(BrokenLink { page: page.clone(), url, problem });
+        }
+    }
+
+    if options.external {
+        #[cfg(feature = "remote")]
+        {
+            let results = external::check(external.keys().cloned().collect(), options)?;
+            for (url, problem) in results {
+                for page in &external[&url] {
+                    report.broken.push(BrokenLink { page: (*page).clone(), url: url.clone(), problem: problem.clone() });
+                }
+            }
+        }
+        #[cfg(not(feature = "remote"))]
+        return Err(ConfigurafoxError::Other("checking external links needs the remote feature".to_string()));
+    }
+
+    report.broken.sort_by(|a, b| (&a.page, &a.url).cmp(&(&b.page, &b.url)));
+    Ok(report)
+}
+
+/// Checks the links of the build in `output_dir`, see `check_links`
+pub fn check_output_dir(output_dir: &Path, options: &LinkCheckOptions) -> Result<LinkReport, ConfigurafoxError> {
+    check_links(&read_build(output_dir)?, options)
+}
+
+/// The files of the build in `output_dir`, by path relative to it. Only pages are read, other files just need
+/// to exist, so they're empty
+pub fn read_build(output_dir: &Path) -> std::io::Result<HashMap<PathBuf, Vec<u8>>> {
+    fn visit(root: &Path, dir: &Path, files: &mut HashMap<PathBuf, Vec<u8>>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                visit(root, &path, files)?;
+            } else {
+                let relative = path.strip_prefix(root).expect("read_dir stays below root").to_owned();
+                let contents = if is_html(&path) { std::fs::read(&path)? } else { Vec::new() };
+                files.insert(relative, contents);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = HashMap::new();
+    visit(output_dir, output_dir, &mut files)?;
+    Ok(files)
+}
+
+#[cfg(feature = "remote")]
+mod external {
+    #[allow(unused)]
+    use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+    use std::collections::{BTreeMap, HashMap};
+    use std::path::Path;
+    use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+    use super::{LinkCheckOptions, LinkProblem};
+    use crate::ConfigurafoxError;
+
+    /// When each external link was last seen working, in seconds since the epoch. Broken links aren't cached,
+    /// so fixing them shows up on the next check
+    type Cache = BTreeMap<String, u64>;
+
+    fn load_cache(path: &Path) -> Cache {
+        std::fs::read(path).ok().and_then(|json| serde_json::from_slice(&json).ok()).unwrap_or_default()
+    }
+
+    fn save_cache(path: &Path, cache: &Cache) -> Result<(), ConfigurafoxError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(cache).map_err(std::io::Error::other)?;
+        Ok(std::fs::write(path, json)?)
+    }
+
+    fn host(url: &str) -> &str {
+        let after_scheme = url.split_once("//").map_or(url, |(_, rest)| rest);
+        after_scheme.split(['/', '?', '#']).next().unwrap_or(after_scheme)
+    }
+
+    /// Requests each of `urls`, with `HEAD` first since it skips the body, falling back to `GET` for servers
+    /// that don't allow it. Returns the broken ones
+    pub(super) fn check(mut urls: Vec<String>, options: &LinkCheckOptions) -> Result<Vec<(String, LinkProblem)>, ConfigurafoxError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut cache = options.cache_file.as_deref().map(load_cache).unwrap_or_default();
+        cache.retain(|_, checked| now.saturating_sub(*checked) < options.cache_ttl.as_secs());
+
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(options.timeout))
+            .build()
+            .into();
+
+        urls.sort();
+        let mut last_request: HashMap<String, Instant> = HashMap::new();
+        let mut broken = Vec::new();
+        for url in urls {
+            if cache.contains_key(&url) {
+                debug!("{url} worked recently, not checking it");
+                continue;
+            }
+            // Protocol-relative links are checked over https, and fragments aren't sent
+            let without_fragment = url.split('#').next().unwrap_or(&url);
+            let request_url = if url.starts_with("//") { format!("https:{without_fragment}") } else { without_fragment.to_string() };
+
+            if let Some(last) = last_request.get(host(&request_url)) {
+                std::thread::sleep(options.host_delay.saturating_sub(last.elapsed()));
+            }
+            last_request.insert(host(&request_url).to_string(), Instant::now());
+
+            let status = match agent.head(&request_url).call() {
+                Ok(response) if matches!(response.status().as_u16(), 405 | 501) => agent.get(&request_url).call().map(|r| r.status().as_u16()),
+                result => result.map(|r| r.status().as_u16()),
+            };
+            match status {
+                Ok(status) if status < 400 => {
+                    cache.insert(url, now);
+                }
+                Ok(status) => broken.push((url, LinkProblem::Status { status })),
+                Err(e) => broken.push((url, LinkProblem::Unreachable { msg: e.to_string() })),
+            }
+        }
+
+        if let Some(path) = &options.cache_file {
+            save_cache(path, &cache)?;
+        }
+        info!("Checked external links, {} broken", broken.len());
+        Ok(broken)
+    }
+}
@@ -0,0 +1,235 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeSet, HashSet};
+
+use html_editor::{Element, Node};
+
+use crate::treewalker::get_attr;
+
+/// A check run over the final DOM of a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintRule {
+    /// `<img>` without an `alt` attribute. `alt=""` is fine, it marks decorative images
+    MissingAlt,
+    /// A heading more than one level below the previous one, e.g. `<h2>` followed by `<h4>`
+    SkippedHeadingLevel,
+    /// `<a>` without a `href`, or without any text or label
+    EmptyLink,
+    /// Several elements with the same `id`
+    DuplicateId,
+    /// `<table>` without any `<th>`
+    TableWithoutHeaders,
+}
+
+impl LintRule {
+    pub const ALL: [LintRule; 5] = [
+        LintRule::MissingAlt,
+        LintRule::SkippedHeadingLevel,
+        LintRule::EmptyLink,
+        LintRule::DuplicateId,
+        LintRule::TableWithoutHeaders,
+    ];
+
+    /// The rule with the diagnostic code `code`
+    pub fn from_code(code: &str) -> Option<LintRule> {
+        LintRule::ALL.into_iter().find(|rule| rule.code() == code)
+    }
+
+    /// The diagnostic code of this rule
+    pub fn code(&self) -> &'static str {
+        match self {
+            LintRule::MissingAlt => "missing-alt",
+            LintRule::SkippedHeadingLevel => "skipped-heading-level",
+            LintRule::EmptyLink => "empty-link",
+            LintRule::DuplicateId => "duplicate-id",
+            LintRule::TableWithoutHeaders => "table-without-headers",
+        }
+    }
+}
+
+/// Which lint rules are enabled. None by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lints {
+    enabled: BTreeSet<LintRule>,
+}
+
+impl Lints {
+    pub fn none() -> Lints {
+        Lints::default()
+    }
+
+    pub fn all() -> Lints {
+        Lints { enabled: LintRule::ALL.into_iter().collect() }
+    }
+
+    pub fn enable(mut self, rule: LintRule) -> Lints {
+        self.enabled.insert(rule);
+        self
+    }
+
+    pub fn disable(mut self, rule: LintRule) -> Lints {
+        self.enabled.remove(&rule);
+        self
+    }
+
+    pub fn is_enabled(&self, rule: LintRule) -> bool {
+        self.enabled.contains(&rule)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.enabled.is_empty()
+    }
+}
+
+/// The elements of the HTML standard, excluding obsolete ones
+const HTML_ELEMENTS: &[&str] = &[
+    "a", "abbr", "address", "area", "article", "aside", "audio", "b", "base", "bdi", "bdo", "blockquote", "body",
+    "br", "button", "canvas", "caption", "cite", "code", "col", "colgroup", "data", "datalist", "dd", "del",
+    "details", "dfn", "dialog", "div", "dl", "dt", "em", "embed", "fieldset", "figcaption", "figure", "footer",
+    "form", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "i", "iframe", "img",
+    "input", "ins", "kbd", "label", "legend", "li", "link", "main", "map", "mark", "menu", "meta", "meter", "nav",
+    "noscript", "object", "ol", "optgroup", "option", "output", "p", "picture", "pre", "progress", "q", "rp",
+    "rt", "ruby", "s", "samp", "script", "search", "section", "select", "slot", "small", "source", "span",
+    "strong", "style", "sub", "summary", "sup", "table", "tbody", "td", "template", "textarea", "tfoot", "th",
+    "thead", "time", "title", "tr", "track", "u", "ul", "var", "video", "wbr",
+];
+
+/// Elements whose contents follow other rules, and aren't checked by `unknown_tags`
+const FOREIGN_ELEMENTS: &[&str] = &["svg", "math"];
+
+/// Names with a `-` that are still not valid custom element names, since SVG and MathML use them
+const RESERVED_CUSTOM_NAMES: &[&str] = &[
+    "annotation-xml", "color-profile", "font-face", "font-face-src", "font-face-uri", "font-face-format", "font-face-name",
+    "missing-glyph",
+];
+
+/// Whether `name` is a valid name for a custom element, like `<my-widget>`: starting with a letter and containing
+/// a `-`. These are as much HTML as the standard elements
+pub fn is_custom_element_name(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower.starts_with(|c: char| c.is_ascii_lowercase()) && lower.contains('-') && !RESERVED_CUSTOM_NAMES.contains(&&*lower)
+}
+
+/// The tags in `dom` that aren't standard HTML elements or custom elements, each once, in document order. Contents
+/// of `<svg>` and `<math>` are not checked
+pub fn unknown_tags(dom: &[Node]) -> Vec<String> {
+    fn visit(dom: &[Node], found: &mut Vec<String>) {
+        for node in dom {
+            let Node::Element(Element { name, children, .. }) = node else {
+                continue;
+            };
+            let lower = name.to_ascii_lowercase();
+            if FOREIGN_ELEMENTS.contains(&&*lower) {
+                continue;
+            }
+            if !HTML_ELEMENTS.contains(&&*lower) && !is_custom_element_name(name) && !found.contains(name) {
+                found.push(name.clone());
+            }
+            visit(children, found);
+        }
+    }
+
+    let mut found = Vec::new();
+    visit(dom, &mut found);
+    found
+}
+
+/// Runs the enabled rules over `dom`, returning the problems found in document order
+pub fn lint(dom: &[Node], lints: &Lints) -> Vec<(LintRule, String)> {
+    let mut linter = Linter { lints, problems: Vec::new(), ids: HashSet::new(), last_heading: None };
+    linter.visit(dom);
+    linter.problems
+}
+
+struct Linter<'a> {
+    lints: &'a Lints,
+    problems: Vec<(LintRule, String)>,
+    ids: HashSet<String>,
+    last_heading: Option<u8>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: LintRule, message: String) {
+        if self.lints.is_enabled(rule) {
+            self.problems.push((rule, message));
+        }
+    }
+
+    fn visit(&mut self, dom: &[Node]) {
+        for node in dom {
+            if let Node::Element(element) = node {
+                self.check(element);
+                self.visit(&element.children);
+            }
+        }
+    }
+
+    fn check(&mut self, element: &Element) {
+        let Element { name, attrs, children } = element;
+
+        if let Some(id) = get_attr(attrs, "id") {
+            if !self.ids.insert(id.to_string()) {
+                self.report(LintRule::DuplicateId, format!("id {id:?} is used more than once"));
+            }
+        }
+
+        match name.as_str() {
+            "img" if get_attr(attrs, "alt").is_none() => {
+                let src = get_attr(attrs, "src").unwrap_or("");
+                self.report(LintRule::MissingAlt, format!("<img src={src:?}> has no alt text"));
+            }
+            "a" => {
+                let href = get_attr(attrs, "href").unwrap_or("");
+                if href.is_empty() {
+                    self.report(LintRule::EmptyLink, "<a> has no href".to_string());
+                } else if get_attr(attrs, "aria-label").is_none() && get_attr(attrs, "title").is_none() && !has_content(children) {
+                    self.report(LintRule::EmptyLink, format!("<a href={href:?}> has no text"));
+                }
+            }
+            "table" if !contains_element(children, "th") => {
+                self.report(LintRule::TableWithoutHeaders, "<table> has no <th> headers".to_string());
+            }
+            heading @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                let level = heading.as_bytes()[1] - b'0';
+                if let Some(last) = self.last_heading {
+                    if level > last + 1 {
+                        self.report(LintRule::SkippedHeadingLevel, format!("<{heading}> follows <h{last}>"));
+                    }
+                }
+                self.last_heading = Some(level);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// If `dom` has any non-whitespace text, raw HTML, or an image with alt text
+fn has_content(dom: &[Node]) -> bool {
+    dom.iter().any(|node| match node {
+        Node::Text(text) | Node::RawHTML(text) => !text.trim().is_empty(),
+        Node::Element(Element { name, attrs, children }) => {
+            (name == "img" && get_attr(attrs, "alt").is_some_and(|alt| !alt.is_empty())) || has_content(children)
+        }
+        _ => false,
+    })
+}
+
+fn contains_element(dom: &[Node], tag: &str) -> bool {
+    dom.iter().any(|node| match node {
+        Node::Element(Element { name, children, .. }) => name == tag || contains_element(children, tag),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unknown_tags;
+    use crate::entities::parse;
+
+    #[test]
+    fn custom_elements_are_html() {
+        let dom = parse("<my-widget><sl-button>Go</sl-button></my-widget><note><p>x</p></note><font-face></font-face><svg><g/></svg>").unwrap();
+        assert_eq!(unknown_tags(&dom), ["note", "font-face"]);
+    }
+}
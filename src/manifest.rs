@@ -0,0 +1,225 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+
+use html_editor::Node;
+
+use crate::{ConfigurafoxError, relative_url};
+use crate::favicon::favicons;
+use crate::feed::DESCRIPTION_KEY;
+use crate::metadata::{Metadata, Value};
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::site::SiteData;
+use crate::treewalker::{Context, TreeWalker, element};
+
+/// Where the manifest is generated, relative to the directory given to `WebManifest::generate`
+pub const MANIFEST_FILE: &str = "manifest.webmanifest";
+
+/// Metadata keys of the manifest `WebManifest` generates: `true`, and its theme color if it has one
+pub const MANIFEST_KEY: &str = "web_manifest";
+pub const THEME_COLOR_KEY: &str = "theme_color";
+
+/// An icon of a manifest besides the favicons
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestIcon {
+    pub identifier: String,
+    /// E.g. `512x512`, or `any` for SVGs
+    pub sizes: String,
+    /// E.g. `maskable`
+    pub purpose: Option<String>,
+}
+
+/// Generates a web app manifest, `manifest.webmanifest`, as a resource with its contents in memory, so the site
+/// can be installed as an app. Its icons are the ones of the favicon set `favicon::Favicons` registered that
+/// aren't linked from pages, and any given by identifier, with their URLs relative to the manifest. Generate
+/// after registering the icons
+#[derive(Debug, Clone)]
+pub struct WebManifest {
+    /// By default `SiteData::title`
+    pub name: Option<String>,
+    /// Shown where there's little room, e.g. under the icon. By default the name
+    pub short_name: Option<String>,
+    /// By default the site variable `DESCRIPTION_KEY`
+    pub description: Option<String>,
+    /// Relative to the manifest
+    pub start_url: String,
+    /// `standalone`, `fullscreen`, `minimal-ui` or `browser`
+    pub display: String,
+    /// CSS colors
+    pub theme_color: Option<String>,
+    pub background_color: Option<String>,
+    pub icons: Vec<ManifestIcon>,
+}
+
+impl Default for WebManifest {
+    fn default() -> Self {
+        WebManifest {
+            name: None,
+            short_name: None,
+            description: None,
+            start_url: "./".to_string(),
+            display: "standalone".to_string(),
+            theme_color: None,
+            background_color: None,
+            icons: Vec::new(),
+        }
+    }
+}
+
+impl WebManifest {
+    pub fn new() -> WebManifest {
+        WebManifest::default()
+    }
+
+    pub fn with_name(mut self, name: &str) -> WebManifest {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_short_name(mut self, short_name: &str) -> WebManifest {
+        self.short_name = Some(short_name.to_string());
+        self
+    }
+
+    pub fn with_description(mut self, description: &str) -> WebManifest {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn with_start_url(mut self, start_url: &str) -> WebManifest {
+        self.start_url = start_url.to_string();
+        self
+    }
+
+    pub fn with_display(mut self, display: &str) -> WebManifest {
+        self.display = display.to_string();
+        self
+    }
+
+    pub fn with_colors(mut self, theme_color: &str, background_color: &str) -> WebManifest {
+        self.theme_color = Some(theme_color.to_string());
+        self.background_color = Some(background_color.to_string());
+        self
+    }
+
+    pub fn with_icon(mut self, icon: ManifestIcon) -> WebManifest {
+        self.icons.push(icon);
+        self
+    }
+
+    /// Registers the manifest under `dir`, usually the output root, with the resource `make_resource` gives for
+    /// its path, like `ResourceManager::register_all_files_in_directory` does. Returns the registered resource,
+    /// None if `make_resource` gave None
+    pub fn generate<R: Resource, D: SiteData + ?Sized, F: Fn(&Path) -> Option<R>>(
+        &self,
+        resources: &mut ResourceManager<R>,
+        site: &D,
+        dir: &Path,
+        make_resource: F,
+    ) -> Result<Option<R>, ConfigurafoxError> {
+        let path = dir.join(MANIFEST_FILE);
+        let Some(resource) = make_resource(&path) else {
+            return Ok(None);
+        };
+        info!("{}: Generating the web app manifest", path.display());
+
+        let manifest_output = resources.output_path(&resource);
+        let mut icons = Vec::new();
+        for (icon, file) in favicons(resources) {
+            if file.rel.is_none() {
+                let src = relative_url(&resources.output_path(icon), &manifest_output)?;
+                icons.push(serde_json::json!({ "src": src, "sizes": file.sizes_attr(), "type": file.mime_type() }));
+            }
+        }
+        for icon in &self.icons {
+            let target = resources
+                .resource_by_identifier(&icon.identifier)
+                .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: icon.identifier.clone() })?;
+            let output = resources.output_path(target);
+            let mut entry = serde_json::json!({ "src": relative_url(&output, &manifest_output)?, "sizes": icon.sizes });
+            if let Some(mime_type) = output.extension().and_then(|ext| ext.to_str()).and_then(image_mime_type) {
+                entry["type"] = Value::from(mime_type);
+            }
+            if let Some(purpose) = &icon.purpose {
+                entry["purpose"] = Value::from(purpose.as_str());
+            }
+            icons.push(entry);
+        }
+        if icons.is_empty() {
+            warn!("{}: The manifest has no icons, browsers won't offer to install the site", path.display());
+        }
+
+        let name = self.name.as_deref().or(site.title()).ok_or(ConfigurafoxError::MissingSiteData { key: "title" })?;
+        let mut manifest = serde_json::Map::new();
+        manifest.insert("name".to_string(), Value::from(name));
+        manifest.insert("short_name".to_string(), Value::from(self.short_name.as_deref().unwrap_or(name)));
+        let description = self.description.as_deref().or_else(|| site.var(DESCRIPTION_KEY).and_then(Value::as_str));
+        if let Some(description) = description {
+            manifest.insert("description".to_string(), Value::from(description));
+        }
+        manifest.insert("start_url".to_string(), Value::from(self.start_url.as_str()));
+        manifest.insert("display".to_string(), Value::from(self.display.as_str()));
+        if let Some(color) = &self.theme_color {
+            manifest.insert("theme_color".to_string(), Value::from(color.as_str()));
+        }
+        if let Some(color) = &self.background_color {
+            manifest.insert("background_color".to_string(), Value::from(color.as_str()));
+        }
+        manifest.insert("icons".to_string(), Value::Array(icons));
+        let contents = serde_json::to_string_pretty(&Value::Object(manifest)).expect("JSON values serialize");
+
+        let mut metadata = Metadata::new();
+        metadata.insert(MANIFEST_KEY, true);
+        if let Some(color) = &self.theme_color {
+            metadata.insert(THEME_COLOR_KEY, color.as_str());
+        }
+        resources.insert_with_origin(resource.clone(), path, Origin::Memory(contents.into_bytes().into()), metadata);
+        Ok(Some(resource))
+    }
+}
+
+pub(crate) fn image_mime_type(extension: &str) -> Option<&'static str> {
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        "webp" => Some("image/webp"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "ico" => Some("image/x-icon"),
+        _ => None,
+    }
+}
+
+/// Replaces `<manifest-link/>`, which belongs in `<head>`, with a `<link rel="manifest">` to the manifest
+/// `WebManifest` generated, and a `<meta name="theme-color">` if it has a theme color
+pub struct ManifestLink;
+
+impl<R: Resource, D> TreeWalker<R, D> for ManifestLink {
+    fn describe(&self) -> String {
+        "ManifestLink".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "manifest-link"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["manifest-link"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let manifest = ctx
+            .resources
+            .iter()
+            .map(|(_, resource)| resource)
+            .find(|resource| ctx.resources.metadata(resource).get(MANIFEST_KEY).is_some())
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "web manifest", name: MANIFEST_FILE.to_string() })?;
+
+        let mut nodes = vec![element("link", vec![("rel", "manifest".to_string()), ("href", ctx.link_to(manifest)?)], vec![])];
+        if let Some(color) = ctx.resources.metadata(manifest).get_str(THEME_COLOR_KEY) {
+            nodes.push(element("meta", vec![("name", "theme-color".to_string()), ("content", color.to_string())], vec![]));
+        }
+        Ok(nodes)
+    }
+}
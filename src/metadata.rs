@@ -0,0 +1,107 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+
+pub use serde_json::Value;
+
+/// Metadata key overriding where a resource's output is written, relative to the output root
+pub const OUTPUT_PATH_KEY: &str = "output_path";
+
+/// Extension of sidecar files: the metadata of `photo.jpg` is read from `photo.jpg.toml`
+pub const SIDECAR_EXTENSION: &str = "toml";
+
+/// Front matter is TOML between two lines consisting of this, at the start of a document
+pub const FRONT_MATTER_DELIMITER: &str = "+++";
+
+/// Arbitrary key/value data attached to a registered resource (title, date, tags, ...)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata(pub BTreeMap<String, Value>);
+
+impl Metadata {
+    pub const fn new() -> Metadata {
+        Metadata(BTreeMap::new())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+
+    /// Returns the value of `key` if it is a string
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(Value::as_str)
+    }
+
+    pub fn insert<V: Into<Value>>(&mut self, key: &str, value: V) -> Option<Value> {
+        self.0.insert(key.to_string(), value.into())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.0.remove(key)
+    }
+
+    /// Copies all keys of `other` into `self`, overwriting existing ones
+    pub fn merge(&mut self, other: Metadata) {
+        self.0.extend(other.0);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.0.iter().map(|(k, v)| (&**k, v))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<(String, Value)> for Metadata {
+    fn from_iter<T: IntoIterator<Item = (String, Value)>>(iter: T) -> Self {
+        Metadata(iter.into_iter().collect())
+    }
+}
+
+/// Parses a TOML document into metadata, e.g. a sidecar file or front matter
+pub fn parse_toml(source: &str) -> Result<Metadata, String> {
+    let table = source.parse::<toml::Table>().map_err(|e| e.to_string())?;
+    Ok(table.into_iter().map(|(k, v)| (k, toml_to_value(v))).collect())
+}
+
+/// Dates and times become ISO 8601 strings
+pub fn toml_to_value(v: toml::Value) -> Value {
+    match v {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(dt) => Value::String(dt.to_string()),
+        toml::Value::Array(arr) => Value::Array(arr.into_iter().map(toml_to_value).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(k, v)| (k, toml_to_value(v))).collect()),
+    }
+}
+
+/// Splits a document into its front matter and the rest. None if the document has no front matter
+pub fn split_front_matter(source: &str) -> Option<(&str, &str)> {
+    let rest = source.strip_prefix(FRONT_MATTER_DELIMITER)?;
+    let rest = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n'))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+/// The document without its front matter, if it has any
+pub fn strip_front_matter(source: &str) -> &str {
+    split_front_matter(source).map(|(_, body)| body).unwrap_or(source)
+}
+
+/// The line `strip_front_matter(source)` starts at in `source`, 1-based
+pub fn body_first_line(source: &str) -> usize {
+    let body = strip_front_matter(source);
+    source[..source.len() - body.len()].matches('\n').count() + 1
+}
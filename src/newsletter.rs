@@ -0,0 +1,410 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use html_editor::{Doctype, Element, Node};
+
+use crate::{ConfigurafoxError, url_path};
+use crate::linkcheck::{percent_decode, resolve};
+use crate::manifest::image_mime_type;
+use crate::treewalker::{AttributeRules, element, get_attr, text_content};
+
+/// Elements of a post that do nothing in an email, and are left out
+const REMOVED_ELEMENTS: &[&str] = &["script", "style", "link", "template"];
+
+#[derive(Debug, Clone)]
+pub struct NewsletterOptions {
+    /// The URL the site is served at, which links and images are made absolute against
+    pub site_url: String,
+    /// Of the email, by default the `<title>` of the first post
+    pub title: Option<String>,
+    /// Embed the images of the build as `data:` URLs instead of linking to them, for readers that don't load
+    /// remote images
+    pub embed_images: bool,
+    /// Larger images are linked even with `embed_images`, in bytes
+    pub max_embedded_size: usize,
+}
+
+impl NewsletterOptions {
+    pub fn new(site_url: &str) -> NewsletterOptions {
+        NewsletterOptions { site_url: site_url.to_string(), title: None, embed_images: false, max_embedded_size: 100 * 1024 }
+    }
+}
+
+/// Renders built posts, given by path relative to the output directory, into one self-contained HTML file to
+/// send as an email: the `<article>` of each post (or its `<main>`, or its `<body>`), with its links and images
+/// made absolute and the rules of its stylesheets inlined into `style` attributes, since many mail clients
+/// ignore stylesheets. Rules that can't be inlined, like those with combinators, pseudo-classes or media queries,
+/// are kept in a `<style>` in the head for the clients that support it
+pub fn export(files: &HashMap<PathBuf, Vec<u8>>, posts: &[PathBuf], options: &NewsletterOptions) -> Result<String, ConfigurafoxError> {
+    let site_url = options.site_url.trim_end_matches('/');
+    let mut title = options.title.clone();
+    let mut stylesheet = Stylesheet::default();
+    let mut seen_css = HashSet::new();
+    let mut sections = Vec::new();
+
+    for post in posts {
+        let contents = files
+            .get(post)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} wasn't built", post.display())))?;
+        let html = String::from_utf8_lossy(contents);
+        let dom = crate::entities::parse(&html).map_err(|e| ConfigurafoxError::parse_html(post, &html, 1, e))?;
+        debug!("Adding {} to the newsletter", post.display());
+
+        if title.is_none() {
+            title = find(&dom, "title").map(|element| text_content(&element.children));
+        }
+        collect_css(&dom, post, files, site_url, &mut seen_css, &mut stylesheet)?;
+
+        let mut content = ["article", "main", "body"]
+            .into_iter()
+            .find_map(|name| find(&dom, name))
+            .cloned()
+            .unwrap_or_else(|| Element { name: "div".to_string(), attrs: vec![], children: dom.clone() });
+        if content.name != "article" {
+            content.name = "div".to_string();
+        }
+        clean(&mut content.children);
+        sections.push((post, content));
+    }
+
+    let mut body = Element { name: "body".to_string(), attrs: vec![], children: vec![] };
+    for (post, content) in sections {
+        if !body.children.is_empty() {
+            body.children.push(element("hr", vec![], vec![]));
+        }
+        let mut nodes = vec![Node::Element(content)];
+        stylesheet.inline(&mut nodes);
+        rewrite_urls(&mut nodes, post, files, site_url, options)?;
+        body.children.extend(nodes);
+    }
+
+    let mut head = vec![
+        element("meta", vec![("charset", "utf-8".to_string())], vec![]),
+        element("meta", vec![("name", "viewport".to_string()), ("content", "width=device-width, initial-scale=1".to_string())], vec![]),
+    ];
+    if let Some(title) = title {
+        head.push(element("title", vec![], vec![Node::Text(title)]));
+    }
+    if !stylesheet.rest.is_empty() {
+        head.push(element("style", vec![], vec![Node::Text(stylesheet.rest.clone())]));
+    }
+    // Rules for `html` and `body` apply to the email's own
+    stylesheet.inline_element(&mut body);
+    let mut html = Element { name: "html".to_string(), attrs: vec![], children: vec![element("head", vec![], head), Node::Element(body)] };
+    stylesheet.inline_element(&mut html);
+
+    info!("Exported {} posts to the newsletter", posts.len());
+    Ok(crate::serialize::to_html(&[Node::Doctype(Doctype::Html), Node::Element(html)]))
+}
+
+/// The first element named `name`, depth first
+fn find<'a>(dom: &'a [Node], name: &str) -> Option<&'a Element> {
+    dom.iter().find_map(|node| match node {
+        Node::Element(element) if element.name == name => Some(element),
+        Node::Element(element) => find(&element.children, name),
+        _ => None,
+    })
+}
+
+/// Removes `REMOVED_ELEMENTS` and comments, and unwraps `<noscript>`, since mail clients don't run scripts
+fn clean(dom: &mut Vec<Node>) {
+    let mut cleaned = Vec::with_capacity(dom.len());
+    for node in dom.drain(..) {
+        match node {
+            Node::Element(element) if REMOVED_ELEMENTS.contains(&element.name.as_str()) => {}
+            Node::Element(mut element) if element.name == "noscript" => {
+                clean(&mut element.children);
+                cleaned.extend(element.children);
+            }
+            Node::Element(mut element) => {
+                clean(&mut element.children);
+                cleaned.push(Node::Element(element));
+            }
+            Node::Comment(_) => {}
+            node => cleaned.push(node),
+        }
+    }
+    *dom = cleaned;
+}
+
+/// Adds the CSS of the `<style>`s of a page and of the stylesheets it links to that are in the build, with their
+/// `url(...)`s made absolute. The same CSS is only added once
+fn collect_css(
+    dom: &[Node],
+    page: &Path,
+    files: &HashMap<PathBuf, Vec<u8>>,
+    site_url: &str,
+    seen: &mut HashSet<String>,
+    stylesheet: &mut Stylesheet,
+) -> Result<(), ConfigurafoxError> {
+    for node in dom {
+        let Node::Element(Element { name, attrs, children }) = node else {
+            continue;
+        };
+        let (css, base) = match name.as_str() {
+            "style" => (text_content(children), page.to_owned()),
+            "link" if get_attr(attrs, "rel").is_some_and(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("stylesheet"))) => {
+                let Some(href) = get_attr(attrs, "href") else {
+                    continue;
+                };
+                let (_, target) = resolve_url(href, page, site_url)?;
+                match target.as_ref().and_then(|target| files.get(target)) {
+                    Some(css) => (String::from_utf8_lossy(css).into_owned(), target.expect("found in the build")),
+                    None => {
+                        warn!("{}: The stylesheet {href} isn't in the build, its rules can't be inlined", page.display());
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                collect_css(children, page, files, site_url, seen, stylesheet)?;
+                continue;
+            }
+        };
+        let css = AttributeRules::all().rewrite("style", css, |url| resolve_url(url, &base, site_url).map(|(absolute, _)| absolute))?;
+        if seen.insert(css.clone()) {
+            stylesheet.add(&css);
+        }
+    }
+    Ok(())
+}
+
+/// Makes the URLs of the attributes in `LINK_ATTRIBUTES` absolute, embedding the images of `<img src>` and
+/// `url(...)` in `style`s if `options.embed_images` is set
+fn rewrite_urls(
+    dom: &mut [Node],
+    page: &Path,
+    files: &HashMap<PathBuf, Vec<u8>>,
+    site_url: &str,
+    options: &NewsletterOptions,
+) -> Result<(), ConfigurafoxError> {
+    let rules = AttributeRules::links();
+    for node in dom {
+        let Node::Element(Element { name, attrs, children }) = node else {
+            continue;
+        };
+        for (key, value) in attrs.iter_mut() {
+            let embed = options.embed_images && (key == "style" || (name == "img" && key == "src"));
+            *value = rules.rewrite(key, std::mem::take(value), |url| {
+                let (absolute, target) = resolve_url(url, page, site_url)?;
+                let data_url = target.filter(|_| embed).and_then(|target| data_url(files, &target, options.max_embedded_size));
+                Ok::<_, ConfigurafoxError>(data_url.unwrap_or(absolute))
+            })?;
+        }
+        rewrite_urls(children, page, files, site_url, options)?;
+    }
+    Ok(())
+}
+
+/// `url` as seen from the output `base`, made absolute against `site_url`, and the output path it points at if
+/// it's on the site. URLs with a scheme are kept as they are
+fn resolve_url(url: &str, base: &Path, site_url: &str) -> Result<(String, Option<PathBuf>), ConfigurafoxError> {
+    let url = url.trim();
+    if let Some(rest) = url.strip_prefix("//") {
+        return Ok((format!("https://{rest}"), None));
+    }
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+
+    let has_scheme = path
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)));
+    if has_scheme {
+        let on_site = url.strip_prefix(site_url).and_then(|rest| rest.strip_prefix('/'));
+        let target = on_site.map(|_| &path[site_url.len() + 1..]).and_then(|path| resolve(Path::new(""), &percent_decode(path)));
+        return Ok((url.to_string(), target));
+    }
+    if path.is_empty() {
+        // The fragment would point at the post in the email, but ids of different posts may clash
+        return Ok((format!("{site_url}/{}{suffix}", url_path(base)?), Some(base.to_owned())));
+    }
+
+    let Some(resolved) = resolve(base, &percent_decode(path)) else {
+        warn!("{}: {url} points outside the site, leaving it relative", base.display());
+        return Ok((url.to_string(), None));
+    };
+    let mut absolute = format!("{site_url}/{}", url_path(&resolved)?);
+    if path.ends_with('/') && !resolved.as_os_str().is_empty() {
+        absolute.push('/');
+    }
+    absolute.push_str(suffix);
+    Ok((absolute, Some(resolved)))
+}
+
+/// The image at `path` in the build as a `data:` URL, if it's there and no larger than `max_size`
+fn data_url(files: &HashMap<PathBuf, Vec<u8>>, path: &Path, max_size: usize) -> Option<String> {
+    let mime_type = path.extension().and_then(|ext| ext.to_str()).and_then(image_mime_type)?;
+    let contents = files.get(path).filter(|contents| contents.len() <= max_size)?;
+    Some(format!("data:{mime_type};base64,{}", base64::engine::general_purpose::STANDARD.encode(contents)))
+}
+
+/// A compound selector of only a tag name, classes and an id, like `p`, `.note` or `a.button#subscribe`: what
+/// can be matched against one element to inline a rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Selector {
+    /// None for anything more complicated
+    fn parse(selector: &str) -> Option<Selector> {
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if selector.is_empty() || !selector.chars().all(|c| is_name_char(c) || c == '.' || c == '#') {
+            return None;
+        }
+
+        let tag_end = selector.find(['.', '#']).unwrap_or(selector.len());
+        let mut parsed = Selector {
+            tag: Some(selector[..tag_end].to_ascii_lowercase()).filter(|tag| !tag.is_empty()),
+            id: None,
+            classes: Vec::new(),
+        };
+        let mut rest = &selector[tag_end..];
+        while let Some(marker) = rest.chars().next() {
+            let len = rest[1..].find(['.', '#']).map_or(rest.len(), |len| len + 1);
+            let name = rest[1..len].to_string();
+            if name.is_empty() {
+                return None;
+            }
+            match marker {
+                '#' if parsed.id.is_none() => parsed.id = Some(name),
+                '.' => parsed.classes.push(name),
+                _ => return None,
+            }
+            rest = &rest[len..];
+        }
+        Some(parsed)
+    }
+
+    /// Ids, classes and tags, compared in that order
+    fn specificity(&self) -> (usize, usize, usize) {
+        (self.id.is_some() as usize, self.classes.len(), self.tag.is_some() as usize)
+    }
+
+    fn matches(&self, element: &Element) -> bool {
+        let classes = get_attr(&element.attrs, "class").unwrap_or("");
+        self.tag.as_ref().is_none_or(|tag| element.name.eq_ignore_ascii_case(tag))
+            && self.id.as_deref().is_none_or(|id| get_attr(&element.attrs, "id") == Some(id))
+            && self.classes.iter().all(|class| classes.split_whitespace().any(|other| other == class))
+    }
+}
+
+/// The rules of the collected CSS that can be inlined, in order, and the rest as CSS
+#[derive(Debug, Default)]
+struct Stylesheet {
+    rules: Vec<(Selector, String)>,
+    rest: String,
+}
+
+impl Stylesheet {
+    fn add(&mut self, css: &str) {
+        let css = strip_comments(css);
+        let mut css = css.as_str();
+        loop {
+            css = css.trim_start();
+            let Some(open) = css.find('{') else {
+                // A trailing at-rule statement like `@import`, or nothing
+                if !css.is_empty() {
+                    self.rest.push_str(css);
+                    self.rest.push('\n');
+                }
+                break;
+            };
+
+            if css.starts_with('@') {
+                let end = match css.find(';') {
+                    // A statement, like `@import url(...);`
+                    Some(semicolon) if semicolon < open => semicolon + 1,
+                    // A block, like `@media`, which may contain other blocks
+                    _ => block_end(css, open),
+                };
+                self.rest.push_str(&css[..end]);
+                self.rest.push('\n');
+                css = &css[end..];
+                continue;
+            }
+
+            let end = block_end(css, open);
+            let block = &css[open + 1..end];
+            let declarations = block.strip_suffix('}').unwrap_or(block).trim();
+            let mut kept = Vec::new();
+            for selector in css[..open].split(',').map(str::trim) {
+                match Selector::parse(selector) {
+                    Some(parsed) => self.rules.push((parsed, declarations.to_string())),
+                    None => kept.push(selector),
+                }
+            }
+            if !kept.is_empty() {
+                self.rest.push_str(&format!("{} {{ {declarations} }}\n", kept.join(", ")));
+            }
+            css = &css[end..];
+        }
+    }
+
+    /// Inlines the rules matching the elements of `dom`, see `inline_element`. `<head>` is left alone
+    fn inline(&self, dom: &mut [Node]) {
+        for node in dom {
+            if let Node::Element(element) = node {
+                if element.name != "head" {
+                    self.inline_element(element);
+                    self.inline(&mut element.children);
+                }
+            }
+        }
+    }
+
+    /// Adds the declarations of the rules matching `element` to its `style`, most specific last, and before its
+    /// own declarations so they win like they do in the browser
+    fn inline_element(&self, element: &mut Element) {
+        let mut matching = self.rules.iter().enumerate().filter(|(_, (selector, _))| selector.matches(element)).collect::<Vec<_>>();
+        if matching.is_empty() {
+            return;
+        }
+        matching.sort_by_key(|(order, (selector, _))| (selector.specificity(), *order));
+
+        let mut declarations = matching
+            .into_iter()
+            .map(|(_, (_, declarations))| declarations.trim_end_matches(';').trim().to_string())
+            .filter(|declarations| !declarations.is_empty())
+            .collect::<Vec<_>>();
+        if let Some(index) = element.attrs.iter().position(|(key, _)| key == "style") {
+            declarations.push(element.attrs.remove(index).1);
+        }
+        element.attrs.push(("style".to_string(), declarations.join("; ")));
+    }
+}
+
+/// The index just past the `}` closing the block opened at `open`, or the end of `css` if it isn't closed
+fn block_end(css: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in css[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    css.len()
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = rest[start + 2..].find("*/").map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,317 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+#[cfg(feature = "og-images")]
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::calendar::DATE_KEY;
+use crate::feed::DESCRIPTION_KEY;
+use crate::graph::DependencyKind;
+#[cfg(feature = "og-images")]
+use crate::metadata::Metadata;
+#[cfg(feature = "og-images")]
+use crate::resource_manager::Origin;
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::site::{SiteData, absolute_url};
+use crate::treewalker::{Context, TreeWalker};
+
+/// Metadata key with the preview image of a page, an absolute URL or a path relative to the output root. Takes
+/// precedence over a generated card
+pub const IMAGE_KEY: &str = "image";
+
+/// Metadata key with the `og:type` of a page. By default `article` for pages with a `calendar::DATE_KEY` and
+/// `website` for others
+pub const OG_TYPE_KEY: &str = "og_type";
+
+/// Metadata keys of the cards `SocialCards` generates: the identifier of the page they're for, and their size
+pub const CARD_OF_KEY: &str = "card_of";
+pub const CARD_WIDTH_KEY: &str = "width";
+pub const CARD_HEIGHT_KEY: &str = "height";
+
+/// The card `SocialCards` generated for `page`, if any
+fn card_of<'a, R: Resource>(resources: &'a ResourceManager<R>, page: &R) -> Option<&'a R> {
+    resources.resources_with(CARD_OF_KEY, &page.identifier()).into_iter().next()
+}
+
+/// Replaces `<opengraph-meta/>` with the OpenGraph `<meta>` tags of the page: its title, type, URL, description,
+/// the site name, and its preview image, from `IMAGE_KEY` or else the card `SocialCards` generated for it, along
+/// with `twitter:card`. URLs are absolute, so the site needs a `SiteData::base_url`
+pub struct OpenGraphMeta;
+
+impl<R: Resource, D: SiteData> TreeWalker<R, D> for OpenGraphMeta {
+    fn describe(&self) -> String {
+        "OpenGraphMeta".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "opengraph-meta"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["opengraph-meta"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let metadata = ctx.metadata();
+        let mut properties = Vec::new();
+        if let Some(title) = metadata.get_str("title").or(ctx.data.title()) {
+            properties.push(("og:title", title.to_string()));
+        }
+        let og_type = metadata.get_str(OG_TYPE_KEY).unwrap_or(if metadata.get(DATE_KEY).is_some() { "article" } else { "website" });
+        properties.push(("og:type", og_type.to_string()));
+        properties.push(("og:url", absolute_url(ctx.data, &ctx.resources.output_path(ctx.resource))?));
+        if let Some(description) = metadata.get_str(DESCRIPTION_KEY) {
+            properties.push(("og:description", description.to_string()));
+        }
+        if let Some(site_name) = ctx.data.title() {
+            properties.push(("og:site_name", site_name.to_string()));
+        }
+
+        let image = match metadata.get_str(IMAGE_KEY) {
+            Some(url) if url.contains("://") => Some(url.to_string()),
+            Some(path) => Some(absolute_url(ctx.data, path.trim_start_matches('/').as_ref())?),
+            None => None,
+        };
+        if let Some(image) = image {
+            properties.push(("og:image", image));
+        } else if let Some(card) = card_of(ctx.resources, ctx.resource) {
+            ctx.resources.record_dependency(ctx.resource, card, DependencyKind::Link);
+            properties.push(("og:image", absolute_url(ctx.data, &ctx.resources.output_path(card))?));
+            let card_metadata = ctx.resources.metadata(card);
+            for (property, key) in [("og:image:width", CARD_WIDTH_KEY), ("og:image:height", CARD_HEIGHT_KEY)] {
+                if let Some(size) = card_metadata.get(key).and_then(|size| size.as_u64()) {
+                    properties.push((property, size.to_string()));
+                }
+            }
+        }
+
+        let has_image = properties.iter().any(|(property, _)| *property == "og:image");
+        let mut nodes = properties.into_iter().map(|(property, content)| meta("property", property, content)).collect::<Vec<_>>();
+        if has_image {
+            nodes.push(meta("name", "twitter:card", "summary_large_image".to_string()));
+        }
+        Ok(nodes)
+    }
+}
+
+fn meta(key: &str, name: &str, content: String) -> Node {
+    Node::Element(Element {
+        name: "meta".to_string(),
+        attrs: vec![(key.to_string(), name.to_string()), ("content".to_string(), content)],
+        children: vec![],
+    })
+}
+
+/// Generates a social preview card for every member of a collection, `<output path>.png`, as resources with their
+/// contents in memory: the page's title and the site's title drawn onto a template image. The `og:image` of the
+/// members' `<opengraph-meta/>`. Generate after registering the members
+#[cfg(feature = "og-images")]
+#[derive(Debug, Clone)]
+pub struct SocialCards {
+    pub collection: String,
+    /// The background, a PNG or JPEG relative to the project root. Cards have its size, 1200x630 is what most sites
+    /// expect
+    pub template: PathBuf,
+    /// A TrueType or OpenType font, relative to the project root
+    pub font: PathBuf,
+    /// In pixels
+    pub title_size: f32,
+    pub site_size: f32,
+    /// Of the text, RGB
+    pub color: [u8; 3],
+    /// Between the text and the edges, in pixels
+    pub margin: u32,
+    /// Longer titles are cut off with an ellipsis
+    pub max_lines: usize,
+}
+
+#[cfg(feature = "og-images")]
+impl SocialCards {
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(collection: &str, template: P, font: Q) -> SocialCards {
+        SocialCards {
+            collection: collection.to_string(),
+            template: template.into(),
+            font: font.into(),
+            title_size: 64.0,
+            site_size: 32.0,
+            color: [0x20, 0x20, 0x20],
+            margin: 80,
+            max_lines: 3,
+        }
+    }
+
+    pub fn with_sizes(mut self, title_size: f32, site_size: f32) -> SocialCards {
+        self.title_size = title_size;
+        self.site_size = site_size;
+        self
+    }
+
+    pub fn with_color(mut self, color: [u8; 3]) -> SocialCards {
+        self.color = color;
+        self
+    }
+
+    pub fn with_margin(mut self, margin: u32) -> SocialCards {
+        self.margin = margin;
+        self
+    }
+
+    pub fn with_max_lines(mut self, max_lines: usize) -> SocialCards {
+        self.max_lines = max_lines;
+        self
+    }
+
+    /// Registers the cards under `dir`, with the resource `make_resource` gives for the path of each, like
+    /// `ResourceManager::register_all_files_in_directory` does. Cards it gives None for are skipped.
+    /// Returns the registered resources
+    pub fn generate<R: Resource, D: SiteData + ?Sized, F: Fn(&Path) -> Option<R>>(
+        &self,
+        resources: &mut ResourceManager<R>,
+        site: &D,
+        dir: &Path,
+        make_resource: F,
+    ) -> Result<Vec<R>, ConfigurafoxError> {
+        let image_error = |path: &Path, msg: String| ConfigurafoxError::Image { path: path.to_owned(), msg };
+        let template = image::load_from_memory(&resources.vfs().read(&self.template)?)
+            .map_err(|e| image_error(&self.template, e.to_string()))?
+            .into_rgba8();
+        let font = ab_glyph::FontVec::try_from_vec(resources.vfs().read(&self.font)?).map_err(|e| image_error(&self.font, e.to_string()))?;
+
+        let members = resources
+            .collection(&self.collection)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "collection", name: self.collection.clone() })?
+            .into_iter()
+            .map(|(_, resource)| resource.clone())
+            .collect::<Vec<_>>();
+
+        let mut generated = Vec::new();
+        for member in members {
+            let path = dir.join(resources.output_path(&member).with_extension("png"));
+            let Some(resource) = make_resource(&path) else {
+                continue;
+            };
+            info!("{}: Generating the card of {}", path.display(), member.identifier());
+
+            let title = resources.metadata(&member).get_str("title").map_or_else(|| member.identifier(), str::to_string);
+            let card = self.render(&template, &font, &title, site.title());
+            let mut png = std::io::Cursor::new(Vec::new());
+            card.write_to(&mut png, image::ImageFormat::Png).map_err(|e| image_error(&path, e.to_string()))?;
+
+            let mut metadata = Metadata::new();
+            metadata.insert(CARD_OF_KEY, member.identifier().as_str());
+            metadata.insert(CARD_WIDTH_KEY, card.width());
+            metadata.insert(CARD_HEIGHT_KEY, card.height());
+            resources.insert_with_origin(resource.clone(), path, Origin::Memory(png.into_inner().into()), metadata);
+            generated.push(resource);
+        }
+        Ok(generated)
+    }
+
+    /// The title wrapped from the top left corner, the site name in the bottom left corner
+    fn render(&self, template: &image::RgbaImage, font: &ab_glyph::FontVec, title: &str, site_name: Option<&str>) -> image::RgbaImage {
+        use ab_glyph::{Font, ScaleFont};
+
+        let mut card = template.clone();
+        let width = card.width().saturating_sub(2 * self.margin) as f32;
+        let margin = self.margin as f32;
+
+        let title_font = font.as_scaled(self.title_size);
+        let mut baseline = margin + title_font.ascent();
+        for line in wrap(font, self.title_size, title, width, self.max_lines) {
+            draw_text(&mut card, font, self.title_size, self.color, margin, baseline, &line);
+            baseline += title_font.height() + title_font.line_gap();
+        }
+
+        if let Some(site_name) = site_name {
+            let site_font = font.as_scaled(self.site_size);
+            let baseline = card.height() as f32 - margin + site_font.descent();
+            draw_text(&mut card, font, self.site_size, self.color, margin, baseline, site_name);
+        }
+        card
+    }
+}
+
+/// `text` in pixels
+#[cfg(feature = "og-images")]
+fn text_width(font: &ab_glyph::FontVec, size: f32, text: &str) -> f32 {
+    use ab_glyph::{Font, ScaleFont};
+
+    let font = font.as_scaled(size);
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph);
+        }
+        width += font.h_advance(glyph);
+        previous = Some(glyph);
+    }
+    width
+}
+
+/// `text` broken into lines at most `width` wide, at spaces. Words wider than that get a line of their own. Past
+/// `max_lines`, the last line ends in an ellipsis
+#[cfg(feature = "og-images")]
+fn wrap(font: &ab_glyph::FontVec, size: f32, text: &str, width: f32, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if text_width(font, size, &format!("{line} {word}")) <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            while !last.is_empty() && text_width(font, size, &format!("{last}…")) > width {
+                last.pop();
+            }
+            *last = format!("{}…", last.trim_end());
+        }
+    }
+    lines
+}
+
+/// Draws `text` starting at `x`, blending `color` in by the glyphs' coverage. Parts outside `image` are cut off
+#[cfg(feature = "og-images")]
+fn draw_text(image: &mut image::RgbaImage, font: &ab_glyph::FontVec, size: f32, color: [u8; 3], x: f32, baseline: f32, text: &str) {
+    use ab_glyph::{Font, ScaleFont};
+
+    let scaled = font.as_scaled(size);
+    let mut caret = x;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(size, ab_glyph::point(caret, baseline));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+            if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                return;
+            }
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+            let coverage = coverage.clamp(0.0, 1.0);
+            for (channel, &value) in pixel.0.iter_mut().zip(&color) {
+                *channel = (*channel as f32 * (1.0 - coverage) + value as f32 * coverage).round() as u8;
+            }
+            pixel.0[3] = pixel.0[3].max((coverage * 255.0) as u8);
+        });
+    }
+}
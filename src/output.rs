@@ -0,0 +1,314 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::ConfigurafoxError;
+use crate::vfs;
+
+/// Somewhere to put the generated files. `run` hands every processed resource to a backend
+/// instead of writing to disk directly, so a build can go straight to a remote host
+pub trait OutputBackend {
+    fn describe(&self) -> String;
+
+    /// `path` is the resource's output path, relative to the root of the output
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), ConfigurafoxError>;
+
+    /// Removes a previously written output, e.g. when its source was deleted while watching. Does nothing by default
+    fn remove_file(&mut self, _path: &Path) -> Result<(), ConfigurafoxError> {
+        Ok(())
+    }
+
+    /// Called once after all resources have been written
+    fn finish(&mut self) -> Result<(), ConfigurafoxError> {
+        Ok(())
+    }
+}
+
+/// `path`, an output path, normalized, failing if it is absolute or climbs above the output root, as an
+/// `output_path` override like `../../x` would. Backends writing to the filesystem check paths with this
+pub fn confine_output(path: &Path) -> Result<PathBuf, ConfigurafoxError> {
+    vfs::confine(path).map_err(|_| ConfigurafoxError::PathOutsideProject { path: path.to_owned() })
+}
+
+/// Writes outputs to a directory on the local filesystem
+pub struct LocalBackend {
+    pub root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: PathBuf) -> LocalBackend {
+        LocalBackend { root }
+    }
+
+    fn resolve(&self, path: &Path) -> Result<PathBuf, ConfigurafoxError> {
+        Ok(self.root.join(confine_output(path)?))
+    }
+}
+
+impl OutputBackend for LocalBackend {
+    fn describe(&self) -> String {
+        format!("LocalBackend({})", self.root.display())
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), ConfigurafoxError> {
+        let output_path = self.resolve(path)?;
+
+        if let Some(output_dir) = output_path.parent() {
+            if !output_dir.exists() {
+                debug!("Creating output directory {}", output_dir.display());
+                std::fs::create_dir_all(output_dir)?;
+            }
+        }
+
+        debug!("Writing {} bytes to {}", contents.len(), output_path.display());
+
+        let mut f = std::fs::File::create(output_path)?;
+        f.write_all(contents)?;
+
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<(), ConfigurafoxError> {
+        let output_path = self.resolve(path)?;
+        if output_path.exists() {
+            debug!("Removing {}", output_path.display());
+            std::fs::remove_file(output_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps all outputs in memory. Useful for tests, and for serving a build without touching the disk
+#[derive(Default)]
+pub struct MemoryBackend {
+    pub files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&[u8]> {
+        self.files.get(path.as_ref()).map(|x| &**x)
+    }
+}
+
+impl OutputBackend for MemoryBackend {
+    fn describe(&self) -> String {
+        "MemoryBackend".to_string()
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), ConfigurafoxError> {
+        debug!("Storing {} bytes as {}", contents.len(), path.display());
+        self.files.insert(path.to_owned(), contents.to_vec());
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<(), ConfigurafoxError> {
+        self.files.remove(path);
+        Ok(())
+    }
+}
+
+/// Guesses a Content-Type from the file extension, for backends and servers that need one
+pub fn mime_type_for(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|x| x.to_str()).unwrap_or("").to_ascii_lowercase();
+    match &*ext {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Uploads outputs to an S3-compatible bucket (AWS, MinIO, R2, ...) using path-style requests
+/// signed with AWS signature version 4
+#[cfg(feature = "s3")]
+pub struct S3Backend {
+    /// e.g. `https://s3.eu-north-1.amazonaws.com`
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to every object key
+    pub prefix: String,
+}
+
+#[cfg(feature = "s3")]
+impl S3Backend {
+    pub fn new(endpoint: &str, bucket: &str, region: &str, access_key: &str, secret_key: &str) -> S3Backend {
+        S3Backend {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            prefix: String::new(),
+        }
+    }
+
+    /// Reads the credentials from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+    pub fn from_env(endpoint: &str, bucket: &str, region: &str) -> Result<S3Backend, ConfigurafoxError> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .map_err(|_| ConfigurafoxError::OutputBackend { backend: "S3Backend".to_string(), msg: format!("{name} is not set") })
+        };
+
+        Ok(S3Backend::new(endpoint, bucket, region, &var("AWS_ACCESS_KEY_ID")?, &var("AWS_SECRET_ACCESS_KEY")?))
+    }
+
+    fn object_key(&self, path: &Path) -> Result<String, ConfigurafoxError> {
+        let path_str = path.to_str().ok_or_else(|| ConfigurafoxError::OutputBackend {
+            backend: self.describe(),
+            msg: format!("Invalid UTF-8 in path {}", path.display()),
+        })?;
+        let components = path_str.split(std::path::MAIN_SEPARATOR).filter(|x| !x.is_empty()).collect::<Vec<_>>();
+
+        Ok(format!("{}{}", self.prefix, components.join("/")))
+    }
+
+    /// Sends a signed `method` request for the object at `path`. Returns the object key
+    fn request(&self, method: &str, path: &Path, contents: &[u8]) -> Result<String, ConfigurafoxError> {
+        let key = self.object_key(path)?;
+        let canonical_uri = format!("/{}/{}", uri_encode(&self.bucket), key.split('/').map(uri_encode).collect::<Vec<_>>().join("/"));
+        let host = self.endpoint.split("://").last().unwrap_or(&self.endpoint);
+
+        let (date, datetime) = sigv4::timestamp(std::time::SystemTime::now());
+        let payload_hash = sigv4::hex_sha256(contents);
+
+        let authorization = sigv4::authorization(
+            &sigv4::Request {
+                method,
+                canonical_uri: &canonical_uri,
+                host,
+                payload_hash: &payload_hash,
+                datetime: &datetime,
+                date: &date,
+            },
+            &self.region,
+            &self.access_key,
+            &self.secret_key,
+        );
+
+        let url = format!("{}{}", self.endpoint, canonical_uri);
+        let result = match method {
+            "PUT" => ureq::put(&url)
+                .header("x-amz-date", &datetime)
+                .header("x-amz-content-sha256", &payload_hash)
+                .header("authorization", &authorization)
+                .header("content-type", mime_type_for(path))
+                .send(contents),
+            _ => ureq::delete(&url)
+                .header("x-amz-date", &datetime)
+                .header("x-amz-content-sha256", &payload_hash)
+                .header("authorization", &authorization)
+                .call(),
+        };
+        result.map_err(|e| ConfigurafoxError::OutputBackend { backend: self.describe(), msg: format!("{method} {key}: {e}") })?;
+
+        Ok(key)
+    }
+}
+
+#[cfg(feature = "s3")]
+impl OutputBackend for S3Backend {
+    fn describe(&self) -> String {
+        format!("S3Backend({}/{})", self.endpoint, self.bucket)
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), ConfigurafoxError> {
+        debug!("Uploading {} bytes of {} to s3://{}", contents.len(), path.display(), self.bucket);
+        self.request("PUT", path, contents)?;
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<(), ConfigurafoxError> {
+        let key = self.request("DELETE", path, &[])?;
+        debug!("Deleted s3://{}/{}", self.bucket, key);
+        Ok(())
+    }
+}
+
+/// `segment` percent-encoded, leaving only unreserved characters as they are
+#[cfg(any(feature = "s3", feature = "syntax-highlight"))]
+pub(crate) fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(feature = "s3")]
+mod sigv4 {
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+
+    pub struct Request<'a> {
+        pub method: &'a str,
+        pub canonical_uri: &'a str,
+        pub host: &'a str,
+        pub payload_hash: &'a str,
+        pub datetime: &'a str,
+        pub date: &'a str,
+    }
+
+    pub fn hex_sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    pub fn authorization(req: &Request, region: &str, access_key: &str, secret_key: &str) -> String {
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            req.method, req.canonical_uri, req.host, req.payload_hash, req.datetime, signed_headers, req.payload_hash,
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", req.date, region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", req.datetime, scope, hex_sha256(canonical_request.as_bytes()));
+
+        let key = hmac(format!("AWS4{secret_key}").as_bytes(), req.date);
+        let key = hmac(&key, region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+
+        format!("AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}")
+    }
+
+    /// Returns (`YYYYMMDD`, `YYYYMMDDTHHMMSSZ`) in UTC
+    pub fn timestamp(time: std::time::SystemTime) -> (String, String) {
+        let secs = time.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (days, rem) = (secs / 86400, secs % 86400);
+        let (year, month, day) = crate::calendar::civil_from_days(days as i64);
+
+        let date = format!("{year:04}{month:02}{day:02}");
+        let datetime = format!("{date}T{:02}{:02}{:02}Z", rem / 3600, rem / 60 % 60, rem % 60);
+        (date, datetime)
+    }
+}
@@ -0,0 +1,246 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use html_editor::operation::Htmlifiable;
+use html_editor::{Element, Node};
+use serde_json::{json, Value};
+use wasmtime::{Config, Engine, Instance, Memory, Module, Store, TypedFunc};
+
+use crate::ConfigurafoxError;
+use crate::resource_manager::Resource;
+use crate::treewalker::{Context, TreeWalker};
+
+/// `node` as JSON, the way it's handed to plugins: `{"element": {"name", "attrs": [[key, value], ...],
+/// "children": [...]}}`, `{"text": ...}`, `{"raw": ...}` or `{"comment": ...}`. Doctypes become raw HTML
+pub fn node_to_json(node: &Node) -> Value {
+    match node {
+        Node::Element(Element { name, attrs, children }) => json!({
+            "element": {
+                "name": name,
+                "attrs": attrs.iter().map(|(k, v)| json!([k, v])).collect::<Vec<_>>(),
+                "children": children.iter().map(node_to_json).collect::<Vec<_>>(),
+            }
+        }),
+        Node::Text(text) => json!({ "text": text }),
+        Node::RawHTML(html) => json!({ "raw": html }),
+        Node::Comment(comment) => json!({ "comment": comment }),
+        other => json!({ "raw": other.html() }),
+    }
+}
+
+/// The inverse of `node_to_json`. None if `value` isn't shaped like a node
+pub fn node_from_json(value: &Value) -> Option<Node> {
+    let string = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+
+    if let Some(element) = value.get("element") {
+        let name = element.get("name")?.as_str()?.to_string();
+        let attrs = element
+            .get("attrs")
+            .and_then(Value::as_array)
+            .map(|attrs| {
+                attrs
+                    .iter()
+                    .map(|attr| Some((attr.get(0)?.as_str()?.to_string(), attr.get(1)?.as_str()?.to_string())))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .unwrap_or(Some(Vec::new()))?;
+        let children = element
+            .get("children")
+            .and_then(Value::as_array)
+            .map(|children| children.iter().map(node_from_json).collect::<Option<Vec<_>>>())
+            .unwrap_or(Some(Vec::new()))?;
+        return Some(Node::Element(Element { name, attrs, children }));
+    }
+
+    string("text")
+        .map(Node::Text)
+        .or_else(|| string("raw").map(Node::RawHTML))
+        .or_else(|| string("comment").map(Node::Comment))
+}
+
+/// Fuel a plugin gets per call, roughly the number of WebAssembly instructions it may run, so a plugin stuck in a
+/// loop fails the build instead of hanging it. See `WasmWalker::with_fuel`
+pub const DEFAULT_PLUGIN_FUEL: u64 = 1_000_000_000;
+
+/// The exports of an instantiated plugin
+struct Plugin {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    dealloc: TypedFunc<(i32, i32), ()>,
+    matches: TypedFunc<(i32, i32), i32>,
+    replace: TypedFunc<(i32, i32), i64>,
+    fuel: u64,
+}
+
+impl Plugin {
+    /// Gives the plugin a fresh allowance of fuel, before each call
+    fn refuel(&mut self) -> wasmtime::Result<()> {
+        self.store.set_fuel(self.fuel)
+    }
+
+    fn dealloc(&mut self, ptr: i32, len: i32) -> wasmtime::Result<()> {
+        self.refuel()?;
+        self.dealloc.call(&mut self.store, (ptr, len))
+    }
+
+    /// Copies `input` into the plugin's memory, returning its address and length. The caller frees it
+    fn write(&mut self, input: &Value) -> wasmtime::Result<(i32, i32)> {
+        let bytes = input.to_string().into_bytes();
+        let len = i32::try_from(bytes.len())?;
+        self.refuel()?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, &bytes)?;
+        Ok((ptr, len))
+    }
+
+    fn matches(&mut self, input: &Value) -> wasmtime::Result<bool> {
+        let (ptr, len) = self.write(input)?;
+        self.refuel()?;
+        let matches = self.matches.call(&mut self.store, (ptr, len))?;
+        self.dealloc(ptr, len)?;
+        Ok(matches != 0)
+    }
+
+    fn replace(&mut self, input: &Value) -> wasmtime::Result<Value> {
+        let (ptr, len) = self.write(input)?;
+        self.refuel()?;
+        let packed = self.replace.call(&mut self.store, (ptr, len))? as u64;
+        self.dealloc(ptr, len)?;
+        let (output_ptr, output_len) = ((packed >> 32) as u32, (packed & 0xffff_ffff) as u32);
+
+        let mut output = vec![0; output_len as usize];
+        self.memory.read(&self.store, output_ptr as usize, &mut output)?;
+        self.dealloc(output_ptr as i32, output_len as i32)?;
+        Ok(serde_json::from_slice(&output)?)
+    }
+}
+
+/// A walker compiled to WebAssembly, so a site can use walkers without recompiling the host.
+///
+/// A plugin exports its linear `memory` and:
+/// - `alloc(len: i32) -> i32`: reserves `len` bytes for the host to write input to
+/// - `dealloc(ptr: i32, len: i32)`: frees what `alloc` reserved once the host is done with it, and the output of
+///   `replace` once the host has read it
+/// - `matches(ptr: i32, len: i32) -> i32`: gets `{"tag", "attrs"}` as JSON, returns nonzero to replace the element
+/// - `replace(ptr: i32, len: i32) -> i64`: gets `{"tag", "attrs", "children", "resource", "path"}` as JSON, where
+///   `resource` is the identifier of the page being processed. Returns the address of its JSON output in the
+///   upper 32 bits and its length in the lower, the output being `{"nodes": [...]}` or `{"error": "message"}`
+///
+/// Attributes are `[key, value]` pairs, and nodes are encoded as in `node_to_json`. Plugins get no imports, and
+/// each call may use `DEFAULT_PLUGIN_FUEL`, see `with_fuel`
+pub struct WasmWalker {
+    name: String,
+    // Calls need the store mutably, and walkers are shared
+    plugin: Mutex<Plugin>,
+}
+
+impl WasmWalker {
+    /// Loads the plugin at `path`, named after its file stem
+    pub fn load(path: &Path) -> Result<WasmWalker, ConfigurafoxError> {
+        let name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| path.display().to_string());
+        WasmWalker::from_bytes(&name, &std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(name: &str, wasm: &[u8]) -> Result<WasmWalker, ConfigurafoxError> {
+        let plugin_err = |e: wasmtime::Error| ConfigurafoxError::Plugin { plugin: name.to_string(), msg: format!("{e:#}") };
+
+        let engine = Engine::new(Config::new().consume_fuel(true)).map_err(plugin_err)?;
+        let module = Module::new(&engine, wasm).map_err(plugin_err)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).map_err(plugin_err)?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| ConfigurafoxError::Plugin { plugin: name.to_string(), msg: "no exported memory".to_string() })?;
+        let alloc = instance.get_typed_func(&mut store, "alloc").map_err(plugin_err)?;
+        let dealloc = instance.get_typed_func(&mut store, "dealloc").map_err(plugin_err)?;
+        let matches = instance.get_typed_func(&mut store, "matches").map_err(plugin_err)?;
+        let replace = instance.get_typed_func(&mut store, "replace").map_err(plugin_err)?;
+
+        info!("Loaded plugin {name}");
+
+        Ok(WasmWalker {
+            name: name.to_string(),
+            plugin: Mutex::new(Plugin { store, memory, alloc, dealloc, matches, replace, fuel: DEFAULT_PLUGIN_FUEL }),
+        })
+    }
+
+    /// Sets the fuel each call may use, see `DEFAULT_PLUGIN_FUEL`
+    pub fn with_fuel(self, fuel: u64) -> WasmWalker {
+        self.plugin.lock().unwrap_or_else(|e| e.into_inner()).fuel = fuel;
+        self
+    }
+
+    fn plugin_err(&self, msg: String) -> ConfigurafoxError {
+        ConfigurafoxError::Plugin { plugin: self.name.clone(), msg }
+    }
+}
+
+fn attrs_to_json(attrs: &[(String, String)]) -> Value {
+    attrs.iter().map(|(k, v)| json!([k, v])).collect()
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for WasmWalker {
+    fn describe(&self) -> String {
+        format!("WasmWalker({})", self.name)
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        let input = json!({ "tag": tag_name, "attrs": attrs_to_json(attrs) });
+        let mut plugin = self.plugin.lock().unwrap_or_else(|e| e.into_inner());
+        plugin.matches(&input).unwrap_or_else(|e| {
+            warn!("Plugin {} failed to match <{tag_name}>: {e:#}", self.name);
+            false
+        })
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let input = json!({
+            "tag": tag_name,
+            "attrs": attrs_to_json(&attrs),
+            "children": children.iter().map(node_to_json).collect::<Vec<_>>(),
+            "resource": ctx.resource.identifier(),
+            "path": ctx.source_path.to_string_lossy(),
+        });
+
+        let output = {
+            let mut plugin = self.plugin.lock().unwrap_or_else(|e| e.into_inner());
+            plugin.replace(&input).map_err(|e| self.plugin_err(format!("{e:#}")))?
+        };
+
+        if let Some(msg) = output.get("error").and_then(Value::as_str) {
+            return Err(self.plugin_err(msg.to_string()));
+        }
+        output
+            .get("nodes")
+            .and_then(Value::as_array)
+            .and_then(|nodes| nodes.iter().map(node_from_json).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| self.plugin_err(format!("invalid output when replacing <{tag_name}>: {output}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WasmWalker;
+    use crate::testing::run_walker;
+
+    /// Exports `memory`, `alloc` and `dealloc` that do nothing, a `matches` that loops forever and a `replace`
+    /// that returns nothing
+    const LOOPING_PLUGIN: &[u8] = &[
+        0, 97, 115, 109, 1, 0, 0, 0, 1, 23, 4, 96, 1, 127, 1, 127, 96, 2, 127, 127, 0, 96, 2, 127, 127, 1, 127, 96, 2, 127,
+        127, 1, 126, 3, 5, 4, 0, 1, 2, 3, 5, 3, 1, 0, 1, 7, 48, 5, 6, 109, 101, 109, 111, 114, 121, 2, 0, 5, 97, 108, 108,
+        111, 99, 0, 0, 7, 100, 101, 97, 108, 108, 111, 99, 0, 1, 7, 109, 97, 116, 99, 104, 101, 115, 0, 2, 7, 114, 101,
+        112, 108, 97, 99, 101, 0, 3, 10, 24, 4, 4, 0, 65, 0, 11, 2, 0, 11, 9, 0, 3, 64, 12, 0, 11, 65, 0, 11, 4, 0, 66, 0,
+        11,
+    ];
+
+    #[test]
+    fn runs_out_of_fuel() {
+        let walker = WasmWalker::from_bytes("loop", LOOPING_PLUGIN).unwrap().with_fuel(1_000_000);
+        assert_eq!(run_walker(walker, "<x-a>hi</x-a>").unwrap(), "<x-a>hi</x-a>");
+    }
+}
@@ -0,0 +1,358 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+
+use html_editor::Node;
+
+use crate::ConfigurafoxError;
+use crate::graph::DependencyKind;
+use crate::output::mime_type_for;
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker, at_reference, element, get_attr};
+
+/// Metadata key of an episode page with the identifier of its audio file, which podcast feeds enclose, see
+/// `feed::FeedGenerator::with_podcast`
+pub const AUDIO_KEY: &str = "audio";
+
+/// Metadata keys of episode pages with their episode and season numbers, and whether they are explicit
+pub const EPISODE_KEY: &str = "episode";
+pub const SEASON_KEY: &str = "season";
+pub const EXPLICIT_KEY: &str = "explicit";
+
+/// Audio formats, as (extension, MIME type, name shown to readers)
+pub const AUDIO_FORMATS: &[(&str, &str, &str)] = &[
+    ("mp3", "audio/mpeg", "MP3"),
+    ("m4a", "audio/mp4", "M4A"),
+    ("ogg", "audio/ogg", "Ogg"),
+    ("oga", "audio/ogg", "Ogg"),
+    ("opus", "audio/ogg", "Opus"),
+    ("wav", "audio/wav", "WAV"),
+];
+
+fn audio_format(path: &Path) -> Option<&'static (&'static str, &'static str, &'static str)> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    AUDIO_FORMATS.iter().find(|(ext, ..)| *ext == extension)
+}
+
+pub fn is_audio(path: &Path) -> bool {
+    audio_format(path).is_some()
+}
+
+/// What players and feed readers want to know about an audio file before downloading it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub mime_type: &'static str,
+    /// Shown to readers, e.g. `MP3`
+    pub format: String,
+    /// In bytes
+    pub size: u64,
+    /// In seconds, if `audio_duration` could read it
+    pub duration: Option<f64>,
+}
+
+impl AudioInfo {
+    /// The duration as `H:MM:SS`, or `M:SS` if shorter than an hour, as podcast apps show it
+    pub fn duration_text(&self) -> Option<String> {
+        let seconds = self.duration?.round() as u64;
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        Some(if hours > 0 { format!("{hours}:{minutes:02}:{seconds:02}") } else { format!("{minutes}:{seconds:02}") })
+    }
+
+    /// The duration as an ISO 8601 duration, e.g. `PT42M17S`, as in the `datetime` of a `<time>`
+    pub fn duration_iso(&self) -> Option<String> {
+        let seconds = self.duration?.round() as u64;
+        Some(format!("PT{}H{}M{}S", seconds / 3600, seconds / 60 % 60, seconds % 60))
+    }
+
+    /// The size in megabytes, e.g. `24.3 MB`
+    pub fn size_text(&self) -> String {
+        format!("{:.1} MB", self.size as f64 / 1_000_000.0)
+    }
+}
+
+/// The `AudioInfo` of a registered audio file, see `AUDIO_FORMATS`. Other files, like videos, get the MIME type
+/// of `output::mime_type_for` and no duration
+pub fn audio_info<R: Resource>(resources: &ResourceManager<R>, audio: &R) -> Result<AudioInfo, ConfigurafoxError> {
+    let output = resources.output_path(audio);
+    let (mime_type, format) = match audio_format(&output) {
+        Some((_, mime_type, format)) => (*mime_type, format.to_string()),
+        None => (mime_type_for(&output), output.extension().unwrap_or_default().to_string_lossy().to_uppercase()),
+    };
+    // Read past the content cache, episodes are large and only needed here
+    let contents = resources.with_file(audio, |path| std::fs::read(path))??;
+    let duration = audio_format(&output).and_then(|_| audio_duration(&contents));
+    if duration.is_none() && is_audio(&output) {
+        warn!("{}: Could not read the duration", output.display());
+    }
+    Ok(AudioInfo { mime_type, format, size: contents.len() as u64, duration })
+}
+
+/// Length in seconds of an MP3, M4A, Ogg (Vorbis or Opus) or WAV file, read from its headers without decoding
+/// it. None for other formats, or if the headers don't say
+pub fn audio_duration(audio: &[u8]) -> Option<f64> {
+    if audio.starts_with(b"RIFF") && audio.get(8..12) == Some(b"WAVE") {
+        wav_duration(audio)
+    } else if audio.starts_with(b"OggS") {
+        ogg_duration(audio)
+    } else if audio.get(4..8) == Some(b"ftyp") {
+        mp4_duration(audio)
+    } else {
+        mp3_duration(audio)
+    }
+}
+
+fn u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// From the frame count of the Xing or VBRI header of the first frame, which encoders write for variable bit rate
+/// files, or else from the bit rate of the first frame. Only MPEG layer III, which is what MP3 files are
+fn mp3_duration(mp3: &[u8]) -> Option<f64> {
+    let mut start = 0;
+    if mp3.starts_with(b"ID3") {
+        // The size is "synchsafe", 7 bits per byte
+        let size = mp3.get(6..10)?.iter().fold(0, |size, &byte| size << 7 | (byte & 0x7F) as usize);
+        let footer = if mp3[5] & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + size + footer;
+    }
+    let (frame, header) = (start..mp3.len().saturating_sub(4)).find_map(|i| Some((i, Mp3Frame::parse(&mp3[i..i + 4])?)))?;
+
+    let xing = frame + 4 + header.side_info_size();
+    if matches!(mp3.get(xing..xing + 4), Some(b"Xing" | b"Info")) && u32_be(mp3, xing + 4)? & 1 == 1 {
+        return Some(u32_be(mp3, xing + 8)? as f64 * header.samples as f64 / header.sample_rate as f64);
+    }
+    let vbri = frame + 4 + 32;
+    if mp3.get(vbri..vbri + 4) == Some(b"VBRI") {
+        return Some(u32_be(mp3, vbri + 14)? as f64 * header.samples as f64 / header.sample_rate as f64);
+    }
+
+    let id3v1 = if mp3.len() >= 128 && &mp3[mp3.len() - 128..mp3.len() - 125] == b"TAG" { 128 } else { 0 };
+    let audio_bytes = mp3.len().saturating_sub(frame + id3v1);
+    Some(audio_bytes as f64 * 8.0 / (header.bit_rate as f64 * 1000.0))
+}
+
+/// The header of an MPEG layer III frame
+struct Mp3Frame {
+    mpeg1: bool,
+    mono: bool,
+    /// In kbit/s
+    bit_rate: u32,
+    sample_rate: u32,
+    /// Per frame
+    samples: u32,
+}
+
+impl Mp3Frame {
+    fn parse(header: &[u8]) -> Option<Mp3Frame> {
+        const MPEG1_BIT_RATES: [u32; 16] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0];
+        const MPEG2_BIT_RATES: [u32; 16] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0];
+
+        if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = (header[1] >> 3) & 0b11;
+        let layer = (header[1] >> 1) & 0b11;
+        if version == 0b01 || layer != 0b01 {
+            return None;
+        }
+        let mpeg1 = version == 0b11;
+        let bit_rate = if mpeg1 { MPEG1_BIT_RATES } else { MPEG2_BIT_RATES }[(header[2] >> 4) as usize];
+        let sample_rate = match ((header[2] >> 2) & 0b11, version) {
+            (0b11, _) => return None,
+            (index, 0b11) => [44100, 48000, 32000][index as usize],
+            (index, 0b10) => [22050, 24000, 16000][index as usize],
+            (index, _) => [11025, 12000, 8000][index as usize],
+        };
+        if bit_rate == 0 {
+            return None;
+        }
+        Some(Mp3Frame { mpeg1, mono: header[3] >> 6 == 0b11, bit_rate, sample_rate, samples: if mpeg1 { 1152 } else { 576 } })
+    }
+
+    /// Bytes between the header and the Xing header
+    fn side_info_size(&self) -> usize {
+        match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+}
+
+/// From the duration and time scale of the movie header
+fn mp4_duration(mp4: &[u8]) -> Option<f64> {
+    let mvhd = mp4_box(mp4_box(mp4, b"moov")?, b"mvhd")?;
+    let (time_scale, duration) = match mvhd.first()? {
+        0 => (u32_be(mvhd, 12)?, u32_be(mvhd, 16)? as u64),
+        1 => (u32_be(mvhd, 20)?, u64::from_be_bytes(mvhd.get(24..32)?.try_into().ok()?)),
+        _ => return None,
+    };
+    (time_scale > 0).then(|| duration as f64 / time_scale as f64)
+}
+
+/// The contents of the first box of type `kind` in `data`
+fn mp4_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut i = 0;
+    while i + 8 <= data.len() {
+        let (header, size) = match u32_be(data, i)? {
+            0 => (8, data.len() - i),
+            1 => (16, u64::from_be_bytes(data.get(i + 8..i + 16)?.try_into().ok()?) as usize),
+            size => (8, size as usize),
+        };
+        if size < header {
+            return None;
+        }
+        if &data[i + 4..i + 8] == kind {
+            return data.get(i + header..i + size);
+        }
+        i += size;
+    }
+    None
+}
+
+/// From the granule position of the last page, in samples, and the sample rate of the Vorbis or Opus header
+fn ogg_duration(ogg: &[u8]) -> Option<f64> {
+    let find = |needle: &[u8]| ogg.windows(needle.len()).position(|window| window == needle);
+    let (rate, pre_skip) = if let Some(head) = find(b"OpusHead") {
+        // Opus granule positions are always at 48 kHz
+        (48000, u16::from_le_bytes(ogg.get(head + 10..head + 12)?.try_into().ok()?) as u64)
+    } else {
+        (u32_le(ogg, find(b"\x01vorbis")? + 12)?, 0)
+    };
+    let last_page = ogg.windows(4).rposition(|window| window == b"OggS")?;
+    let granule = u64::from_le_bytes(ogg.get(last_page + 6..last_page + 14)?.try_into().ok()?);
+    (rate > 0).then(|| granule.saturating_sub(pre_skip) as f64 / rate as f64)
+}
+
+/// From the size of the data chunk and the byte rate of the format chunk
+fn wav_duration(wav: &[u8]) -> Option<f64> {
+    let mut byte_rate = None;
+    let mut i = 12;
+    while i + 8 <= wav.len() {
+        let size = u32_le(wav, i + 4)? as usize;
+        match &wav[i..i + 4] {
+            b"fmt " => byte_rate = u32_le(wav, i + 16),
+            b"data" => return byte_rate.filter(|rate| *rate > 0).map(|rate| size as f64 / rate as f64),
+            _ => {}
+        }
+        i += 8 + size + size % 2;
+    }
+    None
+}
+
+/// Replaces `<audio-episode src="@episodes/1.mp3">`, or `<audio-episode @intro.mp3>` for identifiers without a
+/// `/`, with a `<figure class="audio-episode">` holding a player for the audio file, captioned with the `title`
+/// attribute or else the page's title, and a download link with the file's format, duration and size, read from
+/// the file, see `audio_info`. The element's children, like a link to a transcript, go after them
+pub struct AudioEpisode;
+
+impl<R: Resource, D> TreeWalker<R, D> for AudioEpisode {
+    fn describe(&self) -> String {
+        "AudioEpisode".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "audio-episode"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["audio-episode"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let Some((identifier, _)) = at_reference(&attrs) else {
+            return Err(ConfigurafoxError::MissingAttr { key_name: "src".to_string(), msg: "an @-reference to the audio file".to_string() });
+        };
+        let audio = ctx
+            .resources
+            .resource_by_identifier(&identifier)
+            .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: identifier.clone() })?;
+        let info = audio_info(ctx.resources, audio)?;
+        ctx.resources.record_dependency(ctx.resource, audio, DependencyKind::Include);
+        let url = ctx.link_to(audio)?;
+        let title = get_attr(&attrs, "title").or_else(|| ctx.metadata().get_str("title"));
+
+        let mut figure = Vec::new();
+        let mut player_attrs = vec![
+            ("controls", String::new()),
+            ("preload", "metadata".to_string()),
+            ("src", url.clone()),
+        ];
+        if let Some(title) = title {
+            figure.push(element("figcaption", vec![], vec![Node::Text(title.to_string())]));
+            player_attrs.push(("aria-label", title.to_string()));
+        }
+        // Shown by browsers that can't play it
+        let fallback = element("a", vec![("href", url.clone())], vec![Node::Text("Download the episode".to_string())]);
+        figure.push(element("audio", player_attrs, vec![fallback]));
+
+        let mut details = vec![Node::Text(format!(" ({}, ", info.format))];
+        if let (Some(text), Some(iso)) = (info.duration_text(), info.duration_iso()) {
+            details.push(element("time", vec![("datetime", iso)], vec![Node::Text(text)]));
+            details.push(Node::Text(", ".to_string()));
+        }
+        details.push(Node::Text(format!("{})", info.size_text())));
+        let download = element("a", vec![("href", url), ("download", String::new())], vec![Node::Text("Download".to_string())]);
+        figure.push(element("p", vec![("class", "audio-episode-details".to_string())], std::iter::once(download).chain(details).collect()));
+
+        figure.extend(children);
+        Ok(vec![element("figure", vec![("class", "audio-episode".to_string())], figure)])
+    }
+}
+
+/// The channel of a podcast feed, see `feed::FeedGenerator::with_podcast`. Its items enclose the audio file in
+/// `AUDIO_KEY`, members without one are left out
+#[derive(Debug, Clone, Default)]
+pub struct Podcast {
+    pub author: Option<String>,
+    /// The cover art, by identifier or absolute URL. Apple wants it square, 1400 to 3000 pixels wide
+    pub image: Option<String>,
+    /// Apple Podcasts categories, with a `/` before subcategories, like `Society & Culture/Documentary`
+    pub categories: Vec<String>,
+    pub explicit: bool,
+    /// Name and email address of whoever podcast directories contact
+    pub owner: Option<(String, String)>,
+    /// E.g. `en-us`
+    pub language: Option<String>,
+}
+
+impl Podcast {
+    pub fn new() -> Podcast {
+        Podcast::default()
+    }
+
+    pub fn with_author(mut self, author: &str) -> Podcast {
+        self.author = Some(author.to_string());
+        self
+    }
+
+    pub fn with_image(mut self, image: &str) -> Podcast {
+        self.image = Some(image.to_string());
+        self
+    }
+
+    pub fn with_category(mut self, category: &str) -> Podcast {
+        self.categories.push(category.to_string());
+        self
+    }
+
+    pub fn explicit(mut self, explicit: bool) -> Podcast {
+        self.explicit = explicit;
+        self
+    }
+
+    pub fn with_owner(mut self, name: &str, email: &str) -> Podcast {
+        self.owner = Some((name.to_string(), email.to_string()));
+        self
+    }
+
+    pub fn with_language(mut self, language: &str) -> Podcast {
+        self.language = Some(language.to_string());
+        self
+    }
+}
@@ -0,0 +1,216 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::fmt;
+use std::path::Path;
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+
+/// Attribute carrying the source position of an element between parsing and walking. `walk` removes it
+/// before any walker sees the element
+pub const POSITION_ATTR: &str = "data-configurafox-pos";
+
+/// Elements whose contents are not parsed as HTML
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// A 1-based line and column (in characters) in a source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePos {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl SourcePos {
+    fn parse(s: &str) -> Option<SourcePos> {
+        let (line, column) = s.split_once(':')?;
+        Some(SourcePos { line: line.parse().ok()?, column: column.parse().ok()? })
+    }
+}
+
+fn is_tag_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '$' || c == '@'
+}
+
+/// Adds a `POSITION_ATTR` attribute with its line and column to every opening tag in `source`. `first_line` is
+/// the line `source` starts at in its file, e.g. after front matter. This is a lexical pass, so positions are
+/// approximate for malformed documents
+pub fn annotate_positions(source: &str, first_line: usize) -> String {
+    let mut out = String::with_capacity(source.len() + source.len() / 4);
+    let mut line = first_line;
+    let mut column = 1;
+
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let pos = SourcePos { line, column };
+        out.push(c);
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+
+        if c != '<' {
+            continue;
+        }
+
+        let rest = &source[i + 1..];
+        if rest.starts_with("!--") {
+            // Copy the comment verbatim
+            let end = rest.find("-->").map(|e| e + 3).unwrap_or(rest.len());
+            for (_, c) in chars.by_ref().take(rest[..end].chars().count()) {
+                out.push(c);
+                if c == '\n' {
+                    line += 1;
+                    column = 1;
+                } else {
+                    column += 1;
+                }
+            }
+            continue;
+        }
+        if !rest.starts_with(is_tag_start) {
+            continue;
+        }
+
+        let name_len = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        for _ in 0..name.chars().count() {
+            if let Some((_, c)) = chars.next() {
+                out.push(c);
+                column += 1;
+            }
+        }
+        out.push_str(&format!(" {POSITION_ATTR}=\"{pos}\""));
+
+        // Copy the rest of the tag, where `<` in quoted attribute values doesn't start a tag
+        let mut quote = None;
+        let mut tag_end = 0;
+        for (j, c) in rest[name_len..].char_indices() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), _) if c == q => quote = None,
+                (None, '>') => {
+                    tag_end = name_len + j + 1;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        if tag_end == 0 {
+            tag_end = rest.len();
+        }
+
+        // Raw text elements are copied up to their closing tag
+        let mut copy_end = tag_end;
+        if RAW_TEXT_ELEMENTS.iter().any(|raw| raw.eq_ignore_ascii_case(name)) {
+            let closing = format!("</{}", name.to_ascii_lowercase());
+            copy_end = rest[tag_end..]
+                .to_ascii_lowercase()
+                .find(&closing)
+                .map(|e| tag_end + e)
+                .unwrap_or(rest.len());
+        }
+
+        for (_, c) in chars.by_ref().take(rest[name_len..copy_end].chars().count()) {
+            out.push(c);
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Where parsing `source` most likely fails: the `<` starting the first comment, tag or raw text element that isn't
+/// closed, as in `<p class="a>` or `<!-- …` running to the end. `first_line` is the line `source` starts at in its
+/// file, as in `annotate_positions`. None if there is no such thing, so the parser failed for another reason
+pub fn parse_error_position(source: &str, first_line: usize) -> Option<SourcePos> {
+    let pos_at = |index: usize| {
+        let before = &source[..index];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        SourcePos { line: first_line + before.matches('\n').count(), column: before[line_start..].chars().count() + 1 }
+    };
+
+    let mut from = 0;
+    while let Some(offset) = source[from..].find('<') {
+        let start = from + offset;
+        let rest = &source[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            match comment.find("-->") {
+                Some(end) => from = start + 4 + end + 3,
+                None => return Some(pos_at(start)),
+            }
+            continue;
+        }
+        if !rest.starts_with(is_tag_start) && !rest.starts_with(['/', '!']) {
+            from = start + 1;
+            continue;
+        }
+
+        // The end of the tag, where `>` in quoted attribute values doesn't end it
+        let mut quote = None;
+        let mut tag_end = None;
+        for (j, c) in rest.char_indices() {
+            match (quote, c) {
+                (None, '"' | '\'') if rest.starts_with(is_tag_start) => quote = Some(c),
+                (Some(q), _) if c == q => quote = None,
+                (None, '>') => {
+                    tag_end = Some(j + 1);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let Some(tag_end) = tag_end else {
+            return Some(pos_at(start));
+        };
+        from = start + 1 + tag_end;
+
+        let name = &rest[..rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(rest.len())];
+        if RAW_TEXT_ELEMENTS.iter().any(|raw| raw.eq_ignore_ascii_case(name)) && !rest[..tag_end].ends_with("/>") {
+            let closing = format!("</{}", name.to_ascii_lowercase());
+            match source[from..].to_ascii_lowercase().find(&closing) {
+                Some(end) => from += end,
+                None => return Some(pos_at(start)),
+            }
+        }
+    }
+    None
+}
+
+/// Removes the position attribute from `attrs`, returning it
+pub fn take_position(attrs: &mut Vec<(String, String)>) -> Option<SourcePos> {
+    let index = attrs.iter().position(|(k, _)| k == POSITION_ATTR)?;
+    SourcePos::parse(&attrs.remove(index).1)
+}
+
+/// Removes all position attributes from `dom`
+pub fn strip_positions(dom: &mut [Node]) {
+    for node in dom {
+        if let Node::Element(Element { attrs, children, .. }) = node {
+            attrs.retain(|(k, _)| k != POSITION_ATTR);
+            strip_positions(children);
+        }
+    }
+}
+
+/// Attaches `pos` in `path` to `error`, unless it already has a position
+pub(crate) fn locate(error: ConfigurafoxError, path: &Path, pos: Option<SourcePos>) -> ConfigurafoxError {
+    match (error, pos) {
+        (error @ ConfigurafoxError::Located { .. }, _) | (error, None) => error,
+        (error, Some(pos)) => ConfigurafoxError::Located { path: path.to_owned(), pos, error: Box::new(error) },
+    }
+}
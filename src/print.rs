@@ -0,0 +1,285 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use html_editor::{Element, Node};
+use serde::Deserialize;
+
+use crate::{ConfigurafoxError, relative_url};
+use crate::graph::DependencyKind;
+use crate::metadata::Metadata;
+use crate::resource_manager::{Origin, Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker, element, get_attr};
+
+/// Metadata key of the variants `PrintVariants` registers, with the identifier of the page they are of
+pub const PRINT_OF_KEY: &str = "print_of";
+
+/// Elements with this class are left out of print variants, besides those `PrintOptions::strip` names
+pub const NO_PRINT_CLASS: &str = "no-print";
+
+/// Registers a print variant of every page of a collection, which `HTMLProcessor` renders from the page, as if
+/// it were the page, then turns into a page for printing or reading, see `PrintOptions`. Pages link to their
+/// variant with `<print-link>`, see `PrintLink`
+#[derive(Debug, Clone)]
+pub struct PrintVariants {
+    pub collection: String,
+}
+
+impl PrintVariants {
+    pub fn new(collection: &str) -> PrintVariants {
+        PrintVariants { collection: collection.to_string() }
+    }
+
+    /// Registers the variants under `dir`, at the output path of their page, with the resource `make_resource`
+    /// gives for the path of each, like `ResourceManager::register_all_files_in_directory` does. Pages it gives
+    /// None for are skipped. Variants have no contents or metadata of their own, so listings of the collection
+    /// don't list them as well as long as `dir` is outside it. Returns the registered resources
+    pub fn generate<R: Resource, F: Fn(&Path) -> Option<R>>(&self, resources: &mut ResourceManager<R>, dir: &Path, make_resource: F) -> Result<Vec<R>, ConfigurafoxError> {
+        let members = resources
+            .collection(&self.collection)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "collection", name: self.collection.clone() })?
+            .into_iter()
+            .map(|(_, resource)| resource.clone())
+            .collect::<Vec<_>>();
+
+        let mut generated = Vec::new();
+        for member in members {
+            let path = dir.join(resources.output_path(&member));
+            let Some(resource) = make_resource(&path) else {
+                continue;
+            };
+            debug!("{}: Adding the print variant of {}", path.display(), member.identifier());
+
+            let mut metadata = Metadata::new();
+            metadata.insert(PRINT_OF_KEY, member.identifier().as_str());
+            resources.insert_with_origin(resource.clone(), path, Origin::Memory(Arc::from([])), metadata);
+            generated.push(resource);
+        }
+        Ok(generated)
+    }
+}
+
+/// The page `resource` is the print variant of, if `PrintVariants` registered it
+pub fn print_of<'a, R: Resource>(resources: &'a ResourceManager<R>, resource: &R) -> Option<&'a R> {
+    resources.metadata(resource).get_str(PRINT_OF_KEY).and_then(|identifier| resources.resource_by_identifier(identifier))
+}
+
+/// The print variant of `resource`, if it has one
+pub fn print_variant<'a, R: Resource>(resources: &'a ResourceManager<R>, resource: &R) -> Option<&'a R> {
+    resources.resources_with(PRINT_OF_KEY, &resource.identifier()).into_iter().next()
+}
+
+/// How an `HTMLProcessor` turns the print variants `PrintVariants` registered into pages for printing, after
+/// running its walkers. Other pages are left alone
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrintOptions {
+    /// Identifier of a stylesheet linked after the page's own
+    #[serde(default)]
+    pub stylesheet: Option<String>,
+    /// Tag names of the elements left out, like navigation. Elements with the class `NO_PRINT_CLASS` are too
+    #[serde(default = "default_strip")]
+    pub strip: Vec<String>,
+    /// Number the links to other sites like footnotes, listing their URLs at the end of the page, since they
+    /// can't be followed on paper
+    #[serde(default = "default_footnote_links")]
+    pub footnote_links: bool,
+    /// Heading of that list
+    #[serde(default = "default_links_heading")]
+    pub links_heading: String,
+}
+
+fn default_strip() -> Vec<String> {
+    ["nav", "aside", "script", "noscript", "iframe"].into_iter().map(str::to_string).collect()
+}
+
+fn default_footnote_links() -> bool {
+    true
+}
+
+fn default_links_heading() -> String {
+    "Links".to_string()
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions { stylesheet: None, strip: default_strip(), footnote_links: default_footnote_links(), links_heading: default_links_heading() }
+    }
+}
+
+impl PrintOptions {
+    pub fn with_stylesheet(mut self, identifier: &str) -> PrintOptions {
+        self.stylesheet = Some(identifier.to_string());
+        self
+    }
+
+    /// Strips the elements, links the stylesheet and footnotes the links of `dom`, rendered from `page` for its
+    /// print variant `variant`. Since it was rendered as if it were the page, relative URLs are resolved from the
+    /// page with a `<base>`, except fragments, which stay on the variant. The variant points search engines at
+    /// the page, and isn't indexed itself
+    pub(crate) fn apply<R: Resource>(&self, dom: &mut Vec<Node>, variant: &R, page: &R, resources: &ResourceManager<R>) -> Result<(), ConfigurafoxError> {
+        self.strip(dom);
+        resources.record_dependency(variant, page, DependencyKind::Link);
+
+        let page_output = resources.output_path(page);
+        let variant_url = relative_url(&resources.output_path(variant), &page_output)?;
+        rebase_fragments(dom, &variant_url);
+
+        let mut head = vec![
+            element("base", vec![("href", relative_url(&page_output, &resources.output_path(variant))?)], vec![]),
+            element("meta", vec![("name", "robots".to_string()), ("content", "noindex".to_string())], vec![]),
+        ];
+        if !has_canonical_link(dom) {
+            head.push(element("link", vec![("rel", "canonical".to_string()), ("href", relative_url(&page_output, &page_output)?)], vec![]));
+        }
+        // The `<base>` must come before any URL in the head, the stylesheet after the page's own
+        let mut stylesheet = Vec::new();
+        if let Some(identifier) = &self.stylesheet {
+            let target = resources
+                .resource_by_identifier(identifier)
+                .ok_or_else(|| ConfigurafoxError::UnknownIdentifier { identifier: identifier.clone() })?;
+            resources.record_dependency(variant, target, DependencyKind::Link);
+            let href = relative_url(&resources.output_path(target), &page_output)?;
+            stylesheet.push(element("link", vec![("rel", "stylesheet".to_string()), ("href", href)], vec![]));
+        }
+        match find_mut(dom, "head") {
+            Some(element) => element.children = head.into_iter().chain(element.children.drain(..)).chain(stylesheet).collect(),
+            None => *dom = head.into_iter().chain(stylesheet).chain(dom.drain(..)).collect(),
+        }
+
+        if self.footnote_links {
+            let mut urls = Vec::new();
+            footnote_links(dom, &mut urls);
+            if !urls.is_empty() {
+                let items = urls.into_iter().map(|url| element("li", vec![], vec![Node::Text(url)])).collect();
+                let list = element(
+                    "section",
+                    vec![("class", "print-links".to_string())],
+                    vec![element("h2", vec![], vec![Node::Text(self.links_heading.clone())]), element("ol", vec![], items)],
+                );
+                let container = ["article", "main", "body"].into_iter().find(|name| find_mut(dom, name).is_some());
+                match container.and_then(|name| find_mut(dom, name)) {
+                    Some(element) => element.children.push(list),
+                    None => dom.push(list),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Also removes the links `PrintLink` made, which would point at the variant itself
+    fn strip(&self, dom: &mut Vec<Node>) {
+        dom.retain(|node| match node {
+            Node::Element(Element { name, attrs, .. }) => {
+                let no_print = get_attr(attrs, "class").is_some_and(|classes| classes.split_whitespace().any(|class| class == NO_PRINT_CLASS));
+                let print_link = name == "a" && get_attr(attrs, "media") == Some("print") && get_attr(attrs, "rel") == Some("alternate");
+                !no_print && !print_link && !self.strip.contains(name)
+            }
+            _ => true,
+        });
+        for node in dom {
+            if let Node::Element(Element { children, .. }) = node {
+                self.strip(children);
+            }
+        }
+    }
+}
+
+/// Points `href`s to fragments at `variant_url#fragment`, so the `<base>` doesn't send them to the page
+fn rebase_fragments(dom: &mut [Node], variant_url: &str) {
+    for node in dom {
+        if let Node::Element(Element { attrs, children, .. }) = node {
+            for (key, value) in attrs.iter_mut() {
+                if key == "href" && value.starts_with('#') {
+                    value.insert_str(0, variant_url);
+                }
+            }
+            rebase_fragments(children, variant_url);
+        }
+    }
+}
+
+fn has_canonical_link(dom: &[Node]) -> bool {
+    dom.iter().any(|node| match node {
+        Node::Element(Element { name, attrs, children }) => {
+            (name == "link" && get_attr(attrs, "rel") == Some("canonical")) || has_canonical_link(children)
+        }
+        _ => false,
+    })
+}
+
+/// Adds a `<sup>` with the number of its URL in `urls` after every link to another site, adding the URL if it's
+/// new
+fn footnote_links(dom: &mut Vec<Node>, urls: &mut Vec<String>) {
+    let mut i = 0;
+    while i < dom.len() {
+        if let Node::Element(Element { name, attrs, children }) = &mut dom[i] {
+            let href = get_attr(attrs, "href").filter(|href| name == "a" && (href.starts_with("http://") || href.starts_with("https://")));
+            if let Some(href) = href.map(str::to_string) {
+                let number = match urls.iter().position(|url| *url == href) {
+                    Some(index) => index + 1,
+                    None => {
+                        urls.push(href);
+                        urls.len()
+                    }
+                };
+                let reference = element("sup", vec![("class", "print-link-ref".to_string())], vec![Node::Text(format!("[{number}]"))]);
+                dom.insert(i + 1, reference);
+                i += 1;
+            } else {
+                footnote_links(children, urls);
+            }
+        }
+        i += 1;
+    }
+}
+
+/// The first element named `name`, depth first
+fn find_mut<'a>(dom: &'a mut [Node], name: &str) -> Option<&'a mut Element> {
+    for node in dom {
+        if let Node::Element(element) = node {
+            if element.name == name {
+                return Some(element);
+            }
+            if let Some(found) = find_mut(&mut element.children, name) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+/// Replaces `<print-link>` with a `<a rel="alternate" media="print">` to the print variant of the page, containing
+/// the element's children, or "Print version" if it has none. Left out of pages without a variant, and of the
+/// variants themselves
+pub struct PrintLink;
+
+impl<R: Resource, D> TreeWalker<R, D> for PrintLink {
+    fn describe(&self) -> String {
+        "PrintLink".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "print-link"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["print-link"])
+    }
+
+    fn replace(&self, _tag_name: &str, mut attrs: Vec<(String, String)>, mut children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let Some(variant) = print_variant(ctx.resources, ctx.resource) else {
+            return Ok(vec![]);
+        };
+        attrs.retain(|(key, _)| !["href", "rel", "media"].contains(&key.as_str()));
+        attrs.push(("href".to_string(), ctx.link_to(variant)?));
+        attrs.push(("rel".to_string(), "alternate".to_string()));
+        attrs.push(("media".to_string(), "print".to_string()));
+        if children.is_empty() {
+            children.push(Node::Text("Print version".to_string()));
+        }
+        Ok(vec![Node::Element(Element { name: "a".to_string(), attrs, children })])
+    }
+}
@@ -0,0 +1,164 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::error_pages::error_status;
+use crate::metadata::{Metadata, Value};
+use crate::resource_manager::{Resource, ResourceManager};
+
+/// The metadata key holding a resource's tags, as an array of strings
+pub const TAGS_KEY: &str = "tags";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+type Predicate<'a, R> = Box<dyn Fn(&Path, &R, &Metadata) -> bool + 'a>;
+
+/// A filtered, ordered view over the resources of a `ResourceManager`. Create with `ResourceManager::query`
+pub struct Query<'a, R: Resource> {
+    resources: &'a ResourceManager<R>,
+    filters: Vec<Predicate<'a, R>>,
+    sort_key: Option<(String, SortOrder)>,
+    limit: Option<usize>,
+}
+
+impl<'a, R: Resource> Query<'a, R> {
+    pub fn new(resources: &'a ResourceManager<R>) -> Query<'a, R> {
+        Query {
+            resources,
+            filters: Vec::new(),
+            sort_key: None,
+            limit: None,
+        }
+    }
+
+    /// Only resources whose `tags` metadata contains `tag`
+    pub fn tag(self, tag: &str) -> Self {
+        let tag = tag.to_string();
+        self.filter(move |_, _, metadata| tags_of(metadata).any(|t| t == tag))
+    }
+
+    /// Only resources whose source path starts with `prefix`, e.g. `posts`
+    pub fn path_prefix<P: AsRef<Path>>(self, prefix: P) -> Self {
+        let prefix = prefix.as_ref().to_owned();
+        self.filter(move |path, _, _| path.starts_with(&prefix))
+    }
+
+    /// Only resources whose source path has the extension `ext` (without the dot)
+    pub fn extension(self, ext: &str) -> Self {
+        let ext = ext.to_string();
+        self.filter(move |path, _, _| path.extension().is_some_and(|e| e.eq_ignore_ascii_case(&ext)))
+    }
+
+    /// Only resources where the metadata `key` exists and satisfies `predicate`
+    pub fn metadata<F: Fn(&Value) -> bool + 'a>(self, key: &str, predicate: F) -> Self {
+        let key = key.to_string();
+        self.filter(move |_, _, metadata| metadata.get(&key).is_some_and(&predicate))
+    }
+
+    /// Only resources satisfying an arbitrary predicate
+    pub fn filter<F: Fn(&Path, &R, &Metadata) -> bool + 'a>(mut self, predicate: F) -> Self {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    /// Order the results by the metadata value `key`. Resources without the key are put last.
+    /// Without this, results are ordered by source path
+    pub fn sort_by(mut self, key: &str, order: SortOrder) -> Self {
+        self.sort_key = Some((key.to_string(), order));
+        self
+    }
+
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    pub fn run(self) -> Vec<(&'a Path, &'a R)> {
+        let resources = self.resources;
+        let mut results = resources
+            .iter()
+            .filter(|(path, res)| {
+                let metadata = resources.metadata(res);
+                self.filters.iter().all(|f| f(path, res, metadata))
+            })
+            .collect::<Vec<_>>();
+
+        results.sort_by(|(path_a, res_a), (path_b, res_b)| {
+            let by_key = match &self.sort_key {
+                Some((key, order)) => {
+                    let a = resources.metadata(res_a).get(key);
+                    let b = resources.metadata(res_b).get(key);
+                    match (a, b) {
+                        (Some(a), Some(b)) if *order == SortOrder::Ascending => compare_values(a, b),
+                        (Some(a), Some(b)) => compare_values(b, a),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    }
+                }
+                None => Ordering::Equal,
+            };
+            by_key.then_with(|| path_a.cmp(path_b))
+        });
+
+        if let Some(limit) = self.limit {
+            results.truncate(limit);
+        }
+
+        results
+    }
+}
+
+type Definition<R> = Box<dyn for<'a> Fn(Query<'a, R>) -> Query<'a, R> + Send + Sync>;
+
+/// A named, ordered set of resources, e.g. "posts" = everything under `posts/`, newest first.
+/// Registered with `ResourceManager::define_collection`, so listings, pagination and feeds share one definition
+pub struct Collection<R: Resource> {
+    pub name: String,
+    definition: Definition<R>,
+}
+
+impl<R: Resource> Collection<R> {
+    /// `definition` narrows down and orders a query over all resources:
+    /// `Collection::new("posts", |q| q.path_prefix("posts").sort_by("date", SortOrder::Descending))`
+    pub fn new<F: for<'a> Fn(Query<'a, R>) -> Query<'a, R> + Send + Sync + 'static>(name: &str, definition: F) -> Collection<R> {
+        Collection {
+            name: name.to_string(),
+            definition: Box::new(definition),
+        }
+    }
+
+    /// The members of the collection, in order. Error pages never are, see `error_pages::ERROR_PAGE_KEY`
+    pub fn members<'a>(&self, resources: &'a ResourceManager<R>) -> Vec<(&'a Path, &'a R)> {
+        (self.definition)(resources.query().filter(|_, _, metadata| error_status(metadata).is_none())).run()
+    }
+}
+
+/// The tags of a resource, i.e. the strings in its `tags` metadata
+pub fn tags_of(metadata: &Metadata) -> impl Iterator<Item = &str> {
+    metadata
+        .get(TAGS_KEY)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+/// Orders numbers numerically and everything else by its string representation.
+/// Dates stored as ISO 8601 strings thus sort chronologically
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => {
+            a.as_f64().unwrap_or(0.0).total_cmp(&b.as_f64().unwrap_or(0.0))
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
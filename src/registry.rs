@@ -0,0 +1,542 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::{ConfigurafoxError, HTMLProcessor, IdentityProcessor, ResourceProcessor};
+use crate::authors::{AuthorPosts, Byline};
+use crate::calendar::ArchiveList;
+use crate::comments::{CommentList, DEFAULT_COMMENTS_DIR};
+use crate::favicon::FaviconLinks;
+#[cfg(feature = "images")]
+use crate::favicon::FaviconProcessor;
+use crate::feed::FeedLink;
+use crate::git::{Contributors, LastUpdated};
+use crate::headings::{DEFAULT_ANCHORS_DIR, DEFAULT_SIMILARITY, HeadingAnchors, HeadingShift};
+use crate::tables::{DEFAULT_WRAPPER_CLASS, TableEnhancer};
+use crate::images::{DEFAULT_KEPT_EXIF, EXIF_TAGS, ImageProcessor, exif_tag};
+#[cfg(feature = "images")]
+use crate::images::ThumbnailImage;
+use crate::jsonld::JsonLd;
+use crate::lint::{LintRule, Lints};
+use crate::manifest::ManifestLink;
+use crate::metadata::toml_to_value;
+use crate::opengraph::OpenGraphMeta;
+use crate::podcast::AudioEpisode;
+use crate::print::{PrintLink, PrintOptions};
+use crate::resource_manager::Resource;
+use crate::serialize::Whitespace;
+use crate::service_worker::ServiceWorkerRegistration;
+use crate::site::SiteData;
+use crate::slides::{DEFAULT_SPLIT_AT, SlideDeck};
+use crate::treewalker::{AttributeRules, BacklinksWalker, CanonicalLink, LinkReplacer, TreeWalker, VariableReplacer};
+#[cfg(feature = "katex")]
+use crate::treewalker::KatexReplacer;
+#[cfg(feature = "syntax-highlight")]
+use crate::treewalker::SyntaxHighlighter;
+#[cfg(all(feature = "remote", feature = "syntax-highlight"))]
+use crate::snippet::GitSnippet;
+#[cfg(feature = "remote")]
+use crate::integrity::{HashAlgorithm, SubresourceIntegrity};
+use crate::video::VideoWalker;
+use crate::webmention::{DEFAULT_MENTIONS_FILE, WebmentionList};
+
+/// A walker to build, by registered name. In TOML, either just the name or a table of the name and options:
+/// `["links", { name = "syntax-highlight", theme = "base16-ocean.dark" }]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "WalkerSpecRepr")]
+pub struct WalkerSpec {
+    pub name: String,
+    pub options: toml::Table,
+}
+
+impl WalkerSpec {
+    pub fn new(name: &str) -> WalkerSpec {
+        WalkerSpec { name: name.to_string(), options: toml::Table::new() }
+    }
+
+    pub fn with_option<V: Into<toml::Value>>(mut self, key: &str, value: V) -> WalkerSpec {
+        self.options.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WalkerSpecRepr {
+    Name(String),
+    Table {
+        name: String,
+        #[serde(flatten)]
+        options: toml::Table,
+    },
+}
+
+impl From<WalkerSpecRepr> for WalkerSpec {
+    fn from(repr: WalkerSpecRepr) -> WalkerSpec {
+        match repr {
+            WalkerSpecRepr::Name(name) => WalkerSpec::new(&name),
+            WalkerSpecRepr::Table { name, options } => WalkerSpec { name, options },
+        }
+    }
+}
+
+/// Options of walkers that don't take any
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoOptions {}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VariablesOptions {
+    /// Nested tables and arrays are addressed with `.`, see `VariableReplacer`
+    #[serde(default)]
+    pub variables: toml::Table,
+    /// Names of the attributes to replace variables in, all of them if not given
+    #[serde(default)]
+    pub attributes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinksOptions {
+    /// Names of the attributes to replace links in, all of them if not given. See `treewalker::LINK_ATTRIBUTES`
+    #[serde(default)]
+    pub attributes: Option<Vec<String>>,
+}
+
+fn attribute_rules(attributes: Option<Vec<String>>) -> AttributeRules {
+    attributes.map_or_else(AttributeRules::all, AttributeRules::only)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectionOptions {
+    /// Name of the collection used unless the page says otherwise, see `ResourceManager::define_collection`
+    #[serde(default = "default_collection")]
+    pub collection: String,
+}
+
+fn default_collection() -> String {
+    "posts".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebmentionsOptions {
+    /// The received webmentions, relative to the project root
+    #[serde(default = "default_mentions_file")]
+    pub file: PathBuf,
+}
+
+fn default_mentions_file() -> PathBuf {
+    PathBuf::from(DEFAULT_MENTIONS_FILE)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommentsOptions {
+    /// The comment files, relative to the project root
+    #[serde(default = "default_comments_dir")]
+    pub dir: PathBuf,
+}
+
+fn default_comments_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_COMMENTS_DIR)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VideoOptions {
+    #[serde(default = "default_ffmpeg")]
+    pub ffmpeg: PathBuf,
+    #[serde(default = "default_ffprobe")]
+    pub ffprobe: PathBuf,
+    /// Seconds into the video posters are taken at, see `VideoWalker::poster_at`
+    #[serde(default)]
+    pub poster_at: Option<f64>,
+}
+
+fn default_ffmpeg() -> PathBuf {
+    VideoWalker::default().ffmpeg
+}
+
+fn default_ffprobe() -> PathBuf {
+    VideoWalker::default().ffprobe
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyntaxHighlightOptions {
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    /// Playground URL templates by language, in addition to the Rust Playground, see
+    /// `SyntaxHighlighter::playgrounds`
+    #[serde(default)]
+    pub playgrounds: BTreeMap<String, String>,
+}
+
+/// Options of the `contributors` walker
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContributorsOptions {
+    /// Profile URLs by email or name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, String>,
+}
+
+/// Options of the `shift-headings` walker
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShiftHeadingsOptions {
+    /// How far `<shift-headings>` without `by` or `to` shifts
+    #[serde(default = "default_shift_by")]
+    pub by: i8,
+}
+
+fn default_shift_by() -> i8 {
+    1
+}
+
+/// Options of the `heading-anchors` walker
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeadingAnchorsOptions {
+    /// Where the anchors of each page are kept between builds, relative to the project root
+    #[serde(default = "default_anchors_dir")]
+    pub dir: PathBuf,
+    /// How alike the old and new text of a heading must be for it to keep its old anchor, from 0 to 1
+    #[serde(default = "default_similarity")]
+    pub min_similarity: f64,
+}
+
+fn default_anchors_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_ANCHORS_DIR)
+}
+
+fn default_similarity() -> f64 {
+    DEFAULT_SIMILARITY
+}
+
+/// Options of the `tables` walker
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TablesOptions {
+    /// Whether to wrap tables in a `<div>`
+    #[serde(default = "default_wrapper")]
+    pub wrapper: bool,
+    /// Class of the wrapper
+    #[serde(default = "default_wrapper_class")]
+    pub class: String,
+    /// Whether the header rows of all tables stick, not only those marked `sticky`
+    #[serde(default)]
+    pub sticky: bool,
+}
+
+fn default_wrapper() -> bool {
+    true
+}
+
+fn default_wrapper_class() -> String {
+    DEFAULT_WRAPPER_CLASS.to_string()
+}
+
+/// Options of the `git-snippet` walker
+#[cfg(all(feature = "remote", feature = "syntax-highlight"))]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GitSnippetOptions {
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    #[serde(default)]
+    pub playgrounds: BTreeMap<String, String>,
+    /// Where fetched files are kept, relative to the project root
+    #[serde(default = "default_snippet_cache_dir")]
+    pub cache_dir: PathBuf,
+}
+
+#[cfg(all(feature = "remote", feature = "syntax-highlight"))]
+fn default_snippet_cache_dir() -> PathBuf {
+    PathBuf::from(crate::remote::DEFAULT_CACHE_DIR)
+}
+
+/// Options of the `integrity` walker
+#[cfg(feature = "remote")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IntegrityOptions {
+    /// `sha256`, `sha384` or `sha512`
+    #[serde(default = "default_integrity_algorithm")]
+    pub algorithm: String,
+    /// Where fetched files are kept, relative to the project root
+    #[serde(default = "default_integrity_cache_dir")]
+    pub cache_dir: PathBuf,
+}
+
+#[cfg(feature = "remote")]
+fn default_integrity_algorithm() -> String {
+    "sha384".to_string()
+}
+
+#[cfg(feature = "remote")]
+fn default_integrity_cache_dir() -> PathBuf {
+    PathBuf::from(crate::remote::DEFAULT_CACHE_DIR)
+}
+
+fn default_theme() -> String {
+    "InspiredGitHub".to_string()
+}
+
+/// Options of the `html` processor
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HtmlOptions {
+    #[serde(default)]
+    pub walkers: Vec<WalkerSpec>,
+    #[serde(default)]
+    pub trim: bool,
+    #[serde(default)]
+    pub pretty: bool,
+    /// Tag names added to `serialize::PREFORMATTED_ELEMENTS`
+    #[serde(default)]
+    pub preformatted: Vec<String>,
+    /// Lint rule codes, see `LintRule::code`
+    #[serde(default)]
+    pub lints: Vec<String>,
+    /// How print variants are made, see `print::PrintVariants`
+    #[serde(default)]
+    pub print: PrintOptions,
+}
+
+/// Options of the `image` processor
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageOptions {
+    #[serde(default = "default_strip_metadata")]
+    pub strip_metadata: bool,
+    /// Names of the EXIF tags kept, see `images::EXIF_TAGS`
+    #[serde(default = "default_keep_exif")]
+    pub keep_exif: Vec<String>,
+}
+
+fn default_strip_metadata() -> bool {
+    true
+}
+
+fn default_keep_exif() -> Vec<String> {
+    EXIF_TAGS.iter().filter(|(_, tag)| DEFAULT_KEPT_EXIF.contains(tag)).map(|(name, _)| name.to_string()).collect()
+}
+
+/// Options of the `slides` processor
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlidesOptions {
+    /// Options of the `html` processor making the pages split into slides
+    #[serde(default)]
+    pub html: toml::Table,
+    /// Tag names, see `SlideDeck::split_at`
+    #[serde(default = "default_split_at")]
+    pub split_at: Vec<String>,
+}
+
+fn default_split_at() -> Vec<String> {
+    DEFAULT_SPLIT_AT.iter().map(|tag| tag.to_string()).collect()
+}
+
+type WalkerFactory<R, D> = Box<dyn Fn(toml::Value) -> Result<Box<dyn TreeWalker<R, D>>, ConfigurafoxError>>;
+type ProcessorFactory<R, D> =
+    Box<dyn for<'d> Fn(&Registry<R, D>, toml::Value, &'d D) -> Result<Box<dyn ResourceProcessor<R> + 'd>, ConfigurafoxError>>;
+
+/// Walkers and processors by name, so a configuration file can assemble a pipeline like
+/// `["variables", "links", "katex", "syntax-highlight"]`. Each factory takes an options struct, deserialized from
+/// the options given with the name
+pub struct Registry<R: Resource, D> {
+    walkers: BTreeMap<String, WalkerFactory<R, D>>,
+    processors: BTreeMap<String, ProcessorFactory<R, D>>,
+}
+
+impl<R: Resource, D> Default for Registry<R, D> {
+    fn default() -> Self {
+        Registry { walkers: BTreeMap::new(), processors: BTreeMap::new() }
+    }
+}
+
+fn options<O: DeserializeOwned>(name: &str, options: toml::Value) -> Result<O, ConfigurafoxError> {
+    options.try_into().map_err(|e: toml::de::Error| ConfigurafoxError::InvalidOptions { name: name.to_string(), msg: e.message().to_string() })
+}
+
+impl<R: Resource + 'static, D: 'static> Registry<R, D> {
+    /// A registry without anything registered
+    pub fn new() -> Registry<R, D> {
+        Registry::default()
+    }
+
+    /// The walkers `variables`, `links`, `backlinks`, `archive-list`, `feed-link`, `webmentions`, `comments`,
+    /// `favicon-links`, `manifest-link`, `service-worker`, `print-link`, `video`, `audio-episode`, `last-updated`,
+    /// `contributors`, `shift-headings`, `heading-anchors` and `tables`, `katex`, `syntax-highlight`,
+    /// `git-snippet`, `integrity` and `thumbnail` with their features, and the processors `html`, `slides`, `copy`
+    /// and `image`, `favicon` with the `images` feature
+    pub fn builtin() -> Registry<R, D> {
+        let mut registry = Registry::new();
+        #[cfg(feature = "katex")]
+        registry.register_walker("katex", |_: NoOptions| Ok(Box::new(KatexReplacer)));
+        registry.register_walker("variables", |o: VariablesOptions| {
+            let variables = toml_to_value(toml::Value::Table(o.variables));
+            Ok(Box::new(VariableReplacer::new(variables).with_attributes(attribute_rules(o.attributes))))
+        });
+        registry.register_walker("links", |o: LinksOptions| Ok(Box::new(LinkReplacer::new().with_attributes(attribute_rules(o.attributes)))));
+        registry.register_walker("backlinks", |_: NoOptions| Ok(Box::new(BacklinksWalker)));
+        registry.register_walker("archive-list", |o: CollectionOptions| Ok(Box::new(ArchiveList::new(&o.collection))));
+        registry.register_walker("feed-link", |o: CollectionOptions| Ok(Box::new(FeedLink::new(&o.collection))));
+        registry.register_walker("webmentions", |o: WebmentionsOptions| Ok(Box::new(WebmentionList::new(o.file))));
+        registry.register_walker("comments", |o: CommentsOptions| Ok(Box::new(CommentList::new(o.dir))));
+        registry.register_walker("favicon-links", |_: NoOptions| Ok(Box::new(FaviconLinks)));
+        registry.register_walker("manifest-link", |_: NoOptions| Ok(Box::new(ManifestLink)));
+        registry.register_walker("service-worker", |_: NoOptions| Ok(Box::new(ServiceWorkerRegistration)));
+        registry.register_walker("print-link", |_: NoOptions| Ok(Box::new(PrintLink)));
+        registry.register_walker("audio-episode", |_: NoOptions| Ok(Box::new(AudioEpisode)));
+        registry.register_walker("last-updated", |_: NoOptions| Ok(Box::new(LastUpdated)));
+        registry.register_walker("contributors", |o: ContributorsOptions| Ok(Box::new(Contributors { profiles: o.profiles })));
+        registry.register_walker("shift-headings", |o: ShiftHeadingsOptions| Ok(Box::new(HeadingShift::new().with_by(o.by))));
+        registry.register_walker("heading-anchors", |o: HeadingAnchorsOptions| {
+            if !(0.0..=1.0).contains(&o.min_similarity) {
+                return Err(ConfigurafoxError::InvalidOptions { name: "heading-anchors".to_string(), msg: "min_similarity must be between 0 and 1".to_string() });
+            }
+            Ok(Box::new(HeadingAnchors::new(o.dir).with_min_similarity(o.min_similarity)))
+        });
+        registry.register_walker("tables", |o: TablesOptions| {
+            Ok(Box::new(TableEnhancer::new().with_wrapper_class(o.wrapper.then_some(o.class)).with_sticky(o.sticky)))
+        });
+        registry.register_walker("video", |o: VideoOptions| {
+            Ok(Box::new(VideoWalker { ffmpeg: o.ffmpeg, ffprobe: o.ffprobe, poster_at: o.poster_at }))
+        });
+        #[cfg(feature = "images")]
+        registry.register_walker("thumbnail", |_: NoOptions| Ok(Box::new(ThumbnailImage)));
+        #[cfg(feature = "syntax-highlight")]
+        registry.register_walker("syntax-highlight", |o: SyntaxHighlightOptions| {
+            let mut highlighter = SyntaxHighlighter::default(&o.theme);
+            for (lang, template) in &o.playgrounds {
+                highlighter = highlighter.with_playground(lang, template);
+            }
+            Ok(Box::new(highlighter))
+        });
+        #[cfg(all(feature = "remote", feature = "syntax-highlight"))]
+        registry.register_walker("git-snippet", |o: GitSnippetOptions| {
+            let mut highlighter = SyntaxHighlighter::default(&o.theme);
+            for (lang, template) in &o.playgrounds {
+                highlighter = highlighter.with_playground(lang, template);
+            }
+            Ok(Box::new(GitSnippet::new(highlighter).with_cache_dir(o.cache_dir)))
+        });
+
+        #[cfg(feature = "remote")]
+        registry.register_walker("integrity", |o: IntegrityOptions| {
+            let algorithm = HashAlgorithm::from_name(&o.algorithm).ok_or_else(|| ConfigurafoxError::InvalidOptions {
+                name: "integrity".to_string(),
+                msg: format!("unknown algorithm {:?}, expected sha256, sha384 or sha512", o.algorithm),
+            })?;
+            Ok(Box::new(SubresourceIntegrity::new().with_algorithm(algorithm).with_cache_dir(o.cache_dir)))
+        });
+
+        registry.register_processor("copy", |_, _: NoOptions, _| Ok(Box::new(IdentityProcessor)));
+        registry.register_processor("image", |_, o: ImageOptions, _| {
+            let keep_exif = o
+                .keep_exif
+                .iter()
+                .map(|name| exif_tag(name).ok_or_else(|| ConfigurafoxError::InvalidOptions { name: "image".to_string(), msg: format!("unknown EXIF tag {name:?}") }))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Box::new(ImageProcessor::new().with_strip_metadata(o.strip_metadata).with_kept_exif(&keep_exif)))
+        });
+        #[cfg(feature = "images")]
+        registry.register_processor("favicon", |_, _: NoOptions, _| Ok(Box::new(FaviconProcessor)));
+        registry.register_processor("html", |registry, o: HtmlOptions, data| {
+            let mut lints = Lints::none();
+            for code in &o.lints {
+                let rule = LintRule::from_code(code)
+                    .ok_or_else(|| ConfigurafoxError::InvalidOptions { name: "html".to_string(), msg: format!("unknown lint {code:?}") })?;
+                lints = lints.enable(rule);
+            }
+            let processor = HTMLProcessor::builder()
+                .data(data)
+                .walkers(registry.walkers(&o.walkers)?)
+                .trim(o.trim)
+                .pretty(o.pretty)
+                .whitespace(Whitespace::default().with_preformatted(o.preformatted))
+                .lints(lints)
+                .print(o.print)
+                .build();
+            Ok(Box::new(processor))
+        });
+        registry.register_processor("slides", |registry, o: SlidesOptions, data| {
+            let pages = registry.processor("html", o.html, data)?;
+            Ok(Box::new(SlideDeck::new(pages).with_split_at(o.split_at)))
+        });
+        registry
+    }
+
+    /// Makes `name` build walkers with `factory`, replacing any walker registered under that name before
+    pub fn register_walker<O, F>(&mut self, name: &str, factory: F)
+    where
+        O: DeserializeOwned,
+        F: Fn(O) -> Result<Box<dyn TreeWalker<R, D>>, ConfigurafoxError> + 'static,
+    {
+        let owned = name.to_string();
+        self.walkers.insert(name.to_string(), Box::new(move |value| factory(options(&owned, value)?)));
+    }
+
+    /// Makes `name` build processors with `factory`, which gets the registry to build walkers with
+    pub fn register_processor<O, F>(&mut self, name: &str, factory: F)
+    where
+        O: DeserializeOwned,
+        F: for<'d> Fn(&Registry<R, D>, O, &'d D) -> Result<Box<dyn ResourceProcessor<R> + 'd>, ConfigurafoxError> + 'static,
+    {
+        let owned = name.to_string();
+        self.processors.insert(name.to_string(), Box::new(move |registry, value, data| factory(registry, options(&owned, value)?, data)));
+    }
+
+    pub fn walker(&self, spec: &WalkerSpec) -> Result<Box<dyn TreeWalker<R, D>>, ConfigurafoxError> {
+        let factory = self
+            .walkers
+            .get(&spec.name)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "walker", name: spec.name.clone() })?;
+        factory(toml::Value::Table(spec.options.clone()))
+    }
+
+    /// The walkers of `specs`, in order
+    pub fn walkers(&self, specs: &[WalkerSpec]) -> Result<Vec<Box<dyn TreeWalker<R, D>>>, ConfigurafoxError> {
+        specs.iter().map(|spec| self.walker(spec)).collect()
+    }
+
+    pub fn processor<'d>(&self, name: &str, options: toml::Table, data: &'d D) -> Result<Box<dyn ResourceProcessor<R> + 'd>, ConfigurafoxError> {
+        let factory = self
+            .processors
+            .get(name)
+            .ok_or_else(|| ConfigurafoxError::NotRegistered { kind: "processor", name: name.to_string() })?;
+        factory(self, toml::Value::Table(options), data)
+    }
+
+    pub fn walker_names(&self) -> impl Iterator<Item = &str> {
+        self.walkers.keys().map(String::as_str)
+    }
+
+    pub fn processor_names(&self) -> impl Iterator<Item = &str> {
+        self.processors.keys().map(String::as_str)
+    }
+}
+
+impl<R: Resource + 'static, D: SiteData + 'static> Registry<R, D> {
+    /// With the walkers that read `SiteData` from the user data: `canonical-link`, `opengraph-meta`, `json-ld`,
+    /// `byline` and `author-posts`
+    pub fn with_site_walkers(mut self) -> Registry<R, D> {
+        self.register_walker("canonical-link", |_: NoOptions| Ok(Box::new(CanonicalLink)));
+        self.register_walker("opengraph-meta", |_: NoOptions| Ok(Box::new(OpenGraphMeta)));
+        self.register_walker("json-ld", |_: NoOptions| Ok(Box::new(JsonLd)));
+        self.register_walker("byline", |_: NoOptions| Ok(Box::new(Byline)));
+        self.register_walker("author-posts", |o: CollectionOptions| Ok(Box::new(AuthorPosts::new(&o.collection))));
+        self
+    }
+}
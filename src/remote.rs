@@ -0,0 +1,169 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::ConfigurafoxError;
+use crate::metadata::Metadata;
+use crate::resource_manager::{Resource, ResourceManager};
+
+/// Where fetched files are stored, relative to the project root
+pub const DEFAULT_CACHE_DIR: &str = ".configurafox-cache/remote";
+
+const MAX_BODY_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Validators sent back to the server to check whether the cached copy is still fresh
+#[derive(Debug, Default)]
+struct CacheInfo {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheInfo {
+    fn load(path: &Path) -> CacheInfo {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return CacheInfo::default();
+        };
+
+        let mut info = CacheInfo::default();
+        for line in contents.lines() {
+            if let Some(etag) = line.strip_prefix("etag: ") {
+                info.etag = Some(etag.to_string());
+            } else if let Some(last_modified) = line.strip_prefix("last-modified: ") {
+                info.last_modified = Some(last_modified.to_string());
+            }
+        }
+        info
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut contents = String::new();
+        if let Some(etag) = &self.etag {
+            contents.push_str(&format!("etag: {etag}\n"));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            contents.push_str(&format!("last-modified: {last_modified}\n"));
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+/// The file name a URL is cached under: a hash of the URL, keeping the extension so processors can still dispatch on it
+fn cache_file_name(url: &str) -> String {
+    let hash = hex::encode(&Sha256::digest(url.as_bytes())[..8]);
+
+    let url_path = url.split(['?', '#']).next().unwrap_or(url);
+    let last_segment = url_path.rsplit('/').next().unwrap_or("");
+    match last_segment.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) => format!("{hash}.{ext}"),
+        _ => hash,
+    }
+}
+
+/// Downloads `url` without caching it
+pub fn fetch(url: &str) -> Result<Vec<u8>, ConfigurafoxError> {
+    let remote_err = |msg: String| ConfigurafoxError::Remote { url: url.to_string(), msg };
+
+    let mut response = ureq::get(url).call().map_err(|e| remote_err(e.to_string()))?;
+    let body = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_BODY_SIZE)
+        .read_to_vec()
+        .map_err(|e| remote_err(e.to_string()))?;
+
+    info!("Fetched {} bytes from {url}", body.len());
+    Ok(body)
+}
+
+/// Downloads `url` into `cache_dir` (absolute), revalidating an existing copy with the server.
+/// Returns the path of the cached file. If the server can't be reached, a stale cached copy is used
+pub fn fetch_cached(url: &str, cache_dir: &Path) -> Result<PathBuf, ConfigurafoxError> {
+    let remote_err = |msg: String| ConfigurafoxError::Remote { url: url.to_string(), msg };
+
+    std::fs::create_dir_all(cache_dir)?;
+
+    let file_name = cache_file_name(url);
+    let cached_path = cache_dir.join(&file_name);
+    let info_path = cache_dir.join(format!("{file_name}.cache-info"));
+
+    let info = if cached_path.exists() { CacheInfo::load(&info_path) } else { CacheInfo::default() };
+
+    let mut request = ureq::get(url);
+    if let Some(etag) = &info.etag {
+        request = request.header("If-None-Match", etag);
+    }
+    if let Some(last_modified) = &info.last_modified {
+        request = request.header("If-Modified-Since", last_modified);
+    }
+
+    let mut response = match request.call() {
+        Ok(response) => response,
+        Err(e) if cached_path.exists() => {
+            warn!("Fetching {url} failed ({e}), using cached copy {}", cached_path.display());
+            return Ok(cached_path);
+        }
+        Err(e) => return Err(remote_err(e.to_string())),
+    };
+
+    if response.status() == 304 {
+        debug!("{url} not modified, using {}", cached_path.display());
+        return Ok(cached_path);
+    }
+
+    let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let new_info = CacheInfo {
+        etag: header("etag"),
+        last_modified: header("last-modified"),
+    };
+
+    let body = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_BODY_SIZE)
+        .read_to_vec()
+        .map_err(|e| remote_err(e.to_string()))?;
+
+    info!("Fetched {} bytes from {url}", body.len());
+
+    std::fs::write(&cached_path, body)?;
+    new_info.save(&info_path)?;
+
+    Ok(cached_path)
+}
+
+/// Downloads `url` into `cache_dir` (absolute) unless it's there already, for URLs whose content never changes,
+/// like a file at a pinned commit. Returns the path of the cached file
+pub fn fetch_pinned(url: &str, cache_dir: &Path) -> Result<PathBuf, ConfigurafoxError> {
+    let cached_path = cache_dir.join(cache_file_name(url));
+    if cached_path.exists() {
+        debug!("{url} is pinned, using {}", cached_path.display());
+        return Ok(cached_path);
+    }
+
+    let body = fetch(url)?;
+    std::fs::create_dir_all(cache_dir)?;
+    std::fs::write(&cached_path, body)?;
+    Ok(cached_path)
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Fetches `url` into the project's remote cache and registers the downloaded file as `resource`.
+    /// From then on it behaves like any local file. The URL is stored in the `source_url` metadata.
+    /// The cache lives on the real filesystem, so this needs a `Vfs` that can see the project root
+    pub fn register_remote(&mut self, url: &str, resource: R) -> Result<(), ConfigurafoxError> {
+        let cache_dir = PathBuf::from(DEFAULT_CACHE_DIR);
+        fetch_cached(url, &self.absolute_path(&cache_dir))?;
+        let path = cache_dir.join(cache_file_name(url));
+
+        info!("{}: Adding {:?} from {url}", path.display(), resource.identifier());
+
+        let mut metadata = Metadata::new();
+        metadata.insert("source_url", url);
+        self.insert(resource, path, metadata);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+
+use miette::{GraphicalReportHandler, GraphicalTheme, LabeledSpan, MietteDiagnostic, NamedSource, Report};
+
+use crate::ConfigurafoxError;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::position::SourcePos;
+use crate::resource_manager::{Resource, ResourceManager};
+
+/// The byte offset of `pos` in `source`
+fn offset_of(source: &str, pos: SourcePos) -> Option<usize> {
+    let mut offset = 0;
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        if i + 1 == pos.line {
+            return line.char_indices().nth(pos.column - 1).map(|(o, _)| offset + o);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// The span to underline for an element starting at `offset`: `needle` if it occurs in the opening tag,
+/// otherwise the opening tag itself
+fn element_span(source: &str, offset: usize, needle: Option<&str>) -> (usize, usize) {
+    let rest = &source[offset..];
+    let tag_len = rest.find('>').map(|e| e + 1).unwrap_or(rest.len());
+    match needle.and_then(|needle| rest[..tag_len].find(needle).map(|start| (start, needle.len()))) {
+        Some((start, len)) => (offset + start, len),
+        None => (offset, tag_len),
+    }
+}
+
+/// What in the offending element the error is about, e.g. the `@reference` that couldn't be resolved
+fn needle_for(error: &ConfigurafoxError) -> Option<String> {
+    match error {
+        ConfigurafoxError::UnknownVariable { name } => Some(format!("${name}")),
+        ConfigurafoxError::UnknownIdentifier { identifier } => Some(format!("@{identifier}")),
+        ConfigurafoxError::UnknownLanguage { lang } => Some(lang.clone()),
+        _ => None,
+    }
+}
+
+/// The source of `path`, as the walkers saw it
+fn source_of<R: Resource>(resources: &ResourceManager<R>, path: &Path) -> Option<String> {
+    match resources.resources_at_path(path).first() {
+        Some(resource) => resources.read_to_string(resource).ok().map(|s| s.to_string()),
+        None => String::from_utf8(resources.vfs().read(path).ok()?).ok(),
+    }
+}
+
+fn render_report(report: Report, color: bool) -> String {
+    let theme = if color { GraphicalTheme::unicode() } else { GraphicalTheme::unicode_nocolor() };
+    let mut out = String::new();
+    if GraphicalReportHandler::new_themed(theme).render_report(&mut out, report.as_ref()).is_err() {
+        return format!("{report}");
+    }
+    out
+}
+
+/// Renders `message` with the source snippet around `pos` in `path`, falling back to a single line if the
+/// source can't be read
+fn render_at<R: Resource>(
+    resources: &ResourceManager<R>,
+    severity: miette::Severity,
+    message: String,
+    path: &Path,
+    pos: Option<SourcePos>,
+    needle: Option<&str>,
+    color: bool,
+) -> String {
+    let mut diagnostic = MietteDiagnostic::new(message).with_severity(severity);
+
+    let Some(source) = source_of(resources, path) else {
+        return render_report(Report::new(diagnostic.with_help(path.display().to_string())), color);
+    };
+
+    if let Some(offset) = pos.and_then(|pos| offset_of(&source, pos)) {
+        let (start, len) = element_span(&source, offset, needle);
+        diagnostic = diagnostic.with_label(LabeledSpan::at(start..start + len, "here"));
+    } else {
+        diagnostic = diagnostic.with_help(path.display().to_string());
+    }
+
+    let report = Report::new(diagnostic).with_source_code(NamedSource::new(path.display().to_string(), source));
+    render_report(report, color)
+}
+
+/// Renders `error` for a terminal, showing the offending part of the source for errors located in a file
+pub fn render_error<R: Resource>(error: &ConfigurafoxError, resources: &ResourceManager<R>, color: bool) -> String {
+    match error {
+        ConfigurafoxError::Located { path, pos, error } => {
+            let needle = needle_for(error.innermost());
+            render_at(resources, miette::Severity::Error, error.to_string(), path, Some(*pos), needle.as_deref(), color)
+        }
+        ConfigurafoxError::ParseHTMLError { path, pos, error } => {
+            render_at(resources, miette::Severity::Error, format!("could not parse html: {error:?}"), path, *pos, None, color)
+        }
+        ConfigurafoxError::InResource { error: inner, .. }
+            if matches!(**inner, ConfigurafoxError::Located { .. } | ConfigurafoxError::ParseHTMLError { .. }) =>
+        {
+            render_error(inner, resources, color)
+        }
+        error => render_report(Report::new(MietteDiagnostic::new(error.to_string())), color),
+    }
+}
+
+/// Renders `diagnostic` for a terminal, showing the part of the source it's about if it has a position
+pub fn render_diagnostic<R: Resource>(diagnostic: &Diagnostic, resources: &ResourceManager<R>, color: bool) -> String {
+    let severity = match diagnostic.severity {
+        Severity::Note => miette::Severity::Advice,
+        Severity::Warning => miette::Severity::Warning,
+        Severity::Error => miette::Severity::Error,
+    };
+
+    match &diagnostic.path {
+        Some(path) => render_at(resources, severity, diagnostic.message.clone(), path, diagnostic.pos, None, color),
+        None => render_report(Report::new(MietteDiagnostic::new(diagnostic.message.clone()).with_severity(severity)), color),
+    }
+}
@@ -2,8 +2,20 @@
 use tracing::{trace, debug, info, warn, error, instrument, Level};
 
 use std::hash::Hash;
-use std::collections::HashMap;
+use std::io::Write;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::ConfigurafoxError;
+use crate::diagnostics::Diagnostics;
+use crate::graph::{DependencyGraph, DependencyKind};
+use crate::metadata::{Metadata, Value, FRONT_MATTER_DELIMITER, OUTPUT_PATH_KEY, SIDECAR_EXTENSION, parse_toml, split_front_matter};
+use crate::query::{Collection, Query, TAGS_KEY, tags_of};
+use crate::vfs::{self, EntryKind, PhysicalFs, Vfs};
 
 // enum Resource {
 //     HTMLFile(PathBuf),
@@ -22,86 +34,1204 @@ pub trait Resource: Eq + Hash + Clone + std::fmt::Debug {
     fn output_path(&self) -> PathBuf;
 }
 
+/// Output extensions of files that `PathResource` treats as pages
+pub const PAGE_EXTENSIONS: [&str; 2] = ["html", "htm"];
+
+/// A ready-made `Resource` for sites where every file is identified by its path, relative to the directory it
+/// was found in. Pages drop their extension from the identifier, so `posts/hello.html` is `@posts/hello`, while
+/// other files keep it, like `@style.css`. Outputs go to the same path, with extensions optionally mapped
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PathResource {
+    path: PathBuf,
+    output: PathBuf,
+}
+
+impl PathResource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> PathResource {
+        let path = path.into();
+        PathResource { output: path.clone(), path }
+    }
+
+    /// Like `new`, with the output's extension replaced according to `map`, e.g. `[("md", "html")]`
+    pub fn with_output_extensions<P: Into<PathBuf>>(path: P, map: &[(&str, &str)]) -> PathResource {
+        let mut resource = PathResource::new(path);
+        let extension = resource.path.extension().and_then(|ext| ext.to_str());
+        if let Some((_, to)) = map.iter().find(|(from, _)| Some(*from) == extension) {
+            resource.output.set_extension(to);
+        }
+        resource
+    }
+
+    /// The path the resource was created with
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// If the output is a page, see `PAGE_EXTENSIONS`
+    pub fn is_page(&self) -> bool {
+        self.output.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| PAGE_EXTENSIONS.contains(&ext))
+    }
+}
+
+impl Resource for PathResource {
+    fn identifier(&self) -> String {
+        let identifier = if self.is_page() { self.path.with_extension("") } else { self.path.clone() };
+        identifier.to_string_lossy().replace('\\', "/")
+    }
+
+    fn output_path(&self) -> PathBuf {
+        self.output.clone()
+    }
+}
+
+/// Where the contents of a resource come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// A file in the manager's `Vfs` (usually on disk), at the resource's path relative to the project root
+    File,
+    /// A file inside a zip or tar archive. `archive` is relative to the project root
+    #[cfg(feature = "archives")]
+    Archive { archive: PathBuf, entry: String },
+    /// Literal contents, not backed by any file
+    Memory(Arc<[u8]>),
+}
+
+/// Everything the manager knows about one registered resource
+#[derive(Debug, Clone)]
+struct Entry {
+    path: PathBuf,
+    origin: Origin,
+    metadata: Metadata,
+    file_info: FileInfo,
+}
+
+/// Filesystem metadata of a resource, captured at registration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileInfo {
+    /// None if unknown, e.g. for in-memory resources or on platforms without mtimes
+    pub modified: Option<SystemTime>,
+    pub size: Option<u64>,
+}
+
+/// What to do with symbolic links found while scanning directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Treat links like their targets. Directory cycles are detected and skipped, and files reachable
+    /// through several links are only registered once
+    #[default]
+    Follow,
+    Skip,
+    /// Fail the scan on the first symlink
+    Error,
+}
+
+/// Options for `register_all_files_in_directory_with_options`
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub recurse: bool,
+    pub symlinks: SymlinkPolicy,
+    /// Read metadata from sidecar files (`photo.jpg.toml` for `photo.jpg`). Sidecar files aren't registered
+    /// themselves then. Off by default, so `.toml` files are registered like any other file
+    pub sidecars: bool,
+    /// Skip files and directories whose name starts with `.`
+    pub skip_hidden: bool,
+    /// How many levels of subdirectories to descend into when recursing. `Some(0)` is the same as not recursing
+    pub max_depth: Option<usize>,
+    /// Files and directories matching any of these (relative to the project root) are skipped. See `exclude`
+    pub excluded: Vec<glob::Pattern>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            recurse: true,
+            symlinks: SymlinkPolicy::default(),
+            sidecars: false,
+            skip_hidden: false,
+            max_depth: None,
+            excluded: Vec::new(),
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Adds a glob pattern of paths to skip, e.g. `content/drafts` or `**/*.bak`. As in `register_glob`,
+    /// `*` does not match across `/`
+    pub fn exclude(mut self, pattern: &str) -> std::io::Result<ScanOptions> {
+        let compiled = glob::Pattern::new(pattern)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid glob {pattern:?}: {e}")))?;
+        self.excluded.push(compiled);
+        Ok(self)
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        self.excluded.iter().any(|pattern| pattern.matches_path_with(path, options))
+    }
+
+    /// If a scan would skip the file at `path` for being hidden or excluded, e.g. to filter files created
+    /// while watching the same way
+    pub fn skips(&self, path: &Path) -> bool {
+        let hidden = path.components().any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        (self.skip_hidden && hidden) || self.is_excluded(path)
+    }
+}
+
+/// The path of the sidecar file of `path`
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(SIDECAR_EXTENSION);
+    PathBuf::from(sidecar)
+}
+
+/// Reads the front matter of the file at `path`, without reading the whole file unless it has any
+pub(crate) fn read_front_matter(vfs: &dyn Vfs, path: &Path) -> std::io::Result<Option<Metadata>> {
+    if vfs.read_prefix(path, FRONT_MATTER_DELIMITER.len())? != FRONT_MATTER_DELIMITER.as_bytes() {
+        return Ok(None);
+    }
+
+    let Ok(source) = String::from_utf8(vfs.read(path)?) else {
+        return Ok(None);
+    };
+    let Some((front_matter, _)) = split_front_matter(&source) else {
+        return Ok(None);
+    };
+
+    parse_toml(front_matter)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: front matter: {e}", path.display())))
+}
+
+/// Calls `f` with the path of a temporary file containing `contents`, in a directory of its own, removed afterwards
+fn with_temp_file<T>(contents: &[u8], extension: &str, f: impl FnOnce(&Path) -> T) -> Result<T, ConfigurafoxError> {
+    let dir = vfs::create_temp_dir("configurafox")?;
+    let path = dir.join(format!("source.{extension}"));
+    let written = std::fs::OpenOptions::new().write(true).create_new(true).open(&path).and_then(|mut file| file.write_all(contents));
+    let result = written.map(|()| f(&path));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(result?)
+}
+
+/// If `path` is the sidecar file of some other existing file
+fn is_sidecar(vfs: &dyn Vfs, path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == SIDECAR_EXTENSION) && vfs.is_file(&path.with_extension(""))
+}
+
+/// A file found by `scan_directory`: its path relative to the project root, and its canonical path
+type ScannedFile = (PathBuf, PathBuf);
+
+/// Lists the files below `dir_path` that are candidates for registration, in directory order.
+/// `ancestors` are the canonical paths of the directories above, to detect symlink cycles, and their number
+/// is the depth of `dir_path`. With the `parallel` feature, subdirectories are scanned concurrently
+fn scan_directory(vfs: &dyn Vfs, dir_path: &Path, options: &ScanOptions, ancestors: &[PathBuf]) -> std::io::Result<Vec<ScannedFile>> {
+    enum Found {
+        File(ScannedFile),
+        Dir(PathBuf),
+    }
+
+    let depth = ancestors.len();
+    let mut ancestors = ancestors.to_vec();
+    ancestors.push(vfs.canonicalize(dir_path)?);
+
+    let mut found = Vec::new();
+    for dir_entry in vfs.read_dir(dir_path)? {
+        let entry_path = {
+            if dir_path == Path::new(".") {
+                PathBuf::from(dir_entry.name)
+            } else {
+                dir_path.join(dir_entry.name)
+            }
+        };
+
+        if options.skip_hidden && entry_path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            debug!("{}: Hidden, skipping", entry_path.display());
+            continue;
+        }
+        if options.is_excluded(&entry_path) {
+            debug!("{}: Excluded, skipping", entry_path.display());
+            continue;
+        }
+
+        let mut kind = dir_entry.kind;
+        if kind == EntryKind::Symlink {
+            match options.symlinks {
+                SymlinkPolicy::Skip => {
+                    debug!("{}: Skipping symlink", entry_path.display());
+                    continue;
+                }
+                SymlinkPolicy::Error => {
+                    return Err(std::io::Error::other(format!("{}: symlinks are not allowed", entry_path.display())));
+                }
+                SymlinkPolicy::Follow => {
+                    // Follows the whole chain, fails on dangling links
+                    kind = vfs.metadata(&entry_path)?.kind;
+                }
+            }
+        }
+
+        if kind == EntryKind::Dir {
+            if options.recurse && options.max_depth.is_none_or(|max| depth < max) {
+                let canonical = vfs.canonicalize(&entry_path)?;
+                if ancestors.contains(&canonical) {
+                    warn!("{}: Symlink cycle to {}, not descending", entry_path.display(), canonical.display());
+                    continue;
+                }
+                found.push(Found::Dir(entry_path));
+            }
+        } else {
+            if options.sidecars && is_sidecar(vfs, &entry_path) {
+                debug!("{}: Sidecar file, not adding", entry_path.display());
+                continue;
+            }
+            let canonical = vfs.canonicalize(&entry_path)?;
+            found.push(Found::File((entry_path, canonical)));
+        }
+    }
+
+    let scan = |found: Found| match found {
+        Found::File(file) => Ok(vec![file]),
+        Found::Dir(dir) => scan_directory(vfs, &dir, options, &ancestors),
+    };
+
+    #[cfg(feature = "parallel")]
+    let nested = {
+        use rayon::prelude::*;
+        found.into_par_iter().map(scan).collect::<std::io::Result<Vec<_>>>()?
+    };
+    #[cfg(not(feature = "parallel"))]
+    let nested = found.into_iter().map(scan).collect::<std::io::Result<Vec<_>>>()?;
+
+    Ok(nested.into_iter().flatten().collect())
+}
+
+/// How identifiers are made comparable, both when resources are registered and when they are looked up,
+/// so that e.g. `@My-Post` finds `my-post`. By default identifiers are compared as-is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdentifierNormalization {
+    pub lowercase: bool,
+    /// Replace runs of characters other than letters, digits, `_`, `.` and `/` with a single `-`
+    pub slugify: bool,
+    /// Unicode canonical composition, so that e.g. a precomposed `é` and `e` + combining accent are equal
+    pub nfc: bool,
+}
+
+impl IdentifierNormalization {
+    /// Lowercase, slugified, NFC
+    pub fn all() -> IdentifierNormalization {
+        IdentifierNormalization { lowercase: true, slugify: true, nfc: true }
+    }
+
+    pub fn apply(&self, identifier: &str) -> String {
+        let mut result = if self.nfc {
+            identifier.nfc().collect::<String>()
+        } else {
+            identifier.to_string()
+        };
+
+        if self.lowercase {
+            result = result.to_lowercase();
+        }
+
+        if self.slugify {
+            let mut slug = String::with_capacity(result.len());
+            for c in result.chars() {
+                if c.is_alphanumeric() || matches!(c, '_' | '.' | '/') {
+                    slug.push(c);
+                } else if !slug.ends_with('-') {
+                    slug.push('-');
+                }
+            }
+            result = slug.trim_matches('-').to_string();
+        }
+
+        result
+    }
+}
+
+/// Metadata key with an array of aliases to register for the resource
+pub const ALIASES_KEY: &str = "aliases";
+
+static NO_METADATA: Metadata = Metadata::new();
+
+/// Makes a `Derivative` from its source's file, see `ResourceManager::with_file`
+pub type DeriveFn = dyn Fn(&Path) -> Result<Vec<u8>, ConfigurafoxError> + Send + Sync;
+
+/// Resources larger than this aren't kept in the content cache, so whole videos aren't held in memory for a run
+pub const MAX_CACHED_SIZE: usize = 16 << 20;
+
+/// How many bytes of contents the content cache holds at most. The least recently read are dropped first
+pub const CONTENT_CACHE_LIMIT: usize = 256 << 20;
+
+/// Contents read by `ResourceManager::read`, with when they were last read
+struct ContentCache<R> {
+    entries: HashMap<R, (Arc<[u8]>, u64)>,
+    size: usize,
+    clock: u64,
+}
+
+impl<R: Resource> ContentCache<R> {
+    fn get(&mut self, resource: &R) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let (contents, last_read) = self.entries.get_mut(resource)?;
+        *last_read = self.clock;
+        Some(contents.clone())
+    }
+
+    fn insert(&mut self, resource: R, contents: Arc<[u8]>) {
+        if contents.len() > MAX_CACHED_SIZE {
+            trace!("{}: Too large to cache", resource.identifier());
+            return;
+        }
+        self.remove(&resource);
+        while self.size + contents.len() > CONTENT_CACHE_LIMIT {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, last_read))| *last_read).map(|(resource, _)| resource.clone()) else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+        self.clock += 1;
+        self.size += contents.len();
+        self.entries.insert(resource, (contents, self.clock));
+    }
+
+    fn remove(&mut self, resource: &R) {
+        if let Some((contents, _)) = self.entries.remove(resource) {
+            self.size -= contents.len();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+}
+
+/// An output made from a registered resource while processing others, instead of being registered itself, like a
+/// thumbnail. See `ResourceManager::request_derivative`
+pub struct Derivative<R> {
+    pub source: R,
+    pub make: Box<DeriveFn>,
+}
+
+/// The contents of the entries of an archive, by path inside it
+#[cfg(feature = "archives")]
+type ArchiveEntries = HashMap<String, Arc<[u8]>>;
+
+/// String value of a metadata key -> the resources with that value
+type MetadataIndex<R> = HashMap<String, Vec<R>>;
+
 /// Holds all resources, along with some user-specified extra data
 pub struct ResourceManager<R: Resource> {
     project_root: PathBuf,
 
-    registered_resources: HashMap<R, PathBuf>,
+    vfs: Arc<dyn Vfs>,
+
+    registered_resources: HashMap<R, Entry>,
+
+    /// Normalized identifier -> resources with that identifier, so lookups don't scan every resource.
+    /// Usually one resource per identifier, `validate` rejects duplicates
+    identifiers: HashMap<String, Vec<R>>,
+
+    /// Extra identifiers resources can be referred to by, as given, so they survive a change of normalization
+    aliases: HashMap<String, R>,
+
+    /// Normalized alias -> resource, rebuilt from `aliases` when the normalization changes
+    alias_index: HashMap<String, R>,
+
+    normalization: IdentifierNormalization,
+
+    dependencies: Mutex<DependencyGraph<R>>,
+
+    diagnostics: Mutex<Diagnostics>,
+
+    content_cache: Mutex<ContentCache<R>>,
+
+    /// Archive path -> the contents of its entries, by path inside it. Archives are read in one pass, not once for
+    /// every entry
+    #[cfg(feature = "archives")]
+    archives: Mutex<HashMap<PathBuf, Arc<ArchiveEntries>>>,
+
+    /// Metadata key -> its index, built by the first `resources_with` for the key and dropped whenever
+    /// registrations or metadata change, so pages looking up e.g. their print variant don't scan every resource
+    metadata_index: Mutex<HashMap<String, Arc<MetadataIndex<R>>>>,
+
+    /// Output path -> derivative, requested during the current run
+    derivatives: Mutex<BTreeMap<PathBuf, Derivative<R>>>,
+
+    collections: HashMap<String, Collection<R>>,
 }
 
 impl<R: Resource> ResourceManager<R> {
     pub fn new(project_root: PathBuf) -> ResourceManager<R> {
+        let vfs = Arc::new(PhysicalFs::new(project_root.clone()));
+        ResourceManager::with_vfs_at(project_root, vfs)
+    }
+
+    /// A manager reading all sources from `vfs` instead of the real filesystem
+    pub fn with_vfs(vfs: Arc<dyn Vfs>) -> ResourceManager<R> {
+        let project_root = vfs.physical_path(Path::new("")).unwrap_or_default();
+        ResourceManager::with_vfs_at(project_root, vfs)
+    }
+
+    fn with_vfs_at(project_root: PathBuf, vfs: Arc<dyn Vfs>) -> ResourceManager<R> {
         ResourceManager {
             project_root,
 
+            vfs,
+
             registered_resources: HashMap::new(),
+
+            identifiers: HashMap::new(),
+
+            aliases: HashMap::new(),
+            alias_index: HashMap::new(),
+
+            normalization: IdentifierNormalization::default(),
+
+            dependencies: Mutex::new(DependencyGraph::new()),
+
+            diagnostics: Mutex::new(Diagnostics::new()),
+
+            content_cache: Mutex::new(ContentCache { entries: HashMap::new(), size: 0, clock: 0 }),
+
+            #[cfg(feature = "archives")]
+            archives: Mutex::new(HashMap::new()),
+            metadata_index: Mutex::new(HashMap::new()),
+
+            derivatives: Mutex::new(BTreeMap::new()),
+
+            collections: HashMap::new(),
         }
     }
 
+    /// The file tree sources are read from
+    pub fn vfs(&self) -> &dyn Vfs {
+        &*self.vfs
+    }
+
+    /// The location of `path_fragment` (relative to the project root) on disk. `..` components can't climb out
+    /// of the project, and absolute fragments are treated as relative to the project root.
+    /// Use `checked_path` to reject such paths instead
     pub fn absolute_path<P: AsRef<Path>>(&self, path_fragment: P) -> PathBuf {
+        let fragment = path_fragment.as_ref();
+        let clamped = vfs::clamp(fragment);
+        if vfs::confine(fragment).ok().as_ref() != Some(&clamped) {
+            warn!("{} points outside the project root, using {}", fragment.display(), clamped.display());
+        }
+
         let mut res = self.project_root.clone();
-        res.push(path_fragment);
+        res.push(clamped);
         res
     }
 
+    /// Normalizes `path_fragment` (relative to the project root), failing if it is absolute or points outside the project
+    pub fn checked_path<P: AsRef<Path>>(&self, path_fragment: P) -> Result<PathBuf, ConfigurafoxError> {
+        vfs::confine(path_fragment.as_ref())
+            .map_err(|_| ConfigurafoxError::PathOutsideProject { path: path_fragment.as_ref().to_owned() })
+    }
+
     pub fn register_all_files_in_directory<F: Fn(&Path) -> Option<R>>(
         &mut self,
         dir_path: PathBuf,
         parse_resource: F,
         recurse: bool,
     ) -> std::io::Result<()> {
-        debug!("Adding files in {}", dir_path.display());
-        self.register_all_files_in_directory_ref(dir_path, &parse_resource, recurse)
+        self.register_all_files_in_directory_with_options(dir_path, parse_resource, &ScanOptions { recurse, ..ScanOptions::default() })
+    }
+
+    /// Registers every file whose path relative to the project root matches `pattern`, e.g. `content/**/*.md`.
+    /// `*` does not match across `/`, use `**` for that
+    pub fn register_glob<F: Fn(&Path) -> Option<R>>(
+        &mut self,
+        pattern: &str,
+        parse_resource: F,
+    ) -> std::io::Result<()> {
+        let compiled = glob::Pattern::new(pattern)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid glob {pattern:?}: {e}")))?;
+
+        // Only scan the part of the tree that can possibly match
+        let base_dir = Path::new(pattern)
+            .components()
+            .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+            .collect::<PathBuf>();
+        let base_dir = if base_dir == Path::new(pattern) {
+            base_dir.parent().map(Path::to_owned).unwrap_or_default()
+        } else {
+            base_dir
+        };
+        let base_dir = if base_dir.as_os_str().is_empty() { PathBuf::from(".") } else { base_dir };
+
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+
+        debug!("Adding files matching {pattern} in {}", base_dir.display());
+
+        let matching = |relative: &Path, path: &Path| -> std::io::Result<Option<(R, Metadata)>> {
+            if compiled.matches_path_with(relative, options) {
+                Ok(parse_resource(path).map(|res| (res, Metadata::new())))
+            } else {
+                Ok(None)
+            }
+        };
+
+        self.register_all_files_in_directory_ref(base_dir, &matching, &ScanOptions::default())
+    }
+
+    /// Registers all files in `dir_path` for which `parse_resource` returns a resource, as configured by `options`
+    pub fn register_all_files_in_directory_with_options<F: Fn(&Path) -> Option<R>>(
+        &mut self,
+        dir_path: PathBuf,
+        parse_resource: F,
+        options: &ScanOptions,
+    ) -> std::io::Result<()> {
+        debug!("Adding files in {} ({:?})", dir_path.display(), options);
+        self.register_all_files_in_directory_ref(dir_path, &|_, path| Ok(parse_resource(path).map(|res| (res, Metadata::new()))), options)
     }
 
-    fn register_all_files_in_directory_ref<F: Fn(&Path) -> Option<R>>(
+    /// Registers all files like `register_all_files_in_directory_with_options`, but reads the TOML front matter
+    /// (delimited by `+++` lines at the very start) of each file first and passes it to `parse_resource`, so the
+    /// resource can be derived from the document itself. The front matter also becomes the resource's metadata.
+    /// Files without front matter get empty metadata
+    pub fn register_all_files_with_front_matter<F: Fn(&Path, &Metadata) -> Option<R>>(
+        &mut self,
+        dir_path: PathBuf,
+        parse_resource: F,
+        options: &ScanOptions,
+    ) -> std::io::Result<()> {
+        debug!("Adding files in {} with front matter ({:?})", dir_path.display(), options);
+
+        let vfs = self.vfs.clone();
+        let with_front_matter = |relative: &Path, path: &Path| -> std::io::Result<Option<(R, Metadata)>> {
+            let metadata = read_front_matter(&*vfs, relative)?.unwrap_or_default();
+            Ok(parse_resource(path, &metadata).map(|res| (res, metadata)))
+        };
+
+        self.register_all_files_in_directory_ref(dir_path, &with_front_matter, options)
+    }
+
+    /// `parse_resource` gets the path relative to the project root, and the absolute path
+    fn register_all_files_in_directory_ref<F: Fn(&Path, &Path) -> std::io::Result<Option<(R, Metadata)>>>(
         &mut self,
         dir_path: PathBuf,
         parse_resource: &F,
-        recurse: bool,
+        options: &ScanOptions,
     ) -> std::io::Result<()> {
-        for dir_entry in std::fs::read_dir(self.absolute_path(&dir_path))? {
-            let dir_entry = dir_entry?;
-            let entry_name = dir_entry.file_name();
-
-            let entry_path = {
-                if dir_path == PathBuf::from(".") {
-                    PathBuf::from(entry_name)
-                } else {
-                    let mut entry_relative = dir_path.clone();
-                    entry_relative.push(entry_name);
-                    entry_relative
-                }
+        let _register = tracing::debug_span!("register", dir = %dir_path.display()).entered();
+        let vfs = self.vfs.clone();
+
+        // Canonical paths of all files seen so far, so that a file isn't registered twice through different links
+        let mut seen_files = HashSet::new();
+
+        for (entry_path, canonical) in scan_directory(&*vfs, &dir_path, options, &[])? {
+            if !seen_files.insert(canonical) {
+                debug!("{}: Already seen through another path, not adding", entry_path.display());
+                continue;
+            }
+
+            let Some((res, mut metadata)) = parse_resource(&entry_path, &self.absolute_path(&entry_path))? else {
+                debug!("{}: Not adding", entry_path.display());
+                continue;
             };
+            info!("{}: Adding {:?}", entry_path.display(), res.identifier());
 
-            let file_type = dir_entry.file_type()?;
-            if file_type.is_dir() {
-                if recurse {
-                    self.register_all_files_in_directory_ref(entry_path.clone(), parse_resource, recurse)?;
+            if options.sidecars {
+                if let Some(sidecar) = self.load_sidecar(&entry_path)? {
+                    metadata.merge(sidecar);
                 }
-            } else {
-                let Some(res) = parse_resource(&dir_entry.path()) else {
-                    debug!("{}: Not adding", entry_path.display());
-                    continue;
-                };
-                info!("{}: Adding {:?}", entry_path.display(), res.identifier());
-
-                self.registered_resources.insert(res, entry_path);
             }
+
+            self.insert(res, entry_path, metadata);
         }
 
         Ok(())
     }
 
-    pub fn resource_by_identifier(&self, identifier: &str) -> Option<&R> {
+    /// Registers the single file at `path` (relative to the project root) as `resource`
+    pub fn register_file(&mut self, path: PathBuf, resource: R) -> std::io::Result<()> {
+        if !self.vfs.is_file(&path) {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not a file in {}", path.display(), self.vfs.describe())));
+        }
+
+        info!("{}: Adding {:?}", path.display(), resource.identifier());
+
+        let metadata = self.load_sidecar(&path)?.unwrap_or_default();
+        self.insert(resource, path, metadata);
+        Ok(())
+    }
+
+    /// Reads the sidecar file of `path` (relative to the project root), if there is one
+    pub(crate) fn load_sidecar(&self, path: &Path) -> std::io::Result<Option<Metadata>> {
+        let sidecar = sidecar_path(path);
+        if !self.vfs.is_file(&sidecar) {
+            return Ok(None);
+        }
+
+        debug!("{}: Reading sidecar {}", path.display(), sidecar.display());
+
+        let source = String::from_utf8(self.vfs.read(&sidecar)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {e}", sidecar.display())))?;
+        parse_toml(&source)
+            .map(Some)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {e}", sidecar.display())))
+    }
+
+    /// Removes `resource` along with its aliases, cached contents and dependencies. Returns its path if it was registered.
+    /// Warns if other resources still reference it
+    pub fn unregister(&mut self, resource: &R) -> Option<PathBuf> {
+        let entry = self.registered_resources.remove(resource)?;
+
+        info!("{}: Removing {:?}", entry.path.display(), resource.identifier());
+
+        for referrer in self.backlinks(resource) {
+            warn!("{:?} is removed but still referenced by {:?}", resource.identifier(), referrer.identifier());
+        }
+
+        self.unindex(resource);
+        self.metadata_index.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+        self.aliases.retain(|_, r| r != resource);
+        // An alias of another resource may have been shadowed by one of these
+        self.index_aliases();
+        self.invalidate(resource);
+        self.dependency_graph().remove(resource);
+
+        Some(entry.path)
+    }
+
+    /// Removes every resource whose contents come from `path`, e.g. when the file has been deleted
+    pub fn unregister_path(&mut self, path: &Path) -> Vec<R> {
+        let removed = self.resources_at_path(path).into_iter().cloned().collect::<Vec<_>>();
+        for resource in &removed {
+            self.unregister(resource);
+        }
+        removed
+    }
+
+    /// Swaps `old` for `new`, keeping its path, contents, metadata and aliases. Dependencies on `old` are dropped
+    /// and will be recorded again when the dependents are processed. Returns false if `old` isn't registered
+    pub fn replace(&mut self, old: &R, new: R) -> bool {
+        let Some(entry) = self.registered_resources.remove(old) else {
+            return false;
+        };
+
+        info!("{}: Replacing {:?} with {:?}", entry.path.display(), old.identifier(), new.identifier());
+
+        for r in self.aliases.values_mut().chain(self.alias_index.values_mut()) {
+            if r == old {
+                *r = new.clone();
+            }
+        }
+        self.unindex(old);
+        self.invalidate(old);
+        self.dependency_graph().remove(old);
+
+        self.index(&new);
+        self.metadata_index.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+        self.registered_resources.insert(new, entry);
+        true
+    }
+
+    /// Registers `resource` with the literal `contents`, without any file on disk. `path` is only used as the
+    /// resource's source path (e.g. for relativizing links), nothing is read from it.
+    /// Useful for tests, and for feeding generated content back into the pipeline
+    pub fn register_memory<C: Into<Vec<u8>>>(&mut self, path: PathBuf, resource: R, contents: C) {
+        info!("{}: Adding {:?} from memory", path.display(), resource.identifier());
+
+        let contents: Vec<u8> = contents.into();
+        self.insert_with_origin(resource, path, Origin::Memory(contents.into()), Metadata::new());
+    }
+
+    pub(crate) fn insert(&mut self, res: R, path: PathBuf, metadata: Metadata) {
+        self.insert_with_origin(res, path, Origin::File, metadata)
+    }
+
+    pub(crate) fn insert_with_origin(&mut self, res: R, path: PathBuf, origin: Origin, metadata: Metadata) {
+        let identifier = self.normalize_identifier(&res.identifier());
+        let same_identifier = self.identifiers.get(&identifier).into_iter().flatten().find(|r| **r != res);
+        if let Some((other, other_entry)) = same_identifier.and_then(|r| self.registered_resources.get_key_value(r)) {
+            warn!(
+                "{}: {:?} has the same identifier as {:?} @ {}",
+                path.display(), res, other, other_entry.path.display(),
+            );
+        }
+
+        let aliases = metadata
+            .get(ALIASES_KEY)
+            .and_then(Value::as_array)
+            .map(|aliases| aliases.iter().filter_map(Value::as_str).map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let file_info = self.stat(&path, &origin);
+
+        self.index(&res);
+        self.metadata_index.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+        self.registered_resources.insert(res.clone(), Entry { path, origin, metadata, file_info });
+
+        for alias in aliases {
+            self.add_alias(&res, &alias);
+        }
+    }
+
+    fn index(&mut self, resource: &R) {
+        let resources = self.identifiers.entry(self.normalization.apply(&resource.identifier())).or_default();
+        if !resources.contains(resource) {
+            resources.push(resource.clone());
+        }
+    }
+
+    fn unindex(&mut self, resource: &R) {
+        let identifier = self.normalize_identifier(&resource.identifier());
+        if let Some(resources) = self.identifiers.get_mut(&identifier) {
+            resources.retain(|r| r != resource);
+            if resources.is_empty() {
+                self.identifiers.remove(&identifier);
+            }
+        }
+    }
+
+    fn stat(&self, path: &Path, origin: &Origin) -> FileInfo {
+        let of_file = |file: &Path| match self.vfs.metadata(file) {
+            Ok(m) => FileInfo { modified: m.modified, size: Some(m.size) },
+            Err(e) => {
+                warn!("{}: Could not stat: {e}", file.display());
+                FileInfo::default()
+            }
+        };
+
+        match origin {
+            Origin::File => of_file(path),
+            // The size of the entry is filled in by `register_archive`
+            #[cfg(feature = "archives")]
+            Origin::Archive { archive, .. } => FileInfo { size: None, ..of_file(archive) },
+            Origin::Memory(contents) => FileInfo { modified: None, size: Some(contents.len() as u64) },
+        }
+    }
+
+    /// The modification time and size of `resource`, as they were when it was registered
+    pub fn file_info(&self, resource: &R) -> Option<FileInfo> {
+        self.registered_resources.get(resource).map(|entry| entry.file_info)
+    }
+
+    /// When `resource` was last modified, as of its registration. None for in-memory resources
+    pub fn modified(&self, resource: &R) -> Option<SystemTime> {
+        self.file_info(resource)?.modified
+    }
+
+    /// The size of `resource` in bytes, as of its registration
+    pub fn size(&self, resource: &R) -> Option<u64> {
+        self.file_info(resource)?.size
+    }
+
+    #[cfg(feature = "archives")]
+    pub(crate) fn set_size(&mut self, resource: &R, size: u64) {
+        if let Some(entry) = self.registered_resources.get_mut(resource) {
+            entry.file_info.size = Some(size);
+        }
+    }
+
+    /// Checks that no two registered resources share an identifier, and that no alias shadows another resource's
+    /// identifier, as `@`-links to them would be ambiguous. All such identifiers are reported in one error. Also checks that all source and output paths are
+    /// valid UTF-8, as they end up in links
+    pub fn validate(&self) -> Result<(), ConfigurafoxError> {
+        for (resource, entry) in &self.registered_resources {
+            for path in [&entry.path, &self.output_path(resource)] {
+                if path.to_str().is_none() {
+                    return Err(ConfigurafoxError::NonUtf8Path { path: path.clone() });
+                }
+            }
+        }
+
+        let mut by_identifier: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for (resource, entry) in &self.registered_resources {
+            by_identifier.entry(self.normalize_identifier(&resource.identifier())).or_default().push(entry.path.clone());
+        }
+        for (alias, resource) in &self.alias_index {
+            if let (Some(paths), Some(entry)) = (by_identifier.get_mut(alias), self.registered_resources.get(resource)) {
+                if !paths.contains(&entry.path) {
+                    paths.push(entry.path.clone());
+                }
+            }
+        }
+
+        let mut duplicates = by_identifier
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(identifier, mut paths)| {
+                paths.sort();
+                (identifier, paths)
+            })
+            .collect::<Vec<_>>();
+        if duplicates.is_empty() {
+            return Ok(());
+        }
+        duplicates.sort();
+
+        Err(ConfigurafoxError::DuplicateIdentifier { duplicates })
+    }
+
+    /// The resources whose contents come from `path` (relative to the project root). Usually one, but
+    /// several for an archive
+    pub fn resources_at_path(&self, path: &Path) -> Vec<&R> {
         self.registered_resources
-            .keys()
-            .find(|r| r.identifier() == identifier)
+            .iter()
+            .filter(|(_, entry)| match &entry.origin {
+                Origin::File => entry.path == path,
+                #[cfg(feature = "archives")]
+                Origin::Archive { archive, .. } => archive == path,
+                Origin::Memory(_) => false,
+            })
+            .map(|(resource, _)| resource)
+            .collect()
+    }
+
+    /// Finds a resource by its identifier, or failing that, by one of its aliases.
+    /// Both sides are compared after normalization
+    pub fn resource_by_identifier(&self, identifier: &str) -> Option<&R> {
+        let identifier = self.normalize_identifier(identifier);
+        self.identifiers
+            .get(&identifier)
+            .and_then(|resources| resources.first())
+            .or_else(|| self.alias_index.get(&identifier))
+    }
+
+    /// Sets how identifiers are normalized before being compared
+    pub fn set_identifier_normalization(&mut self, normalization: IdentifierNormalization) {
+        self.normalization = normalization;
+        self.identifiers.clear();
+        for resource in self.registered_resources.keys().cloned().collect::<Vec<_>>() {
+            self.index(&resource);
+        }
+        self.index_aliases();
+    }
+
+    pub fn normalize_identifier(&self, identifier: &str) -> String {
+        self.normalization.apply(identifier)
+    }
+
+    /// Makes `resource` also reachable as `alias`, e.g. to keep `@old-slug` links working after a rename.
+    /// Returns false if the resource isn't registered
+    pub fn add_alias(&mut self, resource: &R, alias: &str) -> bool {
+        if !self.registered_resources.contains_key(resource) {
+            return false;
+        }
+
+        self.aliases.insert(alias.to_string(), resource.clone());
+        self.index_alias(alias, resource);
+        true
+    }
 
+    fn index_alias(&mut self, alias: &str, resource: &R) {
+        if let Some(previous) = self.alias_index.insert(self.normalize_identifier(alias), resource.clone()) {
+            if previous != *resource {
+                warn!("Alias {alias:?} moved from {:?} to {:?}", previous.identifier(), resource.identifier());
+            }
+        }
+    }
+
+    /// Normalizes every alias again, in sorted order so that which of several colliding aliases wins is stable
+    fn index_aliases(&mut self) {
+        self.alias_index.clear();
+        let mut aliases = self.aliases.iter().map(|(alias, r)| (alias.clone(), r.clone())).collect::<Vec<_>>();
+        aliases.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (alias, resource) in aliases {
+            self.index_alias(&alias, &resource);
+        }
+    }
+
+    /// All aliases of `resource`, sorted
+    pub fn aliases_of(&self, resource: &R) -> Vec<&str> {
+        let mut aliases = self.aliases
+            .iter()
+            .filter(|(_, r)| *r == resource)
+            .map(|(alias, _)| &**alias)
+            .collect::<Vec<_>>();
+        aliases.sort();
+        aliases
+    }
+
+    /// The path `resource` was registered from, relative to the project root
+    pub fn path_of(&self, resource: &R) -> Option<&Path> {
+        self.registered_resources.get(resource).map(|entry| &*entry.path)
+    }
+
+    /// The resources whose `key` metadata is the string `value`, sorted by identifier. The first lookup of a key
+    /// indexes every resource by it, later ones don't scan them again until something is (un)registered or
+    /// metadata changes
+    pub fn resources_with(&self, key: &str, value: &str) -> Vec<&R> {
+        let index = self.metadata_index
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.to_string())
+            .or_insert_with(|| {
+                let mut index = MetadataIndex::new();
+                for (resource, entry) in &self.registered_resources {
+                    if let Some(value) = entry.metadata.get_str(key) {
+                        index.entry(value.to_string()).or_insert_with(Vec::new).push(resource.clone());
+                    }
+                }
+                Arc::new(index)
+            })
+            .clone();
+
+        let mut resources = index
+            .get(value)
+            .into_iter()
+            .flatten()
+            .filter_map(|resource| self.registered_resources.get_key_value(resource).map(|(resource, _)| resource))
+            .collect::<Vec<_>>();
+        resources.sort_by_key(|resource| resource.identifier());
+        resources
+    }
+
+    /// Iterates over all registered resources along with their paths, without cloning
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &R)> {
+        self.registered_resources
+            .iter()
+            .map(|(resource, entry)| (&*entry.path, resource))
     }
 
     pub fn all_registered_files(&self) -> HashMap<R, PathBuf> {
-        self.registered_resources.clone()
+        self.registered_resources
+            .iter()
+            .map(|(resource, entry)| (resource.clone(), entry.path.clone()))
+            .collect()
+    }
+
+    /// Where the contents of `resource` come from
+    pub fn origin(&self, resource: &R) -> Option<&Origin> {
+        self.registered_resources.get(resource).map(|entry| &entry.origin)
+    }
+
+    /// Reads the contents of a registered resource, wherever they are stored.
+    /// Contents are cached, so reading the same resource from several walkers only hits the disk once, up to
+    /// `CONTENT_CACHE_LIMIT` and except for resources larger than `MAX_CACHED_SIZE`. Use `with_file` for large
+    /// media
+    pub fn read(&self, resource: &R) -> std::io::Result<Arc<[u8]>> {
+        if let Some(cached) = self.cache().get(resource) {
+            trace!("{}: Read from cache", resource.identifier());
+            return Ok(cached.clone());
+        }
+
+        let Some(entry) = self.registered_resources.get(resource) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not registered", resource.identifier())));
+        };
+
+        let contents: Arc<[u8]> = match &entry.origin {
+            Origin::File => self.vfs.read(&entry.path)?.into(),
+            #[cfg(feature = "archives")]
+            Origin::Archive { archive, entry } => {
+                return self.archive_entries(archive)?.get(entry).cloned().ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, format!("{entry} not found in {}", archive.display()))
+                });
+            }
+            Origin::Memory(contents) => return Ok(contents.clone()),
+        };
+
+        self.cache().insert(resource.clone(), contents.clone());
+        Ok(contents)
+    }
+
+    /// Like `read`, but for text resources
+    pub fn read_to_string(&self, resource: &R) -> std::io::Result<String> {
+        String::from_utf8(self.read(resource)?.to_vec())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {e}", resource.identifier())))
+    }
+
+    /// Drops the cached contents of `resource`, so the next `read` sees changes on disk
+    pub fn invalidate(&self, resource: &R) {
+        self.cache().remove(resource);
+        #[cfg(feature = "archives")]
+        if let Some(Origin::Archive { archive, .. }) = self.origin(resource) {
+            self.archives.lock().unwrap_or_else(|e| e.into_inner()).remove(archive);
+        }
+    }
+
+    /// Drops all cached contents
+    pub fn clear_cache(&self) {
+        self.cache().clear();
+        #[cfg(feature = "archives")]
+        self.archives.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// The contents of the entries of the archive at `archive`, reading it if it hasn't been yet
+    #[cfg(feature = "archives")]
+    pub(crate) fn archive_entries(&self, archive: &Path) -> std::io::Result<Arc<ArchiveEntries>> {
+        if let Some(entries) = self.archives.lock().unwrap_or_else(|e| e.into_inner()).get(archive) {
+            return Ok(entries.clone());
+        }
+        debug!("{}: Reading all entries", archive.display());
+        let entries = crate::archive::read_entries(archive, &self.vfs.read(archive)?)?;
+        let entries = Arc::new(entries.into_iter().map(|(name, contents)| (name, contents.into())).collect::<HashMap<_, _>>());
+        self.archives.lock().unwrap_or_else(|e| e.into_inner()).insert(archive.to_owned(), entries.clone());
+        Ok(entries)
+    }
+
+    fn cache(&self) -> MutexGuard<'_, ContentCache<R>> {
+        self.content_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Where the source of `resource` is on the real filesystem, if it is read from a file there
+    pub fn physical_path(&self, resource: &R) -> Option<PathBuf> {
+        let entry = self.registered_resources.get(resource)?;
+        match entry.origin {
+            Origin::File => self.vfs.physical_path(&entry.path),
+            _ => None,
+        }
+    }
+
+    /// Calls `f` with the path of the source of `resource` on disk, or of a temporary copy if it isn't read from a
+    /// file as is. For tools that read files themselves, like ffmpeg, and for large media that shouldn't be read
+    /// into memory, or into the content cache
+    pub fn with_file<T>(&self, resource: &R, f: impl FnOnce(&Path) -> T) -> Result<T, ConfigurafoxError> {
+        match self.physical_path(resource) {
+            Some(path) => Ok(f(&path)),
+            None => {
+                // Some tools, like ffmpeg, recognize formats by their extension
+                let extension = self.output_path(resource).extension().unwrap_or_default().to_string_lossy().into_owned();
+                with_temp_file(&self.read(resource)?, &extension, f)
+            }
+        }
+    }
+
+    /// Where the output of `resource` is written, relative to the output root. This is `Resource::output_path`,
+    /// unless overridden by the `output_path` metadata (e.g. from a sidecar file)
+    pub fn output_path(&self, resource: &R) -> PathBuf {
+        match self.metadata(resource).get_str(OUTPUT_PATH_KEY) {
+            Some(path) => PathBuf::from(path),
+            None => resource.output_path(),
+        }
+    }
+
+    /// The metadata attached to `resource`. Empty if none has been set or the resource isn't registered
+    pub fn metadata(&self, resource: &R) -> &Metadata {
+        self.registered_resources
+            .get(resource)
+            .map(|entry| &entry.metadata)
+            .unwrap_or(&NO_METADATA)
+    }
+
+    /// Mutable access to the metadata of a registered resource
+    pub fn metadata_mut(&mut self, resource: &R) -> Option<&mut Metadata> {
+        self.metadata_index.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+        self.registered_resources
+            .get_mut(resource)
+            .map(|entry| &mut entry.metadata)
+    }
+
+    /// Records that `from` references `to`. Called by walkers while processing `from`
+    pub fn record_dependency(&self, from: &R, to: &R, kind: DependencyKind) {
+        self.dependency_graph().add(from, to, kind);
+    }
+
+    /// The dependencies recorded so far
+    pub fn dependency_graph(&self) -> MutexGuard<'_, DependencyGraph<R>> {
+        // The graph is always left in a consistent state, so a panic elsewhere doesn't matter
+        self.dependencies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Requests an output at `path`, relative to the output root, made from the file of `source` by `make` after
+    /// every resource of the run has been processed, see `with_file`. Called by walkers that need files derived from others, like
+    /// thumbnails. Requests for a path that was already requested this run are ignored, so pages asking for the same
+    /// derivative share it. Returns whether the request was new
+    pub fn request_derivative<F>(&self, path: PathBuf, source: &R, make: F) -> bool
+    where
+        F: Fn(&Path) -> Result<Vec<u8>, ConfigurafoxError> + Send + Sync + 'static,
+    {
+        let mut derivatives = self.derivatives.lock().unwrap_or_else(|e| e.into_inner());
+        if derivatives.contains_key(&path) {
+            return false;
+        }
+        debug!("{}: Deriving from {}", path.display(), source.identifier());
+        derivatives.insert(path, Derivative { source: source.clone(), make: Box::new(make) });
+        true
+    }
+
+    /// Takes the derivatives requested so far, by output path. Runs write them when they finish
+    pub fn take_derivatives(&self) -> BTreeMap<PathBuf, Derivative<R>> {
+        std::mem::take(&mut *self.derivatives.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// The warnings emitted so far, e.g. by walkers through `Context::warn`
+    pub fn diagnostics(&self) -> MutexGuard<'_, Diagnostics> {
+        self.diagnostics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The resources referencing `resource`, sorted by identifier
+    pub fn backlinks(&self, resource: &R) -> Vec<R> {
+        let mut backlinks = self.dependency_graph()
+            .dependents_of(resource)
+            .map(|(from, _)| from.clone())
+            .collect::<Vec<_>>();
+        backlinks.sort_by_key(|r| r.identifier());
+        backlinks.dedup();
+        backlinks
+    }
+
+    /// Starts a query over the registered resources, see `Query`
+    pub fn query(&self) -> Query<'_, R> {
+        Query::new(self)
+    }
+
+    /// Registers a named collection, replacing any previous one with the same name
+    pub fn define_collection(&mut self, collection: Collection<R>) {
+        debug!("Defining collection {:?}", collection.name);
+        self.collections.insert(collection.name.clone(), collection);
+    }
+
+    /// The members of the collection `name`, in order. None if no such collection is defined
+    pub fn collection(&self, name: &str) -> Option<Vec<(&Path, &R)>> {
+        Some(self.collections.get(name)?.members(self))
+    }
+
+    /// The names of the collections `resource` is a member of
+    pub fn collections_of(&self, resource: &R) -> Vec<&str> {
+        let mut names = self.collections
+            .values()
+            .filter(|c| c.members(self).iter().any(|(_, r)| *r == resource))
+            .map(|c| &*c.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// The tags of `resource`, as stored in its `tags` metadata
+    pub fn tags(&self, resource: &R) -> Vec<&str> {
+        tags_of(self.metadata(resource)).collect()
+    }
+
+    /// Adds `tag` to a registered resource. Returns false if the resource isn't registered
+    pub fn add_tag(&mut self, resource: &R, tag: &str) -> bool {
+        let Some(metadata) = self.metadata_mut(resource) else {
+            return false;
+        };
+
+        if tags_of(metadata).any(|t| t == tag) {
+            return true;
+        }
+
+        match metadata.0.entry(TAGS_KEY.to_string()).or_insert_with(|| Value::Array(Vec::new())) {
+            Value::Array(tags) => tags.push(Value::from(tag)),
+            other => *other = Value::Array(vec![other.take(), Value::from(tag)]),
+        }
+
+        true
+    }
+
+    /// Registers all files like `register_all_files_in_directory`, but `parse_resource` also returns metadata for the resource
+    pub fn register_all_files_in_directory_with_metadata<F: Fn(&Path) -> Option<(R, Metadata)>>(
+        &mut self,
+        dir_path: PathBuf,
+        parse_resource: F,
+        recurse: bool,
+    ) -> std::io::Result<()> {
+        debug!("Adding files in {}", dir_path.display());
+        let options = ScanOptions { recurse, ..ScanOptions::default() };
+        self.register_all_files_in_directory_ref(dir_path, &|_, path| Ok(parse_resource(path)), &options)
     }
 }
 
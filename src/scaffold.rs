@@ -0,0 +1,336 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{ConfigurafoxError, slugify};
+use crate::calendar::civil_from_days;
+use crate::config::{ProjectConfig, DEFAULT_CONFIG_FILE};
+use crate::entities::escape_text;
+use crate::vfs::create_temp_dir;
+
+/// Where a project keeps its own templates, relative to the project root. Not part of the content directory,
+/// so they aren't built, and `Template::instantiate` copies them without filling in placeholders
+pub const TEMPLATES_DIR: &str = "templates";
+
+/// Where `new_post` looks for a project's own post template, relative to the project root
+pub const POST_TEMPLATE_FILE: &str = "templates/post.html";
+
+/// Where a project keeps the layouts of its pages, relative to the project root. Like `TEMPLATES_DIR`, not part
+/// of the content directory, and copied without filling in placeholders
+pub const LAYOUTS_DIR: &str = "layouts";
+
+/// Where `new_page` looks for the layout of new pages, relative to the project root
+pub const PAGE_LAYOUT_FILE: &str = "layouts/page.html";
+
+const STARTER_CONFIG: &str = r#"site_url = "https://example.com"
+content_dir = "content"
+output_dir = "public"
+
+[variables]
+title = {{title}}
+"#;
+
+const STARTER_INDEX: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>{{title_text}}</title>
+    <link rel="stylesheet" href="@style.css">
+</head>
+<body>
+    <h1><$title /></h1>
+    <p>Welcome! Edit <code>content/index.html</code> to get started, or read the <a href="@posts/hello">first post</a>.</p>
+</body>
+</html>
+"#;
+
+const STARTER_POST: &str = r#"+++
+title = "Hello"
+date = {{date}}
+tags = []
++++
+<!DOCTYPE html>
+<html>
+<head>
+    <title>Hello</title>
+    <link rel="stylesheet" href="@style.css">
+</head>
+<body>
+    <h1>Hello</h1>
+    <p>This is the first post. <a href="@index">Back home</a></p>
+</body>
+</html>
+"#;
+
+const STARTER_STYLE: &str = "body {\n    max-width: 40em;\n    margin: 2em auto;\n    font-family: sans-serif;\n}\n";
+
+/// The post template used when a project has no `POST_TEMPLATE_FILE`
+const DEFAULT_POST_TEMPLATE: &str = r#"+++
+title = {{title}}
+date = {{date}}
+tags = []
++++
+<!DOCTYPE html>
+<html>
+<head>
+    <title>{{title_text}}</title>
+</head>
+<body>
+    <h1>{{title_text}}</h1>
+</body>
+</html>
+"#;
+
+/// The page layout used when a project has no `PAGE_LAYOUT_FILE`, and the one the starter project comes with
+const DEFAULT_PAGE_LAYOUT: &str = r#"+++
+title = {{title}}
++++
+<!DOCTYPE html>
+<html>
+<head>
+    <title>{{title_text}}</title>
+    <link rel="stylesheet" href="@style.css">
+</head>
+<body>
+    <h1>{{title_text}}</h1>
+    <p><a href="@index">Back home</a></p>
+</body>
+</html>
+"#;
+
+/// A set of files to create, with `{{name}}` placeholders filled in on `Template::instantiate`
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    /// Paths are relative to the directory the template is instantiated in. Placeholders are only filled in
+    /// in files that are UTF-8, so images and the like are copied as they are
+    pub files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Template {
+    /// A minimal site: a config, a home page, a post linking back to it, a stylesheet, a page layout and a post
+    /// template
+    pub fn starter() -> Template {
+        Template {
+            files: vec![
+                (PathBuf::from(DEFAULT_CONFIG_FILE), STARTER_CONFIG.into()),
+                (PathBuf::from("content/index.html"), STARTER_INDEX.into()),
+                (PathBuf::from("content/posts/hello.html"), STARTER_POST.into()),
+                (PathBuf::from("content/style.css"), STARTER_STYLE.into()),
+                (PathBuf::from(PAGE_LAYOUT_FILE), DEFAULT_PAGE_LAYOUT.into()),
+                (PathBuf::from(POST_TEMPLATE_FILE), DEFAULT_POST_TEMPLATE.into()),
+            ],
+        }
+    }
+
+    /// Every file below `dir`, e.g. a user's own project template, except for a `.git` directory. Symlinks are
+    /// skipped, so a cloned template can't copy files from elsewhere on the machine into the project
+    pub fn from_dir(dir: &Path) -> Result<Template, ConfigurafoxError> {
+        fn visit(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> std::io::Result<()> {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let file_type = entry.file_type()?;
+                if file_type.is_symlink() {
+                    warn!("{}: Skipping symlink in template", path.display());
+                } else if file_type.is_dir() {
+                    if path.file_name() != Some(".git".as_ref()) {
+                        visit(root, &path, files)?;
+                    }
+                } else {
+                    let relative = path.strip_prefix(root).expect("read_dir stays below root").to_owned();
+                    files.push((relative, std::fs::read(&path)?));
+                }
+            }
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        visit(dir, dir, &mut files)?;
+        files.sort();
+        Ok(Template { files })
+    }
+
+    /// Every file in an archive, like the ones code hosts offer for download. If all files are in one top-level
+    /// directory, as in those, it is left out. `path` is only used for its extension
+    #[cfg(feature = "archives")]
+    pub fn from_archive(path: &Path, data: &[u8]) -> Result<Template, ConfigurafoxError> {
+        let entries = crate::archive::read_entries(path, data)?;
+        let names = entries.iter().map(|(name, _)| name).collect::<Vec<_>>();
+
+        let top_level = |name: &str| name.split_once('/').map(|(dir, _)| dir.to_string());
+        let common = names.first().and_then(|name| top_level(name)).filter(|dir| names.iter().all(|name| top_level(name).as_ref() == Some(dir)));
+
+        let mut files = Vec::new();
+        for (name, contents) in &entries {
+            let relative = match &common {
+                Some(dir) => &name[dir.len() + 1..],
+                None => name,
+            };
+            let relative = Path::new(relative);
+            if relative.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+                return Err(ConfigurafoxError::Other(format!("{}: entry {name:?} leaves the template", path.display())));
+            }
+            if !relative.starts_with(".git") {
+                files.push((relative.to_owned(), contents.clone()));
+            }
+        }
+        files.sort();
+        Ok(Template { files })
+    }
+
+    /// The files of a shallow clone of the git repository at `url`, made with the `git` command
+    pub fn from_git(url: &str) -> Result<Template, ConfigurafoxError> {
+        let clone_dir = create_temp_dir("configurafox-template")?;
+
+        info!("Cloning {url}");
+        let status = Command::new("git")
+            .args(["clone", "--quiet", "--depth", "1", "--", url])
+            .arg(&clone_dir)
+            .status()
+            .map_err(|e| ConfigurafoxError::Remote { url: url.to_string(), msg: format!("could not run git: {e}") });
+        let template = match status {
+            Ok(status) if status.success() => Template::from_dir(&clone_dir),
+            Ok(status) => Err(ConfigurafoxError::Remote { url: url.to_string(), msg: format!("git clone failed with {status}") }),
+            Err(e) => Err(e),
+        };
+
+        std::fs::remove_dir_all(&clone_dir)?;
+        template
+    }
+
+    /// The template at `source`: a local directory or archive, the URL of an archive (with the `remote` and
+    /// `archives` features), or anything else `git clone` takes, like `https://host/user/repo.git`
+    pub fn from_source(source: &str) -> Result<Template, ConfigurafoxError> {
+        let path = Path::new(source);
+        if path.is_dir() {
+            return Template::from_dir(path);
+        }
+
+        #[cfg(feature = "archives")]
+        if crate::archive::is_archive(path) {
+            if path.is_file() {
+                return Template::from_archive(path, &std::fs::read(path)?);
+            }
+            #[cfg(feature = "remote")]
+            if source.starts_with("https://") || source.starts_with("http://") {
+                let url_path = Path::new(source.split(['?', '#']).next().unwrap_or(source));
+                return Template::from_archive(url_path, &crate::remote::fetch(source)?);
+            }
+        }
+
+        if path.exists() {
+            return Err(ConfigurafoxError::Other(format!("{source}: templates are directories or .zip, .tar, .tar.gz or .tgz archives")));
+        }
+        Template::from_git(source)
+    }
+
+    /// Writes the files below `dest`, replacing `{{name}}` with `values[name]` outside of `TEMPLATES_DIR` and `LAYOUTS_DIR`.
+    /// Unknown placeholders are left alone. Fails without writing anything if any of the files already exists. Returns the created paths
+    pub fn instantiate(&self, dest: &Path, values: &HashMap<String, String>) -> Result<Vec<PathBuf>, ConfigurafoxError> {
+        let paths = self.files.iter().map(|(path, _)| dest.join(path)).collect::<Vec<_>>();
+        if let Some(existing) = paths.iter().find(|path| path.exists()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists", existing.display())).into());
+        }
+
+        for ((relative, contents), path) in self.files.iter().zip(&paths) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            info!("Creating {}", path.display());
+            match std::str::from_utf8(contents) {
+                Ok(text) if !relative.starts_with(TEMPLATES_DIR) && !relative.starts_with(LAYOUTS_DIR) => std::fs::write(path, fill_placeholders(text, values))?,
+                _ => std::fs::write(path, contents)?,
+            }
+        }
+
+        Ok(paths)
+    }
+}
+
+fn fill_placeholders(template: &str, values: &HashMap<String, String>) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}").and_then(|end| Some((values.get(after[..end].trim())?, end))) {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The placeholders `new_project`, `new_post` and `new_page` fill in: `title` (quoted as a TOML string, for configs and
+/// front matter), `title_text` (escaped for HTML), `slug` and `date` (today, `YYYY-MM-DD`). `new_project` also
+/// fills in `project`, the name of the project's directory
+fn placeholders(title: &str) -> HashMap<String, String> {
+    HashMap::from([
+        ("title".to_string(), toml::Value::String(title.to_string()).to_string()),
+        ("title_text".to_string(), escape_text(title).into_owned()),
+        ("slug".to_string(), slugify(title)),
+        ("date".to_string(), today()),
+    ])
+}
+
+/// Creates a new project in `dir` from `template`, `Template::starter` if none is given
+pub fn new_project(dir: &Path, title: &str, template: Option<&Template>) -> Result<Vec<PathBuf>, ConfigurafoxError> {
+    let mut values = placeholders(title);
+    let project = dir.canonicalize().unwrap_or_else(|_| dir.to_owned()).file_name().map(|name| name.to_string_lossy().into_owned());
+    values.insert("project".to_string(), project.unwrap_or_else(|| slugify(title)));
+    template.cloned().unwrap_or_else(Template::starter).instantiate(dir, &values)
+}
+
+/// Creates `<content_dir>/<dir>/<date>-<slug>.html` for a post titled `title`, from the project's
+/// `POST_TEMPLATE_FILE` if it has one. Returns the path of the new file
+pub fn new_post(config: &ProjectConfig, dir: &Path, title: &str) -> Result<PathBuf, ConfigurafoxError> {
+    let values = placeholders(title);
+    let file = format!("{}-{}.html", values["date"], values["slug"]);
+    create_from(config, POST_TEMPLATE_FILE, DEFAULT_POST_TEMPLATE, dir, &file, &values)
+}
+
+/// Creates `<content_dir>/<dir>/<slug>.html` for a page titled `title`, from the project's `PAGE_LAYOUT_FILE`
+/// if it has one. Returns the path of the new file
+pub fn new_page(config: &ProjectConfig, dir: &Path, title: &str) -> Result<PathBuf, ConfigurafoxError> {
+    let values = placeholders(title);
+    let file = format!("{}.html", values["slug"]);
+    create_from(config, PAGE_LAYOUT_FILE, DEFAULT_PAGE_LAYOUT, dir, &file, &values)
+}
+
+/// Creates `<content_dir>/<dir>/<file>` from the project's file at `template`, or `default` if it has none
+fn create_from(
+    config: &ProjectConfig,
+    template: &str,
+    default: &str,
+    dir: &Path,
+    file: &str,
+    values: &HashMap<String, String>,
+) -> Result<PathBuf, ConfigurafoxError> {
+    let template_path = config.root.join(template);
+    let template = if template_path.is_file() {
+        std::fs::read_to_string(&template_path)?
+    } else {
+        default.to_string()
+    };
+
+    let dest = config.content_path().join(dir);
+    let created = Template { files: vec![(PathBuf::from(file), template.into_bytes())] }.instantiate(&dest, values)?;
+    Ok(created.into_iter().next().expect("one file was created"))
+}
+
+/// The current UTC date as `YYYY-MM-DD`
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0) as i64;
+    let (year, month, day) = civil_from_days(days);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,305 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeSet;
+
+use html_editor::{Doctype, Element, Node};
+
+use crate::entities::{RAW_TEXT_ELEMENTS, escape_attr, escape_text};
+use crate::treewalker::get_attr;
+
+/// Elements without contents or an end tag
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Attributes whose presence is their value. Written without a value when it's empty or their name
+pub const BOOLEAN_ATTRIBUTES: &[&str] = &[
+    "allowfullscreen", "async", "autofocus", "autoplay", "checked", "controls", "default", "defer", "disabled",
+    "formnovalidate", "hidden", "inert", "ismap", "itemscope", "loop", "multiple", "muted", "nomodule", "novalidate",
+    "open", "playsinline", "readonly", "required", "reversed", "selected",
+];
+
+/// Elements laid out as blocks, which `to_pretty_html` puts on lines of their own
+pub const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "base", "blockquote", "body", "dd", "details", "dialog", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header",
+    "hgroup", "hr", "html", "li", "link", "main", "meta", "nav", "noscript", "ol", "p", "pre", "script", "search",
+    "section", "style", "summary", "table", "tbody", "td", "template", "tfoot", "th", "thead", "title", "tr", "ul",
+];
+
+/// Elements whose whitespace is kept as it is by default, see `Whitespace`
+pub const PREFORMATTED_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+/// Overrides `Whitespace::preformatted` for an element and what it contains: `pre` keeps its whitespace, `normal`
+/// lets it be changed
+pub const WHITESPACE_ATTR: &str = "data-whitespace";
+
+/// Which elements' whitespace is significant, so `trim` and `to_pretty_html` keep it as it is. E.g. a custom
+/// `<ascii-art>` component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Whitespace {
+    /// Tag names
+    pub preformatted: BTreeSet<String>,
+}
+
+impl Default for Whitespace {
+    /// `PREFORMATTED_ELEMENTS`
+    fn default() -> Self {
+        Whitespace { preformatted: PREFORMATTED_ELEMENTS.iter().map(|tag| tag.to_string()).collect() }
+    }
+}
+
+impl Whitespace {
+    /// These as well as the ones so far
+    pub fn with_preformatted<I: IntoIterator<Item = S>, S: Into<String>>(mut self, tags: I) -> Whitespace {
+        self.preformatted.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Whether the whitespace in `element` is kept, by its `WHITESPACE_ATTR` or else its name
+    pub fn is_preformatted(&self, element: &Element) -> bool {
+        match get_attr(&element.attrs, WHITESPACE_ATTR) {
+            Some("pre") => true,
+            Some("normal") => false,
+            _ => self.preformatted.contains(&element.name),
+        }
+    }
+}
+
+/// Removes whitespace-only text between elements, except in preformatted elements
+pub fn trim(dom: &mut Vec<Node>, whitespace: &Whitespace) {
+    dom.retain(|node| !is_blank(node));
+    for node in dom {
+        if let Node::Element(element) = node {
+            if !whitespace.is_preformatted(element) {
+                trim(&mut element.children, whitespace);
+            }
+        }
+    }
+}
+
+/// One level of indentation in `to_pretty_html`
+pub const INDENT: &str = "  ";
+
+/// How text is written
+#[derive(Debug, Clone, Copy)]
+enum TextMode<'w> {
+    Escaped,
+    /// In `RAW_TEXT_ELEMENTS`
+    Raw,
+    /// Escaped, with runs of whitespace written as one space outside preformatted elements
+    Collapsed(&'w Whitespace),
+}
+
+/// `dom` as HTML the way a browser expects it: `<!DOCTYPE html>` as the standard spells it, void elements
+/// without an end tag, boolean attributes without a value, and text and attribute values escaped, see
+/// `entities`. Used for all output of `HTMLProcessor`, where `html_editor`'s own serialization differs from the
+/// input in these cases
+pub fn to_html(dom: &[Node]) -> String {
+    let mut out = String::new();
+    for node in dom {
+        write_node(node, TextMode::Escaped, &mut out);
+    }
+    out
+}
+
+/// Like `to_html`, indented for reading and diffing: elements containing only blocks, see `BLOCK_ELEMENTS`, have
+/// each block on its own line, one `INDENT` deeper. Other contents stay on one line, with whitespace collapsed
+/// outside preformatted elements. Only whitespace the browser ignores is changed, so pages look the same
+pub fn to_pretty_html(dom: &[Node], whitespace: &Whitespace) -> String {
+    let mut out = String::new();
+    if only_blocks(dom) {
+        write_lines(dom, 0, whitespace, &mut out);
+    } else {
+        let mut line = String::new();
+        for node in dom {
+            write_node(node, TextMode::Collapsed(whitespace), &mut line);
+        }
+        out.push_str(line.trim());
+        out.push('\n');
+    }
+    out
+}
+
+fn is_blank(node: &Node) -> bool {
+    matches!(node, Node::Text(text) if text.trim().is_empty())
+}
+
+/// Whether `nodes` can go on lines of their own without changing how they're shown
+fn only_blocks(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| !is_blank(node))
+        && nodes.iter().all(|node| match node {
+            Node::Element(Element { name, .. }) => BLOCK_ELEMENTS.contains(&name.as_str()),
+            Node::Text(_) => is_blank(node),
+            _ => true,
+        })
+}
+
+fn write_lines(nodes: &[Node], depth: usize, whitespace: &Whitespace, out: &mut String) {
+    for node in nodes.iter().filter(|node| !is_blank(node)) {
+        out.push_str(&INDENT.repeat(depth));
+        match node {
+            Node::Element(element @ Element { name, children, .. })
+                if !whitespace.is_preformatted(element) && !VOID_ELEMENTS.contains(&name.as_str()) =>
+            {
+                write_start_tag(element, out);
+                if only_blocks(children) {
+                    out.push('\n');
+                    write_lines(children, depth + 1, whitespace, out);
+                    out.push_str(&INDENT.repeat(depth));
+                } else {
+                    // Whitespace at the edges of a block isn't shown
+                    let mut line = String::new();
+                    for child in children {
+                        write_node(child, TextMode::Collapsed(whitespace), &mut line);
+                    }
+                    out.push_str(line.trim());
+                }
+                write_end_tag(name, out);
+            }
+            other => write_node(other, TextMode::Escaped, out),
+        }
+        out.push('\n');
+    }
+}
+
+fn write_node(node: &Node, mode: TextMode, out: &mut String) {
+    match node {
+        Node::Element(element) => write_element(element, mode, out),
+        Node::Text(text) => match mode {
+            TextMode::Escaped => out.push_str(&escape_text(text)),
+            TextMode::Raw => out.push_str(text),
+            TextMode::Collapsed(_) => {
+                let text = escape_text(text);
+                let mut words = text.split_ascii_whitespace();
+                if text.starts_with(|c: char| c.is_ascii_whitespace()) {
+                    out.push(' ');
+                }
+                if let Some(first) = words.next() {
+                    out.push_str(first);
+                    for word in words {
+                        out.push(' ');
+                        out.push_str(word);
+                    }
+                    if text.ends_with(|c: char| c.is_ascii_whitespace()) {
+                        out.push(' ');
+                    }
+                }
+            }
+        },
+        Node::Comment(comment) => {
+            out.push_str("<!--");
+            out.push_str(comment);
+            out.push_str("-->");
+        }
+        Node::Doctype(Doctype::Html) => out.push_str("<!DOCTYPE html>"),
+        other => out.push_str(&html_editor::operation::Htmlifiable::html(other)),
+    }
+}
+
+fn write_start_tag(Element { name, attrs, .. }: &Element, out: &mut String) {
+    out.push('<');
+    out.push_str(name);
+    for (key, value) in attrs {
+        out.push(' ');
+        out.push_str(key);
+        if BOOLEAN_ATTRIBUTES.contains(&key.as_str()) && (value.is_empty() || value.eq_ignore_ascii_case(key)) {
+            continue;
+        }
+        out.push_str("=\"");
+        out.push_str(&escape_attr(value));
+        out.push('"');
+    }
+    out.push('>');
+}
+
+fn write_end_tag(name: &str, out: &mut String) {
+    out.push_str("</");
+    out.push_str(name);
+    out.push('>');
+}
+
+/// `mode` is how the text around the element is written
+fn write_element(element @ Element { name, children, .. }: &Element, mode: TextMode, out: &mut String) {
+    write_start_tag(element, out);
+
+    if VOID_ELEMENTS.contains(&name.as_str()) {
+        // Can't contain anything, so anything a walker put inside follows it, as a browser would read it
+        for child in children {
+            write_node(child, mode, out);
+        }
+        return;
+    }
+
+    let mode = match mode {
+        _ if RAW_TEXT_ELEMENTS.contains(&name.as_str()) => TextMode::Raw,
+        TextMode::Collapsed(whitespace) if whitespace.is_preformatted(element) => TextMode::Escaped,
+        mode => mode,
+    };
+    for child in children {
+        write_node(child, mode, out);
+    }
+    write_end_tag(name, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use html_editor::{Element, Node};
+
+    use super::to_html;
+    use crate::entities::{decode, escape_attr, escape_text, parse};
+    use crate::testing::run_walker;
+    use crate::treewalker::walker;
+
+    fn round_trip(html: &str) -> String {
+        to_html(&parse(html).expect("valid HTML"))
+    }
+
+    #[test]
+    fn doctype() {
+        let html = "<!DOCTYPE html><html><head><title>T</title></head><body></body></html>";
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn void_elements() {
+        let html = "<head><meta charset=\"utf-8\"></head><p>a<br>b</p><img src=\"a.png\" alt=\"\">";
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn boolean_attributes() {
+        assert_eq!(round_trip("<details open><summary>S</summary></details>"), "<details open><summary>S</summary></details>");
+        assert_eq!(round_trip("<input type=\"checkbox\" checked=\"checked\" disabled=\"\">"), "<input type=\"checkbox\" checked disabled>");
+        // Only empty values and the attribute's name mean the attribute is just there
+        assert_eq!(round_trip("<video muted=\"no\"></video>"), "<video muted=\"no\"></video>");
+    }
+
+    #[test]
+    fn children_of_void_elements_follow_them() {
+        let image = walker("figure-image", |_, children, _| Ok(vec![Node::Element(Element { name: "img".to_string(), attrs: vec![("src".to_string(), "a.png".to_string())], children })]));
+        assert_eq!(run_walker(image, "<figure-image>Caption</figure-image>").unwrap(), "<img src=\"a.png\">Caption");
+    }
+
+    #[test]
+    fn escaped_text_round_trips() {
+        let html = "<p title=\"&quot;Fish&quot; &amp; chips\">1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;!</p><script>if (1 < 2 && x) {}</script>";
+        assert_eq!(round_trip(html), html);
+        assert_eq!(round_trip("<p>caf&eacute; &#233; &#xE9;</p>"), "<p>café é é</p>");
+    }
+
+    #[test]
+    fn decode_references() {
+        assert_eq!(decode("&amp; &lt;b&gt; &#233;&#xe9;&#XE9;"), "& <b> ééé");
+        assert_eq!(decode("fish & chips, &nosuch; &amp"), "fish & chips, &nosuch; &");
+        assert_eq!(decode("&#0; &#xD800; &#x110000;"), "\u{FFFD} \u{FFFD} \u{FFFD}");
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_text("a < b && \"c\"\u{A0}"), "a &lt; b &amp;&amp; \"c\"&nbsp;");
+        assert_eq!(escape_attr("<\"a\" & 'b'>"), "<&quot;a&quot; &amp; 'b'>");
+        assert!(matches!(escape_text("plain"), std::borrow::Cow::Borrowed(_)));
+    }
+}
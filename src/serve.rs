@@ -0,0 +1,162 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::ConfigurafoxError;
+use crate::output::{mime_type_for, OutputBackend};
+
+/// Where pages subscribe to reload events
+pub const RELOAD_PATH: &str = "/__configurafox/reload";
+
+/// Injected into every served HTML page
+const RELOAD_SCRIPT: &str = "<script>new EventSource(\"/__configurafox/reload\").onmessage = () => location.reload();</script>";
+
+#[derive(Clone, Default)]
+struct Shared {
+    files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+    /// Open event streams of connected pages
+    clients: Arc<Mutex<Vec<Box<dyn Write + Send>>>>,
+}
+
+/// An HTTP server for previewing a site while editing it. It serves whatever is written to its `backend` from
+/// memory, and tells open pages to reload after every build, over server-sent events
+pub struct DevServer {
+    shared: Shared,
+    addr: SocketAddr,
+}
+
+impl DevServer {
+    /// Starts serving on `addr`, e.g. `127.0.0.1:8000`, on a background thread
+    pub fn start(addr: &str) -> Result<DevServer, ConfigurafoxError> {
+        let server = Server::http(addr).map_err(|e| ConfigurafoxError::IO(std::io::Error::other(e.to_string())))?;
+        let addr = server.server_addr().to_ip().ok_or_else(|| ConfigurafoxError::Other(format!("{addr} is not an IP address")))?;
+
+        let shared = Shared::default();
+        let handler = shared.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handler.handle(request);
+            }
+        });
+
+        info!("Serving on http://{addr}");
+
+        Ok(DevServer { shared, addr })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A backend writing to the served files. Finishing a build through it reloads open pages
+    pub fn backend(&self) -> ServeBackend {
+        ServeBackend { shared: self.shared.clone() }
+    }
+
+    /// Tells all open pages to reload
+    pub fn reload(&self) {
+        self.shared.reload();
+    }
+}
+
+impl Shared {
+    fn reload(&self) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        // Pages that were closed or navigated away fail to write, and are dropped
+        clients.retain_mut(|client| client.write_all(b"data: reload\n\n").and_then(|_| client.flush()).is_ok());
+        debug!("Reloading {} pages", clients.len());
+    }
+
+    /// The file served for `url`: `/a/` serves `a/index.html`, and `/a` serves `a`, `a.html` or `a/index.html`
+    fn lookup(&self, url: &str) -> Option<(PathBuf, Vec<u8>)> {
+        let path = url.split(['?', '#']).next().unwrap_or("").trim_start_matches('/');
+        let files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+
+        let candidates = if path.is_empty() || path.ends_with('/') {
+            vec![format!("{path}index.html")]
+        } else {
+            vec![path.to_string(), format!("{path}.html"), format!("{path}/index.html")]
+        };
+        candidates.into_iter().map(PathBuf::from).find_map(|candidate| {
+            let contents = files.get(&candidate)?.clone();
+            Some((candidate, contents))
+        })
+    }
+
+    fn handle(&self, request: Request) {
+        trace!("{} {}", request.method(), request.url());
+
+        if request.url() == RELOAD_PATH {
+            let mut stream = request.into_writer();
+            let opened = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")
+                .and_then(|_| stream.flush());
+            if opened.is_ok() {
+                self.clients.lock().unwrap_or_else(|e| e.into_inner()).push(stream);
+            }
+            return;
+        }
+
+        let response = match self.lookup(request.url()) {
+            Some((path, mut contents)) => {
+                let mime = mime_type_for(&path);
+                if mime.starts_with("text/html") {
+                    contents = inject_reload_script(contents);
+                }
+                let content_type = Header::from_bytes("Content-Type", mime).expect("valid header");
+                Response::from_data(contents).with_header(content_type)
+            }
+            None => Response::from_string(format!("{} not found", request.url())).with_status_code(404),
+        };
+        if let Err(e) = request.respond(response) {
+            debug!("Could not respond: {e}");
+        }
+    }
+}
+
+/// Puts `RELOAD_SCRIPT` before `</body>`, or at the end of pages without one
+fn inject_reload_script(html: Vec<u8>) -> Vec<u8> {
+    let mut html = match String::from_utf8(html) {
+        Ok(html) => html,
+        Err(e) => return e.into_bytes(),
+    };
+    match html.rfind("</body>") {
+        Some(end) => html.insert_str(end, RELOAD_SCRIPT),
+        None => html.push_str(RELOAD_SCRIPT),
+    }
+    html.into_bytes()
+}
+
+/// Writes outputs to a `DevServer`, see `DevServer::backend`
+pub struct ServeBackend {
+    shared: Shared,
+}
+
+impl OutputBackend for ServeBackend {
+    fn describe(&self) -> String {
+        "ServeBackend".to_string()
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), ConfigurafoxError> {
+        debug!("Serving {} bytes as {}", contents.len(), path.display());
+        self.shared.files.lock().unwrap_or_else(|e| e.into_inner()).insert(path.to_owned(), contents.to_vec());
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Path) -> Result<(), ConfigurafoxError> {
+        self.shared.files.lock().unwrap_or_else(|e| e.into_inner()).remove(path);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ConfigurafoxError> {
+        self.shared.reload();
+        Ok(())
+    }
+}
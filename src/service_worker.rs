@@ -0,0 +1,217 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::{ConfigurafoxError, relative_url, url_path};
+use crate::deploy::DeployManifest;
+use crate::resource_manager::Resource;
+use crate::treewalker::{Context, TreeWalker};
+
+/// Where the service worker is written, relative to the output root. It must be at the root to serve the whole
+/// site
+pub const SERVICE_WORKER_FILE: &str = "sw.js";
+
+/// Outputs precached unless configured otherwise
+pub const DEFAULT_PRECACHE: &[&str] = &["**/*.html", "**/*.css", "**/*.js"];
+
+/// `__CACHE__`, `__PRECACHE__` and `__OFFLINE__` are filled in by `ServiceWorker::script`
+const SCRIPT_TEMPLATE: &str = r#"// Generated by configurafox. Serves the precached files from the cache, refetching only the ones whose
+// content hash changed since the last version of this worker
+const CACHE = __CACHE__;
+const PRECACHE = __PRECACHE__;
+const OFFLINE = __OFFLINE__;
+
+const base = new URL("./", self.location);
+// URL -> cache key, which includes the content hash
+const keys = new Map(PRECACHE.map(([path, hash]) => [new URL(path, base).href, new URL(path + "?v=" + hash, base).href]));
+
+self.addEventListener("install", event => {
+    event.waitUntil((async () => {
+        const cache = await caches.open(CACHE);
+        for (const [url, key] of keys) {
+            if (!(await cache.match(key))) {
+                const response = await fetch(url, { cache: "reload" });
+                if (response.ok) {
+                    await cache.put(key, response);
+                }
+            }
+        }
+        await self.skipWaiting();
+    })());
+});
+
+self.addEventListener("activate", event => {
+    event.waitUntil((async () => {
+        const cache = await caches.open(CACHE);
+        const current = new Set(keys.values());
+        for (const request of await cache.keys()) {
+            if (!current.has(request.url)) {
+                await cache.delete(request);
+            }
+        }
+        await self.clients.claim();
+    })());
+});
+
+self.addEventListener("fetch", event => {
+    const request = event.request;
+    if (request.method !== "GET") {
+        return;
+    }
+    const url = new URL(request.url);
+    url.search = "";
+    url.hash = "";
+    if (url.pathname.endsWith("/")) {
+        url.pathname += "index.html";
+    }
+    const key = keys.get(url.href);
+    const offline = request.mode === "navigate" && OFFLINE !== null ? keys.get(new URL(OFFLINE, base).href) : undefined;
+    if (key === undefined && offline === undefined) {
+        return;
+    }
+
+    event.respondWith((async () => {
+        const cached = key === undefined ? undefined : await caches.match(key);
+        if (cached) {
+            return cached;
+        }
+        try {
+            return await fetch(request);
+        } catch (e) {
+            const fallback = offline === undefined ? undefined : await caches.match(offline);
+            if (fallback) {
+                return fallback;
+            }
+            throw e;
+        }
+    })());
+});
+"#;
+
+/// A service worker making a built site work offline: it precaches a subset of the outputs when installed, and
+/// serves them from the cache. Every file is cached under its content hash from a `DeployManifest`, so a new
+/// build only makes browsers refetch what changed. Pages register it with `<service-worker/>`, see
+/// `ServiceWorkerRegistration`
+#[derive(Debug, Clone)]
+pub struct ServiceWorker {
+    /// Glob patterns of the outputs to precache, relative to the output root
+    pub precache: Vec<glob::Pattern>,
+    /// Shown when navigating to a page that isn't cached while offline, relative to the output root. Precached
+    /// whether the patterns match it or not
+    pub offline_page: Option<PathBuf>,
+    /// Name of the cache, to tell it from those of other workers on the same origin
+    pub cache_name: String,
+}
+
+impl Default for ServiceWorker {
+    fn default() -> Self {
+        ServiceWorker::with_precache(DEFAULT_PRECACHE).expect("the default patterns are valid")
+    }
+}
+
+impl ServiceWorker {
+    pub fn new() -> ServiceWorker {
+        ServiceWorker::default()
+    }
+
+    /// A service worker precaching the outputs matching `patterns`
+    pub fn with_precache<S: AsRef<str>>(patterns: &[S]) -> Result<ServiceWorker, ConfigurafoxError> {
+        let precache = patterns
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern.as_ref()).map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid glob {:?}: {e}", pattern.as_ref()))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ServiceWorker { precache, offline_page: None, cache_name: "configurafox-precache".to_string() })
+    }
+
+    pub fn with_offline_page<P: Into<PathBuf>>(mut self, path: P) -> ServiceWorker {
+        self.offline_page = Some(path.into());
+        self
+    }
+
+    pub fn with_cache_name(mut self, name: &str) -> ServiceWorker {
+        self.cache_name = name.to_string();
+        self
+    }
+
+    /// The files of `manifest` to precache, with their content hashes
+    pub fn precached<'a>(&self, manifest: &'a DeployManifest) -> Vec<(&'a Path, &'a str)> {
+        let options = glob::MatchOptions { require_literal_separator: true, ..glob::MatchOptions::new() };
+        manifest
+            .files
+            .iter()
+            .filter(|(path, _)| path.as_path() != Path::new(SERVICE_WORKER_FILE))
+            .filter(|(path, _)| {
+                self.offline_page.as_deref() == Some(path.as_path())
+                    || self.precache.iter().any(|pattern| pattern.matches_path_with(path, options))
+            })
+            .map(|(path, hash)| (path.as_path(), hash.as_str()))
+            .collect()
+    }
+
+    /// The script of the worker, for a build whose files have the hashes in `manifest`. Fails if the offline page
+    /// isn't one of them
+    pub fn script(&self, manifest: &DeployManifest) -> Result<String, ConfigurafoxError> {
+        let mut entries = Vec::new();
+        for (path, hash) in self.precached(manifest) {
+            entries.push(serde_json::json!([url_path(path)?, hash]));
+        }
+        let offline = match &self.offline_page {
+            Some(path) if !manifest.files.contains_key(path) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("The offline page {} wasn't built", path.display())).into());
+            }
+            Some(path) => serde_json::Value::from(url_path(path)?),
+            None => serde_json::Value::Null,
+        };
+        info!("Precaching {} files in the service worker", entries.len());
+
+        Ok(SCRIPT_TEMPLATE
+            .replace("__CACHE__", &serde_json::Value::from(self.cache_name.as_str()).to_string())
+            .replace("__PRECACHE__", &serde_json::Value::Array(entries).to_string())
+            .replace("__OFFLINE__", &offline.to_string()))
+    }
+
+    /// Writes the worker for the build in `output_dir` to `SERVICE_WORKER_FILE` in it, returning where. Run after
+    /// the build
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf, ConfigurafoxError> {
+        let manifest = DeployManifest::from_dir(output_dir, "")?;
+        let script = self.script(&manifest)?;
+        let path = output_dir.join(SERVICE_WORKER_FILE);
+        debug!("Writing the service worker to {}", path.display());
+        std::fs::write(&path, script)?;
+        Ok(path)
+    }
+}
+
+/// Replaces `<service-worker/>` with a script registering the worker `ServiceWorker` writes, if the browser
+/// supports service workers
+pub struct ServiceWorkerRegistration;
+
+impl<R: Resource, D> TreeWalker<R, D> for ServiceWorkerRegistration {
+    fn describe(&self) -> String {
+        "ServiceWorkerRegistration".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "service-worker"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["service-worker"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let url = relative_url(Path::new(SERVICE_WORKER_FILE), &ctx.resources.output_path(ctx.resource))?;
+        let script = format!(
+            "if (\"serviceWorker\" in navigator) {{ navigator.serviceWorker.register({}); }}",
+            serde_json::Value::from(url),
+        );
+        Ok(vec![Node::Element(Element { name: "script".to_string(), attrs: vec![], children: vec![Node::Text(script)] })])
+    }
+}
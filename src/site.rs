@@ -0,0 +1,82 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+
+use crate::ConfigurafoxError;
+use crate::metadata::Value;
+
+/// Site-wide settings, for walkers that need them to require of the user data `D`, like `CanonicalLink` does
+/// the base URL, instead of taking them as constructor parameters. Everything defaults to not being set, so a
+/// data type only implements what it has
+pub trait SiteData {
+    /// Where the output root is served, e.g. `https://example.com/blog`
+    fn base_url(&self) -> Option<&str> {
+        None
+    }
+
+    fn title(&self) -> Option<&str> {
+        None
+    }
+
+    /// The site variable `key`. Nested tables and arrays are addressed with `.`, as in `VariableReplacer`
+    fn var(&self, _key: &str) -> Option<&Value> {
+        None
+    }
+}
+
+/// For walkers that need nothing from the site, all of `SiteData` is unset
+impl SiteData for () {}
+
+/// `SiteData` as given in a project configuration, see `ProjectConfig::site`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Site {
+    pub base_url: Option<String>,
+    pub title: Option<String>,
+    /// A table
+    pub variables: Value,
+}
+
+impl Site {
+    pub fn new() -> Site {
+        Site::default()
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Site {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Site {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn with_variables(mut self, variables: Value) -> Site {
+        self.variables = variables;
+        self
+    }
+}
+
+impl SiteData for Site {
+    fn base_url(&self) -> Option<&str> {
+        self.base_url.as_deref()
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    fn var(&self, key: &str) -> Option<&Value> {
+        key.split('.').try_fold(&self.variables, |value, key| match value {
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            other => other.get(key),
+        })
+    }
+}
+
+/// The URL `output_path`, relative to the output root, is served at. Fails if the site has no base URL
+pub fn absolute_url<D: SiteData + ?Sized>(site: &D, output_path: &Path) -> Result<String, ConfigurafoxError> {
+    let base_url = site.base_url().ok_or(ConfigurafoxError::MissingSiteData { key: "base_url" })?;
+    Ok(format!("{}/{}", base_url.trim_end_matches('/'), crate::url_path(output_path)?.trim_start_matches('/')))
+}
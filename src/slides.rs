@@ -0,0 +1,154 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::Path;
+
+use html_editor::{Element, Node};
+
+use crate::{ConfigurafoxError, ResourceProcessor};
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::element;
+
+/// Elements a deck is split at by default. A slide starts at each `<h2>`, and at each `<hr>`, which is dropped
+pub const DEFAULT_SPLIT_AT: &[&str] = &["h2", "hr"];
+
+/// Elements that only separate slides, and aren't part of either
+const SEPARATORS: &[&str] = &["hr"];
+
+const DECK_STYLE: &str = "\
+html, body { margin: 0; height: 100%; overflow: hidden; }
+.slide { display: none; box-sizing: border-box; width: 100vw; height: 100vh; padding: 5vh 8vw; overflow: auto; }
+.slide.current { display: block; }
+.slide-number { position: fixed; right: 1em; bottom: 1em; opacity: 0.5; }
+@media print {
+  html, body { height: auto; overflow: visible; }
+  .slide { display: block; height: auto; break-after: page; }
+  .slide-number { display: none; }
+}
+";
+
+/// Shows one slide at a time, the one in the URL's fragment at first. Arrow keys, Page Up/Down, space, Home and
+/// End move between them
+const DECK_SCRIPT: &str = r##"(() => {
+  const slides = Array.from(document.querySelectorAll(".slide"));
+  const counter = document.querySelector(".slide-number");
+  if (slides.length === 0) {
+    return;
+  }
+  let current = 0;
+  const show = n => {
+    current = Math.max(0, Math.min(slides.length - 1, n));
+    slides.forEach((slide, i) => slide.classList.toggle("current", i === current));
+    counter.textContent = (current + 1) + " / " + slides.length;
+    history.replaceState(null, "", "#" + slides[current].id);
+  };
+  document.addEventListener("keydown", event => {
+    if (event.altKey || event.ctrlKey || event.metaKey) {
+      return;
+    }
+    switch (event.key) {
+      case "ArrowRight": case "ArrowDown": case "PageDown": show(current + 1); break;
+      case "ArrowLeft": case "ArrowUp": case "PageUp": show(current - 1); break;
+      case " ": show(current + (event.shiftKey ? -1 : 1)); break;
+      case "Home": show(0); break;
+      case "End": show(slides.length - 1); break;
+      default: return;
+    }
+    event.preventDefault();
+  });
+  const start = slides.findIndex(slide => "#" + slide.id === location.hash);
+  show(Math.max(start, 0));
+})();
+"##;
+
+/// Turns the pages another processor makes, usually an `HTMLProcessor`, into slide decks: the contents of the
+/// page's `<article>`, else its `<main>`, else its `<body>`, are split into a `<section class="slide">` at each
+/// element of `split_at`, and a little CSS and JavaScript shows them one at a time, moving between them
+/// with the keyboard. Printing the deck prints one slide per page. Content before the first split is a slide of
+/// its own, like a title slide
+pub struct SlideDeck<P> {
+    pub pages: P,
+    /// Tag names. Those in `SEPARATORS` are dropped, the others start the slide they're split at
+    pub split_at: Vec<String>,
+}
+
+impl<P> SlideDeck<P> {
+    pub fn new(pages: P) -> SlideDeck<P> {
+        SlideDeck { pages, split_at: DEFAULT_SPLIT_AT.iter().map(|tag| tag.to_string()).collect() }
+    }
+
+    pub fn with_split_at<I: IntoIterator<Item = S>, S: Into<String>>(mut self, tags: I) -> SlideDeck<P> {
+        self.split_at = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// `nodes` split into slides, leaving out slides with nothing but whitespace
+    fn split(&self, nodes: Vec<Node>) -> Vec<Vec<Node>> {
+        let mut slides = vec![Vec::new()];
+        for node in nodes {
+            match &node {
+                Node::Element(Element { name, .. }) if self.split_at.contains(name) => {
+                    slides.push(Vec::new());
+                    if !SEPARATORS.contains(&name.as_str()) {
+                        slides.last_mut().expect("just pushed").push(node);
+                    }
+                }
+                _ => slides.last_mut().expect("never empty").push(node),
+            }
+        }
+        slides.retain(|slide| slide.iter().any(|node| !matches!(node, Node::Text(text) if text.trim().is_empty())));
+        slides
+    }
+}
+
+impl<R: Resource, P: ResourceProcessor<R>> ResourceProcessor<R> for SlideDeck<P> {
+    fn name(&self) -> String {
+        format!("SlideDeck({})", self.pages.name())
+    }
+
+    fn process_resource(&self, source: &R, source_path: &Path, resources: &ResourceManager<R>) -> Result<Vec<u8>, ConfigurafoxError> {
+        let page = self.pages.process_resource(source, source_path, resources)?;
+        let html = String::from_utf8_lossy(&page);
+        let mut dom = crate::entities::parse(&html).map_err(|e| ConfigurafoxError::parse_html(source_path, &html, 1, e))?;
+
+        let container = ["article", "main", "body"].into_iter().find(|name| find_mut(&mut dom, name).is_some());
+        let contents = match container.and_then(|name| find_mut(&mut dom, name)) {
+            Some(element) => &mut element.children,
+            None => &mut dom,
+        };
+        let slides = self.split(std::mem::take(contents));
+        debug!("{}: {} slides", source_path.display(), slides.len());
+
+        contents.extend(slides.into_iter().enumerate().map(|(i, slide)| {
+            element("section", vec![("class", "slide".to_string()), ("id", format!("slide-{}", i + 1))], slide)
+        }));
+        let counter = element("div", vec![("class", "slide-number".to_string())], vec![]);
+        let style = element("style", vec![], vec![Node::Text(DECK_STYLE.to_string())]);
+        let script = element("script", vec![], vec![Node::Text(DECK_SCRIPT.to_string())]);
+        match find_mut(&mut dom, "body") {
+            Some(body) => body.children.extend([counter, script]),
+            None => dom.extend([counter, script]),
+        }
+        match find_mut(&mut dom, "head") {
+            Some(head) => head.children.push(style),
+            None => dom.insert(0, style),
+        }
+
+        Ok(crate::serialize::to_html(&dom).into_bytes())
+    }
+}
+
+/// The first element named `name`, depth first
+fn find_mut<'a>(dom: &'a mut [Node], name: &str) -> Option<&'a mut Element> {
+    for node in dom {
+        if let Node::Element(element) = node {
+            if element.name == name {
+                return Some(element);
+            }
+            if let Some(found) = find_mut(&mut element.children, name) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
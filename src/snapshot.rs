@@ -0,0 +1,239 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use html_editor::operation::Htmlifiable;
+use html_editor::{Element, Node};
+
+use crate::deploy::content_hash;
+use crate::diff::text_diff;
+use crate::serialize::Whitespace;
+
+/// Where snapshots are stored, relative to the project root
+pub const DEFAULT_SNAPSHOT_DIR: &str = "snapshots";
+
+/// Appended to the output path to get the snapshot's path, so snapshots aren't mistaken for outputs
+pub const SNAPSHOT_EXTENSION: &str = "snap";
+
+/// Set to anything to make `assert_snapshots` update the snapshots instead of comparing against them
+pub const UPDATE_SNAPSHOTS_VAR: &str = "CONFIGURAFOX_UPDATE_SNAPSHOTS";
+
+/// `dom` with one element or text per line, indented by depth, with sorted attributes and whitespace in text
+/// collapsed outside preformatted elements, see `serialize::Whitespace`. Meant for diffing, not for serving
+pub fn snapshot_html(dom: &[Node]) -> String {
+    fn visit(dom: &[Node], depth: usize, whitespace: &Whitespace, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for node in dom {
+            match node {
+                Node::Element(element @ Element { name, attrs, children }) => {
+                    let mut attrs = attrs.clone();
+                    attrs.sort();
+                    let attrs = attrs.iter().map(|(k, v)| format!(" {k}={v:?}")).collect::<String>();
+                    if whitespace.is_preformatted(element) {
+                        out.push_str(&format!("{indent}<{name}{attrs}>{}</{name}>\n", children.html()));
+                    } else if children.is_empty() {
+                        out.push_str(&format!("{indent}<{name}{attrs} />\n"));
+                    } else {
+                        out.push_str(&format!("{indent}<{name}{attrs}>\n"));
+                        visit(children, depth + 1, whitespace, out);
+                        out.push_str(&format!("{indent}</{name}>\n"));
+                    }
+                }
+                Node::Text(text) => {
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !text.is_empty() {
+                        out.push_str(&format!("{indent}{text}\n"));
+                    }
+                }
+                other => out.push_str(&format!("{indent}{}\n", other.html())),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    visit(dom, 0, &Whitespace::default(), &mut out);
+    out
+}
+
+/// The snapshot of an output at `path`: `snapshot_html` for HTML that parses, the contents of other text, and
+/// the size and hash of anything else
+pub fn snapshot(path: &Path, contents: &[u8]) -> String {
+    let Ok(text) = std::str::from_utf8(contents) else {
+        return format!("binary, {} bytes, hash {}\n", contents.len(), content_hash(contents));
+    };
+    let is_html = matches!(path.extension().and_then(|ext| ext.to_str()), Some("html" | "htm"));
+    match html_editor::parse(text) {
+        Ok(dom) if is_html => snapshot_html(&dom),
+        _ => text.to_string(),
+    }
+}
+
+fn snapshot_path(dir: &Path, output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(format!(".{SNAPSHOT_EXTENSION}"));
+    dir.join(name)
+}
+
+/// The snapshots stored in `dir`, by output path
+fn stored_snapshots(dir: &Path) -> std::io::Result<BTreeMap<PathBuf, String>> {
+    fn visit(root: &Path, dir: &Path, snapshots: &mut BTreeMap<PathBuf, String>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                visit(root, &path, snapshots)?;
+            } else if path.extension().is_some_and(|ext| ext == SNAPSHOT_EXTENSION) {
+                let output = path.strip_prefix(root).expect("read_dir stays below root").with_extension("");
+                snapshots.insert(output, std::fs::read_to_string(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    let mut snapshots = BTreeMap::new();
+    if dir.is_dir() {
+        visit(dir, dir, &mut snapshots)?;
+    }
+    Ok(snapshots)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotReport {
+    /// Outputs without a snapshot
+    pub new: Vec<PathBuf>,
+    /// Snapshots of outputs that aren't built anymore
+    pub missing: Vec<PathBuf>,
+    /// Outputs differing from their snapshot, with a diff from the snapshot to the output
+    pub changed: Vec<(PathBuf, String)>,
+    pub matching: usize,
+}
+
+impl SnapshotReport {
+    pub fn is_ok(&self) -> bool {
+        self.new.is_empty() && self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for path in &self.new {
+            writeln!(f, "new: {}", path.display())?;
+        }
+        for path in &self.missing {
+            writeln!(f, "missing: {}", path.display())?;
+        }
+        for (path, diff) in &self.changed {
+            writeln!(f, "changed: {}", path.display())?;
+            write!(f, "{diff}")?;
+        }
+        writeln!(
+            f,
+            "{} new, {} missing, {} changed, {} matching",
+            self.new.len(),
+            self.missing.len(),
+            self.changed.len(),
+            self.matching,
+        )
+    }
+}
+
+/// Compares the snapshots of `outputs`, e.g. the files of a `MemoryBackend`, against the ones stored in `dir`
+pub fn compare_snapshots(dir: &Path, outputs: &HashMap<PathBuf, Vec<u8>>) -> std::io::Result<SnapshotReport> {
+    let mut stored = stored_snapshots(dir)?;
+    let mut paths = outputs.keys().collect::<Vec<_>>();
+    paths.sort();
+
+    let mut report = SnapshotReport::default();
+    for path in paths {
+        let current = snapshot(path, &outputs[path]);
+        match stored.remove(path) {
+            None => report.new.push(path.clone()),
+            Some(previous) if previous == current => report.matching += 1,
+            Some(previous) => {
+                let diff = text_diff(previous.as_bytes(), current.as_bytes()).unwrap_or_default();
+                report.changed.push((path.clone(), diff));
+            }
+        }
+    }
+    report.missing = stored.into_keys().collect();
+    Ok(report)
+}
+
+/// Stores the snapshots of `outputs` in `dir`, removing snapshots of outputs that aren't built anymore
+pub fn update_snapshots(dir: &Path, outputs: &HashMap<PathBuf, Vec<u8>>) -> std::io::Result<()> {
+    for path in stored_snapshots(dir)?.keys().filter(|path| !outputs.contains_key(*path)) {
+        info!("Removing the snapshot of {}", path.display());
+        std::fs::remove_file(snapshot_path(dir, path))?;
+    }
+    for (path, contents) in outputs {
+        let snapshot_path = snapshot_path(dir, path);
+        if let Some(parent) = snapshot_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(snapshot_path, snapshot(path, contents))?;
+    }
+    Ok(())
+}
+
+/// For tests: panics with the report if `outputs` don't match the snapshots in `dir`. With the environment
+/// variable `UPDATE_SNAPSHOTS_VAR` set, updates the snapshots instead
+#[track_caller]
+pub fn assert_snapshots(dir: &Path, outputs: &HashMap<PathBuf, Vec<u8>>) {
+    if std::env::var_os(UPDATE_SNAPSHOTS_VAR).is_some() {
+        update_snapshots(dir, outputs).expect("could not update snapshots");
+        return;
+    }
+    let report = compare_snapshots(dir, outputs).expect("could not read snapshots");
+    assert!(report.is_ok(), "outputs don't match the snapshots in {}, set {UPDATE_SNAPSHOTS_VAR}=1 to update them\n{report}", dir.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::{compare_snapshots, snapshot, update_snapshots};
+
+    fn outputs(files: &[(&str, &str)]) -> HashMap<PathBuf, Vec<u8>> {
+        files.iter().map(|(path, contents)| (PathBuf::from(path), contents.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn html() {
+        let html = "<div class=\"b\" id=\"a\">\n  Some   <em>text</em>\n<pre>  kept\n  as is</pre></div>";
+        assert_eq!(
+            snapshot("index.html".as_ref(), html.as_bytes()),
+            "<div class=\"b\" id=\"a\">\n  Some\n  <em>\n    text\n  </em>\n  <pre>  kept\n  as is</pre>\n</div>\n",
+        );
+    }
+
+    #[test]
+    fn other_files() {
+        assert_eq!(snapshot("style.css".as_ref(), b"a  { }\n"), "a  { }\n");
+        assert!(snapshot("logo.png".as_ref(), &[0x89, 0xff, 0x00]).starts_with("binary, 3 bytes, hash "));
+    }
+
+    #[test]
+    fn update_and_compare() {
+        let dir = std::env::temp_dir().join(format!("configurafox-snapshot-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        update_snapshots(&dir, &outputs(&[("index.html", "<p>Hi</p>"), ("blog/post.html", "<p>Post</p>")])).unwrap();
+        let report = compare_snapshots(&dir, &outputs(&[("index.html", "<p>Hi</p>"), ("blog/post.html", "<p>Post</p>")])).unwrap();
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(report.matching, 2);
+
+        let report = compare_snapshots(&dir, &outputs(&[("index.html", "<p>Hello</p>"), ("about.html", "<p>About</p>")])).unwrap();
+        assert_eq!(report.new, [PathBuf::from("about.html")]);
+        assert_eq!(report.missing, [PathBuf::from("blog/post.html")]);
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.changed[0].0, PathBuf::from("index.html"));
+
+        update_snapshots(&dir, &outputs(&[("index.html", "<p>Hello</p>")])).unwrap();
+        let report = compare_snapshots(&dir, &outputs(&[("index.html", "<p>Hello</p>")])).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(report.is_ok(), "{report}");
+    }
+}
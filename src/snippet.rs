@@ -0,0 +1,185 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::remote::{DEFAULT_CACHE_DIR, fetch_pinned};
+use crate::resource_manager::Resource;
+use crate::treewalker::{Context, SyntaxHighlighter, TreeWalker, get_attr};
+
+/// How a git forge serves raw files and shows them with lines highlighted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    /// Gitea and Forgejo, e.g. Codeberg
+    Gitea,
+    SourceHut,
+}
+
+impl Forge {
+    /// By name, as in `forge="gitlab"`
+    pub fn from_name(name: &str) -> Option<Forge> {
+        match name {
+            "github" => Some(Forge::GitHub),
+            "gitlab" => Some(Forge::GitLab),
+            "gitea" | "forgejo" => Some(Forge::Gitea),
+            "sourcehut" => Some(Forge::SourceHut),
+            _ => None,
+        }
+    }
+
+    /// The forge of the well-known `host`s, and of hosts named after their forge, like `gitlab.example.com`
+    pub fn for_host(host: &str) -> Option<Forge> {
+        match host {
+            "github.com" => Some(Forge::GitHub),
+            "codeberg.org" => Some(Forge::Gitea),
+            "git.sr.ht" => Some(Forge::SourceHut),
+            _ => ["gitlab", "gitea", "forgejo"].into_iter().find(|name| host.split('.').any(|part| part == *name)).and_then(Forge::from_name),
+        }
+    }
+
+    /// Where the file at `path` of `repo` at `rev` is served as is. `repo` is the repository's URL
+    pub fn raw_url(self, repo: &str, rev: &str, path: &str) -> String {
+        match self {
+            Forge::GitHub => format!("{}/{rev}/{path}", repo.replacen("://github.com/", "://raw.githubusercontent.com/", 1)),
+            Forge::GitLab => format!("{repo}/-/raw/{rev}/{path}"),
+            Forge::Gitea => format!("{repo}/raw/commit/{rev}/{path}"),
+            Forge::SourceHut => format!("{repo}/blob/{rev}/{path}"),
+        }
+    }
+
+    /// Where the file is shown, with `lines` (first and last, counting from 1) highlighted
+    pub fn view_url(self, repo: &str, rev: &str, path: &str, lines: Option<(usize, usize)>) -> String {
+        let anchor = match (self, lines) {
+            (_, None) => String::new(),
+            (Forge::GitLab | Forge::SourceHut, Some((first, last))) => format!("#L{first}-{last}"),
+            (Forge::GitHub | Forge::Gitea, Some((first, last))) => format!("#L{first}-L{last}"),
+        };
+        match self {
+            Forge::GitHub => format!("{repo}/blob/{rev}/{path}{anchor}"),
+            Forge::GitLab => format!("{repo}/-/blob/{rev}/{path}{anchor}"),
+            Forge::Gitea => format!("{repo}/src/commit/{rev}/{path}{anchor}"),
+            Forge::SourceHut => format!("{repo}/tree/{rev}/item/{path}{anchor}"),
+        }
+    }
+}
+
+/// `lines="10-30"` or `lines="12"` as first and last line
+fn parse_lines(lines: &str) -> Option<(usize, usize)> {
+    let (first, last) = lines.split_once(['-', '–']).unwrap_or((lines, lines));
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    (1 <= first && first <= last).then_some((first, last))
+}
+
+/// Replaces `<git-snippet repo="https://github.com/owner/name" path="src/lib.rs" lines="10-30" rev="abc123">`
+/// with the lines of the file at that revision, highlighted like `<pre-hl>` by the language of its extension or
+/// `lang`, in a `<figure class="git-snippet">` captioned with a link to them on the forge. Without `lines`, the
+/// whole file is shown. `run` is passed on, see `SyntaxHighlighter`
+///
+/// The forge is recognized by the repository's host, see `Forge::for_host`, or given with `forge="gitlab"`. The
+/// file is fetched at build time and cached in `remote::DEFAULT_CACHE_DIR` for good, so `rev` should be a commit
+/// rather than a branch
+pub struct GitSnippet {
+    pub highlighter: SyntaxHighlighter,
+    /// Where fetched files are kept, relative to the project root
+    pub cache_dir: PathBuf,
+}
+
+impl GitSnippet {
+    pub fn new(highlighter: SyntaxHighlighter) -> GitSnippet {
+        GitSnippet { highlighter, cache_dir: PathBuf::from(DEFAULT_CACHE_DIR) }
+    }
+
+    pub fn with_cache_dir<P: Into<PathBuf>>(mut self, cache_dir: P) -> GitSnippet {
+        self.cache_dir = cache_dir.into();
+        self
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for GitSnippet {
+    fn describe(&self) -> String {
+        "GitSnippet".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "git-snippet"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["git-snippet"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let required = |key: &str, msg: &str| {
+            get_attr(&attrs, key).ok_or_else(|| ConfigurafoxError::MissingAttr { key_name: key.to_string(), msg: msg.to_string() })
+        };
+        let repo = required("repo", "the URL of the repository")?.trim_end_matches('/').trim_end_matches(".git");
+        let path = required("path", "the file in the repository")?.trim_start_matches('/');
+        let rev = required("rev", "the commit to quote")?;
+        let repo = if repo.contains("://") { repo.to_string() } else { format!("https://{repo}") };
+
+        let forge = match get_attr(&attrs, "forge") {
+            Some(name) => Forge::from_name(name)
+                .ok_or_else(|| ConfigurafoxError::MalformedAttrs { key_name: "forge".to_string(), msg: format!("unknown forge {name:?}") })?,
+            None => {
+                let host = repo.split("://").nth(1).and_then(|rest| rest.split('/').next()).unwrap_or_default();
+                Forge::for_host(host)
+                    .ok_or_else(|| ConfigurafoxError::MissingAttr { key_name: "forge".to_string(), msg: format!("can't tell which forge {host} is") })?
+            }
+        };
+        let lines = match get_attr(&attrs, "lines") {
+            Some(lines) => Some(
+                parse_lines(lines)
+                    .ok_or_else(|| ConfigurafoxError::MalformedAttrs { key_name: "lines".to_string(), msg: format!("{lines:?} isn't a line or range like 10-30") })?,
+            ),
+            None => None,
+        };
+
+        let url = forge.raw_url(&repo, rev, path);
+        let cached = fetch_pinned(&url, &ctx.resources.absolute_path(&self.cache_dir))?;
+        let contents = String::from_utf8(std::fs::read(cached)?)
+            .map_err(|_| ConfigurafoxError::Remote { url: url.clone(), msg: "not UTF-8 text".to_string() })?;
+
+        let code = match lines {
+            Some((first, last)) => {
+                let count = contents.lines().count();
+                if last > count {
+                    return Err(ConfigurafoxError::MalformedAttrs { key_name: "lines".to_string(), msg: format!("{path} has only {count} lines") });
+                }
+                contents.lines().skip(first - 1).take(last + 1 - first).collect::<Vec<_>>().join("\n")
+            }
+            None => contents,
+        };
+
+        let lang = get_attr(&attrs, "lang")
+            .or_else(|| Path::new(path).extension().and_then(|ext| ext.to_str()))
+            .unwrap_or("txt");
+        let mut highlight_attrs = vec![("lang".to_string(), lang.to_string())];
+        if let Some(run) = get_attr(&attrs, "run") {
+            highlight_attrs.push(("run".to_string(), run.to_string()));
+        }
+        let mut children = self.highlighter.replace("pre-hl", highlight_attrs, vec![Node::Text(code)], ctx)?;
+
+        let name = repo.split("://").nth(1).and_then(|rest| rest.split_once('/')).map_or(&*repo, |(_, name)| name);
+        let caption = match lines {
+            Some((first, last)) if first == last => format!("{name}: {path}, line {first}"),
+            Some((first, last)) => format!("{name}: {path}, lines {first}–{last}"),
+            None => format!("{name}: {path}"),
+        };
+        children.push(Node::Element(Element {
+            name: "figcaption".to_string(),
+            attrs: vec![],
+            children: vec![Node::Element(Element {
+                name: "a".to_string(),
+                attrs: vec![("href".to_string(), forge.view_url(&repo, rev, path, lines))],
+                children: vec![Node::Text(caption)],
+            })],
+        }));
+
+        Ok(vec![Node::Element(Element { name: "figure".to_string(), attrs: vec![("class".to_string(), "git-snippet".to_string())], children })])
+    }
+}
@@ -0,0 +1,229 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::fingerprint;
+use crate::graph::DependencyKind;
+use crate::metadata::{Metadata, Value};
+use crate::resource_manager::{read_front_matter, Origin, Resource, ResourceManager};
+
+/// Where the state of the previous build is kept by default, relative to the project root
+pub const DEFAULT_STATE_FILE: &str = ".configurafox-cache/state.json";
+
+/// Bumped whenever the format changes. State files of other versions are rejected
+const STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SavedOrigin {
+    File,
+    #[cfg(feature = "archives")]
+    Archive { archive: PathBuf, entry: String },
+    Memory(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedResource<R> {
+    resource: R,
+    path: PathBuf,
+    origin: SavedOrigin,
+    metadata: BTreeMap<String, Value>,
+    aliases: Vec<String>,
+    modified: Option<SystemTime>,
+    size: Option<u64>,
+    /// Hash of the contents as they were built
+    fingerprint: u64,
+}
+
+/// Everything needed to pick up an incremental build in a new process: the registered resources with their
+/// metadata, aliases and fingerprints, and the dependency graph. Collections and identifier normalization are
+/// configuration, and have to be set up again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildState<R> {
+    version: u32,
+    resources: Vec<SavedResource<R>>,
+    dependencies: Vec<(R, R, DependencyKind)>,
+}
+
+impl<R: Serialize> BuildState<R> {
+    /// Writes the state as JSON to `path` on the real filesystem, creating its directory if needed
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+}
+
+impl<R: DeserializeOwned> BuildState<R> {
+    /// Reads a state written by `save`. None if there is no state file, e.g. on the first build
+    pub fn load(path: &Path) -> std::io::Result<Option<BuildState<R>>> {
+        let json = match std::fs::read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let state: BuildState<R> = serde_json::from_slice(&json)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+        if state.version != STATE_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: state version {}, expected {STATE_VERSION}", path.display(), state.version),
+            ));
+        }
+        Ok(Some(state))
+    }
+}
+
+/// What changed since a `BuildState` was saved
+#[derive(Debug, Clone)]
+pub struct StaleResources<R: Resource> {
+    /// Restored resources whose contents differ from the previous build
+    pub changed: HashSet<R>,
+    /// Resources whose source has disappeared. They are not restored
+    pub removed: Vec<R>,
+    /// Everything whose output needs rebuilding: `changed`, everything that includes a changed or removed
+    /// resource, directly or through other includes, and everything that links to one directly
+    pub affected: HashSet<R>,
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Captures the current resources and dependencies, e.g. at the end of a build. Reads every resource to
+    /// fingerprint it, which is cheap right after a build as the contents are cached
+    pub fn build_state(&self) -> std::io::Result<BuildState<R>> {
+        let mut resources = Vec::new();
+        for (path, resource) in self.iter() {
+            let origin = match self.origin(resource) {
+                Some(Origin::File) | None => SavedOrigin::File,
+                #[cfg(feature = "archives")]
+                Some(Origin::Archive { archive, entry }) => SavedOrigin::Archive { archive: archive.clone(), entry: entry.clone() },
+                Some(Origin::Memory(contents)) => SavedOrigin::Memory(contents.to_vec()),
+            };
+            let file_info = self.file_info(resource).unwrap_or_default();
+
+            resources.push(SavedResource {
+                resource: resource.clone(),
+                path: path.to_owned(),
+                origin,
+                metadata: self.metadata(resource).0.clone(),
+                aliases: self.aliases_of(resource).into_iter().map(str::to_string).collect(),
+                modified: file_info.modified,
+                size: file_info.size,
+                fingerprint: fingerprint(&self.read(resource)?),
+            });
+        }
+
+        let dependencies = self
+            .dependency_graph()
+            .edges()
+            .map(|(from, to, kind)| (from.clone(), to.clone(), kind))
+            .collect();
+
+        Ok(BuildState { version: STATE_VERSION, resources, dependencies })
+    }
+
+    /// Registers the resources of a previous build instead of scanning for them, and finds out which of them
+    /// are stale. A resource is only read if its modification time or size changed. Changed resources get
+    /// their front matter and sidecar metadata reloaded.
+    /// Files created since the state was saved are not found, register them separately
+    pub fn restore_state(&mut self, state: BuildState<R>) -> std::io::Result<StaleResources<R>> {
+        let mut changed = HashSet::new();
+        let mut removed = Vec::new();
+
+        for saved in state.resources {
+            let origin = match saved.origin {
+                SavedOrigin::File => Origin::File,
+                #[cfg(feature = "archives")]
+                SavedOrigin::Archive { archive, entry } => Origin::Archive { archive, entry },
+                SavedOrigin::Memory(contents) => Origin::Memory(contents.into()),
+            };
+            let source = match &origin {
+                Origin::File => Some(saved.path.clone()),
+                #[cfg(feature = "archives")]
+                Origin::Archive { archive, .. } => Some(archive.clone()),
+                Origin::Memory(_) => None,
+            };
+
+            if let Some(source) = &source {
+                if self.vfs().metadata(source).is_err() {
+                    info!("{}: Gone since the last build", source.display());
+                    removed.push(saved.resource);
+                    continue;
+                }
+            }
+
+            let resource = saved.resource;
+            self.insert_with_origin(resource.clone(), saved.path.clone(), origin, Metadata(saved.metadata));
+            // Archive entries aren't statted on registration
+            #[cfg(feature = "archives")]
+            if matches!(self.origin(&resource), Some(Origin::Archive { .. })) {
+                if let Some(size) = saved.size {
+                    self.set_size(&resource, size);
+                }
+            }
+            for alias in &saved.aliases {
+                self.add_alias(&resource, alias);
+            }
+
+            let file_info = self.file_info(&resource).unwrap_or_default();
+            if file_info.modified == saved.modified && file_info.size == saved.size {
+                continue;
+            }
+            if fingerprint(&self.read(&resource)?) == saved.fingerprint {
+                debug!("{}: Touched, but unchanged", saved.path.display());
+                continue;
+            }
+
+            info!("{}: Changed since the last build", saved.path.display());
+            if matches!(self.origin(&resource), Some(Origin::File)) {
+                self.reload_metadata(&resource, &saved.path)?;
+            }
+            changed.insert(resource);
+        }
+
+        let mut affected = HashSet::new();
+        {
+            let mut graph = self.dependency_graph();
+            for (from, to, kind) in state.dependencies {
+                graph.add(&from, &to, kind);
+            }
+
+            // `affected_by` only follows includes, but a change can move a resource's output, which its links point to
+            for resource in changed.iter().chain(&removed) {
+                affected.extend(graph.affected_by(resource));
+                affected.extend(graph.dependents_of(resource).map(|(from, _)| from.clone()));
+            }
+            for resource in &removed {
+                affected.remove(resource);
+            }
+        }
+
+        for resource in &removed {
+            self.dependency_graph().remove(resource);
+        }
+
+        Ok(StaleResources { changed, removed, affected })
+    }
+
+    /// Re-reads the front matter and sidecar of a changed resource, replacing the saved metadata if it has either
+    fn reload_metadata(&mut self, resource: &R, path: &Path) -> std::io::Result<()> {
+        let front_matter = read_front_matter(self.vfs(), path)?;
+        let sidecar = self.load_sidecar(path)?;
+        if front_matter.is_none() && sidecar.is_none() {
+            return Ok(());
+        }
+
+        let mut metadata = front_matter.unwrap_or_default();
+        metadata.merge(sidecar.unwrap_or_default());
+        if let Some(current) = self.metadata_mut(resource) {
+            *current = metadata;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::resource_manager::{Resource, ResourceManager};
+
+/// How many of the largest files a report lists by default
+pub const DEFAULT_TOP_N: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, bytes: u64) {
+        self.count += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Counts and sizes of a set of files, grouped by kind (extension) and by directory
+#[derive(Debug, Clone, Default)]
+pub struct SizeReport {
+    pub total: Totals,
+    /// Keyed by lowercase extension, `""` for files without one
+    pub by_kind: BTreeMap<String, Totals>,
+    /// Keyed by the directory containing the file
+    pub by_directory: BTreeMap<PathBuf, Totals>,
+    /// The largest files, biggest first
+    pub largest: Vec<(PathBuf, u64)>,
+}
+
+impl SizeReport {
+    pub fn from_sizes<I: IntoIterator<Item = (PathBuf, u64)>>(sizes: I, top_n: usize) -> SizeReport {
+        let mut report = SizeReport::default();
+
+        for (path, bytes) in sizes {
+            let kind = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            let dir = path.parent().map(Path::to_owned).unwrap_or_default();
+
+            report.total.add(bytes);
+            report.by_kind.entry(kind).or_default().add(bytes);
+            report.by_directory.entry(dir).or_default().add(bytes);
+            report.largest.push((path, bytes));
+        }
+
+        report.largest.sort_by(|(path_a, a), (path_b, b)| b.cmp(a).then_with(|| path_a.cmp(path_b)));
+        report.largest.truncate(top_n);
+
+        report
+    }
+
+    /// Report on the files of a finished build in `output_dir`
+    pub fn from_output_dir(output_dir: &Path, top_n: usize) -> std::io::Result<SizeReport> {
+        let mut sizes = Vec::new();
+        let mut queue = vec![PathBuf::new()];
+        while let Some(dir) = queue.pop() {
+            for entry in std::fs::read_dir(output_dir.join(&dir))? {
+                let entry = entry?;
+                let path = dir.join(entry.file_name());
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    queue.push(path);
+                } else {
+                    sizes.push((path, metadata.len()));
+                }
+            }
+        }
+
+        Ok(SizeReport::from_sizes(sizes, top_n))
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} files, {}", self.total.count, human_size(self.total.bytes))?;
+
+        writeln!(f, "By kind:")?;
+        for (kind, totals) in &self.by_kind {
+            let kind = if kind.is_empty() { "(none)" } else { kind };
+            writeln!(f, "  {kind:<12} {:>6} files {:>12}", totals.count, human_size(totals.bytes))?;
+        }
+
+        writeln!(f, "By directory:")?;
+        for (dir, totals) in &self.by_directory {
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            writeln!(f, "  {:<40} {:>6} files {:>12}", dir.display(), totals.count, human_size(totals.bytes))?;
+        }
+
+        writeln!(f, "Largest:")?;
+        for (path, bytes) in &self.largest {
+            writeln!(f, "  {:>12} {}", human_size(*bytes), path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Sizes of the registered sources, grouped by their output paths. See `SizeReport::from_output_dir`
+    /// for the sizes of the generated files
+    pub fn stats(&self) -> SizeReport {
+        let sizes = self
+            .iter()
+            .map(|(_, resource)| (self.output_path(resource), self.size(resource).unwrap_or(0)))
+            .collect::<Vec<_>>();
+
+        SizeReport::from_sizes(sizes, DEFAULT_TOP_N)
+    }
+}
@@ -0,0 +1,271 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use html_editor::{Element, Node};
+
+use crate::ConfigurafoxError;
+use crate::resource_manager::Resource;
+use crate::treewalker::{Context, TreeWalker, get_attr};
+
+/// Class of the `<div>` `TableEnhancer` wraps tables in by default
+pub const DEFAULT_WRAPPER_CLASS: &str = "table-wrapper";
+
+/// Class of the wrapper, or of the table if it isn't wrapped, when its header row should stick
+pub const STICKY_CLASS: &str = "sticky-header";
+
+/// How the cells of a column are aligned, as given in `columns="l c r"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    /// The class of the cells, `align-left`, `align-center` or `align-right`
+    pub fn class(self) -> &'static str {
+        match self {
+            Align::Left => "align-left",
+            Align::Center => "align-center",
+            Align::Right => "align-right",
+        }
+    }
+}
+
+/// The alignments in `columns="l c r"`, by column. `-` leaves a column as it is, and spaces are optional, so
+/// `columns="l-r"` works too
+pub fn parse_columns(columns: &str) -> Result<Vec<Option<Align>>, ConfigurafoxError> {
+    columns
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c.to_ascii_lowercase() {
+            'l' => Ok(Some(Align::Left)),
+            'c' => Ok(Some(Align::Center)),
+            'r' => Ok(Some(Align::Right)),
+            '-' => Ok(None),
+            c => Err(ConfigurafoxError::MalformedAttrs { key_name: "columns".to_string(), msg: format!("{c:?} isn't l, c, r or -") }),
+        })
+        .collect()
+}
+
+/// Fills in the markup that makes tables accessible and usable on small screens, on every `<table>` of a page:
+///
+/// - `<th>`s get a `scope`: `col` in the `<thead>` and in rows of only headers, `row` otherwise, or `colgroup` and
+///   `rowgroup` when they span several. Given scopes are kept
+/// - `columns="l c r"` on the table gives the cells of each column the class `align-left`, `align-center` or
+///   `align-right`, see `parse_columns`
+/// - The table is wrapped in a `<div class="table-wrapper">`, for the stylesheet to make scroll sideways, unless
+///   `wrapper="off"`
+/// - Tables with `sticky`, or all unless `sticky="off"` if the walker's `sticky` is set, get `STICKY_CLASS` on their
+///   wrapper, for the stylesheet to keep their `<thead>` in view. A first row of only headers is moved into a new
+///   `<thead>` if there is none
+///
+/// The stylesheet could have e.g. `.table-wrapper { overflow-x: auto }` and `.sticky-header { max-height: 80vh }
+/// .sticky-header thead th { position: sticky; top: 0 }`. It works on the finished page, so tables other walkers
+/// added are included
+pub struct TableEnhancer {
+    /// Class of the wrapper `<div>`, None to wrap no tables
+    pub wrapper_class: Option<String>,
+    pub sticky: bool,
+}
+
+impl Default for TableEnhancer {
+    fn default() -> Self {
+        TableEnhancer::new()
+    }
+}
+
+impl TableEnhancer {
+    pub fn new() -> TableEnhancer {
+        TableEnhancer { wrapper_class: Some(DEFAULT_WRAPPER_CLASS.to_string()), sticky: false }
+    }
+
+    pub fn with_wrapper_class(mut self, wrapper_class: Option<String>) -> TableEnhancer {
+        self.wrapper_class = wrapper_class;
+        self
+    }
+
+    pub fn with_sticky(mut self, sticky: bool) -> TableEnhancer {
+        self.sticky = sticky;
+        self
+    }
+
+    fn enhance_all<R: Resource, D>(&self, dom: &mut [Node], ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        for node in dom {
+            let Node::Element(element) = node else { continue };
+            self.enhance_all(&mut element.children, ctx)?;
+            if element.name == "table" {
+                let table = std::mem::replace(element, Element { name: String::new(), attrs: vec![], children: vec![] });
+                *element = self.enhance(table, ctx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `table` enhanced, and wrapped unless it shouldn't be
+    fn enhance<R: Resource, D>(&self, mut table: Element, ctx: Context<'_, '_, R, D>) -> Result<Element, ConfigurafoxError> {
+        let columns = take_attr(&mut table.attrs, "columns").map(|columns| parse_columns(&columns)).transpose()?.unwrap_or_default();
+        let sticky = take_attr(&mut table.attrs, "sticky").map_or(self.sticky, |sticky| sticky != "off");
+        let wrapper_class = match take_attr(&mut table.attrs, "wrapper").as_deref() {
+            Some("off") => None,
+            _ => self.wrapper_class.as_deref(),
+        };
+
+        if sticky && !has_head(&table.children) && !make_head(&mut table.children) {
+            ctx.warn("A table with a sticky header has no header row");
+        }
+        enhance_rows(&mut table.children, false, &columns, &mut vec![]);
+
+        let Some(wrapper_class) = wrapper_class else {
+            if sticky {
+                add_class(&mut table.attrs, STICKY_CLASS);
+            }
+            return Ok(table);
+        };
+        let mut attrs = vec![("class".to_string(), wrapper_class.to_string())];
+        if sticky {
+            add_class(&mut attrs, STICKY_CLASS);
+        }
+        Ok(Element { name: "div".to_string(), attrs, children: vec![Node::Element(table)] })
+    }
+}
+
+/// Removes the attribute `key` from `attrs`, returning its value
+fn take_attr(attrs: &mut Vec<(String, String)>, key: &str) -> Option<String> {
+    let index = attrs.iter().position(|(k, _)| k == key)?;
+    Some(attrs.remove(index).1)
+}
+
+fn add_class(attrs: &mut Vec<(String, String)>, class: &str) {
+    match attrs.iter_mut().find(|(key, _)| key == "class") {
+        Some((_, classes)) if classes.split_ascii_whitespace().any(|c| c == class) => {}
+        Some((_, classes)) => {
+            if !classes.trim().is_empty() {
+                classes.push(' ');
+            }
+            classes.push_str(class);
+        }
+        None => attrs.push(("class".to_string(), class.to_string())),
+    }
+}
+
+fn is_element(node: &Node, name: &str) -> bool {
+    matches!(node, Node::Element(element) if element.name == name)
+}
+
+fn has_head(table: &[Node]) -> bool {
+    table.iter().any(|node| is_element(node, "thead"))
+}
+
+/// The `<th>`s and `<td>`s of a row
+fn cells(row: &[Node]) -> impl Iterator<Item = &Element> {
+    row.iter().filter_map(|node| match node {
+        Node::Element(element) if element.name == "th" || element.name == "td" => Some(element),
+        _ => None,
+    })
+}
+
+fn is_header_row(row: &Element) -> bool {
+    cells(&row.children).next().is_some() && cells(&row.children).all(|cell| cell.name == "th")
+}
+
+/// Moves the first row of the `table` into a new `<thead>` if it has only headers. Whether it did
+fn make_head(table: &mut Vec<Node>) -> bool {
+    let Some(index) = table.iter().position(|node| is_element(node, "tr") || is_element(node, "tbody")) else {
+        return false;
+    };
+    let row = match &mut table[index] {
+        Node::Element(row) if row.name == "tr" => {
+            if !is_header_row(row) {
+                return false;
+            }
+            table.remove(index)
+        }
+        Node::Element(body) => {
+            let Some(first) = body.children.iter().position(|node| is_element(node, "tr")) else { return false };
+            match &body.children[first] {
+                Node::Element(row) if is_header_row(row) => body.children.remove(first),
+                _ => return false,
+            }
+        }
+        _ => return false,
+    };
+    table.insert(index, Node::Element(Element { name: "thead".to_string(), attrs: vec![], children: vec![row] }));
+    true
+}
+
+fn span(cell: &Element, key: &str) -> usize {
+    get_attr(&cell.attrs, key).and_then(|span| span.trim().parse().ok()).filter(|span| *span > 0).unwrap_or(1)
+}
+
+/// Gives the header cells of the rows in `nodes`, a table or one of its row groups, their scope, and the cells
+/// their column's alignment. `covered` is how many more rows each column is spanned by a cell above
+fn enhance_rows(nodes: &mut [Node], in_head: bool, columns: &[Option<Align>], covered: &mut Vec<usize>) {
+    for node in nodes {
+        let Node::Element(element) = node else { continue };
+        match element.name.as_str() {
+            "thead" | "tbody" | "tfoot" => enhance_rows(&mut element.children, element.name == "thead", columns, &mut vec![]),
+            "tr" => enhance_row(element, in_head, columns, covered),
+            _ => {}
+        }
+    }
+}
+
+fn enhance_row(row: &mut Element, in_head: bool, columns: &[Option<Align>], covered: &mut Vec<usize>) {
+    let column_headers = in_head || is_header_row(row);
+    let mut column = 0;
+    for node in &mut row.children {
+        let Node::Element(cell) = node else { continue };
+        if cell.name != "th" && cell.name != "td" {
+            continue;
+        }
+        while covered.get(column).is_some_and(|rows| *rows > 0) {
+            column += 1;
+        }
+        let (colspan, rowspan) = (span(cell, "colspan"), span(cell, "rowspan"));
+
+        if cell.name == "th" && get_attr(&cell.attrs, "scope").is_none() {
+            let scope = match (column_headers, colspan > 1, rowspan > 1) {
+                (true, false, _) => "col",
+                (true, true, _) => "colgroup",
+                (false, _, false) => "row",
+                (false, _, true) => "rowgroup",
+            };
+            cell.attrs.push(("scope".to_string(), scope.to_string()));
+        }
+        if let Some(Some(align)) = columns.get(column) {
+            add_class(&mut cell.attrs, align.class());
+        }
+
+        if covered.len() < column + colspan {
+            covered.resize(column + colspan, 0);
+        }
+        covered[column..column + colspan].fill(rowspan);
+        column += colspan;
+    }
+    for rows in covered.iter_mut() {
+        *rows = rows.saturating_sub(1);
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for TableEnhancer {
+    fn describe(&self) -> String {
+        format!("TableEnhancer({:?}, sticky: {})", self.wrapper_class, self.sticky)
+    }
+
+    fn matches(&self, _tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        false
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec![])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, children: Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok(children)
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        self.enhance_all(dom, ctx)
+    }
+}
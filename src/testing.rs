@@ -0,0 +1,178 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use html_editor::{Element, Node};
+
+use crate::{ConfigurafoxError, HTMLProcessor, ResourceProcessor};
+use crate::resource_manager::{PathResource, ResourceManager};
+use crate::serialize::{BLOCK_ELEMENTS, Whitespace, to_html};
+use crate::snapshot::snapshot_html;
+use crate::treewalker::TreeWalker;
+use crate::vfs::MemoryFs;
+
+/// The path `run_walker` and `run_walkers` put their HTML at
+pub const TEST_PAGE: &str = "index.html";
+
+/// The files of a test site
+pub type TestResource = PathResource;
+
+/// A manager with every file of `files`, as `(path, contents)`, registered as a `TestResource`. Nothing is
+/// read from or written to disk
+pub fn resource_manager(files: &[(&str, &str)]) -> ResourceManager<TestResource> {
+    let mut vfs = MemoryFs::new();
+    for (path, contents) in files {
+        vfs.insert(path, *contents);
+    }
+
+    let mut resman = ResourceManager::with_vfs(Arc::new(vfs));
+    for (path, _) in files {
+        resman.register_file(PathBuf::from(path), PathResource::new(path)).expect("the file was just added");
+    }
+    resman
+}
+
+/// Runs `processor` over the file at `path` of a site made of `files`, returning its output
+pub fn process(processor: &dyn ResourceProcessor<TestResource>, files: &[(&str, &str)], path: &str) -> Result<Vec<u8>, ConfigurafoxError> {
+    let resman = resource_manager(files);
+    processor.process_resource(&PathResource::new(path), Path::new(path), &resman)
+}
+
+/// Runs `walkers` over `html`, as the only page of a site, returning the resulting HTML
+pub fn run_walkers(walkers: Vec<Box<dyn TreeWalker<TestResource, ()>>>, html: &str) -> Result<String, ConfigurafoxError> {
+    let processor = HTMLProcessor::builder().walkers(walkers).build();
+    let output = process(&processor, &[(TEST_PAGE, html)], TEST_PAGE)?;
+    Ok(String::from_utf8(output).expect("HTMLProcessor outputs UTF-8"))
+}
+
+/// Runs `walker` over `html`, see `run_walkers`
+pub fn run_walker<W: TreeWalker<TestResource, ()> + 'static>(walker: W, html: &str) -> Result<String, ConfigurafoxError> {
+    run_walkers(vec![Box::new(walker)], html)
+}
+
+/// The page after each of `walkers`, applied one at a time in order, as pairs of the walker's description and
+/// `snapshot::snapshot_html` of its output. For seeing, or snapshotting, what each stage of a pipeline does
+pub fn walker_stages(walkers: Vec<Box<dyn TreeWalker<TestResource, ()>>>, html: &str) -> Result<Vec<(String, String)>, ConfigurafoxError> {
+    let mut stages = Vec::new();
+    let mut current = html.to_string();
+    for walker in walkers {
+        let name = walker.describe();
+        current = run_walkers(vec![walker], &current)?;
+        let dom = html_editor::parse(&current).map_err(|e| ConfigurafoxError::parse_html(Path::new(TEST_PAGE), &current, 1, e))?;
+        stages.push((name, snapshot_html(&dom)));
+    }
+    Ok(stages)
+}
+
+/// `html` in a form that only changes if its meaning does: outside preformatted elements, runs of whitespace are
+/// collapsed to one space, and whitespace at the start and end of blocks and between them, which browsers don't
+/// show, is dropped, see `serialize::BLOCK_ELEMENTS`. Attributes are sorted. So `<p>a</p>\n<p>b</p>` is the same
+/// as `<p>a</p><p>b</p>`, but `a <b>b</b>` isn't the same as `a<b>b</b>`. Falls back to `html` itself if it can't
+/// be parsed
+pub fn normalize_html(html: &str) -> String {
+    fn is_block(node: Option<&Node>) -> bool {
+        matches!(node, Some(Node::Element(Element { name, .. })) if BLOCK_ELEMENTS.contains(&name.as_str()))
+    }
+
+    /// `in_block` is whether `dom` is the contents of a block, or the whole document
+    fn normalize(dom: &mut Vec<Node>, in_block: bool, whitespace: &Whitespace) {
+        for i in 0..dom.len() {
+            let trim_start = if i == 0 { in_block } else { is_block(dom.get(i - 1)) };
+            let trim_end = if i + 1 == dom.len() { in_block } else { is_block(dom.get(i + 1)) };
+            match &mut dom[i] {
+                Node::Element(element) => {
+                    element.attrs.sort();
+                    if !whitespace.is_preformatted(element) {
+                        let in_block = BLOCK_ELEMENTS.contains(&element.name.as_str());
+                        normalize(&mut element.children, in_block, whitespace);
+                    }
+                }
+                Node::Text(text) => {
+                    let mut collapsed = String::with_capacity(text.len());
+                    for c in text.chars() {
+                        if !c.is_ascii_whitespace() {
+                            collapsed.push(c);
+                        } else if !(collapsed.ends_with(' ') || trim_start && collapsed.is_empty()) {
+                            collapsed.push(' ');
+                        }
+                    }
+                    if trim_end && collapsed.ends_with(' ') {
+                        collapsed.pop();
+                    }
+                    *text = collapsed;
+                }
+                _ => {}
+            }
+        }
+        dom.retain(|node| !matches!(node, Node::Text(text) if text.is_empty()));
+    }
+
+    let Ok(mut dom) = crate::entities::parse(html) else {
+        return html.to_string();
+    };
+    normalize(&mut dom, true, &Whitespace::default());
+    to_html(&dom)
+}
+
+/// Panics unless `actual` and `expected` are the same after `normalize_html`, showing both normalized
+#[track_caller]
+pub fn assert_html_eq(actual: &str, expected: &str) {
+    let (actual, expected) = (normalize_html(actual), normalize_html(expected));
+    assert!(actual == expected, "HTML differs\n  actual: {actual}\nexpected: {expected}");
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{assert_html_eq, normalize_html, run_walker, run_walkers, walker_stages};
+    use crate::headings::HeadingShift;
+    use crate::tables::TableEnhancer;
+    use crate::treewalker::{LinkReplacer, TreeWalker, VariableReplacer};
+
+    #[test]
+    fn normalize_keeps_meaningful_whitespace() {
+        assert_eq!(normalize_html("<p>\n  a   b\n</p>\n<p>c</p>"), "<p>a b</p><p>c</p>");
+        assert_ne!(normalize_html("a <b>b</b>"), normalize_html("a<b>b</b>"));
+        assert_ne!(normalize_html("<p><b>a</b> <i>b</i></p>"), normalize_html("<p><b>a</b><i>b</i></p>"));
+        assert_eq!(normalize_html("<pre>  a\n  b</pre>"), "<pre>  a\n  b</pre>");
+        assert_eq!(normalize_html("<a title=\"t\" href=\"h\">x</a>"), "<a href=\"h\" title=\"t\">x</a>");
+    }
+
+    #[test]
+    fn html_eq_ignores_formatting() {
+        assert_html_eq("<ul>\n  <li class=\"a\" id=\"b\">One</li>\n  <li>Two</li>\n</ul>", "<ul><li id=\"b\" class=\"a\">One</li><li>Two</li></ul>");
+    }
+
+    #[test]
+    #[should_panic(expected = "HTML differs")]
+    fn html_eq_sees_changed_text() {
+        assert_html_eq("<p>One</p>", "<p>Two</p>");
+    }
+
+    #[test]
+    fn variables() {
+        let variables = VariableReplacer::new(json!({ "name": "World" }));
+        let html = run_walker(variables, "<p>Hello, $name!</p><pre>echo <b>$name</b></pre>").unwrap();
+        assert_html_eq(&html, "<p>Hello, World!</p><pre>echo <b>$name</b></pre>");
+    }
+
+    #[test]
+    fn links() {
+        let html = run_walkers(vec![Box::new(LinkReplacer::new())], "<a href=\"@index\">Home</a>").unwrap();
+        assert_html_eq(&html, "<a href=\"index.html\">Home</a>");
+    }
+
+    #[test]
+    fn stages() {
+        let walkers: Vec<Box<dyn TreeWalker<_, ()>>> = vec![Box::new(HeadingShift::new().with_by(2)), Box::new(TableEnhancer::new())];
+        let stages = walker_stages(walkers, "<shift-headings><h1>Prices</h1></shift-headings><table><tr><th>Item</th></tr></table>").unwrap();
+        assert_eq!(stages.len(), 2);
+        assert_eq!(stages[0].0, "HeadingShift(2)");
+        assert!(stages[0].1.starts_with("<h3>\n  Prices\n</h3>\n<table>\n"), "{}", stages[0].1);
+        assert!(stages[1].1.starts_with("<h3>\n  Prices\n</h3>\n<div class=\"table-wrapper\">\n  <table>\n"), "{}", stages[1].1);
+        assert!(stages[1].1.contains("<th scope=\"col\">"), "{}", stages[1].1);
+    }
+}
@@ -0,0 +1,214 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Span fields the summary groups by: `run_with_backend` opens a `process` span per resource, and `walk` a
+/// `replace` span per replaced element
+const LABEL_FIELDS: [&str; 2] = ["resource", "walker"];
+
+/// The stages of a build, in order, as span names. Registration, and parsing, walking, linting and
+/// serializing each resource, then writing it
+pub const STAGES: [&str; 6] = ["register", "parse", "walk", "lint", "serialize", "write"];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpanStats {
+    pub count: usize,
+    /// Time spent inside the span, excluding time it was exited (e.g. across an await)
+    pub busy: Duration,
+}
+
+/// Per-span timing state, stored in the span's extensions
+struct Timing {
+    label: Option<String>,
+    entered: Option<Instant>,
+    busy: Duration,
+    /// Busy time of the span's children, to get the time spent in the span itself
+    children: Duration,
+}
+
+impl Timing {
+    /// How the span appears in a stack of `TimingLayer::folded_stacks`
+    fn frame(&self, name: &str) -> String {
+        match &self.label {
+            // Semicolons separate frames, and spaces the stack from the count
+            Some(label) => format!("{name}:{}", label.replace([';', ' '], "_")),
+            None => name.to_string(),
+        }
+    }
+}
+
+struct LabelVisitor<'a>(&'a mut Option<String>);
+
+impl Visit for LabelVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if LABEL_FIELDS.contains(&field.name()) {
+            *self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if LABEL_FIELDS.contains(&field.name()) {
+            *self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+type Totals = HashMap<(&'static str, String), SpanStats>;
+
+/// A `tracing_subscriber` layer adding up how long each resource, walker and stage took. Install it with
+/// `tracing_subscriber::registry().with(layer)`, and print `TimingLayer::summary` after the build
+#[derive(Clone, Default)]
+pub struct TimingLayer {
+    totals: Arc<Mutex<Totals>>,
+    /// Time spent in each stack of spans itself, excluding children
+    stacks: Arc<Mutex<HashMap<String, Duration>>>,
+}
+
+impl TimingLayer {
+    pub fn new() -> TimingLayer {
+        TimingLayer::default()
+    }
+
+    /// The times collected so far, slowest first. Clone the layer before installing it to keep a handle
+    pub fn summary(&self) -> TimingSummary {
+        let totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = totals.iter().map(|((span, label), stats)| (*span, label.clone(), *stats)).collect::<Vec<_>>();
+        entries.sort_by_key(|(_, _, stats)| std::cmp::Reverse(stats.busy));
+        TimingSummary { entries }
+    }
+
+    /// The collected times in the folded stack format read by flamegraph tools (`inferno-flamegraph`,
+    /// `flamegraph.pl`, speedscope): one `run;process:page;walk;replace:Walker <microseconds>` line per stack
+    pub fn folded_stacks(&self) -> String {
+        let stacks = self.stacks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut lines = stacks
+            .iter()
+            .filter(|(_, time)| time.as_micros() > 0)
+            .map(|(stack, time)| format!("{stack} {}\n", time.as_micros()))
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for TimingLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut label = None;
+        attrs.record(&mut LabelVisitor(&mut label));
+        span.extensions_mut().insert(Timing { label, entered: None, busy: Duration::ZERO, children: Duration::ZERO });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(timing) = extensions.get_mut::<Timing>() {
+            values.record(&mut LabelVisitor(&mut timing.label));
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(timing) = extensions.get_mut::<Timing>() {
+            timing.entered = Some(Instant::now());
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(timing) = extensions.get_mut::<Timing>() {
+            if let Some(entered) = timing.entered.take() {
+                timing.busy += entered.elapsed();
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<Timing>() else {
+            return;
+        };
+
+        let mut stack = vec![timing.frame(span.name())];
+        for ancestor in span.scope().skip(1) {
+            let frame = ancestor.extensions().get::<Timing>().map(|t| t.frame(ancestor.name()));
+            stack.push(frame.unwrap_or_else(|| ancestor.name().to_string()));
+        }
+        stack.reverse();
+        let mut stacks = self.stacks.lock().unwrap_or_else(|e| e.into_inner());
+        *stacks.entry(stack.join(";")).or_default() += timing.busy.saturating_sub(timing.children);
+        drop(stacks);
+
+        if let Some(parent) = span.parent() {
+            if let Some(parent_timing) = parent.extensions_mut().get_mut::<Timing>() {
+                parent_timing.children += timing.busy;
+            }
+        }
+
+        // Stages are totalled under an empty label
+        let mut totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = totals.entry((span.name(), timing.label.unwrap_or_default())).or_default();
+        stats.count += 1;
+        stats.busy += timing.busy;
+    }
+}
+
+/// Total time per span name and label (resource identifier or walker), slowest first
+#[derive(Debug, Clone)]
+pub struct TimingSummary {
+    pub entries: Vec<(&'static str, String, SpanStats)>,
+}
+
+impl TimingSummary {
+    /// The `n` slowest entries of the span `name`
+    pub fn slowest<'a>(&'a self, name: &'a str, n: usize) -> impl Iterator<Item = &'a (&'static str, String, SpanStats)> {
+        self.entries.iter().filter(move |(span, label, _)| *span == name && !label.is_empty()).take(n)
+    }
+
+    /// The total time of the span `name` across all labels
+    pub fn total(&self, name: &str) -> SpanStats {
+        self.entries.iter().filter(|(span, _, _)| *span == name).fold(SpanStats::default(), |total, (_, _, stats)| SpanStats {
+            count: total.count + stats.count,
+            busy: total.busy + stats.busy,
+        })
+    }
+}
+
+impl fmt::Display for TimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stages:")?;
+        for stage in STAGES {
+            let stats = self.total(stage);
+            writeln!(f, "  {:>10.2?} {:>6}x {stage}", stats.busy, stats.count)?;
+        }
+        for (title, span) in [("Slowest resources:", "process"), ("Slowest walkers:", "replace")] {
+            writeln!(f, "{title}")?;
+            for (_, label, stats) in self.slowest(span, 10) {
+                writeln!(f, "  {:>10.2?} {:>6}x {label}", stats.busy, stats.count)?;
+            }
+        }
+        Ok(())
+    }
+}
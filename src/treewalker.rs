@@ -1,14 +1,23 @@
 #[allow(unused)]
 use tracing::{trace, debug, info, warn, error, instrument, Level};
 
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
 
+#[cfg(feature = "syntax-highlight")]
+use std::collections::BTreeMap;
+#[cfg(feature = "syntax-highlight")]
 use syntect::{parsing::SyntaxSet, highlighting::ThemeSet, html::highlighted_html_for_string};
 
 use html_editor::{Node, Element};
 
-use crate::{ConfigurafoxError, resource_manager::{Resource, ResourceManager}};
+use crate::{ConfigurafoxError, graph::DependencyKind, metadata::{Metadata, Value}, resource_manager::{Resource, ResourceManager}};
+use crate::diagnostics::{Diagnostic, Handling, Severity, Strictness};
+use crate::error_pages::is_error_page;
+use crate::position::{SourcePos, locate, strip_positions, take_position};
+use crate::site::{SiteData, absolute_url};
 
 pub fn get_attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
     attrs
@@ -16,11 +25,208 @@ pub fn get_attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str>
         .find_map(|(k, v)| if k == key { Some(&**v) } else { None} )
 }
 
+/// An element named `name`, for walkers that generate markup
+pub(crate) fn element(name: &str, attrs: Vec<(&str, String)>, children: Vec<Node>) -> Node {
+    let attrs = attrs.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+    Node::Element(Element { name: name.to_string(), attrs, children })
+}
+
+/// The text in `dom`, without the markup
+pub fn text_content(dom: &[Node]) -> String {
+    let mut text = String::new();
+    for node in dom {
+        match node {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(element) => text.push_str(&text_content(&element.children)),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// The identifier an element like `<video src="@clips/intro">` or `<video @intro>` refers to, and the attribute
+/// it's given in: a bare attribute named `@identifier`, which can't contain a `/`, or a `src` starting with `@`
+pub fn at_reference(attrs: &[(String, String)]) -> Option<(String, String)> {
+    attrs.iter().find_map(|(key, value)| match (key.strip_prefix('@'), value.strip_prefix('@')) {
+        (Some(identifier), _) => Some((identifier.to_string(), key.clone())),
+        (None, Some(identifier)) if key == "src" => Some((identifier.to_string(), key.clone())),
+        _ => None,
+    })
+}
+
+/// All attribute values in `dom` starting with `prefix`, with the prefix removed. Composite values, like
+/// `srcset`, are split into their parts first, see `value_parts`
+pub fn attr_references(dom: &[Node], prefix: char) -> Vec<String> {
+    let mut refs = Vec::new();
+    for node in dom {
+        if let Node::Element(Element { attrs, children, .. }) = node {
+            for (key, value) in attrs {
+                let parts = value_parts(key, value).into_iter().map(|(start, end)| &value[start..end]);
+                refs.extend(parts.filter_map(|part| part.strip_prefix(prefix)).map(str::to_string));
+            }
+            refs.extend(attr_references(children, prefix));
+        }
+    }
+    refs
+}
+
+/// The byte ranges of the parts of the value of attribute `key` that are rewritten separately: the URLs of a
+/// `srcset` or `imagesrcset`, the `url(...)` arguments of a `style` that has any, and otherwise the whole value
+pub fn value_parts(key: &str, value: &str) -> Vec<(usize, usize)> {
+    let mut parts = Vec::new();
+    match key {
+        "srcset" | "imagesrcset" => {
+            let mut offset = 0;
+            for candidate in value.split(',') {
+                let trimmed = candidate.trim_start();
+                let start = offset + candidate.len() - trimmed.len();
+                let len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+                if len > 0 {
+                    parts.push((start, start + len));
+                }
+                offset += candidate.len() + 1;
+            }
+        }
+        "style" => {
+            let mut offset = 0;
+            while let Some(found) = value[offset..].find("url(") {
+                let argument = &value[offset + found + "url(".len()..];
+                let trimmed = argument.trim_start();
+                let mut start = value.len() - trimmed.len();
+                let end = match trimmed.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        start += 1;
+                        let Some(len) = value[start..].find(quote) else { break };
+                        start + len
+                    }
+                    _ => {
+                        let Some(len) = value[start..].find(')') else { break };
+                        start + value[start..start + len].trim_end().len()
+                    }
+                };
+                parts.push((start, end));
+                offset = end;
+            }
+            // A style without URLs may be a reference or variable itself, like `style="$css"`
+            if parts.is_empty() {
+                parts.push((0, value.len()));
+            }
+        }
+        _ => parts.push((0, value.len())),
+    }
+    parts
+}
+
+/// Which attributes a walker rewriting attribute values applies to. Composite values are rewritten part by
+/// part, see `value_parts`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeRules {
+    /// Names of the attributes rewritten, None for all of them
+    pub only: Option<BTreeSet<String>>,
+}
+
+impl AttributeRules {
+    /// Every attribute
+    pub fn all() -> AttributeRules {
+        AttributeRules { only: None }
+    }
+
+    pub fn only<I: IntoIterator<Item = S>, S: Into<String>>(attributes: I) -> AttributeRules {
+        AttributeRules { only: Some(attributes.into_iter().map(Into::into).collect()) }
+    }
+
+    /// `LINK_ATTRIBUTES`
+    pub fn links() -> AttributeRules {
+        AttributeRules::only(LINK_ATTRIBUTES.iter().copied())
+    }
+
+    pub fn applies_to(&self, key: &str) -> bool {
+        self.only.as_ref().is_none_or(|only| only.contains(key))
+    }
+
+    /// Whether a part of an attribute value in `attrs` these rules apply to satisfies `predicate`
+    pub fn any_part(&self, attrs: &[(String, String)], predicate: impl Fn(&str) -> bool) -> bool {
+        attrs
+            .iter()
+            .filter(|(k, _)| self.applies_to(k))
+            .any(|(k, v)| value_parts(k, v).into_iter().any(|(start, end)| predicate(&v[start..end])))
+    }
+
+    /// `value` with each part replaced by what `rewrite` returns for it, if the rules apply to `key`
+    pub fn rewrite<E>(&self, key: &str, value: String, mut rewrite: impl FnMut(&str) -> Result<String, E>) -> Result<String, E> {
+        if !self.applies_to(key) {
+            return Ok(value);
+        }
+        let mut out = String::with_capacity(value.len());
+        let mut last = 0;
+        for (start, end) in value_parts(key, &value) {
+            out.push_str(&value[last..start]);
+            out.push_str(&rewrite(&value[start..end])?);
+            last = end;
+        }
+        out.push_str(&value[last..]);
+        Ok(out)
+    }
+}
+
+/// The attributes holding URLs, for `AttributeRules::links`
+pub const LINK_ATTRIBUTES: &[&str] = &[
+    "action", "cite", "data", "formaction", "href", "imagesrcset", "poster", "src", "srcset", "style",
+];
+
+/// Values shared by the walkers processing one document, at most one of each type. Lets walkers cooperate, e.g.
+/// one collecting headings for another rendering a table of contents. Starts out empty for each document
+#[derive(Default)]
+pub struct Extensions(Mutex<HashMap<TypeId, Box<dyn Any + Send>>>);
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Stores `value`, returning the previous value of its type
+    pub fn insert<T: Any + Send>(&self, value: T) -> Option<T> {
+        let mut values = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        values.insert(TypeId::of::<T>(), Box::new(value)).map(|old| *old.downcast().expect("stored by type"))
+    }
+
+    pub fn remove<T: Any + Send>(&self) -> Option<T> {
+        let mut values = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        values.remove(&TypeId::of::<T>()).map(|old| *old.downcast().expect("stored by type"))
+    }
+
+    pub fn contains<T: Any + Send>(&self) -> bool {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&TypeId::of::<T>())
+    }
+
+    /// A copy of the stored value of type `T`
+    pub fn get<T: Any + Send + Clone>(&self) -> Option<T> {
+        let values = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        values.get(&TypeId::of::<T>()).map(|value| value.downcast_ref::<T>().expect("stored by type").clone())
+    }
+
+    /// Calls `f` with the stored value of type `T`, storing `T::default()` first if there is none. `f` must not
+    /// use these extensions itself, as they are locked while it runs
+    pub fn with<T: Any + Send + Default, O>(&self, f: impl FnOnce(&mut T) -> O) -> O {
+        let mut values = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let value = values.entry(TypeId::of::<T>()).or_insert_with(|| Box::new(T::default()));
+        f(value.downcast_mut().expect("stored by type"))
+    }
+}
+
+/// What walkers know about the document being processed, made with `Context::new`
+#[non_exhaustive]
 pub struct Context<'res, 'data, R: Resource, D> {
     pub resource: &'res R,
     pub source_path: &'res Path,
     pub resources: &'res ResourceManager<R>,
     pub data: &'data D,
+    /// Where the element currently being matched or replaced starts in `source_path`, if known
+    pub pos: Option<SourcePos>,
+    /// Overrides the build's strictness, see `HTMLProcessor::strictness`
+    pub strictness: Option<Strictness>,
+    /// Shared by the walkers processing this document
+    pub extensions: &'res Extensions,
 }
 
 impl<'res, 'data, R: Resource, D> Clone for Context<'res, 'data, R, D> {
@@ -30,12 +236,84 @@ impl<'res, 'data, R: Resource, D> Clone for Context<'res, 'data, R, D> {
             source_path: self.source_path,
             resources: self.resources,
             data: self.data,
+            pos: self.pos,
+            strictness: self.strictness,
+            extensions: self.extensions,
         }
     }
 }
 
 impl<'res, 'data, R: Resource, D> Copy for Context<'res, 'data, R, D> {}
 
+impl<'res, 'data, R: Resource, D> Context<'res, 'data, R, D> {
+    /// A context for processing `resource`, read from `source_path`, at no particular position and with the
+    /// build's strictness
+    pub fn new(
+        resource: &'res R,
+        source_path: &'res Path,
+        resources: &'res ResourceManager<R>,
+        data: &'data D,
+        extensions: &'res Extensions,
+    ) -> Self {
+        Context { resource, source_path, resources, data, pos: None, strictness: None, extensions }
+    }
+
+    /// The metadata of the resource currently being processed
+    pub fn metadata(&self) -> &'res Metadata {
+        self.resources.metadata(self.resource)
+    }
+
+    /// The processor's `Strictness` if it has one, otherwise the build's, see `Diagnostics::strictness`
+    pub fn strictness(&self) -> Strictness {
+        self.strictness.unwrap_or_else(|| self.resources.diagnostics().strictness)
+    }
+
+    /// The URL of `resource` relative to where the current page is written, recording the link as a dependency
+    pub fn link_to(&self, resource: &R) -> Result<String, ConfigurafoxError> {
+        self.resources.record_dependency(self.resource, resource, DependencyKind::Link);
+
+        crate::relative_url(&self.resources.output_path(resource), &self.resources.output_path(self.resource))
+    }
+
+    /// This context with `()` as data
+    pub fn without_data(&self) -> Context<'res, 'static, R, ()> {
+        Context {
+            resource: self.resource,
+            source_path: self.source_path,
+            resources: self.resources,
+            data: &(),
+            pos: self.pos,
+            strictness: self.strictness,
+            extensions: self.extensions,
+        }
+    }
+
+    /// Handles a problem as configured by `handling`: fails with `error`, or records it as a warning, or
+    /// ignores it. The caller keeps the offending markup unless this fails
+    pub fn tolerate(&self, handling: Handling, error: ConfigurafoxError) -> Result<(), ConfigurafoxError> {
+        match handling {
+            Handling::Error => Err(error),
+            Handling::Warn => {
+                let diagnostic = Diagnostic {
+                    severity: Severity::Warning,
+                    code: Some(error.code().to_string()),
+                    path: Some(self.source_path.to_owned()),
+                    pos: self.pos,
+                    message: error.to_string(),
+                };
+                self.resources.diagnostics().push(diagnostic);
+                Ok(())
+            }
+            Handling::PassThrough => Ok(()),
+        }
+    }
+
+    /// Records a warning about the current element, without failing the build
+    pub fn warn<S: Into<String>>(&self, message: S) {
+        self.resources.diagnostics().warn(Some(self.source_path), self.pos, message);
+    }
+}
+
 
 pub trait TreeWalker<R: Resource, D> {
     fn describe(&self) -> String;
@@ -43,76 +321,597 @@ pub trait TreeWalker<R: Resource, D> {
     fn matches(&self, tag_name: &str, attrs: &[(String, String)], ctx: Context<'_, '_, R, D>) -> bool;
 
     fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError>;
+
+    /// The only tag names this walker matches, if there are such. `walk` then looks the walker up by tag name
+    /// instead of calling `matches` for every element, which still decides among elements with these names.
+    /// None, the default, for walkers that can match any tag
+    fn tags(&self) -> Option<Vec<&str>> {
+        None
+    }
+
+    /// Whether to replace a text node, for walkers working on text rather than elements. `ancestors` are the tag
+    /// names of the elements containing it, outermost first, so the last one is its parent. Empty at the top level
+    fn matches_text(&self, _text: &str, _ancestors: &[&str], _ctx: Context<'_, '_, R, D>) -> bool {
+        false
+    }
+
+    /// Replaces a text node `matches_text` holds for
+    fn replace_text(&self, text: &str, _ancestors: &[&str], _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok(vec![Node::Text(text.to_string())])
+    }
+
+    /// Called with the whole document once `walk` has replaced everything in it, for walkers that need to see
+    /// it in document order, e.g. to number what they replaced wherever it ended up. Walkers finish in the order
+    /// they were given
+    fn finish(&self, _dom: &mut Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        Ok(())
+    }
+}
+
+/// The walkers to try for each tag name, in their original order, see `TreeWalker::tags`
+struct Dispatch<'w, R: Resource, D> {
+    /// Tried for text nodes
+    all: Vec<&'w dyn TreeWalker<R, D>>,
+    by_tag: HashMap<&'w str, Vec<&'w dyn TreeWalker<R, D>>>,
+    /// Tried for every element
+    any_tag: Vec<&'w dyn TreeWalker<R, D>>,
 }
 
+impl<'w, R: Resource, D> Dispatch<'w, R, D> {
+    fn new(walkers: &'w [Box<dyn TreeWalker<R, D>>]) -> Dispatch<'w, R, D> {
+        let tags = walkers.iter().map(|walker| walker.tags()).collect::<Vec<_>>();
+
+        let mut by_tag = HashMap::new();
+        for tag in tags.iter().flatten().flatten() {
+            let candidates = walkers
+                .iter()
+                .zip(&tags)
+                .filter(|(_, tags)| tags.as_ref().is_none_or(|tags| tags.contains(tag)))
+                .map(|(walker, _)| &**walker)
+                .collect();
+            by_tag.insert(*tag, candidates);
+        }
+        let any_tag = walkers.iter().zip(&tags).filter(|(_, tags)| tags.is_none()).map(|(walker, _)| &**walker).collect();
+        Dispatch { all: walkers.iter().map(|walker| &**walker).collect(), by_tag, any_tag }
+    }
+
+    fn candidates(&self, tag_name: &str) -> &[&'w dyn TreeWalker<R, D>] {
+        self.by_tag.get(tag_name).unwrap_or(&self.any_tag)
+    }
+}
+
+/// Applies the first matching walker to each element and text node, top-down, then lets each walker `finish`
+/// the result. Walker errors are located at the element being replaced (or its closest annotated ancestor), see
+/// `position::annotate_positions`
 pub fn walk<'res, 'data, R: Resource, D>(dom: &mut Vec<Node>, replacers: &[Box<dyn TreeWalker<R, D>>], ctx: Context<'res, 'data, R, D>) -> Result<(), ConfigurafoxError> {
+    walk_at(dom, &Dispatch::new(replacers), ctx, None, &[])?;
+
+    for replacer in replacers {
+        if let Err(e) = tracing::debug_span!("finish").in_scope(|| replacer.finish(dom, ctx)) {
+            recover(e, &**replacer, "finishing", ctx, |_| vec![])?;
+        }
+    }
+    Ok(())
+}
+
+/// What the error of `walker` replacing `what` becomes: in lenient mode a diagnostic, with `fallback` put in the
+/// replaced node's place, otherwise the error, located
+fn recover<R: Resource, D>(
+    error: ConfigurafoxError,
+    walker: &dyn TreeWalker<R, D>,
+    what: &str,
+    ctx: Context<'_, '_, R, D>,
+    fallback: impl FnOnce(&ConfigurafoxError) -> Vec<Node>,
+) -> Result<Vec<Node>, ConfigurafoxError> {
+    if ctx.resources.diagnostics().lenient {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: Some(error.code().to_string()),
+            path: Some(ctx.source_path.to_owned()),
+            pos: ctx.pos,
+            message: format!("{what}: {error}"),
+        };
+        ctx.resources.diagnostics().push(diagnostic);
+        Ok(fallback(&error))
+    } else {
+        let error = ConfigurafoxError::InWalker { walker: walker.describe(), error: Box::new(error) };
+        Err(locate(error, ctx.source_path, ctx.pos))
+    }
+}
+
+/// `outer` is the position of the closest annotated ancestor, `ancestors` the tag names of the elements containing
+/// `dom`, outermost first
+fn walk_at<'res, 'data, R: Resource, D>(
+    dom: &mut Vec<Node>,
+    replacers: &Dispatch<'_, R, D>,
+    ctx: Context<'res, 'data, R, D>,
+    outer: Option<SourcePos>,
+    ancestors: &[&str],
+) -> Result<(), ConfigurafoxError> {
     let original_dom = std::mem::replace(dom, Vec::with_capacity(dom.len()));
+    // The position of each node in `dom`
+    let mut positions = Vec::with_capacity(original_dom.len());
 
     'outer: for el in original_dom {
-        let Node::Element(Element { name, attrs, children }) = el else {
+        let Node::Element(Element { name, mut attrs, mut children }) = el else {
+            if let Node::Text(text) = &el {
+                let ctx = Context { pos: outer, ..ctx };
+                if let Some(replacer) = replacers.all.iter().find(|replacer| replacer.matches_text(text, ancestors, ctx)) {
+                    let replaced = tracing::debug_span!("replace-text").in_scope(|| replacer.replace_text(text, ancestors, ctx));
+                    let res = match replaced {
+                        Ok(res) => res,
+                        Err(e) => recover(e, *replacer, "text", ctx, |_| vec![el.clone()])?,
+                    };
+                    positions.extend(std::iter::repeat_n(outer, res.len()));
+                    dom.extend(res);
+                    continue;
+                }
+            }
             dom.push(el);
+            positions.push(outer);
             continue;
         };
+        let pos = take_position(&mut attrs).or(outer);
+        let ctx = Context { pos, ..ctx };
 
-        for replacer in replacers {
+        for replacer in replacers.candidates(&name) {
             if replacer.matches(&name, &attrs, ctx) {
-                let res = replacer.replace(&name, attrs, children, ctx)?;
+                strip_positions(&mut children);
+
+                let span = tracing::debug_span!("replace", walker = tracing::field::Empty, tag = %name);
+                if !span.is_disabled() {
+                    // Some walkers describe themselves at length, only do it when someone is listening
+                    span.record("walker", replacer.describe());
+                }
+                let replaced = span.in_scope(|| replacer.replace(&name, attrs, children, ctx));
+
+                let res = match replaced {
+                    Ok(res) => res,
+                    Err(e) => recover(e, *replacer, &format!("<{name}>"), ctx, |e| error_placeholder(&name, e))?,
+                };
+                positions.extend(std::iter::repeat_n(pos, res.len()));
                 dom.extend(res);
                 continue 'outer;
             }
         }
 
         dom.push(Node::Element(Element { name, attrs, children }));
+        positions.push(pos);
     }
 
-    for el in dom {
-        if let Node::Element(Element { children, .. }) = el {
-            walk(children, replacers, ctx)?;
+    for (el, pos) in dom.iter_mut().zip(positions) {
+        if let Node::Element(Element { name, children, .. }) = el {
+            let ancestors = ancestors.iter().copied().chain([name.as_str()]).collect::<Vec<_>>();
+            walk_at(children, replacers, ctx, pos, &ancestors)?;
         }
     }
 
     Ok(())
 }
 
-pub struct VariableReplacer(pub HashMap<String, String>);
+/// What a failed element is replaced with in lenient mode: a comment with the error, and a visible marker
+/// (class `configurafox-error`) carrying the error in its `title` and `data-configurafox-error`
+pub fn error_placeholder(tag_name: &str, error: &ConfigurafoxError) -> Vec<Node> {
+    let message = error.to_string();
+    vec![
+        Node::Comment(format!(" configurafox: <{tag_name}>: {} ", message.replace("--", "- -"))),
+        Node::Element(Element {
+            name: "span".to_string(),
+            attrs: vec![
+                ("class".to_string(), "configurafox-error".to_string()),
+                ("title".to_string(), message.clone()),
+                ("data-configurafox-error".to_string(), message),
+            ],
+            children: vec![Node::Text(format!("[error in <{tag_name}>]"))],
+        }),
+    ]
+}
+
+/// A walker made of a match closure and a replace closure, for transformations too small to deserve their own
+/// type. See `walker` for the common case of replacing one tag
+pub struct FnWalker<M, F> {
+    name: String,
+    matches: M,
+    replace: F,
+    tags: Option<Vec<String>>,
+}
+
+impl<M, F> FnWalker<M, F> {
+    /// `name` is what the walker describes itself as
+    pub fn new(name: &str, matches: M, replace: F) -> FnWalker<M, F> {
+        FnWalker { name: name.to_string(), matches, replace, tags: None }
+    }
+
+    /// Declares that `matches` only ever holds for these tag names, see `TreeWalker::tags`
+    pub fn with_tags(mut self, tags: &[&str]) -> FnWalker<M, F> {
+        self.tags = Some(tags.iter().map(|tag| tag.to_string()).collect());
+        self
+    }
+}
+
+impl<R: Resource, D, M, F> TreeWalker<R, D> for FnWalker<M, F>
+where
+    M: Fn(&str, &[(String, String)], Context<'_, '_, R, D>) -> bool,
+    F: Fn(&str, Vec<(String, String)>, Vec<Node>, Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError>,
+{
+    fn describe(&self) -> String {
+        format!("FnWalker({})", self.name)
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], ctx: Context<'_, '_, R, D>) -> bool {
+        (self.matches)(tag_name, attrs, ctx)
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        (self.replace)(tag_name, attrs, children, ctx)
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        self.tags.as_ref().map(|tags| tags.iter().map(String::as_str).collect())
+    }
+}
+
+/// A walker replacing every `<tag>` with what `replace` returns for its attributes and children, e.g.
+/// `walker("shout", |_attrs, children, _ctx| Ok(vec![Node::Element(Element { name: "strong".to_string(), attrs: vec![], children })]))`
+pub fn walker<R: Resource, D, F>(
+    tag: &str,
+    replace: F,
+) -> impl TreeWalker<R, D>
+where
+    F: Fn(Vec<(String, String)>, Vec<Node>, Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError>,
+{
+    let owned = tag.to_string();
+    FnWalker::new(
+        tag,
+        move |tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>| tag_name == owned,
+        move |_tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>| replace(attrs, children, ctx),
+    )
+    .with_tags(&[tag])
+}
+
+/// Combinators for composing walkers, available on every walker
+pub trait TreeWalkerExt<R: Resource, D>: TreeWalker<R, D> + Sized {
+    /// Only matches where `predicate` holds, e.g. `|ctx| ctx.source_path.starts_with("posts")`
+    fn when<P: Fn(Context<'_, '_, R, D>) -> bool>(self, predicate: P) -> When<Self, P> {
+        When { walker: self, predicate }
+    }
+
+    /// Tries this walker, then `other`, as one walker
+    fn chain<W: TreeWalker<R, D> + 'static>(self, other: W) -> Chain<R, D>
+    where
+        Self: 'static,
+    {
+        Chain(vec![Box::new(self), Box::new(other)])
+    }
+
+    /// Passes the nodes this walker replaces an element with through `f`
+    fn map_output<F: Fn(Vec<Node>, Context<'_, '_, R, D>) -> Vec<Node>>(self, f: F) -> MapOutput<Self, F> {
+        MapOutput { walker: self, f }
+    }
+}
+
+impl<R: Resource, D, W: TreeWalker<R, D>> TreeWalkerExt<R, D> for W {}
+
+/// See `TreeWalkerExt::when`
+pub struct When<W, P> {
+    walker: W,
+    predicate: P,
+}
+
+impl<R: Resource, D, W: TreeWalker<R, D>, P: Fn(Context<'_, '_, R, D>) -> bool> TreeWalker<R, D> for When<W, P> {
+    fn describe(&self) -> String {
+        format!("When({})", self.walker.describe())
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], ctx: Context<'_, '_, R, D>) -> bool {
+        (self.predicate)(ctx) && self.walker.matches(tag_name, attrs, ctx)
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        self.walker.replace(tag_name, attrs, children, ctx)
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        self.walker.tags()
+    }
+
+    fn matches_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> bool {
+        (self.predicate)(ctx) && self.walker.matches_text(text, ancestors, ctx)
+    }
+
+    fn replace_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        self.walker.replace_text(text, ancestors, ctx)
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        if (self.predicate)(ctx) { self.walker.finish(dom, ctx) } else { Ok(()) }
+    }
+}
+
+/// Walkers tried in order as one walker: the first of them matching an element replaces it. See also
+/// `TreeWalkerExt::chain`
+pub struct Chain<R: Resource, D>(pub Vec<Box<dyn TreeWalker<R, D>>>);
+
+impl<R: Resource, D> Chain<R, D> {
+    /// Adds `walker` to the end of the chain
+    pub fn chain<W: TreeWalker<R, D> + 'static>(mut self, walker: W) -> Chain<R, D> {
+        self.0.push(Box::new(walker));
+        self
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for Chain<R, D> {
+    fn describe(&self) -> String {
+        let walkers = self.0.iter().map(|walker| walker.describe()).collect::<Vec<_>>().join(", ");
+        format!("Chain({walkers})")
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], ctx: Context<'_, '_, R, D>) -> bool {
+        self.0.iter().any(|walker| walker.matches(tag_name, attrs, ctx))
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        match self.0.iter().find(|walker| walker.matches(tag_name, &attrs, ctx)) {
+            Some(walker) => walker.replace(tag_name, attrs, children, ctx),
+            // Only replaced if one matched
+            None => Ok(vec![Node::Element(Element { name: tag_name.to_string(), attrs, children })]),
+        }
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        let mut tags = Vec::new();
+        for walker in &self.0 {
+            tags.extend(walker.tags()?);
+        }
+        Some(tags)
+    }
+
+    fn matches_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> bool {
+        self.0.iter().any(|walker| walker.matches_text(text, ancestors, ctx))
+    }
+
+    fn replace_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        match self.0.iter().find(|walker| walker.matches_text(text, ancestors, ctx)) {
+            Some(walker) => walker.replace_text(text, ancestors, ctx),
+            None => Ok(vec![Node::Text(text.to_string())]),
+        }
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        self.0.iter().try_for_each(|walker| walker.finish(dom, ctx))
+    }
+}
+
+/// See `TreeWalkerExt::map_output`
+pub struct MapOutput<W, F> {
+    walker: W,
+    f: F,
+}
+
+impl<R: Resource, D, W: TreeWalker<R, D>, F: Fn(Vec<Node>, Context<'_, '_, R, D>) -> Vec<Node>> TreeWalker<R, D> for MapOutput<W, F> {
+    fn describe(&self) -> String {
+        format!("MapOutput({})", self.walker.describe())
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], ctx: Context<'_, '_, R, D>) -> bool {
+        self.walker.matches(tag_name, attrs, ctx)
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok((self.f)(self.walker.replace(tag_name, attrs, children, ctx)?, ctx))
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        self.walker.tags()
+    }
+
+    fn matches_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> bool {
+        self.walker.matches_text(text, ancestors, ctx)
+    }
+
+    fn replace_text(&self, text: &str, ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok((self.f)(self.walker.replace_text(text, ancestors, ctx)?, ctx))
+    }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        self.walker.finish(dom, ctx)
+    }
+}
+
+/// The namespace of `VariableReplacer` holding the metadata of the page being processed, as in `$page.title`
+pub const PAGE_NAMESPACE: &str = "page";
+
+/// Elements whose text `VariableReplacer` doesn't interpolate variables into, however deep inside them, as `$` is
+/// common in code
+pub const UNINTERPOLATED_ELEMENTS: &[&str] = &["script", "style", "pre", "code", "textarea"];
+
+/// Replaces `<$name />` elements, attribute values `$name`, and `$name` or `${name}` in text, with the variable
+/// `name`, see `VariableReplacer::interpolate`. Names are paths into the variables, with `.` between keys or
+/// array indices, like `$site.title` or `$authors.0.email`. Variables in `PAGE_NAMESPACE` are read from the
+/// page's metadata, so site configuration, front matter and data loaded into the variables are all addressed
+/// the same way
+pub struct VariableReplacer {
+    pub variables: Value,
+    /// The attributes variables are replaced in
+    pub attributes: AttributeRules,
+}
+
+impl VariableReplacer {
+    /// `variables` should be an object. Replaces variables in all attributes
+    pub fn new(variables: Value) -> VariableReplacer {
+        VariableReplacer { variables, attributes: AttributeRules::all() }
+    }
+
+    pub fn with_attributes(mut self, attributes: AttributeRules) -> VariableReplacer {
+        self.attributes = attributes;
+        self
+    }
+
+    /// The variable `name` on the page with metadata `page`
+    pub fn lookup<'v>(&'v self, name: &str, page: &'v Metadata) -> Option<&'v Value> {
+        let mut keys = name.split('.');
+        let first = keys.next()?;
+        let mut value = if first == PAGE_NAMESPACE { page.get(keys.next()?)? } else { self.variables.get(first)? };
+        for key in keys {
+            value = match value {
+                Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+                other => other.get(key)?,
+            };
+        }
+        Some(value)
+    }
+
+    /// `text` with `${name}` and `$name` replaced by the variable `name`, and `$$` by `$`. Names without braces
+    /// end at the first character that can't be in a name, and are left as they are if there's no such variable,
+    /// as `$` is common in text. Unknown names in braces are handled as `Strictness::unknown_variables` says
+    pub fn interpolate<R: Resource, D>(&self, text: &str, ctx: Context<'_, '_, R, D>) -> Result<String, ConfigurafoxError> {
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('$') {
+            out.push_str(&rest[..start]);
+            let reference = &rest[start..];
+            rest = &reference[1..];
+
+            if let Some(after) = rest.strip_prefix('$') {
+                out.push('$');
+                rest = after;
+                continue;
+            }
+
+            let (name, braced, len) = match rest.strip_prefix('{').and_then(|after| after.find('}').map(|end| &after[..end])) {
+                Some(name) => (name.trim(), true, name.len() + 2),
+                None if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                    let mut len = 0;
+                    loop {
+                        len += rest[len..].find(|c: char| !is_name_char(c)).unwrap_or(rest.len() - len);
+                        // Dots continue the name only when followed by more of it, so a sentence can end with one
+                        if rest[len..].starts_with('.') && rest[len + 1..].starts_with(is_name_char) {
+                            len += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    (&rest[..len], false, len)
+                }
+                None => {
+                    out.push('$');
+                    continue;
+                }
+            };
+            let original = &reference[..len + 1];
+            rest = &rest[len..];
+
+            match self.lookup(name, ctx.metadata()).map(variable_text) {
+                Some(Some(value)) => out.push_str(&value),
+                Some(None) => {
+                    ctx.tolerate(ctx.strictness().unknown_variables, ConfigurafoxError::NonScalarVariable { name: name.to_string() })?;
+                    out.push_str(original);
+                }
+                None => {
+                    if braced {
+                        ctx.tolerate(ctx.strictness().unknown_variables, ConfigurafoxError::UnknownVariable { name: name.to_string() })?;
+                    }
+                    out.push_str(original);
+                }
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+impl From<HashMap<String, String>> for VariableReplacer {
+    fn from(variables: HashMap<String, String>) -> Self {
+        VariableReplacer::new(Value::Object(variables.into_iter().map(|(k, v)| (k, Value::String(v))).collect()))
+    }
+}
+
+/// How a variable is written into the page: strings as they are, numbers and booleans as in JSON and null as
+/// nothing. None for arrays and objects
+pub fn variable_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        Value::Null => Some(String::new()),
+        Value::Array(_) | Value::Object(_) => None,
+    }
+}
 
 impl<R: Resource, D> TreeWalker<R, D> for VariableReplacer {
     fn describe(&self) -> String {
-        let variables = self.0.iter().map(|(k, v)| format!("{k:?} = {v:?}")).collect::<Vec<_>>().join(", ");
+        let variables = match &self.variables {
+            Value::Object(variables) => variables.iter().map(|(k, v)| format!("{k:?} = {v}")).collect::<Vec<_>>().join(", "),
+            other => other.to_string(),
+        };
 
         format!("VariableReplacer({})", variables)
     }
 
     fn matches(&self, tag_name: &str, attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
-        tag_name.starts_with('$') || attrs.iter().map(|(_k, v)| v).any(|v| v.starts_with("$"))
+        tag_name.starts_with('$') || self.attributes.any_part(attrs, |v| v.starts_with('$'))
     }
 
-    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
-        let replace_var = |x: String| -> Result<String, ConfigurafoxError> {
-            if !x.starts_with('$') {
-                return Ok(x);
-            }
-            let Some(var) = self.0.get(&x[1..]) else {
-                return Err(ConfigurafoxError::Other(format!("Unknown variable {x}")));
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let replace_var = |x: &str| -> Result<String, ConfigurafoxError> {
+            let Some(name) = x.strip_prefix('$') else {
+                return Ok(x.to_string());
+            };
+            let Some(value) = self.lookup(name, ctx.metadata()) else {
+                ctx.tolerate(ctx.strictness().unknown_variables, ConfigurafoxError::UnknownVariable { name: name.to_string() })?;
+                return Ok(x.to_string());
+            };
+            let Some(text) = variable_text(value) else {
+                ctx.tolerate(ctx.strictness().unknown_variables, ConfigurafoxError::NonScalarVariable { name: name.to_string() })?;
+                return Ok(x.to_string());
             };
-            Ok(var.clone())
+            Ok(text)
         };
 
         if tag_name.starts_with("$") {
-            Ok(vec![Node::Text(replace_var(tag_name.to_owned())?)])
+            Ok(vec![Node::Text(replace_var(tag_name)?)])
         } else {
             let new_attrs = attrs
                 .into_iter()
-                .map(|(k, v)| Ok((k, replace_var(v)?)))
+                .map(|(k, v)| {
+                    let v = self.attributes.rewrite(&k, v, replace_var)?;
+                    Ok((k, v))
+                })
                 .collect::<Result<Vec<_>, ConfigurafoxError>>()?;
 
             let new_elem = Node::Element(Element { name: tag_name.to_owned(), attrs: new_attrs, children });
             Ok(vec![new_elem])
         }
     }
+
+    fn matches_text(&self, text: &str, ancestors: &[&str], _ctx: Context<'_, '_, R, D>) -> bool {
+        text.contains('$') && !ancestors.iter().any(|ancestor| UNINTERPOLATED_ELEMENTS.contains(ancestor))
+    }
+
+    fn replace_text(&self, text: &str, _ancestors: &[&str], ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        Ok(vec![Node::Text(self.interpolate(text, ctx)?)])
+    }
 }
 
-pub struct LinkReplacer;
+/// Replaces attribute values `@identifier` with a relative link to the resource `identifier`
+#[derive(Debug, Clone, Default)]
+pub struct LinkReplacer {
+    /// The attributes links are replaced in
+    pub attributes: AttributeRules,
+}
+
+impl LinkReplacer {
+    /// Replaces links in all attributes
+    pub fn new() -> LinkReplacer {
+        LinkReplacer::default()
+    }
+
+    pub fn with_attributes(mut self, attributes: AttributeRules) -> LinkReplacer {
+        self.attributes = attributes;
+        self
+    }
+}
 
 impl<R: Resource, D> TreeWalker<R, D> for LinkReplacer {
     fn describe(&self) -> String {
@@ -120,40 +919,28 @@ impl<R: Resource, D> TreeWalker<R, D> for LinkReplacer {
     }
 
     fn matches(&self, _tag_name: &str, attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
-        attrs.iter().map(|(_k, v)| v).any(|v| v.starts_with("@"))
+        self.attributes.any_part(attrs, |v| v.starts_with('@'))
     }
 
     fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
-        let source_dir = ctx.source_path.parent();
-
-        let replace_link = |x: String| -> Result<String, ConfigurafoxError> {
-            if !x.starts_with('@') {
-                return Ok(x);
-            }
-            let identifier = &x[1..];
-
-            for (resource, _) in &ctx.resources.all_registered_files() {
-                let path = resource.output_path();
-                if resource.identifier() == identifier {
-                    let diff = if let Some(source_dir) = source_dir {
-                        pathdiff::diff_paths(&path, source_dir)
-                            .expect(&format!("Resource referenced ({}) could not be relativized from {}", path.display(), ctx.source_path.display()))
-                    } else {
-                        path.clone()
-                    };
-
-                    debug!("{} - {} = {}", path.display(), ctx.source_path.display(), diff.display());
-
-                    return Ok(diff.to_str().expect("Invalid UTF-8 in path").to_owned());
-                }
-            }
+        let replace_link = |x: &str| -> Result<String, ConfigurafoxError> {
+            let Some(identifier) = x.strip_prefix('@') else {
+                return Ok(x.to_string());
+            };
 
-            Err(ConfigurafoxError::Other(format!("Unknown identifier: {x}")))
+            let Some(resource) = ctx.resources.resource_by_identifier(identifier) else {
+                ctx.tolerate(ctx.strictness().unresolved_links, ConfigurafoxError::UnknownIdentifier { identifier: identifier.to_string() })?;
+                return Ok(x.to_string());
+            };
+            ctx.link_to(resource)
         };
 
         let new_attrs = attrs
             .into_iter()
-            .map(|(k, v)| Ok((k, replace_link(v)?)))
+            .map(|(k, v)| {
+                let v = self.attributes.rewrite(&k, v, replace_link)?;
+                Ok((k, v))
+            })
             .collect::<Result<Vec<_>, ConfigurafoxError>>()?;
 
         let new_elem = Node::Element(Element { name: tag_name.to_owned(), attrs: new_attrs, children });
@@ -161,18 +948,220 @@ impl<R: Resource, D> TreeWalker<R, D> for LinkReplacer {
     }
 }
 
+/// Replaces `<backlinks/>` with a list of links to every page that links to the current one, except error pages.
+/// The links are emitted as `@identifier` references, so `LinkReplacer` must also be in use.
+/// Use `graph::scan_links` before building to make sure all links are known
+pub struct BacklinksWalker;
+
+impl<R: Resource, D> TreeWalker<R, D> for BacklinksWalker {
+    fn describe(&self) -> String {
+        "BacklinksWalker".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "backlinks"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["backlinks"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let mut backlinks = ctx.resources.backlinks(ctx.resource);
+        backlinks.retain(|resource| !is_error_page(ctx.resources, resource));
+        if backlinks.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let items = backlinks
+            .iter()
+            .map(|resource| {
+                let identifier = resource.identifier();
+                let title = ctx.resources.metadata(resource).get_str("title").unwrap_or(&identifier).to_string();
+                Node::Element(Element {
+                    name: "li".to_string(),
+                    attrs: vec![],
+                    children: vec![
+                        Node::Element(Element {
+                            name: "a".to_string(),
+                            attrs: vec![("href".to_string(), format!("@{identifier}"))],
+                            children: vec![Node::Text(title)],
+                        }),
+                    ],
+                })
+            })
+            .collect();
+
+        let class = get_attr(&attrs, "class").unwrap_or("backlinks").to_string();
+
+        Ok(vec![
+            Node::Element(Element {
+                name: "ul".to_string(),
+                attrs: vec![("class".to_string(), class)],
+                children: items,
+            }),
+        ])
+    }
+}
+
+/// Page metadata key with the canonical URL of a page published elsewhere first, used by `CanonicalLink` instead
+/// of the page's own URL
+pub const CANONICAL_KEY: &str = "canonical";
+
+/// Replaces `<canonical-link/>` with `<link rel="canonical">` to the page's URL under `SiteData::base_url`,
+/// unless the page's `CANONICAL_KEY` metadata gives one
+pub struct CanonicalLink;
+
+impl<R: Resource, D: SiteData> TreeWalker<R, D> for CanonicalLink {
+    fn describe(&self) -> String {
+        "CanonicalLink".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "canonical-link"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["canonical-link"])
+    }
+
+    fn replace(&self, _tag_name: &str, _attrs: Vec<(String, String)>, _children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let href = match ctx.metadata().get_str(CANONICAL_KEY) {
+            Some(url) => url.to_string(),
+            None => absolute_url(ctx.data, &ctx.resources.output_path(ctx.resource))?,
+        };
+        Ok(vec![
+            Node::Element(Element {
+                name: "link".to_string(),
+                attrs: vec![("rel".to_string(), "canonical".to_string()), ("href".to_string(), href)],
+                children: vec![],
+            }),
+        ])
+    }
+}
+
+/// Renders TeX with KaTeX: `<$>` inline, `<katex>` as a displayed equation, and `<katex-prelude/>` as the
+/// stylesheet both need
+///
+/// A `<katex id="eq:energy">` is numbered, counting the numbered equations of the page in document order once
+/// everything else is walked, so equations brought in by other walkers count where they end up. It's wrapped in a
+/// `<div class="equation" id="eq:energy">`, with its number in a `<span class="equation-number">` after it.
+/// `<eqref to="eq:energy"/>` becomes a link to it reading e.g. "(3)". `<eqref to="eq:maxwell" page="notes/em"/>`
+/// refers to an equation of another page, numbered as its source numbers it. Unknown equations are handled as
+/// `Strictness::unresolved_links` says
+#[cfg(feature = "katex")]
 pub struct KatexReplacer;
 
+/// Whether the page has numbered equations or references to them for `KatexReplacer::finish` to number
+#[cfg(feature = "katex")]
+#[derive(Clone, Copy)]
+struct EquationsToNumber;
+
+#[cfg(feature = "katex")]
+impl KatexReplacer {
+    fn render(tag_name: &str, children: &[Node], display: bool) -> Result<String, ConfigurafoxError> {
+        let mut opts = katex::Opts::builder()
+            .output_type(katex::opts::OutputType::Html)
+            .trust(true)
+            .build()
+            .map_err(|e| ConfigurafoxError::Katex { msg: e.to_string() })?;
+
+        if display {
+            opts.set_display_mode(true);
+        }
+
+        match children {
+            [Node::Text(tex)] => katex::render_with_opts(tex, &opts).map_err(|e| ConfigurafoxError::Katex { msg: e.to_string() }),
+            _ => Err(ConfigurafoxError::MalformedBody { tag: tag_name.to_string(), msg: "must contain only text".to_string() }),
+        }
+    }
+
+    /// A reference to equation `id` of another page, numbered by the `<katex id>`s of its source
+    fn reference_elsewhere<R: Resource, D>(id: &str, page: &str, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let unknown = |identifier: String| -> Result<Vec<Node>, ConfigurafoxError> {
+            ctx.tolerate(ctx.strictness().unresolved_links, ConfigurafoxError::UnknownIdentifier { identifier })?;
+            Ok(vec![Node::Element(eqref(None, "(?)"))])
+        };
+        let Some(resource) = ctx.resources.resource_by_identifier(page) else {
+            return unknown(page.to_string());
+        };
+        ctx.resources.record_dependency(ctx.resource, resource, DependencyKind::Include);
+
+        let source = ctx.resources.read_to_string(resource)?;
+        let body = crate::metadata::strip_front_matter(&source);
+        let dom = crate::entities::parse(body)
+            .map_err(|e| ConfigurafoxError::parse_html(&ctx.resources.output_path(resource), body, crate::metadata::body_first_line(&source), e))?;
+        let mut ids = Vec::new();
+        tagged_attrs(&dom, "katex", "id", &mut ids);
+
+        match ids.iter().position(|other| other == id) {
+            Some(index) => {
+                let href = format!("{}#{id}", ctx.link_to(resource)?);
+                Ok(vec![Node::Element(eqref(Some(href), &format!("({})", index + 1)))])
+            }
+            None => unknown(format!("{page}#{id}")),
+        }
+    }
+}
+
+/// An `<a class="eqref">` reading `text`
+#[cfg(feature = "katex")]
+fn eqref(href: Option<String>, text: &str) -> Element {
+    let mut attrs = vec![("class".to_string(), "eqref".to_string())];
+    attrs.extend(href.map(|href| ("href".to_string(), href)));
+    Element { name: "a".to_string(), attrs, children: vec![Node::Text(text.to_string())] }
+}
+
+/// The values of attribute `key` of the elements named `tag` in `dom` having it, in document order
+#[cfg(feature = "katex")]
+fn tagged_attrs(dom: &[Node], tag: &str, key: &str, out: &mut Vec<String>) {
+    for node in dom {
+        if let Node::Element(Element { name, attrs, children }) = node {
+            if name == tag {
+                out.extend(get_attr(attrs, key).map(str::to_string));
+            }
+            tagged_attrs(children, tag, key, out);
+        }
+    }
+}
+
+/// Fills in the numbers left out by `KatexReplacer::replace`, and the references to them
+#[cfg(feature = "katex")]
+fn number_equations<R: Resource, D>(dom: &mut [Node], numbers: &HashMap<String, usize>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+    for node in dom {
+        let Node::Element(Element { attrs, children, .. }) = node else { continue };
+        let placeholder = attrs.iter().position(|(key, _)| key == "data-equation" || key == "data-eqref");
+        if let Some((_, id)) = placeholder.map(|index| attrs.remove(index)) {
+            let text = match numbers.get(&id) {
+                Some(number) => format!("({number})"),
+                None => {
+                    ctx.tolerate(ctx.strictness().unresolved_links, ConfigurafoxError::UnknownIdentifier { identifier: id })?;
+                    "(?)".to_string()
+                }
+            };
+            *children = vec![Node::Text(text)];
+            continue;
+        }
+        number_equations(children, numbers, ctx)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "katex")]
 impl<R: Resource, D> TreeWalker<R, D> for KatexReplacer {
     fn describe(&self) -> String {
         "KatexReplacer".to_string()
     }
 
     fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
-        tag_name == "$" || tag_name == "katex" || tag_name == "katex-prelude"
+        tag_name == "$" || tag_name == "katex" || tag_name == "katex-prelude" || tag_name == "eqref"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["$", "katex", "katex-prelude", "eqref"])
     }
 
-    fn replace(&self, tag_name: &str, _attrs: Vec<(String, String)>, children: Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
         match tag_name {
             "katex-prelude" => {
                 Ok(vec![
@@ -184,31 +1173,58 @@ impl<R: Resource, D> TreeWalker<R, D> for KatexReplacer {
                 ])
             }
             "katex" | "$" => {
-                let mut opts = katex::Opts::builder()
-                    .output_type(katex::opts::OutputType::Html)
-                    .trust(true)
-                    .build()
-                    .unwrap();
-
-                if tag_name == "katex" {
-                    opts.set_display_mode(true);
-                }
-
-                match &children[..] {
-                    [Node::Text(tex)] => {
-                        let rendered = katex::render_with_opts(tex, &opts).expect("meow");
-                        Ok(vec![Node::RawHTML(rendered)])
-                    }
-                    _ => {
-                        Err(ConfigurafoxError::Other("Katex: malformed body".to_string()))
+                let rendered = Self::render(tag_name, &children, tag_name == "katex")?;
+                match get_attr(&attrs, "id").filter(|_| tag_name == "katex") {
+                    Some(id) => {
+                        ctx.extensions.insert(EquationsToNumber);
+                        let number = Element {
+                            name: "span".to_string(),
+                            attrs: vec![("class".to_string(), "equation-number".to_string()), ("data-equation".to_string(), id.to_string())],
+                            children: vec![],
+                        };
+                        Ok(vec![Node::Element(Element {
+                            name: "div".to_string(),
+                            attrs: vec![("class".to_string(), "equation".to_string()), ("id".to_string(), id.to_string())],
+                            children: vec![Node::RawHTML(rendered), Node::Element(number)],
+                        })])
                     }
+                    None => Ok(vec![Node::RawHTML(rendered)]),
                 }
             }
-            _ => unreachable!("invalid tag {tag_name} for KatexReplacer"),
+            "eqref" => {
+                let Some(id) = get_attr(&attrs, "to") else {
+                    return Err(ConfigurafoxError::MissingAttr { key_name: "to".to_string(), msg: "the id of the equation".to_string() });
+                };
+                if let Some(page) = get_attr(&attrs, "page") {
+                    return Self::reference_elsewhere(id, page, ctx);
+                }
+                ctx.extensions.insert(EquationsToNumber);
+                let mut link = eqref(Some(format!("#{id}")), "");
+                link.attrs.push(("data-eqref".to_string(), id.to_string()));
+                Ok(vec![Node::Element(link)])
+            }
+            _ => Err(ConfigurafoxError::UnexpectedTag { walker: "KatexReplacer".to_string(), tag: tag_name.to_string() }),
         }
     }
+
+    fn finish(&self, dom: &mut Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<(), ConfigurafoxError> {
+        if ctx.extensions.remove::<EquationsToNumber>().is_none() {
+            return Ok(());
+        }
+        let mut ids = Vec::new();
+        tagged_attrs(dom, "span", "data-equation", &mut ids);
+
+        let mut numbers = HashMap::new();
+        for (index, id) in ids.into_iter().enumerate() {
+            if numbers.insert(id.clone(), index + 1).is_some() {
+                return Err(ConfigurafoxError::MalformedAttrs { key_name: "id".to_string(), msg: format!("two equations are labeled {id}") });
+            }
+        }
+        number_equations(dom, &numbers, ctx)
+    }
 }
 
+#[cfg(feature = "syntax-highlight")]
 fn deindent(source: &str) -> String {
     let source = source.trim_start_matches("\n").trim_end();
     let n_spaces = source.chars().take_while(|&c| c == ' ').count();
@@ -221,22 +1237,55 @@ fn deindent(source: &str) -> String {
         .join("\n")
 }
 
+/// The Rust Playground, running the snippet with the latest stable compiler
+pub const RUST_PLAYGROUND: &str = "https://play.rust-lang.org/?version=stable&mode=debug&edition=2021&code={code}";
+
+/// Highlights `<pre-hl lang="rs">` blocks and `<code-hl lang="rs">` inline code with syntect
+///
+/// A `<pre-hl>` with a `run` attribute is followed by an `<a class="run-link">Run</a>` to the playground of its
+/// language, see `SyntaxHighlighter::playgrounds`, with the code as shown in the block
+#[cfg(feature = "syntax-highlight")]
 pub struct SyntaxHighlighter {
     pub syntax_set: SyntaxSet,
     pub theme_set: ThemeSet,
     pub theme: String,
+    /// URL templates of the playgrounds `run` links go to, by `lang`. `{code}` is replaced by the percent-encoded
+    /// code. Has `RUST_PLAYGROUND` for `rs` unless replaced
+    pub playgrounds: BTreeMap<String, String>,
 }
 
+#[cfg(feature = "syntax-highlight")]
 impl SyntaxHighlighter {
     pub fn default(theme: &str) -> SyntaxHighlighter {
         SyntaxHighlighter {
             syntax_set: SyntaxSet::load_defaults_newlines(),
             theme_set: ThemeSet::load_defaults(),
             theme: theme.to_string(),
+            playgrounds: BTreeMap::from([("rs".to_string(), RUST_PLAYGROUND.to_string())]),
         }
     }
+
+    /// Links `run` blocks in `lang` to `template`, e.g. `"https://play.example.org/?lang=py&code={code}"`
+    pub fn with_playground(mut self, lang: &str, template: &str) -> SyntaxHighlighter {
+        self.playgrounds.insert(lang.to_string(), template.to_string());
+        self
+    }
+
+    /// The link after a `run` block in `lang`
+    fn run_link(&self, lang: &str, code: &str) -> Result<Node, ConfigurafoxError> {
+        let Some(template) = self.playgrounds.get(lang) else {
+            return Err(ConfigurafoxError::MalformedAttrs { key_name: "run".to_string(), msg: format!("no playground is configured for {lang}") });
+        };
+        let href = template.replace("{code}", &crate::output::uri_encode(code));
+        Ok(Node::Element(Element {
+            name: "a".to_string(),
+            attrs: vec![("class".to_string(), "run-link".to_string()), ("href".to_string(), href)],
+            children: vec![Node::Text("Run".to_string())],
+        }))
+    }
 }
 
+#[cfg(feature = "syntax-highlight")]
 impl<R: Resource, D> TreeWalker<R, D> for SyntaxHighlighter {
     fn describe(&self) -> String {
         "SyntaxHighlighter".to_string()
@@ -246,36 +1295,44 @@ impl<R: Resource, D> TreeWalker<R, D> for SyntaxHighlighter {
         tag_name == "code-hl" || tag_name == "pre-hl"
     }
 
-    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, _ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["code-hl", "pre-hl"])
+    }
+
+    fn replace(&self, tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
         let code_text = match children.as_slice() {
             [Node::Text(code_text)] => code_text.to_owned(),
-            _ => return Err(ConfigurafoxError::Other(format!("{tag_name} must contain only text children"))),
+            _ => return Err(ConfigurafoxError::MalformedBody { tag: tag_name.to_string(), msg: "must contain only text".to_string() }),
         };
         let code_text = deindent(&code_text);
 
-        let lang = get_attr(&attrs, "lang").ok_or(ConfigurafoxError::Other("Missing lang= attribute".to_string()))?;
+        let lang = get_attr(&attrs, "lang").ok_or_else(|| ConfigurafoxError::MissingAttr { key_name: "lang".to_string(), msg: format!("{tag_name} needs a language") })?;
+        let run = get_attr(&attrs, "run").is_some();
 
-        let theme = &self.theme_set.themes.get(&self.theme).ok_or(ConfigurafoxError::Other(format!("No such theme {}", self.theme)))?;
+        let theme = &self.theme_set.themes.get(&self.theme).ok_or_else(|| ConfigurafoxError::UnknownTheme { theme: self.theme.clone() })?;
 
         let background_color_style = theme.settings.background.map(|col| format!("background: #{:02x}{:02x}{:02x};", col.r, col.g, col.b));
 
-        let syntax_reference = self
-            .syntax_set
-            .find_syntax_by_extension(&lang)
-            .ok_or(ConfigurafoxError::Other(format!("Unknown language {lang}")))?;
+        let syntax_reference = match self.syntax_set.find_syntax_by_extension(lang) {
+            Some(syntax_reference) => syntax_reference,
+            None => {
+                ctx.tolerate(ctx.strictness().unknown_languages, ConfigurafoxError::UnknownLanguage { lang: lang.to_string() })?;
+                self.syntax_set.find_syntax_plain_text()
+            }
+        };
 
         let html_str = highlighted_html_for_string(&code_text, &self.syntax_set, syntax_reference, &theme)?;
 
-        let html_parsed = html_editor::parse(&html_str)
-            .map_err(|e| ConfigurafoxError::ParseHTMLError { path: PathBuf::from("<generated-syntect>"), error: e })?;
+        let html_parsed = crate::entities::parse(&html_str)
+            .map_err(|e| ConfigurafoxError::parse_html(Path::new("<generated-syntect>"), &html_str, 1, e))?;
 
 
         let Some(Node::Element(Element { name, mut attrs, children })) = html_parsed.into_iter().next() else {
-            return Err(ConfigurafoxError::Other(format!("Invalid html generated by syntect: {html_str:?}")));
+            return Err(ConfigurafoxError::InvalidGeneratedHTML { generator: "syntect".to_string(), html: html_str.clone() });
         };
 
         if name != "pre" {
-            return Err(ConfigurafoxError::Other(format!("Invalid html generated by syntect: {html_str:?}")));
+            return Err(ConfigurafoxError::InvalidGeneratedHTML { generator: "syntect".to_string(), html: html_str.clone() });
         }
 
 
@@ -285,13 +1342,17 @@ impl<R: Resource, D> TreeWalker<R, D> for SyntaxHighlighter {
 
         match tag_name {
             "pre-hl" => {
-                Ok(vec![
+                let mut nodes = vec![
                     Node::Element(Element {
                         name: "pre".to_string(),
                         attrs,
                         children,
                     }),
-                ])
+                ];
+                if run {
+                    nodes.push(self.run_link(lang, &code_text)?);
+                }
+                Ok(nodes)
             }
             "code-hl" => {
                 Ok(vec![
@@ -302,7 +1363,51 @@ impl<R: Resource, D> TreeWalker<R, D> for SyntaxHighlighter {
                     }),
                 ])
             }
-            _ => unreachable!(),
+            _ => Err(ConfigurafoxError::UnexpectedTag { walker: "SyntaxHighlighter".to_string(), tag: tag_name.to_string() }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use std::path::Path;
+
+    use super::{LinkReplacer, VariableReplacer, attr_references, value_parts};
+    use crate::{HTMLProcessor, ResourceProcessor};
+    use crate::entities::parse;
+    use crate::testing::{TestResource, assert_html_eq, resource_manager, run_walker};
+
+    #[test]
+    fn style_parts() {
+        let style = "background: url('@bg') no-repeat, url(@tile )";
+        let parts = value_parts("style", style).into_iter().map(|(start, end)| &style[start..end]).collect::<Vec<_>>();
+        assert_eq!(parts, ["@bg", "@tile"]);
+        assert_eq!(value_parts("style", "$css"), [(0, 4)]);
+        assert_eq!(value_parts("srcset", "a.png 1x, b.png 2x"), [(0, 5), (10, 15)]);
+    }
+
+    #[test]
+    fn whole_style_values() {
+        let variables = VariableReplacer::new(json!({ "css": "color: red", "bg": "a.png" }));
+        let html = run_walker(variables, "<div style=\"$css\"></div><div style=\"background: url($bg)\"></div>").unwrap();
+        assert_html_eq(&html, "<div style=\"color: red\"></div><div style=\"background: url(a.png)\"></div>");
+
+        let dom = parse("<div style=\"@bg\"></div><div style=\"background: url(@tile)\"></div>").unwrap();
+        assert_eq!(attr_references(&dom, '@'), ["bg", "tile"]);
+    }
+
+    #[test]
+    fn links_from_content_dir() {
+        let mut resources = resource_manager(&[]);
+        let post = TestResource::new("blog/post.html");
+        resources.register_memory("content/blog/post.html".into(), post.clone(), "<a href=\"@about\">About</a>");
+        resources.register_memory("content/about.html".into(), TestResource::new("about.html"), "");
+
+        let processor = HTMLProcessor::builder().walker(LinkReplacer::new()).build();
+        let html = processor.process_resource(&post, Path::new("content/blog/post.html"), &resources).unwrap();
+        // From `blog/post.html` in the output, not `content/blog/post.html`, which gave `../../about.html`
+        assert_html_eq(&String::from_utf8(html).unwrap(), "<a href=\"../about.html\">About</a>");
+    }
+}
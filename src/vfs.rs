@@ -0,0 +1,334 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone)]
+pub struct VfsEntry {
+    pub name: OsString,
+    /// `Symlink` for links, not what they point to
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VfsMetadata {
+    /// Never `Symlink`, links are followed
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// The file tree a `ResourceManager` reads its sources from. All paths are relative to the root of the tree
+pub trait Vfs: Send + Sync {
+    fn describe(&self) -> String;
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>>;
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<VfsEntry>>;
+
+    /// Follows symlinks
+    fn metadata(&self, path: &Path) -> std::io::Result<VfsMetadata>;
+
+    /// Resolves symlinks, so that the same file reached through different paths can be detected.
+    /// Trees without links can use the default, which only normalizes the path
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        Ok(normalize(path))
+    }
+
+    /// Reads at most the first `n` bytes
+    fn read_prefix(&self, path: &Path, n: usize) -> std::io::Result<Vec<u8>> {
+        let mut data = self.read(path)?;
+        data.truncate(n);
+        Ok(data)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|m| m.kind == EntryKind::File)
+    }
+
+    /// Where `path` lives on the real filesystem, if it does. Needed by things that can't go through the `Vfs`,
+    /// like file watching
+    fn physical_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Removes `.` components, so `./a/./b` and `a/b` compare equal
+pub fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|c| !matches!(c, Component::CurDir)).collect()
+}
+
+/// Resolves `.` and `..` lexically, failing if `path` is absolute or climbs above the root.
+/// Every path handed to the real filesystem goes through this, so `src="../../../etc/passwd"` can't escape
+pub fn confine(path: &Path) -> std::io::Result<PathBuf> {
+    let mut confined = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => confined.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !confined.pop() {
+                    return Err(outside_root(path));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return Err(outside_root(path)),
+        }
+    }
+    Ok(confined)
+}
+
+/// Creates a new, empty directory in the system's temporary directory, named `prefix` and something unique. Never
+/// reuses an existing directory, which someone else could have put there
+pub fn create_temp_dir(prefix: &str) -> std::io::Result<PathBuf> {
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+        let dir = std::env::temp_dir().join(format!("{prefix}-{}-{nanos:x}-{attempt}", std::process::id()));
+        match std::fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Like `confine`, but never fails: leading `/` and `..` components that would escape the root are dropped
+pub fn clamp(path: &Path) -> PathBuf {
+    let mut clamped = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => clamped.push(part),
+            Component::ParentDir => {
+                clamped.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    clamped
+}
+
+fn outside_root(path: &Path) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("{} points outside the project root", path.display()))
+}
+
+fn not_found(path: &Path) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+/// The real filesystem, below `root`
+pub struct PhysicalFs {
+    pub root: PathBuf,
+}
+
+impl PhysicalFs {
+    pub fn new(root: PathBuf) -> PhysicalFs {
+        PhysicalFs { root }
+    }
+
+    fn resolve(&self, path: &Path) -> std::io::Result<PathBuf> {
+        Ok(self.root.join(confine(path)?))
+    }
+}
+
+impl Vfs for PhysicalFs {
+    fn describe(&self) -> String {
+        format!("PhysicalFs({})", self.root.display())
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.resolve(path)?)
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<VfsEntry>> {
+        let mut entries = Vec::new();
+        for dir_entry in std::fs::read_dir(self.resolve(path)?)? {
+            let dir_entry = dir_entry?;
+            let file_type = dir_entry.file_type()?;
+            let kind = if file_type.is_symlink() {
+                EntryKind::Symlink
+            } else if file_type.is_dir() {
+                EntryKind::Dir
+            } else {
+                EntryKind::File
+            };
+            entries.push(VfsEntry { name: dir_entry.file_name(), kind });
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<VfsMetadata> {
+        let m = std::fs::metadata(self.resolve(path)?)?;
+        Ok(VfsMetadata {
+            kind: if m.is_dir() { EntryKind::Dir } else { EntryKind::File },
+            size: m.len(),
+            modified: m.modified().ok(),
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        std::fs::canonicalize(self.resolve(path)?)
+    }
+
+    fn read_prefix(&self, path: &Path, n: usize) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(n);
+        std::fs::File::open(self.resolve(path)?)?.take(n as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn physical_path(&self, path: &Path) -> Option<PathBuf> {
+        self.resolve(path).ok()
+    }
+}
+
+/// A file tree held in memory. Directories exist implicitly. Useful for test fixtures, and for assets embedded
+/// in the binary (e.g. with `include_dir!`)
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    pub fn insert<P: AsRef<Path>, C: Into<Vec<u8>>>(&mut self, path: P, contents: C) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+
+    pub fn with<P: AsRef<Path>, C: Into<Vec<u8>>>(mut self, path: P, contents: C) -> MemoryFs {
+        self.insert(path, contents);
+        self
+    }
+}
+
+impl<P: AsRef<Path>, C: Into<Vec<u8>>> FromIterator<(P, C)> for MemoryFs {
+    fn from_iter<T: IntoIterator<Item = (P, C)>>(iter: T) -> Self {
+        let mut fs = MemoryFs::new();
+        for (path, contents) in iter {
+            fs.insert(path, contents);
+        }
+        fs
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn describe(&self) -> String {
+        format!("MemoryFs({} files)", self.files.len())
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.files.get(&normalize(path)).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<VfsEntry>> {
+        let dir = normalize(path);
+
+        let mut entries: Vec<VfsEntry> = Vec::new();
+        for file in self.files.keys() {
+            let Ok(rest) = file.strip_prefix(&dir) else {
+                continue;
+            };
+            let mut components = rest.components();
+            let Some(first) = components.next() else {
+                continue;
+            };
+            let kind = if components.next().is_some() { EntryKind::Dir } else { EntryKind::File };
+            if !entries.iter().any(|e| e.name == first.as_os_str()) {
+                entries.push(VfsEntry { name: first.as_os_str().to_owned(), kind });
+            }
+        }
+
+        if entries.is_empty() && !dir.as_os_str().is_empty() {
+            return Err(not_found(path));
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<VfsMetadata> {
+        let path = normalize(path);
+        if let Some(contents) = self.files.get(&path) {
+            return Ok(VfsMetadata { kind: EntryKind::File, size: contents.len() as u64, modified: None });
+        }
+        if self.files.keys().any(|file| file.starts_with(&path)) {
+            return Ok(VfsMetadata { kind: EntryKind::Dir, size: 0, modified: None });
+        }
+        Err(not_found(&path))
+    }
+}
+
+/// Several trees stacked on top of each other. A file in an earlier layer hides the same path in later layers,
+/// and directories are merged. E.g. a site's own files over a theme's
+pub struct OverlayFs {
+    pub layers: Vec<Box<dyn Vfs>>,
+}
+
+impl OverlayFs {
+    pub fn new(layers: Vec<Box<dyn Vfs>>) -> OverlayFs {
+        OverlayFs { layers }
+    }
+
+    /// The first layer containing `path`
+    fn layer_for(&self, path: &Path) -> Option<&dyn Vfs> {
+        self.layers.iter().map(|l| &**l).find(|l| l.metadata(path).is_ok())
+    }
+}
+
+impl Vfs for OverlayFs {
+    fn describe(&self) -> String {
+        let layers = self.layers.iter().map(|l| l.describe()).collect::<Vec<_>>().join(", ");
+        format!("OverlayFs({layers})")
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.layer_for(path).ok_or_else(|| not_found(path))?.read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<VfsEntry>> {
+        let mut found = false;
+        let mut entries: Vec<VfsEntry> = Vec::new();
+        for layer in &self.layers {
+            let Ok(layer_entries) = layer.read_dir(path) else {
+                continue;
+            };
+            found = true;
+            for entry in layer_entries {
+                if !entries.iter().any(|e| e.name == entry.name) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        if !found {
+            return Err(not_found(path));
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<VfsMetadata> {
+        self.layer_for(path).ok_or_else(|| not_found(path))?.metadata(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        self.layer_for(path).ok_or_else(|| not_found(path))?.canonicalize(path)
+    }
+
+    fn read_prefix(&self, path: &Path, n: usize) -> std::io::Result<Vec<u8>> {
+        self.layer_for(path).ok_or_else(|| not_found(path))?.read_prefix(path, n)
+    }
+
+    fn physical_path(&self, path: &Path) -> Option<PathBuf> {
+        self.layer_for(path)?.physical_path(path)
+    }
+}
@@ -0,0 +1,202 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use html_editor::{Element, Node};
+
+use crate::{ConfigurafoxError, relative_url};
+use crate::graph::DependencyKind;
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker, at_reference, get_attr};
+
+/// Formats `<source>`s are emitted for, as (extension, MIME type), in the order browsers should try them: the
+/// smaller files first
+pub const VIDEO_FORMATS: &[(&str, &str)] = &[("webm", "video/webm"), ("mp4", "video/mp4"), ("ogv", "video/ogg"), ("mov", "video/quicktime")];
+
+pub fn is_video(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+    VIDEO_FORMATS.iter().any(|(ext, _)| extension.as_deref() == Some(*ext))
+}
+
+/// Where the poster of the video output at `video` is written: next to it, e.g. `clips/intro.poster.jpg`
+pub fn poster_path(video: &Path) -> PathBuf {
+    let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    video.with_file_name(format!("{stem}.poster.jpg"))
+}
+
+/// The videos of `identifier` in each of `VIDEO_FORMATS` that is registered, in that order: those whose
+/// identifier is `identifier` with the format's extension, or in place of its extension
+pub fn video_sources<'a, R: Resource>(resources: &'a ResourceManager<R>, identifier: &str) -> Vec<(&'a R, &'static str)> {
+    let stem = match resources.resource_by_identifier(identifier) {
+        Some(_) => Path::new(identifier).with_extension("").to_string_lossy().into_owned(),
+        None => identifier.to_string(),
+    };
+    VIDEO_FORMATS
+        .iter()
+        .filter_map(|(ext, mime_type)| Some((resources.resource_by_identifier(&format!("{stem}.{ext}"))?, *mime_type)))
+        .collect()
+}
+
+/// Replaces `<video src="@clips/intro">`, or `<video @intro>` for identifiers without a `/`, with a `<video>`
+/// with a `<source>` for each format of the video that is registered, see `video_sources`, e.g. `clips/intro.webm`
+/// and `clips/intro.mp4`. The identifier may name one of them, or leave out the extension. The element gets the
+/// `width` and `height` of the video, a `poster` made from one of its frames, `preload="metadata"`, and
+/// `controls`, or `muted` and `playsinline` if it has `autoplay`, unless it sets them itself. Its children, like
+/// `<track>`s and fallback content, are kept after the sources
+///
+/// Videos are probed with `ffprobe` and posters made with `ffmpeg`, which must be installed. Posters are made at
+/// the end of the run, see `ResourceManager::request_derivative`, once however many pages show the video. Put it
+/// before `LinkReplacer`, which would otherwise resolve the reference first
+#[derive(Debug, Clone)]
+pub struct VideoWalker {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    /// Seconds into the video the poster is taken at. By default `ffmpeg` picks a representative frame from
+    /// the first few seconds
+    pub poster_at: Option<f64>,
+}
+
+impl Default for VideoWalker {
+    fn default() -> Self {
+        VideoWalker { ffmpeg: PathBuf::from("ffmpeg"), ffprobe: PathBuf::from("ffprobe"), poster_at: None }
+    }
+}
+
+impl VideoWalker {
+    pub fn new() -> VideoWalker {
+        VideoWalker::default()
+    }
+
+    pub fn with_commands<P: Into<PathBuf>, Q: Into<PathBuf>>(mut self, ffmpeg: P, ffprobe: Q) -> VideoWalker {
+        self.ffmpeg = ffmpeg.into();
+        self.ffprobe = ffprobe.into();
+        self
+    }
+
+    pub fn with_poster_at(mut self, seconds: f64) -> VideoWalker {
+        self.poster_at = Some(seconds);
+        self
+    }
+
+    /// The width and height the video is shown at, rotated as players rotate it
+    fn dimensions<R: Resource>(&self, resources: &ResourceManager<R>, video: &R) -> Result<(u32, u32), ConfigurafoxError> {
+        let output = resources.output_path(video);
+        let video_error = |msg: String| ConfigurafoxError::Video { path: output.clone(), msg };
+
+        let probe = resources.with_file(video, |path| {
+            run(
+                Command::new(&self.ffprobe)
+                    .args(["-v", "error", "-select_streams", "v:0", "-show_entries", "stream=width,height:stream_side_data=rotation", "-of", "json"])
+                    .arg(path),
+            )
+        })?
+        .map_err(video_error)?;
+
+        let probe: serde_json::Value = serde_json::from_slice(&probe).map_err(|e| video_error(format!("unexpected ffprobe output: {e}")))?;
+        let stream = &probe["streams"][0];
+        let (Some(width), Some(height)) = (stream["width"].as_u64(), stream["height"].as_u64()) else {
+            return Err(video_error("no video stream".to_string()));
+        };
+        let rotation = stream["side_data_list"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find_map(|side_data| side_data["rotation"].as_i64())
+            .unwrap_or(0);
+        let (width, height) = if rotation.rem_euclid(180) == 90 { (height, width) } else { (width, height) };
+        Ok((width as u32, height as u32))
+    }
+
+    /// Requests the poster of `video`, returning where it's written
+    fn request_poster<R: Resource>(&self, resources: &ResourceManager<R>, video: &R) -> PathBuf {
+        let output = resources.output_path(video);
+        let path = poster_path(&output);
+        let ffmpeg = self.ffmpeg.clone();
+        let poster_at = self.poster_at;
+
+        resources.request_derivative(path.clone(), video, move |input| {
+            let mut command = Command::new(&ffmpeg);
+            command.args(["-v", "error"]);
+            if let Some(seconds) = poster_at {
+                command.args(["-ss", &seconds.to_string()]);
+            }
+            command.arg("-i").arg(input);
+            if poster_at.is_none() {
+                command.args(["-vf", "thumbnail"]);
+            }
+            match run(command.args(["-frames:v", "1", "-map_metadata", "-1", "-q:v", "3", "-f", "image2pipe", "-c:v", "mjpeg", "pipe:1"])) {
+                Ok(poster) if !poster.is_empty() => Ok(poster),
+                Ok(_) => Err(ConfigurafoxError::Video { path: output.clone(), msg: "ffmpeg made no poster, is the video shorter than poster_at?".to_string() }),
+                Err(msg) => Err(ConfigurafoxError::Video { path: output.clone(), msg }),
+            }
+        });
+        path
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for VideoWalker {
+    fn describe(&self) -> String {
+        "VideoWalker".to_string()
+    }
+
+    fn matches(&self, tag_name: &str, attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "video" && at_reference(attrs).is_some()
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["video"])
+    }
+
+    fn replace(&self, _tag_name: &str, mut attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let Some((identifier, key)) = at_reference(&attrs) else {
+            return Err(ConfigurafoxError::MissingAttr { key_name: "src".to_string(), msg: "an @-reference to the video".to_string() });
+        };
+        let sources = video_sources(ctx.resources, &identifier);
+        let Some((preferred, _)) = sources.first() else {
+            return Err(ConfigurafoxError::UnknownIdentifier { identifier });
+        };
+        attrs.retain(|(other, _)| *other != key);
+
+        let has = |attrs: &[(String, String)], key: &str| get_attr(attrs, key).is_some();
+        if !has(&attrs, "width") && !has(&attrs, "height") {
+            ctx.resources.record_dependency(ctx.resource, preferred, DependencyKind::Include);
+            let (width, height) = self.dimensions(ctx.resources, preferred)?;
+            attrs.push(("width".to_string(), width.to_string()));
+            attrs.push(("height".to_string(), height.to_string()));
+        }
+        if !has(&attrs, "poster") {
+            ctx.resources.record_dependency(ctx.resource, preferred, DependencyKind::Include);
+            let poster = self.request_poster(ctx.resources, preferred);
+            attrs.push(("poster".to_string(), relative_url(&poster, &ctx.resources.output_path(ctx.resource))?));
+        }
+        if !has(&attrs, "preload") {
+            attrs.push(("preload".to_string(), "metadata".to_string()));
+        }
+        let added = if has(&attrs, "autoplay") { &["muted", "playsinline"][..] } else { &["controls"][..] };
+        for key in added {
+            if !has(&attrs, key) {
+                attrs.push((key.to_string(), String::new()));
+            }
+        }
+
+        let mut nodes = Vec::new();
+        for (source, mime_type) in &sources {
+            let attrs = vec![("src".to_string(), ctx.link_to(source)?), ("type".to_string(), mime_type.to_string())];
+            nodes.push(Node::Element(Element { name: "source".to_string(), attrs, children: vec![] }));
+        }
+        nodes.extend(children);
+        Ok(vec![Node::Element(Element { name: "video".to_string(), attrs, children: nodes })])
+    }
+}
+
+/// Runs `command`, returning what it wrote to stdout, or what went wrong
+pub(crate) fn run(command: &mut Command) -> Result<Vec<u8>, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|e| format!("could not run {program}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!("{program} failed with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
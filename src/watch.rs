@@ -0,0 +1,245 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{EventKind, RecursiveMode, Watcher as _};
+
+use crate::{rebuild_with_backend, run_with_backend, ConfigurafoxError, ResourceProcessor};
+use crate::output::OutputBackend;
+use crate::resource_manager::{Resource, ResourceManager};
+
+/// How long `watch` waits for further changes before rebuilding, see `Watcher::wait`
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// The resources affected by a batch of filesystem changes
+#[derive(Debug, Clone)]
+pub struct ChangeSet<R: Resource> {
+    /// Registered resources whose source changed
+    pub changed: HashSet<R>,
+    /// Everything whose output needs rebuilding: `changed` and everything depending on it
+    pub affected: HashSet<R>,
+    /// Changed paths (relative to the project root) not belonging to any registered resource,
+    /// e.g. newly created files. The resource set may need to be rescanned
+    pub unregistered: Vec<PathBuf>,
+}
+
+impl<R: Resource> ChangeSet<R> {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.unregistered.is_empty()
+    }
+}
+
+impl<R: Resource> ResourceManager<R> {
+    /// Maps changed paths (relative to the project root) to the resources that need rebuilding,
+    /// and drops the cached contents of the changed resources
+    pub fn changes_for_paths(&self, paths: &[PathBuf]) -> ChangeSet<R> {
+        let mut changed = HashSet::new();
+        let mut unregistered = Vec::new();
+
+        for path in paths {
+            let resources = self.resources_at_path(path);
+            if resources.is_empty() {
+                unregistered.push(path.clone());
+            }
+            for resource in resources {
+                self.invalidate(resource);
+                changed.insert(resource.clone());
+            }
+        }
+
+        let graph = self.dependency_graph();
+        let affected = changed
+            .iter()
+            .flat_map(|resource| graph.affected_by(resource))
+            .collect();
+
+        ChangeSet { changed, affected, unregistered }
+    }
+}
+
+/// Watches a project directory for changes
+pub struct Watcher {
+    root: PathBuf,
+    ignored: Vec<PathBuf>,
+    events: Receiver<notify::Result<notify::Event>>,
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl Watcher {
+    /// Starts watching `project_root` recursively. The remote resource cache is ignored
+    pub fn new(project_root: &Path) -> Result<Watcher, ConfigurafoxError> {
+        let root = std::fs::canonicalize(project_root)?;
+
+        let (tx, events) = channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        info!("Watching {}", root.display());
+
+        Ok(Watcher {
+            ignored: vec![PathBuf::from(".configurafox-cache")],
+            root,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Ignores changes below `path` (relative to the project root). Put the output directory here if it's inside
+    /// the project, or every build triggers another one
+    pub fn ignore<P: AsRef<Path>>(&mut self, path: P) {
+        self.ignored.push(path.as_ref().to_owned());
+    }
+
+    fn relativize(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.root).ok()?.to_owned();
+        if self.ignored.iter().any(|ignored| relative.starts_with(ignored)) {
+            return None;
+        }
+        Some(relative)
+    }
+
+    /// Blocks until something changes, then keeps collecting changes until none have happened for `debounce`
+    /// (editors tend to write files in several steps). Returns the changed paths relative to the project root
+    pub fn wait(&self, debounce: Duration) -> Result<Vec<PathBuf>, ConfigurafoxError> {
+        let mut changed = Vec::new();
+
+        let mut timeout = None;
+        loop {
+            let event = match timeout {
+                None => self.events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(timeout) => self.events.recv_timeout(timeout),
+            };
+
+            let event = match event {
+                Ok(event) => event?,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(ConfigurafoxError::WatcherStopped);
+                }
+            };
+
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+
+            for path in &event.paths {
+                if let Some(relative) = self.relativize(path) {
+                    trace!("{:?}: {}", event.kind, relative.display());
+                    if !changed.contains(&relative) {
+                        changed.push(relative);
+                    }
+                }
+            }
+
+            if !changed.is_empty() {
+                timeout = Some(debounce);
+            }
+        }
+
+        debug!("{} paths changed", changed.len());
+
+        Ok(changed)
+    }
+
+    /// Like `wait`, but maps the changes to resources of `resources`
+    pub fn wait_for_changes<R: Resource>(&self, resources: &ResourceManager<R>, debounce: Duration) -> Result<ChangeSet<R>, ConfigurafoxError> {
+        Ok(resources.changes_for_paths(&self.wait(debounce)?))
+    }
+}
+
+/// A build done by `watch`
+#[derive(Debug)]
+pub struct Rebuild<R: Resource> {
+    /// What was rebuilt. For the initial build, and after files were created, this is every resource
+    pub changes: ChangeSet<R>,
+    /// Resources whose source was deleted. Their outputs have been removed from the backend
+    pub removed: Vec<R>,
+    pub result: Result<(), ConfigurafoxError>,
+}
+
+/// Builds everything, then rebuilds whatever is affected each time a file in the project changes, until
+/// `on_rebuild` breaks or the watcher fails. A failed build doesn't stop the loop, it's passed to `on_rebuild`.
+///
+/// Files created while watching are registered as `resource_for(path)` (relative to the project root), and
+/// ignored if it returns `None`. Resources of deleted files are unregistered, and their outputs removed
+#[allow(clippy::too_many_arguments)]
+pub fn watch<'data, R, D, P, F, C>(
+    watcher: &Watcher,
+    backend: &mut dyn OutputBackend,
+    resman: &mut ResourceManager<R>,
+    resource_for: P,
+    processor_for: F,
+    data: &'data D,
+    mut on_rebuild: C,
+) -> Result<(), ConfigurafoxError>
+where
+    R: Resource,
+    P: Fn(&Path) -> Option<R>,
+    F: Fn(&Path, &R, &'data D) -> Box<dyn ResourceProcessor<R> + 'data>,
+    C: FnMut(&ResourceManager<R>, &Rebuild<R>) -> ControlFlow<()>,
+{
+    let everything = |resman: &ResourceManager<R>| resman.iter().map(|(_, r)| r.clone()).collect::<HashSet<_>>();
+
+    let all = everything(resman);
+    let result = run_with_backend(backend, resman, &processor_for, data);
+    let initial = Rebuild {
+        changes: ChangeSet { changed: all.clone(), affected: all, unregistered: Vec::new() },
+        removed: Vec::new(),
+        result,
+    };
+    if on_rebuild(resman, &initial).is_break() {
+        return Ok(());
+    }
+
+    loop {
+        let paths = watcher.wait(DEBOUNCE)?;
+
+        let mut added = false;
+        let mut removed = Vec::new();
+        // Pages linking to a deleted resource need rebuilding too, to report the broken link
+        let mut dependents = HashSet::new();
+        for path in &paths {
+            if resman.vfs().is_file(path) {
+                if resman.resources_at_path(path).is_empty() {
+                    if let Some(resource) = resource_for(path) {
+                        resman.register_file(path.clone(), resource)?;
+                        added = true;
+                    }
+                }
+                continue;
+            }
+
+            for resource in resman.resources_at_path(path) {
+                let graph = resman.dependency_graph();
+                dependents.extend(graph.dependents_of(resource).flat_map(|(dependent, _)| graph.affected_by(dependent)));
+                backend.remove_file(&resman.output_path(resource))?;
+            }
+            removed.extend(resman.unregister_path(path));
+        }
+
+        let mut changes = resman.changes_for_paths(&paths);
+        changes.unregistered.retain(|path| resman.vfs().is_file(path));
+        changes.affected.extend(dependents.into_iter().filter(|r| !removed.contains(r)));
+        if changes.is_empty() && removed.is_empty() && !added {
+            continue;
+        }
+
+        let result = if added {
+            // Links that didn't resolve before may now, and those aren't in the dependency graph
+            changes.affected = everything(resman);
+            run_with_backend(backend, resman, &processor_for, data)
+        } else {
+            rebuild_with_backend(backend, resman, &changes.affected, &processor_for, data)
+        };
+
+        if on_rebuild(resman, &Rebuild { changes, removed, result }).is_break() {
+            return Ok(());
+        }
+    }
+}
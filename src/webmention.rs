@@ -0,0 +1,415 @@
+#[allow(unused)]
+use tracing::{trace, debug, info, warn, error, instrument, Level};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use html_editor::{Element, Node};
+use serde::{Deserialize, Serialize};
+
+use crate::{ConfigurafoxError, is_web_url};
+use crate::linkcheck::is_html;
+use crate::metadata::Value;
+use crate::resource_manager::{Resource, ResourceManager};
+use crate::treewalker::{Context, TreeWalker, element, get_attr};
+
+/// Where the webmentions sent so far are remembered, relative to the project root
+pub const DEFAULT_SENT_LOG: &str = ".configurafox-cache/webmentions-sent.json";
+
+/// Where received webmentions are read from, relative to the project root. JF2, as webmention.io's API returns
+/// it, see `parse_mentions`
+pub const DEFAULT_MENTIONS_FILE: &str = ".configurafox-cache/webmentions.json";
+
+/// A link from a page of the site to another site, which the other site can be sent a webmention about
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OutgoingLink {
+    /// The absolute URL of the page
+    pub source: String,
+    pub target: String,
+}
+
+impl fmt::Display for OutgoingLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.source, self.target)
+    }
+}
+
+/// The links of `<a>` elements to other sites in the pages of a build, given by path relative to the output
+/// directory as for `linkcheck::check_links`. `site_url` is where the build is served, links below it aren't to
+/// other sites. Each link once per page, sorted
+pub fn outgoing_links(files: &HashMap<PathBuf, Vec<u8>>, site_url: &str) -> Result<Vec<OutgoingLink>, ConfigurafoxError> {
+    fn collect(dom: &[Node], hrefs: &mut Vec<String>) {
+        for node in dom {
+            if let Node::Element(Element { name, attrs, children }) = node {
+                if let Some(href) = get_attr(attrs, "href").filter(|_| name == "a") {
+                    hrefs.push(href.to_string());
+                }
+                collect(children, hrefs);
+            }
+        }
+    }
+
+    let site_url = site_url.trim_end_matches('/');
+    let mut links = BTreeSet::new();
+    for (page, contents) in files.iter().filter(|(page, _)| is_html(page)) {
+        let html = String::from_utf8_lossy(contents);
+        let dom = crate::entities::parse(&html).map_err(|e| ConfigurafoxError::parse_html(page, &html, 1, e))?;
+        let mut hrefs = Vec::new();
+        collect(&dom, &mut hrefs);
+
+        let source = format!("{site_url}/{}", crate::url_path(page)?);
+        for href in hrefs {
+            let target = href.split('#').next().unwrap_or(&href);
+            let is_external = target.starts_with("http://") || target.starts_with("https://");
+            if is_external && !target.starts_with(site_url) {
+                links.insert(OutgoingLink { source: source.clone(), target: target.to_string() });
+            }
+        }
+    }
+    Ok(links.into_iter().collect())
+}
+
+/// The targets each source has been sent a webmention about, or found not to accept them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SentLog(pub BTreeMap<String, BTreeSet<String>>);
+
+impl SentLog {
+    /// The log at `path`, empty if there is none
+    pub fn load(path: &Path) -> Result<SentLog, ConfigurafoxError> {
+        match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| ConfigurafoxError::Other(format!("{}: {e}", path.display()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SentLog::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigurafoxError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        Ok(std::fs::write(path, json)?)
+    }
+
+    pub fn contains(&self, link: &OutgoingLink) -> bool {
+        self.0.get(&link.source).is_some_and(|targets| targets.contains(&link.target))
+    }
+
+    pub fn insert(&mut self, link: &OutgoingLink) {
+        self.0.entry(link.source.clone()).or_default().insert(link.target.clone());
+    }
+
+    /// The links of `links` not in the log, e.g. those of newly published posts
+    pub fn unsent<'l>(&self, links: &'l [OutgoingLink]) -> Vec<&'l OutgoingLink> {
+        links.iter().filter(|link| !self.contains(link)).collect()
+    }
+}
+
+/// What sending a webmention came to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendOutcome {
+    Accepted { endpoint: String },
+    /// The target doesn't take webmentions
+    NoEndpoint,
+    Failed { msg: String },
+}
+
+impl fmt::Display for SendOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendOutcome::Accepted { endpoint } => write!(f, "sent to {endpoint}"),
+            SendOutcome::NoEndpoint => write!(f, "no webmention endpoint"),
+            SendOutcome::Failed { msg } => write!(f, "failed: {msg}"),
+        }
+    }
+}
+
+/// Sends a webmention for each of `links`, to the endpoint its target advertises, and logs the ones that were
+/// accepted or have no endpoint so they aren't sent again. Failed ones are tried again next time
+#[cfg(feature = "remote")]
+pub fn send(links: &[&OutgoingLink], log: &mut SentLog, timeout: std::time::Duration) -> Vec<SendOutcome> {
+    let agent: ureq::Agent = ureq::Agent::config_builder().timeout_global(Some(timeout)).build().into();
+
+    let mut outcomes = Vec::new();
+    for link in links {
+        let outcome = match discover_endpoint(&agent, &link.target) {
+            Ok(Some(endpoint)) => {
+                let form = [("source", link.source.as_str()), ("target", link.target.as_str())];
+                match agent.post(&endpoint).send_form(form) {
+                    Ok(_) => SendOutcome::Accepted { endpoint },
+                    Err(e) => SendOutcome::Failed { msg: e.to_string() },
+                }
+            }
+            Ok(None) => SendOutcome::NoEndpoint,
+            Err(e) => SendOutcome::Failed { msg: e.to_string() },
+        };
+        info!("{link}: {outcome}");
+        if !matches!(outcome, SendOutcome::Failed { .. }) {
+            log.insert(link);
+        }
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+/// The webmention endpoint of `target`, from its `Link` header or else its first `<link>` or `<a>` with
+/// `rel="webmention"`, resolved against `target`
+#[cfg(feature = "remote")]
+fn discover_endpoint(agent: &ureq::Agent, target: &str) -> Result<Option<String>, ureq::Error> {
+    let mut response = agent.get(target).call()?;
+    for header in response.headers().get_all("link") {
+        let Ok(header) = header.to_str() else {
+            continue;
+        };
+        if let Some(endpoint) = header.split(',').find_map(webmention_link) {
+            return Ok(Some(resolve_url(target, endpoint)));
+        }
+    }
+
+    let is_html = response.headers().get("content-type").and_then(|v| v.to_str().ok()).is_some_and(|v| v.contains("html"));
+    if !is_html {
+        return Ok(None);
+    }
+    let Ok(dom) = crate::entities::parse(&response.body_mut().read_to_string()?) else {
+        return Ok(None);
+    };
+    Ok(endpoint_in(&dom).map(|endpoint| resolve_url(target, &endpoint)))
+}
+
+/// The URL of a `Link` header entry like `<https://example.com/webmention>; rel="webmention"`, if that's its rel
+#[cfg(feature = "remote")]
+fn webmention_link(entry: &str) -> Option<&str> {
+    let (url, params) = entry.trim().strip_prefix('<')?.split_once('>')?;
+    let is_webmention = params.split(';').any(|param| {
+        let Some((key, value)) = param.split_once('=') else {
+            return false;
+        };
+        key.trim() == "rel" && value.trim().trim_matches('"').split_ascii_whitespace().any(|rel| rel == "webmention")
+    });
+    is_webmention.then_some(url)
+}
+
+#[cfg(feature = "remote")]
+fn endpoint_in(dom: &[Node]) -> Option<String> {
+    dom.iter().find_map(|node| {
+        let Node::Element(Element { name, attrs, children }) = node else {
+            return None;
+        };
+        let is_endpoint = (name == "link" || name == "a")
+            && get_attr(attrs, "rel").is_some_and(|rel| rel.split_ascii_whitespace().any(|rel| rel == "webmention"));
+        match get_attr(attrs, "href") {
+            Some(href) if is_endpoint => Some(href.to_string()),
+            _ => endpoint_in(children),
+        }
+    })
+}
+
+/// `href` relative to the absolute URL `base`
+#[cfg(feature = "remote")]
+fn resolve_url(base: &str, href: &str) -> String {
+    if href.contains("://") {
+        return href.to_string();
+    }
+    let (scheme, rest) = base.split_once("://").unwrap_or(("https", base));
+    if let Some(rest) = href.strip_prefix("//") {
+        return format!("{scheme}://{rest}");
+    }
+    let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let origin = format!("{scheme}://{}", &rest[..host_end]);
+    let path = rest[host_end..].split(['?', '#']).next().unwrap_or("");
+    match href {
+        "" => base.to_string(),
+        _ if href.starts_with('/') => format!("{origin}{href}"),
+        _ if href.starts_with(['?', '#']) => format!("{origin}{path}{href}"),
+        _ => format!("{origin}{}/{href}", &path[..path.rfind('/').unwrap_or(0)]),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MentionKind {
+    Reply,
+    Like,
+    Repost,
+    Bookmark,
+    Mention,
+}
+
+impl MentionKind {
+    /// From JF2's `wm-property`
+    pub fn from_property(property: &str) -> MentionKind {
+        match property {
+            "in-reply-to" => MentionKind::Reply,
+            "like-of" => MentionKind::Like,
+            "repost-of" => MentionKind::Repost,
+            "bookmark-of" => MentionKind::Bookmark,
+            _ => MentionKind::Mention,
+        }
+    }
+
+    /// Whether it has something to say, rather than just being counted
+    pub fn is_comment(&self) -> bool {
+        matches!(self, MentionKind::Reply | MentionKind::Mention)
+    }
+}
+
+/// A received webmention
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    /// The page mentioning the target
+    pub source: String,
+    pub target: String,
+    pub kind: MentionKind,
+    pub author_name: Option<String>,
+    pub author_url: Option<String>,
+    pub author_photo: Option<String>,
+    /// ISO 8601
+    pub published: Option<String>,
+    /// Plain text
+    pub content: Option<String>,
+}
+
+/// The webmentions in `json`: a JF2 feed, as webmention.io's API returns it, or an array of its entries.
+/// Entries without a `wm-target` are left out
+pub fn parse_mentions(json: &str) -> Result<Vec<Mention>, serde_json::Error> {
+    let feed: Value = serde_json::from_str(json)?;
+    let entries = match &feed {
+        Value::Array(entries) => entries.as_slice(),
+        feed => feed.get("children").and_then(Value::as_array).map_or(&[][..], Vec::as_slice),
+    };
+
+    let mentions = entries
+        .iter()
+        .filter_map(|entry| {
+            let field = |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+            let author = entry.get("author").unwrap_or(&Value::Null);
+            Some(Mention {
+                source: field(entry, "url").or_else(|| field(entry, "wm-source"))?,
+                target: field(entry, "wm-target")?,
+                kind: MentionKind::from_property(entry.get("wm-property").and_then(Value::as_str).unwrap_or_default()),
+                author_name: field(author, "name"),
+                author_url: field(author, "url"),
+                author_photo: field(author, "photo"),
+                published: field(entry, "published").or_else(|| field(entry, "wm-received")),
+                content: entry.get("content").and_then(|content| field(content, "text")),
+            })
+        })
+        .collect();
+    Ok(mentions)
+}
+
+/// The path of `url` without what doesn't change the page, so `https://example.com/posts/` and
+/// `/posts/index.html` are the same
+fn page_path(url: &str) -> &str {
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest.find('/').map_or("", |start| &rest[start..]));
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    path.strip_suffix("index.html").unwrap_or(path).trim_matches('/')
+}
+
+/// Replaces `<webmentions/>` with the webmentions the page received, read from a JSON file in the project, see
+/// `parse_mentions`: replies and mentions as a list of comments, likes, reposts and bookmarks as counts. The
+/// element's children, like a heading, come first. Nothing if the page has no webmentions, or the file doesn't
+/// exist
+pub struct WebmentionList {
+    /// Relative to the project root
+    pub file: PathBuf,
+    /// Read when first needed
+    mentions: OnceLock<Result<Vec<Mention>, String>>,
+}
+
+impl WebmentionList {
+    pub fn new<P: Into<PathBuf>>(file: P) -> WebmentionList {
+        WebmentionList { file: file.into(), mentions: OnceLock::new() }
+    }
+
+    fn mentions<R: Resource>(&self, resources: &ResourceManager<R>) -> Result<&[Mention], ConfigurafoxError> {
+        let mentions = self.mentions.get_or_init(|| match resources.vfs().read(&self.file) {
+            Ok(json) => parse_mentions(&String::from_utf8_lossy(&json)).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("{} doesn't exist, no webmentions", self.file.display());
+                Ok(Vec::new())
+            }
+            Err(e) => Err(e.to_string()),
+        });
+        mentions.as_deref().map_err(|msg| ConfigurafoxError::Other(format!("{}: {msg}", self.file.display())))
+    }
+}
+
+impl<R: Resource, D> TreeWalker<R, D> for WebmentionList {
+    fn describe(&self) -> String {
+        format!("WebmentionList({})", self.file.display())
+    }
+
+    fn matches(&self, tag_name: &str, _attrs: &[(String, String)], _ctx: Context<'_, '_, R, D>) -> bool {
+        tag_name == "webmentions"
+    }
+
+    fn tags(&self) -> Option<Vec<&str>> {
+        Some(vec!["webmentions"])
+    }
+
+    fn replace(&self, _tag_name: &str, attrs: Vec<(String, String)>, children: Vec<Node>, ctx: Context<'_, '_, R, D>) -> Result<Vec<Node>, ConfigurafoxError> {
+        let page = crate::url_path(&ctx.resources.output_path(ctx.resource))?;
+        let mentions = self
+            .mentions(ctx.resources)?
+            .iter()
+            .filter(|mention| page_path(&mention.target) == page_path(&page))
+            .collect::<Vec<_>>();
+        if mentions.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut section = children;
+        let counts = [(MentionKind::Like, "like"), (MentionKind::Repost, "repost"), (MentionKind::Bookmark, "bookmark")]
+            .into_iter()
+            .filter_map(|(kind, noun)| match mentions.iter().filter(|mention| mention.kind == kind).count() {
+                0 => None,
+                1 => Some(format!("1 {noun}")),
+                n => Some(format!("{n} {noun}s")),
+            })
+            .collect::<Vec<_>>();
+        if !counts.is_empty() {
+            section.push(element("p", vec![("class", "webmention-reactions".to_string())], vec![Node::Text(counts.join(", "))]));
+        }
+
+        let comments = mentions.iter().filter(|mention| mention.kind.is_comment()).map(|mention| comment(mention)).collect::<Vec<_>>();
+        if !comments.is_empty() {
+            section.push(element("ol", vec![("class", "webmention-comments".to_string())], comments));
+        }
+
+        let class = get_attr(&attrs, "class").unwrap_or("webmentions").to_string();
+        Ok(vec![element("section", vec![("class", class)], section)])
+    }
+}
+
+/// URLs in it that `is_web_url` doesn't accept aren't linked to, as anyone can send them
+fn comment(mention: &Mention) -> Node {
+    let mut author = Vec::new();
+    if let Some(photo) = mention.author_photo.as_ref().filter(|photo| is_web_url(photo)) {
+        author.push(element("img", vec![("class", "avatar".to_string()), ("src", photo.clone()), ("alt", String::new())], vec![]));
+    }
+    author.push(Node::Text(mention.author_name.clone().unwrap_or_else(|| "Someone".to_string())));
+    let author = match mention.author_url.as_ref().filter(|url| is_web_url(url)) {
+        Some(url) => element("a", vec![("class", "author".to_string()), ("href", url.clone())], author),
+        None => element("span", vec![("class", "author".to_string())], author),
+    };
+
+    let mut header = vec![author, Node::Text(" ".to_string())];
+    let link_text = mention.published.as_deref().map_or("link", |published| published.get(..10).unwrap_or(published));
+    let link = if is_web_url(&mention.source) {
+        element("a", vec![("href", mention.source.clone()), ("rel", "nofollow".to_string())], vec![Node::Text(link_text.to_string())])
+    } else {
+        Node::Text(link_text.to_string())
+    };
+    header.push(match &mention.published {
+        Some(published) => element("time", vec![("datetime", published.clone())], vec![link]),
+        None => link,
+    });
+
+    let mut children = vec![element("p", vec![("class", "webmention-meta".to_string())], header)];
+    if let Some(content) = &mention.content {
+        children.push(element("p", vec![], vec![Node::Text(content.clone())]));
+    }
+    element("li", vec![("class", "webmention".to_string())], children)
+}